@@ -4,8 +4,8 @@ layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec3 aNormal;
 layout (location = 2) in vec2 aTexCoords;
 
-uniform mat4 view;
-uniform mat4 projection;
+#include "common/camera.glsl"
+
 uniform mat4 model;
 
 out vec2 tex_coord;
@@ -19,10 +19,11 @@ void main() {
     // x    x   x    x
     // Where tx, ty, and tz are the x, y, and z translations repsectively.
     //       sx, sy, and sz are the x, y, and z scales repsectively.
-    vec3 billboard_center = vec3(model[0][3], model[1][3], model[2][3]);
-    vec2 scale = vec2(model[0][0], model[1][1]);
+    // GLSL matrices are indexed by column first, so the translation is model[3].
+    vec3 billboard_center = model[3].xyz;
+    vec2 scale = vec2(length(model[0].xyz), length(model[1].xyz));
 
-    gl_Position = projection * view * vec4( billboard_center, 1 );
+    gl_Position = u_camera.projection * u_camera.view * vec4( billboard_center, 1 );
     gl_Position /= abs(gl_Position.w);
 
     gl_Position.xy += aPos.xy * scale;
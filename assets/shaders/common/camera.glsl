@@ -0,0 +1,7 @@
+// Written once a frame from `CameraBlock` in uniform_blocks.rs.
+layout(std140) uniform Camera {
+    mat4 view;
+    mat4 projection;
+    vec3 view_pos;
+    float time; // Seconds since the editor started
+} u_camera;
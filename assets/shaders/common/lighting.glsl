@@ -0,0 +1,177 @@
+// Lights gathered by `light_system.rs`, shared by the shaders that are lit.
+//
+// Point and spot lights are only looked at by the fragments of the clusters they reach,
+// see light_clusters.rs.
+//
+// The sizes come from the Rust side as defines, see `global_defines` in shader_loader.rs.
+// The fallbacks are only for compiling this file on its own.
+
+#include "camera.glsl"
+
+#ifndef CLUSTERS_X
+#define CLUSTERS_X 16
+#endif
+
+#ifndef CLUSTERS_Y
+#define CLUSTERS_Y 9
+#endif
+
+#ifndef CLUSTERS_Z
+#define CLUSTERS_Z 24
+#endif
+
+#ifndef DATA_TEXTURE_WIDTH
+#define DATA_TEXTURE_WIDTH 1024
+#endif
+
+#ifndef MAX_SPOT_SHADOWS
+#define MAX_SPOT_SHADOWS 2
+#endif
+
+#ifndef MAX_POINT_SHADOWS
+#define MAX_POINT_SHADOWS 3
+#endif
+
+struct DirectionalLight {
+    vec3 direction;
+  
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+    bool is_enabled;
+
+    bool casts_shadows;
+    float shadow_bias;
+    mat4 light_space;
+};
+
+struct PointLight {
+    vec3 position;
+    float range; // Left out past this distance
+    
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+    
+    vec3 attenuation_constants; // quadratic, linear, constant  
+    bool is_enabled;
+
+    int shadow_map_index; // Into u_point_shadow_maps, -1 if it doesn't cast shadows
+    float shadow_bias;
+    float shadow_far; // Distance stored as 1.0 in the cube map
+};
+
+struct SpotLight {
+    vec3 position;
+    vec3 direction;
+    float range; // Left out past this distance
+    vec2 cutoff_cos; // inner and outer cutoff cosine of angles  
+
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+
+    vec3 attenuation_constants; // quadratic, linear, constant  
+    bool is_enabled;
+
+    int shadow_map_index; // Into u_spot_shadow_maps, -1 if it doesn't cast shadows
+    float shadow_bias;
+    mat4 light_space;
+};
+
+// Written once a frame from `LightsBlock` in uniform_blocks.rs, the std140 layout of
+// `DirectionalLight` has to match `GpuDirectionalLight` there.
+layout(std140) uniform Lights {
+    DirectionalLight directional;
+    int num_point_lights;
+    int num_spot_lights;
+    vec2 cluster_depth_range; // Depths the slices are spread between
+} u_lights;
+
+// `GpuPointLight::texels` of every point light, followed by `GpuSpotLight::texels` of every spot light
+uniform sampler2D u_light_data;
+
+// Offset, point and spot light count of every cluster, followed by the lists of lights,
+// see `LightClusters::gpu_data`
+uniform usampler2D u_light_clusters;
+
+// The light textures are flat arrays wrapped into rows
+ivec2 dataTexel(int index) {
+    return ivec2(index % DATA_TEXTURE_WIDTH, index / DATA_TEXTURE_WIDTH);
+}
+
+vec4 lightData(int index) {
+    return texelFetch(u_light_data, dataTexel(index), 0);
+}
+
+int clusterData(int index) {
+    return int(texelFetch(u_light_clusters, dataTexel(index), 0).r);
+}
+
+PointLight fetchPointLight(int index) {
+    int texel = index * 5;
+
+    PointLight light;
+    light.position = lightData(texel).xyz;
+    light.range = lightData(texel).w;
+    light.ambient = lightData(texel + 1).rgb;
+    light.is_enabled = lightData(texel + 1).w != 0.0;
+    light.diffuse = lightData(texel + 2).rgb;
+    light.shadow_map_index = int(lightData(texel + 2).w);
+    light.specular = lightData(texel + 3).rgb;
+    light.shadow_bias = lightData(texel + 3).w;
+    light.attenuation_constants = lightData(texel + 4).xyz;
+    light.shadow_far = lightData(texel + 4).w;
+    return light;
+}
+
+SpotLight fetchSpotLight(int index) {
+    int texel = u_lights.num_point_lights * 5 + index * 10;
+
+    SpotLight light;
+    light.position = lightData(texel).xyz;
+    light.range = lightData(texel).w;
+    light.direction = lightData(texel + 1).xyz;
+    light.cutoff_cos = vec2(lightData(texel + 1).w, lightData(texel + 2).w);
+    light.ambient = lightData(texel + 2).rgb;
+    light.diffuse = lightData(texel + 3).rgb;
+    light.is_enabled = lightData(texel + 3).w != 0.0;
+    light.specular = lightData(texel + 4).rgb;
+    light.shadow_map_index = int(lightData(texel + 4).w);
+    light.attenuation_constants = lightData(texel + 5).xyz;
+    light.shadow_bias = lightData(texel + 5).w;
+    light.light_space = mat4(lightData(texel + 6), lightData(texel + 7), lightData(texel + 8), lightData(texel + 9));
+    return light;
+}
+
+// Cluster the world space `position` is in, same as `depth_slice` and the tiles in light_clusters.rs
+int clusterIndex(vec3 position) {
+    vec4 view_position = u_camera.view * vec4(position, 1.0);
+    vec4 clip = u_camera.projection * view_position;
+    vec2 screen = clip.xy / clip.w * 0.5 + 0.5;
+
+    ivec2 tile = clamp(ivec2(screen * vec2(CLUSTERS_X, CLUSTERS_Y)), ivec2(0), ivec2(CLUSTERS_X - 1, CLUSTERS_Y - 1));
+
+    float near = u_lights.cluster_depth_range.x;
+    float far = u_lights.cluster_depth_range.y;
+    float slice = log(-view_position.z / near) / log(far / near) * float(CLUSTERS_Z);
+    int depth_slice = clamp(int(floor(max(slice, 0.0))), 0, CLUSTERS_Z - 1);
+
+    return tile.x + CLUSTERS_X * (tile.y + CLUSTERS_Y * depth_slice);
+}
+
+// How much of a point or spot light reaches `dist` away from it
+float computeAttenuation(vec3 constants, float dist) {
+    return 1.0 / (
+        constants.z                    // Constant attenuation
+        + constants.y * dist           // Linear attenuation
+        + constants.x * dist * dist    // Quadratic attenuation
+    );
+}
+
+// Specular highlight strength for light coming from `light_dir`
+float phongSpecular(vec3 normal, vec3 light_dir, vec3 view_direction, float shininess) {
+    vec3 reflect_dir = reflect(-light_dir, normal);
+    // Below 1 the highlight would cover the whole lit side
+    return pow(max(dot(view_direction, reflect_dir), 0.0), max(shininess, 1.0));
+}
@@ -0,0 +1,19 @@
+// Normal mapping without precomputed tangents, the tangent frame comes from how the position
+// and texture coordinates change across the screen (Christian Schüler, "Normal Mapping Without Precomputed Tangents").
+vec3 perturbNormal(vec3 normal, vec3 position, vec2 uv, sampler2D normal_map) {
+    vec3 dp1 = dFdx(position);
+    vec3 dp2 = dFdy(position);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    float scale = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-12));
+    mat3 tbn = mat3(tangent * scale, bitangent * scale, normal);
+
+    vec3 mapped = texture(normal_map, uv).xyz * 2.0 - 1.0;
+    return normalize(tbn * mapped);
+}
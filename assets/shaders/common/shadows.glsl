@@ -0,0 +1,87 @@
+// Shadow maps drawn by `Renderer::render_shadow_maps`, for the lit shaders.
+// Each function returns the fraction of `position` (world space) that's lit.
+
+#include "lighting.glsl"
+
+uniform sampler2DShadow u_directional_shadow_map;
+uniform sampler2DShadow u_spot_shadow_maps[MAX_SPOT_SHADOWS];
+
+uniform samplerCubeShadow u_point_shadow_maps[MAX_POINT_SHADOWS];
+
+// The depth of a surface changes faster across a texel at grazing angles to the light,
+// so the offset scales with tan(angle)
+float computeSlopeBias(float bias, vec3 normal, vec3 light_dir) {
+    float cos_theta = clamp(dot(normal, light_dir), 0.0, 1.0);
+    float tan_theta = sqrt(1.0 - cos_theta * cos_theta) / max(cos_theta, 0.001);
+    return bias * clamp(tan_theta, 0.2, 10.0);
+}
+
+// Fraction of the fragment that's lit, from a 3x3 PCF kernel.
+// Each tap is itself a bilinear 2x2 comparison since the shadow maps use linear filtering.
+float computeShadow(sampler2DShadow shadow_map, vec3 position, mat4 light_space, float bias, vec3 normal, vec3 light_dir) {
+    vec4 light_space_pos = light_space * vec4(position, 1.0);
+    vec3 coords = light_space_pos.xyz / light_space_pos.w * 0.5 + 0.5;
+
+    // Past the far plane
+    if(coords.z > 1.0) return 1.0;
+
+    float slope_bias = computeSlopeBias(bias, normal, light_dir);
+    vec2 texel_size = 1.0 / vec2(textureSize(shadow_map, 0));
+
+    float lit = 0.0;
+    for(int x = -1; x <= 1; x++) {
+        for(int y = -1; y <= 1; y++) {
+            lit += texture(shadow_map, vec3(coords.xy + vec2(x, y) * texel_size, coords.z - slope_bias));
+        }
+    }
+
+    return lit / 9.0;
+}
+
+// GLSL 3.30 only allows indexing sampler arrays with constants
+float computeSpotShadow(SpotLight light, vec3 position, vec3 normal, vec3 light_dir) {
+    switch(light.shadow_map_index) {
+        case 0: return computeShadow(u_spot_shadow_maps[0], position, light.light_space, light.shadow_bias, normal, light_dir);
+        case 1: return computeShadow(u_spot_shadow_maps[1], position, light.light_space, light.shadow_bias, normal, light_dir);
+        default: return 1.0;
+    }
+}
+
+// Cube maps store the distance to the light divided by `far`, see point_shadow_depth.frag.
+// PCF samples 20 directions around the one towards the fragment.
+float computeCubeShadow(samplerCubeShadow shadow_map, vec3 position, vec3 light_position, float far, float bias, vec3 normal, vec3 light_dir) {
+    vec3 light_to_frag = position - light_position;
+    float dist = length(light_to_frag) / far;
+
+    if(dist > 1.0) return 1.0;
+
+    const vec3 offsets[20] = vec3[](
+        vec3(1,  1,  1), vec3( 1, -1,  1), vec3(-1, -1,  1), vec3(-1,  1,  1),
+        vec3(1,  1, -1), vec3( 1, -1, -1), vec3(-1, -1, -1), vec3(-1,  1, -1),
+        vec3(1,  1,  0), vec3( 1, -1,  0), vec3(-1, -1,  0), vec3(-1,  1,  0),
+        vec3(1,  0,  1), vec3(-1,  0,  1), vec3( 1,  0, -1), vec3(-1,  0, -1),
+        vec3(0,  1,  1), vec3( 0, -1,  1), vec3( 0, -1, -1), vec3( 0,  1, -1)
+    );
+
+    float reference = dist - computeSlopeBias(bias, normal, light_dir);
+
+    // A cube face spans 2 units at a distance of 1, spread the samples about a texel apart
+    float texel_size = 2.0 / float(textureSize(shadow_map, 0).x);
+    float radius = length(light_to_frag) * texel_size;
+
+    float lit = 0.0;
+    for(int i = 0; i < 20; i++) {
+        lit += texture(shadow_map, vec4(light_to_frag + offsets[i] * radius, reference));
+    }
+
+    return lit / 20.0;
+}
+
+float computePointShadow(PointLight light, vec3 position, vec3 normal, vec3 light_dir) {
+    switch(light.shadow_map_index) {
+        case 0: return computeCubeShadow(u_point_shadow_maps[0], position, light.position, light.shadow_far, light.shadow_bias, normal, light_dir);
+        case 1: return computeCubeShadow(u_point_shadow_maps[1], position, light.position, light.shadow_far, light.shadow_bias, normal, light_dir);
+        case 2: return computeCubeShadow(u_point_shadow_maps[2], position, light.position, light.shadow_far, light.shadow_bias, normal, light_dir);
+        default: return 1.0;
+    }
+}
@@ -10,6 +10,13 @@
 // as the first emissive texture. 
 // Querying the position of `texture_diffuse1` will not error out. Instead, it
 // will return the position of `texture_emissive1`.
+//
+// `ShaderProgram` reads the active uniforms when linking, so setting an optimized out uniform
+// is skipped quietly while a name that isn't in the source at all gets reported.
+
+// Samplers without a texture read whatever is bound to unit 0, so the optional maps are only
+// read in the variants compiled with HAS_NORMAL_MAP, HAS_EMISSIVE_MAP and HAS_OPACITY_MAP,
+// see `Material::variant_defines`.
 
 // ! TODO: Use multiple textures for lighting
 // ! Currently, only the first of each type is used.
@@ -23,44 +30,22 @@ struct Material {
     sampler2D texture_specular3;
     
     sampler2D texture_emissive1;
-    vec3 emissive_factor;
+    sampler2D texture_normal1;
+    sampler2D texture_opacity1;
+
+    // Multiplied with the textures, see `MaterialParameters::defaults`
+    vec3 ambient_color;
+    vec3 diffuse_color;
+    vec3 specular_color;
+    vec3 emissive_color;
     float shininess;
+    float opacity;
 }; 
 
-struct DirectionalLight {
-    vec3 direction;
-  
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-    bool is_enabled;
-};
-
-
-struct PointLight {
-    vec3 position;
-    
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-    
-    vec3 attenuation_constants; // quadratic, linear, constant  
-    bool is_enabled;
-};
-
-struct SpotLight {
-    vec3 position;
-    vec3 direction;
-    vec2 cutoff_cos; // inner and outer cutoff cosine of angles  
-
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-
-    vec3 attenuation_constants; // quadratic, linear, constant  
-    bool is_enabled;
-};
-
+#include "common/camera.glsl"
+#include "common/lighting.glsl"
+#include "common/shadows.glsl"
+#include "common/normal_map.glsl"
 
 in vec3 normal;
 in vec3 frag_pos;
@@ -68,43 +53,61 @@ in vec2 tex_coord;
 
 out vec4 frag_color;
 
-uniform vec3 u_view_pos;
 uniform Material u_material;
 
-uniform DirectionalLight u_directional_light;
-
-#define MAX_POINT_LIGHTS 16
-uniform int u_num_point_lights;
-uniform PointLight u_point_lights[MAX_POINT_LIGHTS];
-
-#define MAX_SPOT_LIGHTS 16
-uniform int u_num_spot_lights;
-uniform SpotLight u_spot_lights[MAX_SPOT_LIGHTS];
-
 vec3 computeDirectionalLight(DirectionalLight light, vec3 normal, vec3 view_direction);
 vec3 computePointLight(PointLight light, vec3 normal, vec3 frag_pos, vec3 view_direction);
 vec3 computeSpotLight(SpotLight light, vec3 normal, vec3 frag_pos, vec3 view_direction);
 
+// Material colors at this fragment, set at the start of main
+vec3 material_ambient;
+vec3 material_diffuse;
+vec3 material_specular;
+
 void main() {
-    
+    vec3 diffuse_texel = texture(u_material.texture_diffuse1, tex_coord).rgb;
+    material_ambient = diffuse_texel * u_material.ambient_color;
+    material_diffuse = diffuse_texel * u_material.diffuse_color;
+    material_specular = texture(u_material.texture_specular1, tex_coord).rgb * u_material.specular_color;
+
     vec3 norm = normalize(normal);
-    vec3 view_direction = normalize(u_view_pos - frag_pos);
+#ifdef HAS_NORMAL_MAP
+    norm = perturbNormal(norm, frag_pos, tex_coord, u_material.texture_normal1);
+#endif
+
+    vec3 view_direction = normalize(u_camera.view_pos - frag_pos);
 
     vec3 result = 
-        computeDirectionalLight(u_directional_light, norm, view_direction);
+        computeDirectionalLight(u_lights.directional, norm, view_direction);
     
-    for (int i = 0; i < MAX_POINT_LIGHTS; i++){
+    // Only the lights reaching this fragment's cluster
+    int cluster = clusterIndex(frag_pos) * 3;
+    int lights_start = clusterData(cluster);
+    int num_point_lights = clusterData(cluster + 1);
+    int num_spot_lights = clusterData(cluster + 2);
+
+    for (int i = 0; i < num_point_lights; i++){
         result += 
-            computePointLight(u_point_lights[i], norm, frag_pos, view_direction);
+            computePointLight(fetchPointLight(clusterData(lights_start + i)), norm, frag_pos, view_direction);
     }    
     
-    for (int i = 0; i < MAX_SPOT_LIGHTS; i++){
+    for (int i = 0; i < num_spot_lights; i++){
         result += 
-            computeSpotLight(u_spot_lights[i], norm, frag_pos, view_direction);
+            computeSpotLight(fetchSpotLight(clusterData(lights_start + num_point_lights + i)), norm, frag_pos, view_direction);
     }    
     
-    result += texture(u_material.texture_emissive1, tex_coord).rrr * u_material.emissive_factor;
-    frag_color = vec4(result, 1.0);
+    vec3 emissive = u_material.emissive_color;
+#ifdef HAS_EMISSIVE_MAP
+    emissive *= texture(u_material.texture_emissive1, tex_coord).rgb;
+#endif
+    result += emissive;
+
+    float opacity = u_material.opacity;
+#ifdef HAS_OPACITY_MAP
+    opacity *= texture(u_material.texture_opacity1, tex_coord).r;
+#endif
+
+    frag_color = vec4(result, opacity);
 }
 
 vec3 computeDirectionalLight(DirectionalLight light, vec3 normal, vec3 view_direction) {
@@ -113,12 +116,17 @@ vec3 computeDirectionalLight(DirectionalLight light, vec3 normal, vec3 view_dire
     vec3 light_dir = normalize(-light.direction);
     float diff = max(dot(normal, light_dir), 0.0);
     
-    vec3 reflect_dir = reflect(-light_dir, normal);
-    float spec = pow(max(dot(view_direction, reflect_dir), 0.0), u_material.shininess);
+    float spec = phongSpecular(normal, light_dir, view_direction, u_material.shininess);
     
-    vec3 ambient = light.ambient * texture(u_material.texture_diffuse1, tex_coord).rgb;
-    vec3 diffuse = light.diffuse * diff * texture(u_material.texture_diffuse1, tex_coord).rgb;
-    vec3 specular = light.specular * spec * texture(u_material.texture_specular1, tex_coord).rgb;
+    vec3 ambient = light.ambient * material_ambient;
+    vec3 diffuse = light.diffuse * diff * material_diffuse;
+    vec3 specular = light.specular * spec * material_specular;
+
+    if(light.casts_shadows) {
+        float shadow = computeShadow(u_directional_shadow_map, frag_pos, light.light_space, light.shadow_bias, normal, light_dir);
+        diffuse *= shadow;
+        specular *= shadow;
+    }
     
     return ambient + diffuse + specular;
 }
@@ -129,24 +137,20 @@ vec3 computePointLight(PointLight light, vec3 normal, vec3 frag_pos, vec3 view_d
     vec3 light_dir = normalize(light.position - frag_pos);    
     float diff = max(dot(normal, light_dir), 0.0);
     
-    vec3 reflect_dir = reflect(-light_dir, normal);
-    float spec = pow(max(dot(view_direction, reflect_dir), 0.0), u_material.shininess);
+    float spec = phongSpecular(normal, light_dir, view_direction, u_material.shininess);
     
-    float dist = length(light.position - frag_pos);
-    float attenuation = 1.0 / (
-        light.attenuation_constants.z             // Constant anttenuation
-        + light.attenuation_constants.y  * dist   // Linear attenuation 
-        + light.attenuation_constants.x  * dist * dist // Quadratic attenuation
-    );
+    float attenuation = computeAttenuation(light.attenuation_constants, length(light.position - frag_pos));
     
-    vec3 ambient = light.ambient * texture(u_material.texture_diffuse1, tex_coord).rgb;
-    vec3 diffuse = light.diffuse * diff * texture(u_material.texture_diffuse1, tex_coord).rgb;
-    vec3 specular = light.specular * spec * texture(u_material.texture_specular1, tex_coord).rgb;
+    vec3 ambient = light.ambient * material_ambient;
+    vec3 diffuse = light.diffuse * diff * material_diffuse;
+    vec3 specular = light.specular * spec * material_specular;
 
     
+    float shadow = computePointShadow(light, frag_pos, normal, light_dir);
+
     ambient *= attenuation;
-    diffuse *= attenuation;
-    specular *= attenuation;
+    diffuse *= attenuation * shadow;
+    specular *= attenuation * shadow;
     
     
     return ambient + diffuse + specular;
@@ -159,19 +163,13 @@ vec3 computeSpotLight(SpotLight light, vec3 normal, vec3 frag_pos, vec3 view_dir
     vec3 light_dir = normalize(light.position - frag_pos);    
     float diff = max(dot(normal, light_dir), 0.0);
     
-    vec3 reflect_dir = reflect(-light_dir, normal);
-    float spec = pow(max(dot(view_direction, reflect_dir), 0.0), u_material.shininess);
+    float spec = phongSpecular(normal, light_dir, view_direction, u_material.shininess);
     
-    float dist = length(light.position - frag_pos);
-    float attenuation = 1.0 / (
-        light.attenuation_constants.z             // Constant anttenuation
-        + light.attenuation_constants.y  * dist   // Linear attenuation 
-        + light.attenuation_constants.x  * dist * dist // Quadratic attenuation
-    );
+    float attenuation = computeAttenuation(light.attenuation_constants, length(light.position - frag_pos));
     
-    vec3 ambient = light.ambient * texture(u_material.texture_diffuse1, tex_coord).rgb;
-    vec3 diffuse = light.diffuse * diff * texture(u_material.texture_diffuse1, tex_coord).rgb;
-    vec3 specular = light.specular * spec * texture(u_material.texture_specular1, tex_coord).rgb;
+    vec3 ambient = light.ambient * material_ambient;
+    vec3 diffuse = light.diffuse * diff * material_diffuse;
+    vec3 specular = light.specular * spec * material_specular;
     
     float theta = dot(light_dir, normalize(-light.direction));
 
@@ -182,9 +180,11 @@ vec3 computeSpotLight(SpotLight light, vec3 normal, vec3 frag_pos, vec3 view_dir
     // cos (15 deg) > cos(20 degsj)
     float intensity = smoothstep(light.cutoff_cos.y, light.cutoff_cos.x, theta);
 
+    float shadow = computeSpotShadow(light, frag_pos, normal, light_dir);
+
     ambient *= attenuation ;
-    diffuse *= attenuation * intensity;
-    specular *= attenuation * intensity;
+    diffuse *= attenuation * intensity * shadow;
+    specular *= attenuation * intensity * shadow;
     
     
     return ambient + diffuse + specular;
@@ -0,0 +1,199 @@
+#version 330 core
+
+// Metallic-roughness materials lit with a Cook-Torrance BRDF, the material model of glTF.
+// The Phong shaded counterpart is lit-textured.frag, both read the same lights and shadows.
+//
+// Optional maps are only read in the variants compiled with their HAS_*_MAP define,
+// see `Material::variant_defines`.
+
+struct Material {
+    sampler2D texture_diffuse1; // Base color
+
+    sampler2D texture_metallic_roughness1; // Roughness in green, metallic in blue, like glTF
+    sampler2D texture_roughness1;
+    sampler2D texture_metallic1;
+    sampler2D texture_occlusion1;
+    sampler2D texture_emissive1;
+    sampler2D texture_normal1;
+    sampler2D texture_opacity1;
+
+    // Multiplied with the textures, see `MaterialParameters::defaults`
+    vec3 base_color;
+    float metallic;
+    float roughness;
+    float occlusion_strength;
+    vec3 emissive_color;
+    float opacity;
+};
+
+#include "common/camera.glsl"
+#include "common/lighting.glsl"
+#include "common/shadows.glsl"
+#include "common/normal_map.glsl"
+
+in vec3 normal;
+in vec3 frag_pos;
+in vec2 tex_coord;
+
+out vec4 frag_color;
+
+uniform Material u_material;
+
+const float PI = 3.14159265359;
+
+// Below this highlights get too small to be sampled without aliasing
+const float MIN_ROUGHNESS = 0.04;
+
+vec3 computeDirectionalLight(DirectionalLight light, vec3 normal, vec3 view_direction);
+vec3 computePointLight(PointLight light, vec3 normal, vec3 frag_pos, vec3 view_direction);
+vec3 computeSpotLight(SpotLight light, vec3 normal, vec3 frag_pos, vec3 view_direction);
+
+// Surface at this fragment, set at the start of main
+vec3 albedo;
+float metallic;
+float roughness;
+float occlusion;
+
+void main() {
+    albedo = texture(u_material.texture_diffuse1, tex_coord).rgb * u_material.base_color;
+    metallic = u_material.metallic;
+    roughness = u_material.roughness;
+    occlusion = 1.0;
+
+#ifdef HAS_METALLIC_ROUGHNESS_MAP
+    vec4 metallic_roughness = texture(u_material.texture_metallic_roughness1, tex_coord);
+    roughness *= metallic_roughness.g;
+    metallic *= metallic_roughness.b;
+#endif
+#ifdef HAS_ROUGHNESS_MAP
+    roughness *= texture(u_material.texture_roughness1, tex_coord).r;
+#endif
+#ifdef HAS_METALLIC_MAP
+    metallic *= texture(u_material.texture_metallic1, tex_coord).r;
+#endif
+#ifdef HAS_OCCLUSION_MAP
+    occlusion = mix(1.0, texture(u_material.texture_occlusion1, tex_coord).r, u_material.occlusion_strength);
+#endif
+
+    metallic = clamp(metallic, 0.0, 1.0);
+    roughness = clamp(roughness, MIN_ROUGHNESS, 1.0);
+
+    vec3 norm = normalize(normal);
+#ifdef HAS_NORMAL_MAP
+    norm = perturbNormal(norm, frag_pos, tex_coord, u_material.texture_normal1);
+#endif
+
+    vec3 view_direction = normalize(u_camera.view_pos - frag_pos);
+
+    vec3 result =
+        computeDirectionalLight(u_lights.directional, norm, view_direction);
+
+    // Only the lights reaching this fragment's cluster
+    int cluster = clusterIndex(frag_pos) * 3;
+    int lights_start = clusterData(cluster);
+    int num_point_lights = clusterData(cluster + 1);
+    int num_spot_lights = clusterData(cluster + 2);
+
+    for (int i = 0; i < num_point_lights; i++){
+        result +=
+            computePointLight(fetchPointLight(clusterData(lights_start + i)), norm, frag_pos, view_direction);
+    }
+
+    for (int i = 0; i < num_spot_lights; i++){
+        result +=
+            computeSpotLight(fetchSpotLight(clusterData(lights_start + num_point_lights + i)), norm, frag_pos, view_direction);
+    }
+
+    vec3 emissive = u_material.emissive_color;
+#ifdef HAS_EMISSIVE_MAP
+    emissive *= texture(u_material.texture_emissive1, tex_coord).rgb;
+#endif
+    result += emissive;
+
+    float opacity = u_material.opacity;
+#ifdef HAS_OPACITY_MAP
+    opacity *= texture(u_material.texture_opacity1, tex_coord).r;
+#endif
+
+    frag_color = vec4(result, opacity);
+}
+
+// Trowbridge-Reitz GGX, how many microfacets face along `halfway`
+float distributionGGX(float n_dot_h) {
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * denominator * denominator);
+}
+
+// Schlick-GGX, how many microfacets aren't hidden behind others seen from one direction
+float geometrySchlickGGX(float n_dot_x) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// How much light is reflected rather than refracted. Dielectrics reflect about 4% head on,
+// metals reflect their base color.
+vec3 fresnelSchlick(float cos_theta) {
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Light reflected towards the viewer from `radiance` arriving from `light_dir`.
+// Lights are scaled by PI, so a white surface facing a light is as bright as with the Phong shader.
+vec3 cookTorrance(vec3 normal, vec3 view_direction, vec3 light_dir, vec3 radiance) {
+    vec3 halfway = normalize(view_direction + light_dir);
+    float n_dot_l = max(dot(normal, light_dir), 0.0);
+    float n_dot_v = max(dot(normal, view_direction), 0.0);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+
+    vec3 fresnel = fresnelSchlick(max(dot(halfway, view_direction), 0.0));
+    float visibility = geometrySchlickGGX(n_dot_v) * geometrySchlickGGX(n_dot_l);
+    vec3 specular = distributionGGX(n_dot_h) * visibility * fresnel / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+
+    // Metals have no diffuse reflection
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+
+    return (diffuse + specular) * radiance * PI * n_dot_l;
+}
+
+vec3 computeDirectionalLight(DirectionalLight light, vec3 normal, vec3 view_direction) {
+    if(!light.is_enabled) return vec3(0.0);
+
+    vec3 light_dir = normalize(-light.direction);
+    vec3 ambient = light.ambient * albedo * occlusion;
+    vec3 reflected = cookTorrance(normal, view_direction, light_dir, light.diffuse);
+
+    if(light.casts_shadows) {
+        reflected *= computeShadow(u_directional_shadow_map, frag_pos, light.light_space, light.shadow_bias, normal, light_dir);
+    }
+
+    return ambient + reflected;
+}
+
+vec3 computePointLight(PointLight light, vec3 normal, vec3 frag_pos, vec3 view_direction) {
+    if(!light.is_enabled) return vec3(0.0);
+
+    vec3 light_dir = normalize(light.position - frag_pos);
+    float attenuation = computeAttenuation(light.attenuation_constants, length(light.position - frag_pos));
+
+    vec3 ambient = light.ambient * albedo * occlusion;
+    vec3 reflected = cookTorrance(normal, view_direction, light_dir, light.diffuse);
+    reflected *= computePointShadow(light, frag_pos, normal, light_dir);
+
+    return (ambient + reflected) * attenuation;
+}
+
+vec3 computeSpotLight(SpotLight light, vec3 normal, vec3 frag_pos, vec3 view_direction) {
+    if(!light.is_enabled) return vec3(0.0);
+
+    vec3 light_dir = normalize(light.position - frag_pos);
+    float attenuation = computeAttenuation(light.attenuation_constants, length(light.position - frag_pos));
+    float intensity = smoothstep(light.cutoff_cos.y, light.cutoff_cos.x, dot(light_dir, normalize(-light.direction)));
+
+    vec3 ambient = light.ambient * albedo * occlusion;
+    vec3 reflected = cookTorrance(normal, view_direction, light_dir, light.diffuse);
+    reflected *= intensity * computeSpotShadow(light, frag_pos, normal, light_dir);
+
+    return (ambient + reflected) * attenuation;
+}
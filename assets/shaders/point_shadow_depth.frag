@@ -0,0 +1,12 @@
+#version 330 core
+
+in vec3 frag_pos;
+
+uniform vec3 light_position;
+uniform float far;
+
+// Stores the linear distance to the light instead of the perspective depth,
+// so every cube face can be compared against the same value.
+void main() {
+    gl_FragDepth = length(frag_pos - light_position) / far;
+}
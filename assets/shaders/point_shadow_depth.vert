@@ -0,0 +1,13 @@
+#version 330 core
+
+layout (location = 0) in vec3 aPos;
+
+uniform mat4 light_space;
+uniform mat4 model;
+
+out vec3 frag_pos;
+
+void main() {
+    frag_pos = vec3(model * vec4(aPos, 1.0));
+    gl_Position = light_space * vec4(frag_pos, 1.0);
+}
@@ -0,0 +1,5 @@
+#version 330 core
+
+// Only depth is written, the shadow map framebuffer has no color attachment.
+void main() {
+}
@@ -2,9 +2,15 @@
 
 in vec2 tex_coord;
 
-uniform sampler2D texture_diffuse1;
+// Multiplied with the texture, see `MaterialParameters::defaults`
+struct Material {
+    sampler2D texture_diffuse1;
+    vec3 diffuse_color;
+    float opacity;
+};
+
+uniform Material u_material;
 
 void main(){
-    gl_FragColor = texture(texture_diffuse1, tex_coord);
+    gl_FragColor = texture(u_material.texture_diffuse1, tex_coord) * vec4(u_material.diffuse_color, u_material.opacity);
 }
-
@@ -5,11 +5,11 @@ layout (location = 1) in vec2 aTexCoord;
 
 out vec2 tex_coord;
 
+#include "common/camera.glsl"
+
 uniform mat4 model;
-uniform mat4 view;
-uniform mat4 projection;
 
 void main() {
     tex_coord = aTexCoord;
-    gl_Position = projection * view * model * vec4(aPos, 1.0);
+    gl_Position = u_camera.projection * u_camera.view * model * vec4(aPos, 1.0);
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 
-use std::{sync::Arc, env};
+use std::{sync::Arc, env, path::Path};
 
+use bevy_ecs::entity::Entity;
 use glow::HasContext;
 use glutin::{event::*, event_loop::ControlFlow};
 
@@ -10,18 +11,21 @@ use crate::loaders::utils::Handle;
 
 use crate::renderer::GlutinWindow;
 use crate::{
-    add_component, 
     ui::*, 
-    light::*,
-    ecs::AddableComponent, renderer::Renderer
+    ecs,
+    renderer::Renderer
 };
 
 use crate::{
     loaders::*,
     gl::get_gl,
     scene::Scene,
+    model::Model,
     input::InputSystem,
     transform::Transform,
+    hierarchy,
+    notifications,
+    scene_file::LoaderAssets,
 };
 
 pub type EventLoop = glutin::event_loop::EventLoop<()>;
@@ -31,21 +35,28 @@ enum Panels {
     #[default]
     Entities,
     Models,
+    Materials,
+    Log,
 }
 
 pub struct App {
     glow: egui_glow::EguiGlow,
+    window: GlutinWindow,
     renderer: Renderer,
     current_scene: Scene, 
 
-    shader_loader : ShaderLoader,
-    texture_loader: TextureLoader,
-    object_loader : ObjLoader,
+    assets: AssetServer,
     input: InputSystem,
 
+    /// Root entities of glTF files still loading in the background, their node trees are
+    /// spawned under them once loaded.
+    pending_gltf: Vec<(String, Entity)>,
+
     last_frame: std::time::Instant,
     cumulative_time: std::time::Instant,
-    current_panel: Panels
+    current_panel: Panels,
+    material_editor: MaterialEditor,
+    thumbnails: TextureThumbnails,
 }
 
 impl App {
@@ -100,42 +111,86 @@ impl App {
 
         println!("Loading, please wait...");
 
-        let mut shader_loader = ShaderLoader::new(&[]);
-        let mut texture_loader = TextureLoader::new();
-        let object_loader = ObjLoader::new(&mut shader_loader, &mut texture_loader);
+        let assets = AssetServer::new(&[]);
 
 
         let last_frame = std::time::Instant::now();
         let input = InputSystem::new();
 
-        let mut renderer = Renderer::new(window, window_width, window_height, &mut shader_loader);
+        let renderer = Renderer::new(window_width, window_height);
+
+        let mut empty_scene = Scene::empty(window_width as i32, window_height as i32);
+        register_component_ui(&mut empty_scene.world);
 
-        let empty_scene = Scene::empty(window_width as i32, window_height as i32);
         let app = App {
+            window,
             renderer,
             glow: egui_glow,
 
             current_scene: empty_scene,
 
-            shader_loader,
-            texture_loader,
-            object_loader,       
+            assets,
             input,
+            pending_gltf: Vec::new(),
 
             last_frame,
             current_panel: Panels::Entities,
+            material_editor: MaterialEditor::default(),
+            thumbnails: TextureThumbnails::default(),
             cumulative_time
         };
 
         (app, event_loop)
     }
 
-    pub fn set_scene(&mut self, scene: Scene) {
-        self.current_scene = scene
+    pub fn set_scene(&mut self, mut scene: Scene) {
+        register_component_ui(&mut scene.world);
+        self.current_scene = scene;
+        self.pending_gltf.clear();
+    }
+
+    fn update_assets(&mut self) {
+        self.assets.reload_changed();
+
+        let loaded_models = self.assets.update();
+        let any_loaded = !loaded_models.is_empty();
+
+        for loaded in loaded_models {
+            let key = canonical_key(&loaded.path);
+            let (pending, rest) = std::mem::take(&mut self.pending_gltf)
+                .into_iter()
+                .partition(|(path, _)| canonical_key(path) == key);
+            self.pending_gltf = rest;
+
+            if let Err(err) = loaded.result {
+                notifications::error(err);
+                continue;
+            }
+
+            // Swap the placeholder for the node tree
+            let world = &mut self.current_scene.world;
+            for (path, root) in pending {
+                let Some(mut root_entity) = world.get_entity_mut(root) else { continue };
+                root_entity.remove::<Handle<Model>>();
+
+                if let Err(err) = self.assets.object_loader.spawn_gltf_nodes(world, &path, root) {
+                    notifications::error(err);
+                }
+            }
+        }
+
+        // Entities of an opened scene waiting for these files
+        if any_loaded {
+            self.current_scene.resolve_assets(&mut LoaderAssets::new(&mut self.assets));
+        }
     }
 
     fn app_ui(&mut self) {
-        self.glow.run(self.renderer.window.window(), |egui_ctx| {
+        let mut file_action = None;
+
+        self.thumbnails.register(&mut self.glow.painter, &self.assets.texture_loader);
+
+        self.glow.run(self.window.window(), |egui_ctx| {
             egui::Window::new("ggl")
                 .hscroll(false)
                 .vscroll(false)
@@ -149,67 +204,142 @@ impl App {
                     .show_inside(ui, |ui| {
 
                         ui.horizontal(|ui| {
+                            file_action = file_menu(ui);
+                            ui.separator();
                             ui.selectable_value(&mut self.current_panel, Panels::Entities, "Entities");
                             ui.selectable_value(&mut self.current_panel, Panels::Models, "Models");
+                            ui.selectable_value(&mut self.current_panel, Panels::Materials, "Materials");
+                            ui.selectable_value(&mut self.current_panel, Panels::Log, "Log");
                         });
                     });
 
                 selected_entity_gizmo(egui_ctx, &mut self.current_scene, &self.input);
+                notification_toasts(egui_ctx);
 
                 match self.current_panel {
                     Panels::Entities => {
-                        let (selected_entity, add_entity, add_component) = entities_panel(
+                        let response = entities_panel(
                             ui,
                             &mut self.current_scene,
                             &mut self.renderer.lights_on
                         );
 
-                        self.current_scene.selected_entity = selected_entity;
+                        self.current_scene.selected_entity = response.selected_entity;
 
-                        if add_entity {
-                            self.current_scene.ecs.add_empty_entity();
+                        if response.add_entity {
+                            self.current_scene.add_empty_entity();
                         }
 
                         if let Some(selected_entity) = self.current_scene.selected_entity {
-                            if add_component {
-                                add_component!(
-                                    &mut self.current_scene.ecs,
-                                    selected_entity,
-                                    [Transform, PointLight, SpotLight, DirectionalLight]
-                                );
+                            if let Some(component) = response.add_component {
+                                ecs::add_component(&mut self.current_scene.world, selected_entity, component);
+
+                                // Models are added as a `ModelRef` to the default cube
+                                self.current_scene.resolve_assets(&mut LoaderAssets::new(&mut self.assets));
                             }
+
+                            if let Some(component) = response.remove_component {
+                                ecs::remove_component(&mut self.current_scene.world, selected_entity, component);
+                            }
+
+                            if response.delete_entity {
+                                hierarchy::despawn_recursive(&mut self.current_scene.world, selected_entity);
+                                self.current_scene.selected_entity = None;
+                            }
+                        }
+
+                        if let Some((child, new_parent)) = response.reparent {
+                            hierarchy::set_parent(&mut self.current_scene.world, child, new_parent);
                         }
                     }, 
                     Panels::Models => {
-                        let path = models_panel(ui, &mut self.object_loader);
+                        let path = models_panel(ui, &mut self.assets);
                         
                         let Some(path) = path else { return; };
 
                         let str_path = path.to_str().unwrap();
-                        let transform = Transform::with_name(str_path);
 
-                        let model_name = format!("Model {}", self.object_loader.models().len());
-                        let loaded_model = 
-                            self.object_loader.load_model(model_name, str_path, &mut self.texture_loader, &mut self.shader_loader);
-
-                        match loaded_model {
-                            Ok(model_rc) => { 
-                                self
-                                    .current_scene
-                                    .ecs
-                                    .add_entity()
-                                    .with(transform)
-                                    .with(Handle::clone(&model_rc)); 
-                            },
-                            Err(_) => eprintln!("Failed to load model at \"{str_path}\""),
-                        };
+                        // glTF node trees become entity hierarchies
+                        if is_gltf(str_path) {
+                            if self.assets.object_loader.get(str_path).is_some() && !self.assets.is_loading(str_path) {
+                                let (texture_loader, object_loader, shader_loader) = self.assets.loaders();
+                                let spawned = object_loader.spawn_gltf(
+                                    &mut self.current_scene.world,
+                                    str_path,
+                                    texture_loader,
+                                    shader_loader
+                                );
+
+                                if let Err(err) = spawned {
+                                    notifications::error(err);
+                                }
+                                return;
+                            }
+
+                            let placeholder = self.assets.load_model_async(str_path);
+                            let root_name = placeholder.borrow().name.clone();
+                            let root = self
+                                .current_scene
+                                .world
+                                .spawn()
+                                .insert(Transform::with_name(root_name))
+                                .insert(placeholder)
+                                .id();
+
+                            self.pending_gltf.push((str_path.to_string(), root));
+                            return;
+                        }
+
+                        let transform = Transform::with_name(str_path);
+                        let model = self.assets.load_model_async(str_path);
+
+                        self
+                            .current_scene
+                            .world
+                            .spawn()
+                            .insert(transform)
+                            .insert(model);
                     }
+                    Panels::Materials => materials_panel(
+                        ui,
+                        &mut self.current_scene.world,
+                        &mut self.assets,
+                        &mut self.material_editor,
+                        &self.thumbnails
+                    ),
+                    Panels::Log => log_panel(ui),
                 };
             });
         });
 
 
-        self.glow.paint(self.renderer.window.window());
+        self.glow.paint(self.window.window());
+
+        match file_action {
+            Some(FileAction::Open(path)) => self.open_scene(&path),
+            Some(FileAction::Save(path)) => self.save_scene(&path),
+            None => {}
+        }
+    }
+
+    fn open_scene(&mut self, path: &Path) {
+        match Scene::load(path) {
+            Ok(mut scene) => {
+                scene.load_models_async(&mut self.assets);
+                scene.resolve_assets(&mut LoaderAssets::new(&mut self.assets));
+                scene.camera.update_aspect_ratio(self.renderer.window_width as f32, self.renderer.window_height as f32);
+                self.set_scene(scene);
+            }
+            Err(err) => notifications::error(format!("Failed to open scene \"{}\": {err}", path.display())),
+        }
+    }
+
+    fn save_scene(&mut self, path: &Path) {
+        let mut assets = LoaderAssets::new(&mut self.assets);
+
+        if let Err(err) = self.current_scene.save(path, &mut assets) {
+            notifications::error(format!("Failed to save scene \"{}\": {err}", path.display()));
+        }
     }
 
     fn handle_events(
@@ -230,6 +360,7 @@ impl App {
                 // Resize window
                 if let WindowEvent::Resized(physical_size) = &event {
                     self.current_scene.window_size_changed(physical_size);
+                    self.window.resize(*physical_size);
                     self.renderer.window_resized(physical_size)
 
                 } else if let WindowEvent::ScaleFactorChanged {
@@ -237,6 +368,7 @@ impl App {
                     ..
                 } = &event
                 {
+                    self.window.resize(**new_inner_size);
                     self.renderer.window_resized(new_inner_size)
                 }
 
@@ -252,7 +384,7 @@ impl App {
                 }
 
 
-                self.renderer.window.window().request_redraw(); // TODO(emilk): ask egui if the events warrants a repaint instead
+                self.window.window().request_redraw(); // TODO(emilk): ask egui if the events warrants a repaint instead
             }
 
             Event::LoopDestroyed => {
@@ -276,11 +408,12 @@ impl App {
                 self.input.update((current_frame - self.last_frame).as_secs_f32());
 
                 self.current_scene.camera.update(&mut self.input);
+                self.update_assets();
 
                 self.renderer.render(
                     &mut self.current_scene.camera,
-                    &mut self.current_scene.ecs,
-                    &mut self.shader_loader,
+                    &mut self.current_scene.world,
+                    &mut self.assets.shader_loader,
                     (current_frame-self.cumulative_time).as_secs_f32()
                 );
 
@@ -290,7 +423,7 @@ impl App {
                 self.last_frame = current_frame;
                 self.input.frame_end();
 
-                self.renderer.window.swap_buffers().unwrap();
+                self.window.swap_buffers().unwrap();
             }
         }
 
@@ -301,10 +434,9 @@ impl App {
     }
 
     pub fn get_resource_managers(&mut self) -> (&mut TextureLoader, &mut ObjLoader, &mut ShaderLoader) {
-        (&mut self.texture_loader, &mut self.object_loader, &mut self.shader_loader)
+        self.assets.loaders()
     }
 }
 
 // TODO: Adding models to entities
-// TODO: Material list
 // TODO: Texture list
@@ -1,10 +1,13 @@
 extern crate nalgebra_glm;
 
-use crate::InputSystem;
+use crate::input::InputSystem;
 use glm::*;
 use glutin::event;
 use nalgebra_glm as glm;
 
+const NEAR_PLANE: f32 = 0.01;
+const FAR_PLANE: f32 = 1000.0;
+
 pub struct Camera {
     pos: Vec3,
 
@@ -107,11 +110,26 @@ impl Camera {
         glm::perspective(
             self.aspect_ratio,
             self.get_fov_euler().to_radians(),
-            0.01,
-            1000.0,
+            NEAR_PLANE,
+            FAR_PLANE,
         )
     }
 
+    /// World space corners of the view frustum, cut off at `far` instead of the far plane.
+    pub fn frustum_corners(&self, far: f32) -> [Vec3; 8] {
+        let proj = glm::perspective(self.aspect_ratio, self.get_fov_euler().to_radians(), NEAR_PLANE, far);
+        let inverse_view_proj = glm::inverse(&(proj * self.get_view_matrix()));
+
+        let mut corners = [Vec3::zeros(); 8];
+        for (index, corner) in corners.iter_mut().enumerate() {
+            let ndc = |bit: usize| if index & bit == 0 { -1.0 } else { 1.0 };
+            let world = inverse_view_proj * vec4(ndc(1), ndc(2), ndc(4), 1.0);
+            *corner = world.xyz() / world.w;
+        }
+
+        corners
+    }
+
     pub fn get_view_matrix(&self) -> Mat4 {
         look_at(&self.pos, &(self.pos + self.forward), &self.up)
     }
@@ -131,6 +149,19 @@ impl Camera {
         self.fov
     }
 
+    pub fn set_fov_euler(&mut self, fov: f32) {
+        self.fov = fov;
+    }
+
+    /// Rotation about the x and y axes in degrees.
+    pub fn get_orientation(&self) -> Vec2 {
+        self.orientation
+    }
+
+    pub fn get_world_up(&self) -> Vec3 {
+        self.world_up
+    }
+
     pub fn get_pos(&self) -> Vec3 {
         self.pos
     }
@@ -2,287 +2,311 @@
 
 extern crate nalgebra_glm as glm;
 
-use std::{
-    cell::{RefCell, RefMut},
-    borrow::BorrowMut,
-    fmt
-};
+use bevy_ecs::{prelude::*, world::EntityMut};
+use egui::Ui;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 
 use crate::{
     egui_drawable::EguiDrawable,
+    light::{DirectionalLight, PointLight, SpotLight},
+    light_system::ShadowBudget,
+    loaders::{utils::Handle, DEFAULT_CUBE_NAME},
+    model::Model,
+    renderer::MAX_POINT_SHADOWS,
+    scene_file::{DirectionalLightData, ModelRef, PointLightData, SceneAssets, SpotLightData, TransformData},
     transform::Transform,
 };
-use egui::Ui;
 
-macro_rules! type_vec_mut { ($self: expr, $type: ty) => { $self.as_any_mut().downcast_mut::<RefCell<CompVec<$type>>>() } }
-macro_rules! type_vec_ref { ($self: expr, $type: ty) => { $self.as_any().downcast_ref::<RefCell<CompVec<$type>>>() } }
+/// Draws a component of the given entity if it has one.
+pub type ComponentDrawer = fn(&mut World, Entity, &mut Ui);
+
+/// Serializes the entity's component, None if the entity doesn't have one.
+pub type ComponentSaver = fn(&World, Entity, &mut dyn SceneAssets) -> Option<Value>;
+
+/// Inserts a component deserialized from a scene file.
+pub type ComponentLoader = fn(&mut EntityMut, Value) -> serde_json::Result<()>;
 
-/// returns a reference to the component corresponding to the given entity ID.
-macro_rules! entity_comp { ($comp_vec: ident, $entity_id: expr) => { $comp_vec.get_mut()[$entity_id].as_mut() } }
+/// Everything the editor needs to know about one component type without naming it.
+#[derive(Clone, Copy)]
+pub struct ComponentInfo {
+    /// Shown in the inspector's combo boxes.
+    pub name: &'static str,
 
-macro_rules! count {
-    () => (0usize);
-    ( $x:ident $($xs:tt)* ) => (1usize + count!($($xs)*));
+    /// Key of the component in the scene file's entities.
+    pub key: &'static str,
+
+    pub contains: fn(&EntityMut) -> bool,
+    pub add_default: fn(&mut EntityMut),
+    pub remove: fn(&mut EntityMut),
+    pub draw: ComponentDrawer,
+    pub save: ComponentSaver,
+    pub load: ComponentLoader,
+
+    /// Drawn after `draw` by modules the component's own module can't depend on, like the UI's
+    /// editors. See `ComponentRegistry::set_extra_ui`.
+    pub extra_ui: Option<ComponentDrawer>,
 }
 
-macro_rules! addable_component_def {
-    ($($comp: ident),+) => {
-
-       #[derive(Debug, PartialEq, Clone, Copy)]
-       pub enum AddableComponent {
-          $($comp,)+ 
-       }  
-
-       impl fmt::Display for AddableComponent {
-           fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-               fmt::Debug::fmt(self, f)
-           }
-       }
-       pub const ADDABLE_COMPONENTS: [AddableComponent; count!($($comp)+)] = [$(AddableComponent::$comp),+];
-    };
+impl ComponentInfo {
+    /// Info for a component that's stored as is, constructed with `Default` and serialized through `D`.
+    pub fn new<T, D>(name: &'static str, key: &'static str) -> Self
+    where
+        T: Component + EguiDrawable + Default + From<D>,
+        D: Serialize + DeserializeOwned + for<'a> From<&'a T>,
+    {
+        ComponentInfo {
+            name,
+            key,
+            contains: |entity| entity.contains::<T>(),
+            add_default: |entity| { entity.insert(T::default()); },
+            remove: |entity| { entity.remove::<T>(); },
+            draw: draw_component::<T>,
+            save: save_component::<T, D>,
+            load: load_component::<T, D>,
+            extra_ui: None,
+        }
+    }
 }
 
-addable_component_def!(Transform, PointLight, SpotLight, DirectionalLight);
+fn draw_component<T: Component + EguiDrawable>(world: &mut World, entity: Entity, ui: &mut Ui) {
+    let Some(mut comp) = world.get_mut::<T>(entity) else { return };
 
-#[macro_export]
-macro_rules! add_component {
-    ($ecs: expr, $selected_entity: expr, [$($k:ident),*]) => {
-        match $ecs.component_to_add {
-            $(
-                AddableComponent::$k => $ecs.add_comp_to_entity($selected_entity, $k::default()),
-            )*
-        };
-    };
+    comp.on_egui(ui, entity.id() as usize);
 }
 
-type CompVec<T> = Vec<Option<T>>;
+fn save_component<T, D>(world: &World, entity: Entity, _assets: &mut dyn SceneAssets) -> Option<Value>
+where
+    T: Component,
+    D: Serialize + for<'a> From<&'a T>,
+{
+    let comp = world.get::<T>(entity)?;
 
-pub trait ComponentVec {
-    fn as_any(&self) -> &dyn std::any::Any;
-    fn as_any_mut(&mut self) -> &mut dyn std::any::Any;
-    fn push_none(&mut self);
+    serde_json::to_value(D::from(comp))
+        .map_err(|err| eprintln!("Failed to serialize {}: {err}", std::any::type_name::<T>()))
+        .ok()
+}
 
-    fn draw_egui(&mut self, ui: &mut Ui, entity_id: usize);
+fn load_component<T, D>(entity: &mut EntityMut, value: Value) -> serde_json::Result<()>
+where
+    T: Component + From<D>,
+    D: DeserializeOwned,
+{
+    let data: D = serde_json::from_value(value)?;
+    entity.insert(T::from(data));
+    Ok(())
 }
 
-impl<T: 'static + EguiDrawable> ComponentVec for RefCell<CompVec<T>> {
-    fn as_any(&self) -> &dyn std::any::Any {
-        self as &dyn std::any::Any
-    }
+/// Point lights also show the scene-wide `ShadowBudget` while they cast shadows.
+fn point_light_info() -> ComponentInfo {
+    ComponentInfo {
+        draw: |world, entity, ui| {
+            draw_component::<PointLight>(world, entity, ui);
 
-    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
-        self as &mut dyn std::any::Any
-    }
+            let casts_shadows = world.get::<PointLight>(entity).is_some_and(|light| light.enabled && light.shadows.casts_shadows);
+            let Some(mut budget) = world.get_resource_mut::<ShadowBudget>() else { return };
+            if !casts_shadows { return; }
 
-    fn push_none(&mut self) {
-        self.get_mut().push(None);
-    }
+            ui.horizontal(|ui| {
+                ui.label("Shadowed point lights");
+                ui.add(egui::Slider::new(&mut budget.point_lights, 0..=MAX_POINT_SHADOWS));
+            });
 
-    fn draw_egui(&mut self, ui: &mut Ui, entity_id: usize) {
-        let Some(comp_vec) = &mut type_vec_mut!(self, T) else { return; };
-        let Some(comp) = entity_comp!(comp_vec, entity_id) else { return };
+            ui.label(format!("{} of {} in use", budget.shadowed_point_lights.len(), budget.point_lights));
+
+            if !budget.shadowed_point_lights.contains(&entity) {
+                ui.colored_label(egui::Color32::YELLOW, "Over the shadow budget, not casting shadows");
+            }
+        },
+        ..ComponentInfo::new::<PointLight, PointLightData>("Point light", "point_light")
+    }
+}
 
-        comp.on_egui(ui, entity_id);
+/// Models are resolved through the asset loaders, so entities either hold a loaded model
+/// or a `ModelRef` waiting for `Scene::resolve_assets`.
+fn model_info() -> ComponentInfo {
+    ComponentInfo {
+        name: "Model",
+        key: "model",
+        contains: |entity| entity.contains::<Handle<Model>>() || entity.contains::<ModelRef>(),
+        add_default: |entity| {
+            entity.insert(ModelRef {
+                name: DEFAULT_CUBE_NAME.into(),
+                asset: DEFAULT_CUBE_NAME.into(),
+                enabled: true,
+                material_overrides: vec![],
+            });
+        },
+        remove: |entity| {
+            entity.remove::<Handle<Model>>();
+            entity.remove::<ModelRef>();
+        },
+        draw: draw_component::<Handle<Model>>,
+        save: |world, entity, assets| {
+            let model_ref = match world.get::<Handle<Model>>(entity) {
+                Some(model) => assets.model_ref(&model.borrow()),
+                None => world.get::<ModelRef>(entity)?.clone(),
+            };
+
+            serde_json::to_value(model_ref).ok()
+        },
+        load: |entity, value| {
+            entity.insert(serde_json::from_value::<ModelRef>(value)?);
+            Ok(())
+        },
+        extra_ui: None,
     }
 }
 
-pub struct Ecs {
-    entity_count: usize,
-    pub component_vecs: Vec<Box<dyn ComponentVec>>,
+/// bevy_ecs has no way to enumerate an entity's components as trait objects, so every component
+/// type the editor knows about is registered here. Drives the inspector, the add/remove component
+/// UI and scene files.
+/// Stored as a resource in the scene's `World`.
+pub struct ComponentRegistry {
+    components: Vec<ComponentInfo>,
 
-    // For UI 
-    pub component_to_add: AddableComponent
+    // For UI
+    pub component_to_add: usize,
+    pub component_to_remove: usize,
 }
 
-impl Ecs {
+impl ComponentRegistry {
     pub fn new() -> Self {
-        Ecs {
-            entity_count: 0,
-            component_vecs: Vec::new(),
-            component_to_add: AddableComponent::Transform
-        }
-    }
+        let mut registry = ComponentRegistry {
+            components: Vec::new(),
+            component_to_add: 0,
+            component_to_remove: 0,
+        };
 
-    pub fn add_entity(&mut self) -> EntityBuilder {
-        let e_id = self.entity_count;
-        for comp_vec in &mut self.component_vecs {
-            comp_vec.push_none();
-        }
+        registry
+            .register(ComponentInfo::new::<Transform, TransformData>("Transform", "transform"))
+            .register(point_light_info())
+            .register(ComponentInfo::new::<SpotLight, SpotLightData>("Spot light", "spot_light"))
+            .register(ComponentInfo::new::<DirectionalLight, DirectionalLightData>("Directional light", "directional_light"))
+            .register(model_info());
 
-        self.entity_count += 1;
-        EntityBuilder {
-            entity_id: e_id,
-            ecs: self,
-        }
+        registry
     }
 
-    pub fn add_comp_to_entity<T>(
-        &mut self,
-        entity: usize,
-        component: T,
-    ) -> &mut Self 
-        where T: 'static + EguiDrawable + Clone 
-    {
-        for comp_vec in self.component_vecs.iter_mut() {
-            let Some(comp_vec) = type_vec_mut!(comp_vec, T) else { continue; };
+    pub fn register(&mut self, info: ComponentInfo) -> &mut Self {
+        debug_assert!(
+            self.components.iter().all(|c| c.name != info.name && c.key != info.key),
+            "Component \"{}\" registered twice", info.name
+        );
 
-            if entity_comp!(comp_vec, entity).is_some() {
-                let type_name = std::any::type_name::<T>();
-                eprintln!("Attempted to add an duplicate component ({type_name}) onto entity ({entity})");
-                return self;
-            }
+        self.components.push(info);
+        self
+    }
 
-            comp_vec.get_mut()[entity] = Some(component);
-            return self;
+    /// Draws `draw` under the component with the scene file key `key`, replacing what was there.
+    pub fn set_extra_ui(&mut self, key: &str, draw: ComponentDrawer) -> &mut Self {
+        match self.components.iter_mut().find(|c| c.key == key) {
+            Some(info) => info.extra_ui = Some(draw),
+            None => debug_assert!(false, "No component with the key \"{key}\""),
         }
 
-        let mut new_comp_vec: Vec<Option<T>> = vec![None; self.entity_count];
-        new_comp_vec.fill(None);
-
-        new_comp_vec[entity] = Some(component);
-
-        self.component_vecs.push(Box::new(RefCell::new(new_comp_vec)));
         self
     }
 
-    pub fn borrow_comp_vec<T: 'static>( &self,) -> Option<RefMut<CompVec<T>>> {
-        for comp_vec in self.component_vecs.iter() {
-            if let Some(comp_vec) =  type_vec_ref!(comp_vec, T) {
-                return Some(comp_vec.borrow_mut());
-            }
-        }
-
-        None
+    pub fn components(&self) -> &[ComponentInfo] {
+        &self.components
     }
 
-    pub fn do_n_mut<T , U, V>(&self, mut f: impl (FnMut(&mut T, &mut U) -> Option<V>), n: usize)
-        -> Vec<Option<V>>
-        where T: 'static, U: 'static
-    {
-        let (Some(mut t), Some(mut u)) = (self.borrow_comp_vec::<T>(), self.borrow_comp_vec::<U>()) else {
-            // use std::any::type_name;
-            // println!("do_all: Component type {:?} or {:?} not found", type_name::<T>(), type_name::<U>());
-            return vec![];
-        };
+    pub fn get(&self, index: usize) -> Option<&ComponentInfo> {
+        self.components.get(index)
+    }
 
-        t 
-            .iter_mut()
-            .zip(u.iter_mut())
-            .filter(|(x,y)| x.is_some() && y.is_some())
-            .map(|(x,y)| (x.as_mut().unwrap(), y.as_mut().unwrap()))
-            .take(n)
-            .map(|(x,y)| f(x, y))
-            .collect()
+    pub fn by_key(&self, key: &str) -> Option<&ComponentInfo> {
+        self.components.iter().find(|c| c.key == key)
     }
 
-    pub fn do_n<T , U, V>(&self, f: impl (Fn(&T, &U) -> Option<V>), n: usize)
-        -> Vec<Option<V>>
-        where T: 'static, U: 'static
-    {
-        let (Some(t), Some(u)) = (self.borrow_comp_vec::<T>(), self.borrow_comp_vec::<U>()) else {
-            // use std::any::type_name;
-            // println!("do_all: Component type {:?} or {:?} not found", type_name::<T>(), type_name::<U>());
-            return vec![];
-        };
+    /// Indices of the registered components the entity has.
+    pub fn components_of(&self, world: &mut World, entity: Entity) -> Vec<usize> {
+        let entity = world.entity_mut(entity);
 
-        t 
-            .iter()
-            .zip(u.iter())
-            .filter(|(x,y)| x.is_some() && y.is_some())
-            .map(|(x,y)| (x.as_ref().unwrap(), y.as_ref().unwrap()))
-            .take(n)
-            .map(|(x,y)| f(x, y))
+        (0..self.components.len())
+            .filter(|i| (self.components[*i].contains)(&entity))
             .collect()
     }
 
-    pub fn do_all_mut<T , U, V>(&self, f: impl (FnMut(&mut T, &mut U) -> Option<V>)) 
+    /// Draws every registered component the entity has, in registration order.
+    pub fn draw_entity(&self, world: &mut World, entity: Entity, ui: &mut Ui) {
+        for info in &self.components {
+            (info.draw)(world, entity, ui);
 
-        -> Vec<Option<V>>
-        where T: 'static, U: 'static 
-    {
-        self.do_n_mut(f, self.entity_count)
+            if let Some(extra_ui) = info.extra_ui {
+                extra_ui(world, entity, ui);
+            }
+        }
     }
+}
 
-    pub fn do_all<T , U, V>(&self, f: impl (Fn(&T, &U) -> Option<V>)) 
-        -> Vec<Option<V>>
-        where T: 'static, U: 'static 
-    {
-        self.do_n(f, self.entity_count)
+impl Default for ComponentRegistry {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn do_one<T, U, V>(&self, f: impl (FnMut(&mut T, &mut U) -> Option<V>)) 
-        -> Option<V>
-        where T: 'static, U: 'static 
-    {
-        self.do_n_mut(f, 1).pop().unwrap_or(None)
+/// Adds the default value of the `index`th registered component unless the entity already has one.
+pub fn add_component(world: &mut World, entity: Entity, index: usize) {
+    let Some(info) = world.resource::<ComponentRegistry>().get(index).copied() else { return };
+    let mut entity_mut = world.entity_mut(entity);
+
+    if (info.contains)(&entity_mut) {
+        eprintln!("Attempted to add an duplicate component ({}) onto entity ({:?})", info.name, entity);
+    } else {
+        (info.add_default)(&mut entity_mut);
     }
+}
 
-    pub fn do_entity<T, U>(&self, entity_id: usize, mut f: impl (FnMut(&mut T) -> U)) -> U
-        where T: 'static 
-    {
-        assert!( entity_id < self.entity_count );
+pub fn remove_component(world: &mut World, entity: Entity, index: usize) {
+    let Some(info) = world.resource::<ComponentRegistry>().get(index).copied() else { return };
 
-        let mut comp_vec = self.borrow_comp_vec::<T>()
-            .unwrap();
+    (info.remove)(&mut world.entity_mut(entity));
+}
 
-        let comp = comp_vec
-            .borrow_mut()[entity_id]
-            .as_mut().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        f(comp)
-    }
+    struct NoAssets;
 
-    pub fn num_entities(&self) -> usize {
-        self.entity_count 
+    impl SceneAssets for NoAssets {
+        fn load_model(&mut self, _: &ModelRef) -> Option<Handle<Model>> { None }
+        fn model_ref(&mut self, _: &Model) -> ModelRef { unreachable!("No models are loaded") }
     }
 
+    #[test]
+    fn extra_ui_is_found_by_key() {
+        let mut registry = ComponentRegistry::new();
+        assert!(registry.components().iter().all(|c| c.extra_ui.is_none()));
 
-    pub fn do_all_some<T, U>(&self, f: impl (FnMut((usize, &mut T))-> Option<U>)) -> Vec<U>
-        where T: 'static {
-        self.borrow_comp_vec::<T>()
-            .unwrap()
-            .iter_mut()
-            .enumerate()
-            .filter_map(|(id, it)| {
-                it.as_mut().map(|it| (id, it))
-            })
-            .flat_map(f)
-            .collect()
+        registry.set_extra_ui("model", |_, _, _| {});
+        let with_extra_ui: Vec<&str> = registry.components().iter().filter(|c| c.extra_ui.is_some()).map(|c| c.key).collect();
+        assert_eq!(with_extra_ui, ["model"]);
     }
 
-    pub fn add_empty_entity(&mut self) {
-        let num_entities = self.num_entities();
-        self
-            .add_entity()
-            .with(Transform::with_name(format!("Entity {num_entities}")));
-    }
-}
+    #[test]
+    fn registered_components_round_trip() {
+        let mut world = World::new();
+        world.insert_resource(ComponentRegistry::new());
 
-pub struct EntityBuilder<'a> {
-    entity_id: usize,
-    ecs: &'a mut Ecs,
-}
+        let count = world.resource::<ComponentRegistry>().components().len();
 
-impl<'a> EntityBuilder<'a> {
-    pub fn with_default<ComponentType>(&mut self) -> &mut Self 
-        where ComponentType: 'static + Default + Clone + EguiDrawable
-    {
-        self.ecs.add_comp_to_entity(self.entity_id, ComponentType::default());
-        self
-    }
+        for index in 0..count {
+            let info = world.resource::<ComponentRegistry>().components()[index];
+            let entity = world.spawn().id();
 
-    pub fn with<ComponentType>( &mut self, comp: ComponentType) -> &mut Self 
-        where ComponentType: 'static + Clone + EguiDrawable
-    {
-        self.ecs.add_comp_to_entity(self.entity_id, comp);
-        self
-    }
+            add_component(&mut world, entity, index);
+            assert!((info.contains)(&world.entity_mut(entity)), "{} wasn't added", info.name);
 
-    pub fn with_clone<ComponentType>( &mut self, comp: &ComponentType) -> &mut Self 
-        where ComponentType: 'static + Default + Clone + EguiDrawable
-    {
-        self.ecs.add_comp_to_entity(self.entity_id, comp.clone());
-        self
+            let saved = (info.save)(&world, entity, &mut NoAssets).unwrap();
+
+            remove_component(&mut world, entity, index);
+            assert!(!(info.contains)(&world.entity_mut(entity)), "{} wasn't removed", info.name);
+
+            (info.load)(&mut world.entity_mut(entity), saved.clone()).unwrap();
+            assert_eq!((info.save)(&world, entity, &mut NoAssets), Some(saved), "{} didn't round trip", info.name);
+        }
     }
 }
-
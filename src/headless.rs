@@ -0,0 +1,295 @@
+use std::{fmt, path::{Path, PathBuf}};
+
+use image::RgbaImage;
+
+use crate::{
+    loaders::{AssetServer, ObjLoader, ShaderLoader, TextureLoader},
+    notifications::{self, Severity},
+    renderer::Renderer,
+    scene::Scene,
+    scene_file::{LoaderAssets, SceneFileError},
+};
+
+/// Name accepted in place of a scene file to render `Scene::light_test`.
+pub const LIGHT_TEST_SCENE: &str = "light_test";
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    Context(String),
+    Scene(SceneFileError),
+    Image(image::ImageError),
+    Usage(String),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::Context(err) => write!(f, "Failed to create a headless OpenGL context: {err}"),
+            HeadlessError::Scene(err) => write!(f, "Failed to load scene: {err}"),
+            HeadlessError::Image(err) => write!(f, "Failed to write image: {err}"),
+            HeadlessError::Usage(err) => write!(f, "{err}\n\n{}", HeadlessArgs::USAGE),
+        }
+    }
+}
+
+impl From<SceneFileError> for HeadlessError {
+    fn from(value: SceneFileError) -> Self {
+        HeadlessError::Scene(value)
+    }
+}
+
+impl From<image::ImageError> for HeadlessError {
+    fn from(value: image::ImageError) -> Self {
+        HeadlessError::Image(value)
+    }
+}
+
+/// Renders scenes into images without creating a window.
+/// Goes through the same `Renderer::render` path as the editor, into an offscreen framebuffer.
+///
+/// The GL context is made current on the creating thread and the global GL handle is replaced,
+/// so only use one at a time, from one thread.
+pub struct HeadlessRenderer {
+    renderer: Renderer,
+    assets: AssetServer,
+
+    width: u32,
+    height: u32,
+
+    // Dropped last, everything above owns GL objects
+    _context: context::HeadlessContext,
+}
+
+impl HeadlessRenderer {
+    pub fn new(width: u32, height: u32) -> Result<Self, HeadlessError> {
+        let context = context::HeadlessContext::new().map_err(HeadlessError::Context)?;
+
+        let assets = AssetServer::new(&[]);
+        let renderer = Renderer::new(width as usize, height as usize);
+
+        Ok(HeadlessRenderer {
+            renderer,
+            assets,
+            width,
+            height,
+            _context: context,
+        })
+    }
+
+    pub fn get_resource_managers(&mut self) -> (&mut TextureLoader, &mut ObjLoader, &mut ShaderLoader) {
+        self.assets.loaders()
+    }
+
+    pub fn assets(&mut self) -> &mut AssetServer {
+        &mut self.assets
+    }
+
+    /// Loads a scene file with its models, or the built in light test scene for `LIGHT_TEST_SCENE`.
+    pub fn load_scene(&mut self, scene: &str) -> Result<Scene, HeadlessError> {
+        let (width, height) = (self.width as i32, self.height as i32);
+
+        if scene == LIGHT_TEST_SCENE {
+            let (texture_loader, object_loader, shader_loader) = self.get_resource_managers();
+            return Ok(Scene::light_test(width, height, texture_loader, shader_loader, object_loader));
+        }
+
+        let mut scene = Scene::load(scene)?;
+        scene.resolve_assets(&mut LoaderAssets::new(&mut self.assets));
+        scene.camera.update_aspect_ratio(width as f32, height as f32);
+
+        Ok(scene)
+    }
+
+    pub fn render(&mut self, scene: &mut Scene) -> RgbaImage {
+        self.renderer.render_to_image(
+            &scene.camera,
+            &mut scene.world,
+            &mut self.assets.shader_loader,
+            self.width,
+            self.height
+        )
+    }
+}
+
+/// Renders a scene file (or `LIGHT_TEST_SCENE`) into a PNG.
+pub fn render_to_png(scene: &str, output: &Path, width: u32, height: u32) -> Result<(), HeadlessError> {
+    let mut renderer = HeadlessRenderer::new(width, height)?;
+    let mut scene = renderer.load_scene(scene)?;
+
+    renderer.render(&mut scene).save(output)?;
+    Ok(())
+}
+
+/// Command line arguments of the headless mode.
+pub struct HeadlessArgs {
+    pub scene: String,
+    pub output: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl HeadlessArgs {
+    pub const USAGE: &'static str = "Usage: ggl --headless <scene.json | light_test> <output.png> [--size <width>x<height>]";
+
+    /// None if `--headless` wasn't passed and the editor should start as usual.
+    pub fn from_env() -> Option<Result<Self, HeadlessError>> {
+        let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+        if !args.iter().any(|arg| arg == "--headless") {
+            return None;
+        }
+
+        Some(Self::parse(&args))
+    }
+
+    pub fn parse(args: &[String]) -> Result<Self, HeadlessError> {
+        let usage = |msg: &str| HeadlessError::Usage(msg.to_string());
+
+        let mut positional = Vec::new();
+        let (mut width, mut height) = (1280, 720);
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => {}
+                "--size" => {
+                    let size = args.next().ok_or_else(|| usage("Missing value for --size"))?;
+                    let (w, h) = size
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .filter(|(w, h)| *w > 0 && *h > 0)
+                        .ok_or_else(|| usage(&format!("Invalid size \"{size}\"")))?;
+
+                    (width, height) = (w, h);
+                }
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        match positional.as_slice() {
+            [scene, output] => Ok(HeadlessArgs { scene: scene.clone(), output: output.into(), width, height }),
+            _ => Err(usage("Expected a scene and an output path")),
+        }
+    }
+
+    pub fn run(self) -> Result<(), HeadlessError> {
+        render_to_png(&self.scene, &self.output, self.width, self.height)?;
+        notifications::notify(Severity::Info, format!("Rendered \"{}\" to \"{}\"", self.scene, self.output.display()));
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod context {
+    use std::{ffi::{c_void, CStr, CString}, ptr, sync::Arc};
+
+    use glutin_egl_sys::egl::{self, Egl};
+
+    use crate::{gl::set_gl, notifications::{self, Severity}};
+
+    /// From EGL_MESA_platform_surfaceless, which the bindings don't include.
+    const PLATFORM_SURFACELESS_MESA: egl::types::EGLenum = 0x31DD;
+
+    /// OpenGL 3.3 core context on EGL's surfaceless platform.
+    /// Needs neither a display server nor a GPU, Mesa falls back to software rendering (llvmpipe).
+    pub struct HeadlessContext {
+        egl: Egl,
+        display: egl::types::EGLDisplay,
+        context: egl::types::EGLContext,
+        _lib: libloading::Library,
+    }
+
+    impl HeadlessContext {
+        pub fn new() -> Result<Self, String> {
+            unsafe {
+                let lib = libloading::Library::new("libEGL.so.1").map_err(|err| err.to_string())?;
+                let egl = Egl::load_with(|name| {
+                    let name = CString::new(name).unwrap();
+                    lib.get::<*const c_void>(name.as_bytes_with_nul())
+                        .map(|symbol| *symbol)
+                        .unwrap_or(ptr::null())
+                });
+
+                let error = |call: &str| format!("{call} failed (EGL error 0x{:x})", egl.GetError());
+
+                let display = egl.GetPlatformDisplay(PLATFORM_SURFACELESS_MESA, ptr::null_mut(), ptr::null());
+                if display == egl::NO_DISPLAY {
+                    return Err(error("eglGetPlatformDisplay"));
+                }
+
+                let (mut major, mut minor) = (0, 0);
+                if egl.Initialize(display, &mut major, &mut minor) == egl::FALSE {
+                    return Err(error("eglInitialize"));
+                }
+
+                if egl.BindAPI(egl::OPENGL_API) == egl::FALSE {
+                    return Err(error("eglBindAPI"));
+                }
+
+                let config_attribs = [
+                    egl::SURFACE_TYPE as i32, egl::PBUFFER_BIT as i32,
+                    egl::RENDERABLE_TYPE as i32, egl::OPENGL_BIT as i32,
+                    egl::NONE as i32,
+                ];
+
+                let mut config = ptr::null();
+                let mut num_configs = 0;
+                if egl.ChooseConfig(display, config_attribs.as_ptr(), &mut config, 1, &mut num_configs) == egl::FALSE
+                    || num_configs == 0 {
+                    return Err(error("eglChooseConfig"));
+                }
+
+                let context_attribs = [
+                    egl::CONTEXT_MAJOR_VERSION as i32, 3,
+                    egl::CONTEXT_MINOR_VERSION as i32, 3,
+                    egl::CONTEXT_OPENGL_PROFILE_MASK as i32, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT as i32,
+                    egl::NONE as i32,
+                ];
+
+                let context = egl.CreateContext(display, config, egl::NO_CONTEXT, context_attribs.as_ptr());
+                if context == egl::NO_CONTEXT {
+                    return Err(error("eglCreateContext"));
+                }
+
+                if egl.MakeCurrent(display, egl::NO_SURFACE, egl::NO_SURFACE, context) == egl::FALSE {
+                    return Err(error("eglMakeCurrent"));
+                }
+
+                let gl = glow::Context::from_loader_function(|name| {
+                    let name = CString::new(name).unwrap();
+                    egl.GetProcAddress(name.as_ptr()) as *const _
+                });
+                set_gl(Arc::new(gl));
+
+                let vendor = egl.QueryString(display, egl::VENDOR as i32);
+                if !vendor.is_null() {
+                    notifications::notify(Severity::Info, format!("Headless EGL {major}.{minor} ({})", CStr::from_ptr(vendor).to_string_lossy()));
+                }
+
+                Ok(HeadlessContext { egl, display, context, _lib: lib })
+            }
+        }
+    }
+
+    impl Drop for HeadlessContext {
+        fn drop(&mut self) {
+            unsafe {
+                self.egl.MakeCurrent(self.display, egl::NO_SURFACE, egl::NO_SURFACE, egl::NO_CONTEXT);
+                self.egl.DestroyContext(self.display, self.context);
+                self.egl.Terminate(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod context {
+    pub struct HeadlessContext;
+
+    impl HeadlessContext {
+        pub fn new() -> Result<Self, String> {
+            Err("Headless rendering is only supported on Linux (EGL)".into())
+        }
+    }
+}
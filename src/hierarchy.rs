@@ -0,0 +1,267 @@
+extern crate nalgebra_glm as glm;
+
+use bevy_ecs::prelude::*;
+use glm::Mat4;
+
+use crate::transform::Transform;
+
+/// The entity this entity's transform is relative to.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+/// Direct children of an entity, kept in sync with their `Parent` components by `set_parent`.
+#[derive(Component, Clone, Debug, Default)]
+pub struct Children(pub Vec<Entity>);
+
+impl Children {
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.0.iter()
+    }
+}
+
+/// Computes the world matrix of every `Transform` from the root entities down.
+/// Runs before anything that reads world matrices (lights, render commands).
+/// Entities without a `Transform` pass their parent's matrix on to their children, like `world_matrix`.
+pub fn propagate_transforms(
+    roots: Query<Entity, Without<Parent>>,
+    children: Query<&Children>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for root in &roots {
+        propagate_recursive(root, &Mat4::identity(), &children, &mut transforms);
+    }
+}
+
+fn propagate_recursive(
+    entity: Entity,
+    parent_world: &Mat4,
+    children: &Query<&Children>,
+    transforms: &mut Query<&mut Transform>,
+) {
+    let world = match transforms.get_mut(entity) {
+        Ok(mut transform) => {
+            let world = parent_world * transform.get_model_matrix();
+            transform.set_world_matrix(world);
+            world
+        }
+        Err(_) => *parent_world,
+    };
+
+    let Ok(entity_children) = children.get(entity) else { return };
+    for child in entity_children.iter() {
+        propagate_recursive(*child, &world, children, transforms);
+    }
+}
+
+/// Walks up the `Parent` chain and multiplies the local matrices.
+/// Unlike `Transform::get_world_matrix`, doesn't depend on `propagate_transforms` having run.
+pub fn world_matrix(world: &World, entity: Entity) -> Mat4 {
+    let local = world
+        .get::<Transform>(entity)
+        .map(|t| t.get_model_matrix())
+        .unwrap_or_else(Mat4::identity);
+
+    match world.get::<Parent>(entity) {
+        Some(Parent(parent)) => world_matrix(world, *parent) * local,
+        None => local,
+    }
+}
+
+/// Returns true if `ancestor` is `entity` or one of its ancestors.
+pub fn is_ancestor(world: &World, ancestor: Entity, entity: Entity) -> bool {
+    let mut current = Some(entity);
+
+    while let Some(e) = current {
+        if e == ancestor { return true; }
+        current = world.get::<Parent>(e).map(|p| p.0);
+    }
+
+    false
+}
+
+/// Moves `child` under `new_parent` (or makes it a root if None) while keeping its world pose.
+/// Returns false if the move would create a cycle.
+pub fn set_parent(world: &mut World, child: Entity, new_parent: Option<Entity>) -> bool {
+    if let Some(new_parent) = new_parent {
+        if is_ancestor(world, child, new_parent) {
+            eprintln!("Cannot parent entity ({child:?}) to itself or one of its descendants ({new_parent:?})");
+            return false;
+        }
+    }
+
+    let child_world = world_matrix(world, child);
+    let parent_world = new_parent
+        .map(|p| world_matrix(world, p))
+        .unwrap_or_else(Mat4::identity);
+
+    detach(world, child);
+
+    if let Some(new_parent) = new_parent {
+        add_child(world, new_parent, child);
+    }
+
+    if let Some(mut transform) = world.get_mut::<Transform>(child) {
+        let local = glm::inverse(&parent_world) * child_world;
+        transform.set_model(local);
+        transform.set_world_matrix(child_world);
+    }
+
+    true
+}
+
+/// Links `child` under `parent` without touching its transform.
+/// The child's local transform is interpreted relative to the new parent from now on.
+pub fn add_child(world: &mut World, parent: Entity, child: Entity) {
+    world.entity_mut(child).insert(Parent(parent));
+
+    let mut parent = world.entity_mut(parent);
+    match parent.get_mut::<Children>() {
+        Some(mut children) => children.0.push(child),
+        None => { parent.insert(Children(vec![child])); }
+    }
+}
+
+/// Removes the entity from its parent's `Children` and drops its `Parent`.
+fn detach(world: &mut World, child: Entity) {
+    let Some(Parent(old_parent)) = world.entity_mut(child).remove::<Parent>() else { return };
+
+    if let Some(mut children) = world.get_mut::<Children>(old_parent) {
+        children.0.retain(|c| *c != child);
+    }
+}
+
+/// Despawns the entity and all of its descendants.
+pub fn despawn_recursive(world: &mut World, entity: Entity) {
+    if world.get_entity(entity).is_none() { return; }
+
+    detach(world, entity);
+    despawn_descendants(world, entity);
+}
+
+fn despawn_descendants(world: &mut World, entity: Entity) {
+    if let Some(children) = world.get::<Children>(entity).cloned() {
+        for child in children.0 {
+            despawn_descendants(world, child);
+        }
+    }
+
+    world.despawn(entity);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glm::{vec3, Vec3};
+
+    use crate::transform::Degree3;
+
+    fn assert_mat_eq(a: &Mat4, b: &Mat4) {
+        assert!((a - b).abs().max() < 1e-4, "{a} != {b}");
+    }
+
+    fn spawn(world: &mut World, pos: Vec3, euler: Degree3, scale: Vec3) -> Entity {
+        world.spawn().insert(Transform::with_scale(pos, euler, scale, "")).id()
+    }
+
+    fn propagate(world: &mut World) {
+        SystemStage::single_threaded().with_system(propagate_transforms).run(world);
+    }
+
+    fn world_of(world: &World, entity: Entity) -> Mat4 {
+        world.get::<Transform>(entity).unwrap().get_world_matrix()
+    }
+
+    #[test]
+    fn set_parent_keeps_the_world_pose() {
+        let mut world = World::new();
+        let parent = spawn(&mut world, vec3(1., 2., 3.), Degree3::xyz(0., 90., 0.), vec3(2., 2., 2.));
+        let child = spawn(&mut world, vec3(-4., 0., 1.), Degree3::xyz(30., 0., 10.), vec3(1., 1., 1.));
+        let before = world_matrix(&world, child);
+
+        assert!(set_parent(&mut world, child, Some(parent)));
+        assert_eq!(world.get::<Parent>(child), Some(&Parent(parent)));
+        assert_eq!(world.get::<Children>(parent).unwrap().0, [child]);
+        assert_mat_eq(&world_matrix(&world, child), &before);
+
+        assert!(set_parent(&mut world, child, None));
+        assert!(world.get::<Parent>(child).is_none());
+        assert!(world.get::<Children>(parent).unwrap().0.is_empty());
+        assert_mat_eq(&world.get::<Transform>(child).unwrap().get_model_matrix(), &before);
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut world = World::new();
+        let root = spawn(&mut world, vec3(0., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let child = spawn(&mut world, vec3(1., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let grandchild = spawn(&mut world, vec3(0., 1., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        add_child(&mut world, root, child);
+        add_child(&mut world, child, grandchild);
+
+        assert!(!set_parent(&mut world, root, Some(grandchild)));
+        assert!(!set_parent(&mut world, child, Some(child)));
+        assert!(world.get::<Parent>(root).is_none());
+        assert_eq!(world.get::<Parent>(child), Some(&Parent(root)));
+    }
+
+    #[test]
+    fn transforms_propagate_from_the_roots() {
+        let mut world = World::new();
+        let root = spawn(&mut world, vec3(1., 0., 0.), Degree3::xyz(0., 0., 90.), vec3(2., 2., 2.));
+        let child = spawn(&mut world, vec3(1., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let grandchild = spawn(&mut world, vec3(0., 0., 3.), Degree3::xyz(45., 0., 0.), vec3(1., 1., 1.));
+        add_child(&mut world, root, child);
+        add_child(&mut world, child, grandchild);
+
+        propagate(&mut world);
+
+        for entity in [root, child, grandchild] {
+            assert_mat_eq(&world_of(&world, entity), &world_matrix(&world, entity));
+        }
+        assert!((world.get::<Transform>(child).unwrap().get_world_pos() - vec3(1., 2., 0.)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn entities_without_a_transform_pass_on_their_parents() {
+        let mut world = World::new();
+        let root = spawn(&mut world, vec3(0., 5., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let group = world.spawn().id();
+        let child = spawn(&mut world, vec3(1., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        add_child(&mut world, root, group);
+        add_child(&mut world, group, child);
+
+        propagate(&mut world);
+        assert!((world.get::<Transform>(child).unwrap().get_world_pos() - vec3(1., 5., 0.)).norm() < 1e-5);
+
+        // A root without a transform too
+        let orphan = spawn(&mut world, vec3(0., 0., 2.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let empty_root = world.spawn().id();
+        add_child(&mut world, empty_root, orphan);
+        world.get_mut::<Transform>(orphan).unwrap().set_world_matrix(Mat4::zeros());
+
+        propagate(&mut world);
+        assert_mat_eq(&world_of(&world, orphan), &world_matrix(&world, orphan));
+    }
+
+    #[test]
+    fn despawn_recursive_removes_descendants_only() {
+        let mut world = World::new();
+        let root = spawn(&mut world, vec3(0., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let child = spawn(&mut world, vec3(0., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let sibling = spawn(&mut world, vec3(0., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        let grandchild = spawn(&mut world, vec3(0., 0., 0.), Degree3::xyz(0., 0., 0.), vec3(1., 1., 1.));
+        add_child(&mut world, root, child);
+        add_child(&mut world, root, sibling);
+        add_child(&mut world, child, grandchild);
+
+        despawn_recursive(&mut world, child);
+
+        assert!(world.get_entity(child).is_none());
+        assert!(world.get_entity(grandchild).is_none());
+        assert!(world.get_entity(sibling).is_some());
+        assert_eq!(world.get::<Children>(root).unwrap().0, [sibling]);
+
+        // Already despawned
+        despawn_recursive(&mut world, child);
+    }
+}
@@ -0,0 +1,23 @@
+pub mod loaders;
+pub mod ui;
+pub mod renderer;
+
+pub mod camera;
+pub mod ecs;
+pub mod egui_drawable;
+pub mod headless;
+pub mod hierarchy;
+pub mod input;
+pub mod light;
+pub mod light_system;
+pub mod notifications;
+pub mod scene;
+pub mod scene_file;
+pub mod shader;
+pub mod texture;
+pub mod transform;
+pub mod app;
+pub mod gl;
+
+pub mod model;
+pub mod mesh;
@@ -4,9 +4,10 @@ extern crate nalgebra_glm as glm;
 use std::format;
 
 use crate::egui_drawable::EguiDrawable;
-use crate::shader::ShaderProgram;
-use crate::Transform;
+use crate::renderer::{GpuDirectionalLight, GpuPointLight, GpuSpotLight};
+use crate::transform::Transform;
 
+use bevy_ecs::prelude::Component;
 use egui::Ui;
 use nalgebra_glm::*;
 
@@ -47,17 +48,6 @@ macro_rules! enabled_header {
 
 
 pub trait Light {
-    fn upload_data(
-        &self,
-        transform: &Transform,
-
-        //* String containing uniform name into light array
-        //* Example: u_point_lights[0]
-        uniform_name: &str,
-        shader: &ShaderProgram,
-        global_enable: &bool
-    );
-
     fn is_enabled(&self) -> bool;
     fn set_enabled(&mut self, enabled: &bool);
 }
@@ -109,131 +99,204 @@ impl LightColors {
     }
 }
 
-#[derive(Default, Clone)]
+/// Shadow map resolutions selectable in the inspector.
+pub const SHADOW_RESOLUTIONS: [u32; 5] = [256, 512, 1024, 2048, 4096];
+
+/// Portion of the camera frustum covered by the directional light's shadow map.
+pub const DIRECTIONAL_SHADOW_DISTANCE: f32 = 20.0;
+
+/// How far behind the camera frustum (towards the light) casters are still rendered into the directional shadow map.
+const DIRECTIONAL_SHADOW_CASTER_MARGIN: f32 = 20.0;
+
+/// Perspective depth loses precision quickly past the near plane, keep it away from the light.
+const SPOT_SHADOW_NEAR: f32 = 0.5;
+const SPOT_SHADOW_FAR: f32 = 100.0;
+
+/// Distance past which nothing is shadowed by a point light.
+pub const POINT_SHADOW_FAR: f32 = 25.0;
+
+/// Lights are left out where they'd add less than this to a color channel, see `light_range`.
+pub const LIGHT_CUTOFF: f32 = 1.0 / 256.0;
+
+/// Distance at which the brightest of `colors`, attenuated by `attenuation_constants`
+/// (quadratic, linear, constant), drops below `LIGHT_CUTOFF`.
+/// Infinite for lights that don't fade with distance.
+pub fn light_range(attenuation_constants: Vec3, colors: &LightColors) -> f32 {
+    let brightness = colors.ambient.max().max(colors.diffuse.max()).max(colors.specular.max());
+
+    // quadratic * d^2 + linear * d + constant = brightness / LIGHT_CUTOFF
+    let (quadratic, linear) = (attenuation_constants.x, attenuation_constants.y);
+    let constant = attenuation_constants.z - brightness / LIGHT_CUTOFF;
+
+    if constant >= 0.0 {
+        // Never bright enough
+        0.0
+    } else if quadratic > 0.0 {
+        (-linear + (linear * linear - 4.0 * quadratic * constant).sqrt()) / (2.0 * quadratic)
+    } else if linear > 0.0 {
+        -constant / linear
+    } else {
+        f32::INFINITY
+    }
+}
+
+#[derive(Clone)]
+pub struct ShadowSettings {
+    pub casts_shadows: bool,
+
+    /// Depth offset against shadow acne, scaled up for surfaces at grazing angles to the light.
+    pub bias: f32,
+
+    /// Width and height of the shadow map in texels.
+    pub resolution: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            casts_shadows: false,
+            bias: 0.005,
+            resolution: 1024
+        }
+    }
+}
+
+#[derive(Default, Clone, Component)]
 pub struct DirectionalLight {
     pub enabled: bool,
     pub colors: LightColors,
+    pub shadows: ShadowSettings,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Component)]
 pub struct PointLight {
     pub enabled: bool,
     pub colors: LightColors,
     pub attenuation_constants: Vec3,
+    pub shadows: ShadowSettings,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Component)]
 pub struct SpotLight {
     pub enabled: bool,
     pub cutoff_angles: Vec2, // Angles in degress, converted to cos(rad(angle)) on upload
 
     pub colors: LightColors,
     pub attenuation_constants: Vec3,
+    pub shadows: ShadowSettings,
 }
 
-impl Light for DirectionalLight {
-    fn upload_data(
-        &self,
-        transform: &Transform,
-
-        uniform_name: &str,
-        shader: &ShaderProgram,
-        global_enable: &bool
-    ) {
-        let direction = (transform.get_model_matrix() * glm::vec4(0.0, -1.0, 0.0, 0.0f32)).xyz();
-
-        shader
-            .set_vec3(&format!("{}.direction", uniform_name), direction)
-            .set_vec3(
-                &format!("{}.ambient", uniform_name),
-                self.colors.ambient,
-            )
-            .set_vec3(
-                &format!("{}.diffuse", uniform_name),
-                self.colors.diffuse,
-            )
-            .set_vec3(
-                &format!("{}.specular", uniform_name),
-                self.colors.specular,
-            )
-            .set_bool(&format!("{uniform_name}.is_enabled"), *global_enable && self.is_enabled());
+/// Lights point down their local -y axis.
+fn light_direction(transform: &Transform) -> Vec3 {
+    (transform.get_world_matrix() * glm::vec4(0.0, -1.0, 0.0, 0.0f32)).xyz()
+}
+
+/// Any up vector that isn't parallel to the light direction, for building a view matrix.
+fn light_up(direction: &Vec3) -> Vec3 {
+    if direction.normalize().y.abs() > 0.99 { vec3(0., 0., 1.) } else { vec3(0., 1., 0.) }
+}
+
+impl DirectionalLight {
+    /// Orthographic projection and view of the light, fit around the given camera frustum corners.
+    pub fn light_space_matrix(&self, transform: &Transform, frustum_corners: &[Vec3; 8]) -> Mat4 {
+        let direction = light_direction(transform).normalize();
+        let center = frustum_corners.iter().sum::<Vec3>() / frustum_corners.len() as f32;
+        let view = look_at(&(center - direction), &center, &light_up(&direction));
+
+        let (mut min, mut max) = (Vec3::repeat(f32::MAX), Vec3::repeat(f32::MIN));
+        for corner in frustum_corners {
+            let corner = (view * corner.push(1.0)).xyz();
+            min = min.inf(&corner);
+            max = max.sup(&corner);
+        }
+
+        // The light looks down -z, so the nearest corner has the largest z
+        let near = -max.z - DIRECTIONAL_SHADOW_CASTER_MARGIN;
+        let far = -min.z;
+
+        ortho(min.x, max.x, min.y, max.y, near, far) * view
     }
+}
 
-    shared_light_fn!{}
+impl SpotLight {
+    /// Perspective projection and view of the light, covering its outer cone.
+    pub fn light_space_matrix(&self, transform: &Transform) -> Mat4 {
+        let direction = light_direction(transform).normalize();
+        let position = transform.get_world_pos();
+        let view = look_at(&position, &(position + direction), &light_up(&direction));
+
+        let fov = (2.0 * self.cutoff_angles.y).clamp(1.0, 170.0);
+        perspective(1.0, fov.to_radians(), SPOT_SHADOW_NEAR, SPOT_SHADOW_FAR) * view
+    }
 }
 
-impl Light for PointLight {
-    fn upload_data(
-        &self,
-        transform: &Transform,
-        uniform_name: &str,
-        shader: &ShaderProgram,
-        global_enable: &bool
-    ) {
-        let position = (transform.get_model_matrix() * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
-
-        shader
-            .set_vec3(&format!("{}.position", uniform_name), position)
-            .set_vec3(
-                &format!("{}.ambient", uniform_name),
-                self.colors.ambient,
-            )
-            .set_vec3(
-                &format!("{}.diffuse", uniform_name),
-                self.colors.diffuse,
-            )
-            .set_vec3(
-                &format!("{}.specular", uniform_name),
-                self.colors.specular,
-            )
-            .set_vec3(
-                &format!("{}.attenuation_constants", uniform_name),
-                self.attenuation_constants,
-            )
-            .set_bool(&format!("{uniform_name}.is_enabled"), *global_enable && self.is_enabled());
+impl DirectionalLight {
+    /// The light as the shaders see it, shadows are filled in by the light system.
+    pub fn gpu_light(&self, transform: &Transform, lights_on: bool) -> GpuDirectionalLight {
+        GpuDirectionalLight {
+            direction: light_direction(transform),
+            ambient: self.colors.ambient,
+            diffuse: self.colors.diffuse,
+            specular: self.colors.specular,
+            is_enabled: lights_on && self.enabled,
+            ..GpuDirectionalLight::default()
+        }
     }
+}
 
-    shared_light_fn!{}
+impl PointLight {
+    pub fn range(&self) -> f32 {
+        light_range(self.attenuation_constants, &self.colors)
+    }
+
+    /// The light as the shaders see it, shadows are filled in by the light system.
+    pub fn gpu_light(&self, transform: &Transform, lights_on: bool) -> GpuPointLight {
+        GpuPointLight {
+            position: (transform.get_world_matrix() * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz(),
+            ambient: self.colors.ambient,
+            diffuse: self.colors.diffuse,
+            specular: self.colors.specular,
+            range: self.range(),
+            attenuation_constants: self.attenuation_constants,
+            is_enabled: lights_on && self.enabled,
+            shadow_map_index: -1,
+            ..GpuPointLight::default()
+        }
+    }
 }
 
-impl Light for SpotLight {
-    fn upload_data(
-        &self,
-        transform: &Transform,
-
-        uniform_name: &str,
-        shader: &ShaderProgram,
-        global_enable: &bool
-    ) {
-        let direction = (transform.get_model_matrix() * glm::vec4(0.0, -1.0, 0.0, 0.0f32)).xyz();
-        let position = (transform.get_model_matrix() * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
-
-        shader
-            .set_vec3(&format!("{}.position", uniform_name), position)
-            .set_vec3(&format!("{}.direction", uniform_name), direction)
-            .set_vec3(
-                &format!("{}.ambient", uniform_name),
-                self.colors.ambient,
-            )
-            .set_vec3(
-                &format!("{}.diffuse", uniform_name),
-                self.colors.diffuse,
-            )
-            .set_vec3(
-                &format!("{}.specular", uniform_name),
-                self.colors.specular,
-            )
-            .set_vec3(
-                &format!("{}.attenuation_constants", uniform_name),
-                self.attenuation_constants,
-            )
-            .set_vec2(
-                &format!("{}.cutoff_cos", uniform_name),
-                glm::cos(&glm::radians(&self.cutoff_angles)),
-            )
-            .set_bool(&format!("{uniform_name}.is_enabled"), *global_enable && self.is_enabled());
+impl SpotLight {
+    pub fn range(&self) -> f32 {
+        light_range(self.attenuation_constants, &self.colors)
+    }
+
+    /// The light as the shaders see it, shadows are filled in by the light system.
+    pub fn gpu_light(&self, transform: &Transform, lights_on: bool) -> GpuSpotLight {
+        GpuSpotLight {
+            position: (transform.get_world_matrix() * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz(),
+            direction: light_direction(transform),
+            cutoff_cos: glm::cos(&glm::radians(&self.cutoff_angles)),
+            ambient: self.colors.ambient,
+            diffuse: self.colors.diffuse,
+            specular: self.colors.specular,
+            range: self.range(),
+            attenuation_constants: self.attenuation_constants,
+            is_enabled: lights_on && self.enabled,
+            shadow_map_index: -1,
+            ..GpuSpotLight::default()
+        }
     }
+}
+
+impl Light for DirectionalLight {
+    shared_light_fn!{}
+}
 
+impl Light for PointLight {
+    shared_light_fn!{}
+}
+
+impl Light for SpotLight {
     shared_light_fn!{}
 }
 
@@ -259,6 +322,39 @@ impl EguiDrawable for LightColors {
     }
 }
 
+impl EguiDrawable for ShadowSettings {
+    #[allow(unused_variables)]
+    fn on_egui(&mut self, ui: &mut Ui, index: usize) -> bool {
+        let mut changed = ui.checkbox(&mut self.casts_shadows, "Casts shadows").changed();
+
+        ui.add_enabled_ui(self.casts_shadows, |ui| {
+            egui::Grid::new("Shadow settings")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Bias");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut self.bias).speed(0.0001).clamp_range(0.0..=0.1))
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Resolution");
+                    egui::ComboBox::from_id_source("Shadow resolution")
+                        .selected_text(format!("{0}x{0}", self.resolution))
+                        .show_ui(ui, |ui| {
+                            for resolution in SHADOW_RESOLUTIONS {
+                                changed |= ui
+                                    .selectable_value(&mut self.resolution, resolution, format!("{resolution}x{resolution}"))
+                                    .changed();
+                            }
+                        });
+                    ui.end_row();
+                });
+        });
+
+        changed
+    }
+}
+
 pub fn float3_slider(float3: &mut Vec3, ui: &mut Ui) -> bool {
 
     use egui::Color32;
@@ -323,6 +419,7 @@ impl EguiDrawable for SpotLight {
             self.attenuation_constants.on_egui(ui, index);
 
             self.colors.on_egui(ui, index);
+            self.shadows.on_egui(ui, index);
         });
         
         false
@@ -334,7 +431,8 @@ impl Default for PointLight {
         PointLight { 
             enabled: true, 
             colors: LightColors::no_ambient(vec3(1., 1., 1.), 0.1), 
-            attenuation_constants: vec3(1., 1., 1.) 
+            attenuation_constants: vec3(1., 1., 1.),
+            shadows: ShadowSettings::default()
         }
     }
 }
@@ -347,6 +445,7 @@ impl EguiDrawable for PointLight {
             self.attenuation_constants.on_egui(ui, index);
 
             self.colors.on_egui(ui, index);
+            self.shadows.on_egui(ui, index);
         });
 
         false
@@ -357,8 +456,44 @@ impl EguiDrawable for DirectionalLight {
     fn on_egui(&mut self, ui: &mut Ui, index: usize) -> bool {
         enabled_header!(self, ui, "Directional light", index, {
             self.colors.on_egui(ui, index);
+            self.shadows.on_egui(ui, index);
         });
 
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera::Camera, transform::Degree3};
+
+    #[test]
+    fn directional_shadow_covers_camera_frustum() {
+        let camera = Camera::new(vec3(2., 3., 8.), vec3(0., 1., 0.), vec2(-20., 30.), 16. / 9.);
+        let corners = camera.frustum_corners(DIRECTIONAL_SHADOW_DISTANCE);
+
+        let transform = Transform::new(vec3(0., 5., 0.), Degree3::xyz(30., 0., 40.), "sun");
+        let light_space = DirectionalLight::default().light_space_matrix(&transform, &corners);
+
+        for corner in corners {
+            let ndc = light_space * corner.push(1.0);
+            let ndc = ndc.xyz() / ndc.w;
+            assert!(ndc.iter().all(|c| (-1.0001..=1.0001).contains(c)), "{corner:?} maps to {ndc:?}");
+        }
+    }
+
+    #[test]
+    fn range_is_where_lights_fade_out() {
+        let colors = LightColors::from_specular(vec3(1., 0.5, 0.5), 0.8);
+        let attenuation_constants = vec3(0.032, 0.09, 1.);
+        let range = light_range(attenuation_constants, &colors);
+
+        let attenuation = |dist: f32| 1.0 / (attenuation_constants.x * dist * dist + attenuation_constants.y * dist + attenuation_constants.z);
+        assert!((attenuation(range) - LIGHT_CUTOFF).abs() < 1e-6, "{range}");
+
+        assert_eq!(light_range(vec3(0., 0.5, 1.), &colors), (256. - 1.) / 0.5);
+        assert_eq!(light_range(vec3(0., 0., 1.), &colors), f32::INFINITY);
+        assert_eq!(light_range(vec3(1., 1., 1.), &LightColors::default()), 0.);
+    }
+}
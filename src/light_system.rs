@@ -1,87 +1,170 @@
 extern crate nalgebra_glm as glm;
 
+use bevy_ecs::prelude::*;
 use glm::{Vec3, vec3};
 
-use std::collections::HashMap;
 use crate::{
-    ecs::Ecs,
-    light::Light,
-    light::{DirectionalLight, PointLight, SpotLight},
-    shader::{ShaderProgram, Uniform},
-    transform::{Transform, Degree3}, renderer::Renderer, loaders::{ShaderLoader, DEFAULT_LIT_SHADER, DEFAULT_UNLIT_SHADER}, map, camera::Camera
+    light::{DirectionalLight, PointLight, SpotLight, POINT_SHADOW_FAR},
+    renderer::{
+        GpuDirectionalLight, GpuPointLight, GpuSpotLight, LightsBlock,
+        ShadowPass, ShadowPasses, ShadowTarget, ShadowView, MAX_POINT_SHADOWS, MAX_SPOT_SHADOWS
+    },
+    transform::Transform,
 };
 
-pub fn light_subsystem<T: Light>(
-    lit_shader: &ShaderProgram,
-    ecs: &mut Ecs,
-    u_light_array: &str,
-    global_enable: &bool
-) 
-    where T: 'static
-{
-    let mut enabled_light_index = 0;
-    ecs.do_all_mut::<Transform, T, ()>(|transform, light| {
-        light.upload_data(
-            transform,
-            &format!("{}[{}]", u_light_array, enabled_light_index),
-            lit_shader,
-            global_enable
-        );
-        enabled_light_index += 1;
-
-        None
-    });
+/// Per-frame resource telling the light system whether lights are on and what to shadow.
+pub struct LightSettings {
+    pub lights_on: bool,
+
+    /// Part of the camera frustum the directional shadow map has to cover, see `Camera::frustum_corners`.
+    pub camera_frustum: [Vec3; 8],
+}
+
+/// Cube shadow maps cost six depth passes each, so only this many point lights get one.
+/// Lives in the scene's `World` and is edited from the point light inspector.
+pub struct ShadowBudget {
+    /// At most `MAX_POINT_SHADOWS`.
+    pub point_lights: usize,
+
+    /// Point lights that got a shadow map last frame.
+    pub shadowed_point_lights: Vec<Entity>,
+}
+
+impl Default for ShadowBudget {
+    fn default() -> Self {
+        ShadowBudget { point_lights: 1, shadowed_point_lights: Vec::new() }
+    }
+}
+
+/// Lines to be drawn by the renderer after the schedule runs, as (start, end) pairs in world space.
+#[derive(Default)]
+pub struct DebugLines(pub Vec<(Vec3, Vec3)>);
+
+/// Assigns shadow maps to the first `MAX_SPOT_SHADOWS` shadow casting spot lights.
+/// `gpu_lights` are the `lights` in the same order.
+fn spot_light_shadows<'a>(
+    gpu_lights: &mut [GpuSpotLight],
+    lights: impl Iterator<Item = (&'a Transform, &'a SpotLight)>,
+    lights_on: bool,
+    shadow_passes: &mut ShadowPasses
+) {
+    let mut shadow_index = 0;
+
+    for (gpu_light, (transform, light)) in gpu_lights.iter_mut().zip(lights) {
+        let casts_shadows = lights_on && light.enabled && light.shadows.casts_shadows && shadow_index < MAX_SPOT_SHADOWS;
+        if !casts_shadows {
+            continue;
+        }
+
+        let light_space = light.light_space_matrix(transform);
+
+        gpu_light.shadow_map_index = shadow_index as i32;
+        gpu_light.shadow_bias = light.shadows.bias;
+        gpu_light.light_space = light_space;
+
+        shadow_passes.0.push(ShadowPass {
+            target: ShadowTarget::Spot(shadow_index),
+            view: ShadowView::Single(light_space),
+            resolution: light.shadows.resolution
+        });
+
+        shadow_index += 1;
+    }
+}
+
+/// Same as `spot_light_shadows` with cube maps, for as many point lights as the budget allows.
+fn point_light_shadows<'a>(
+    gpu_lights: &mut [GpuPointLight],
+    lights: impl Iterator<Item = (Entity, &'a Transform, &'a PointLight)>,
+    lights_on: bool,
+    budget: &mut ShadowBudget,
+    shadow_passes: &mut ShadowPasses
+) {
+    let max_shadows = budget.point_lights.min(MAX_POINT_SHADOWS);
+    budget.shadowed_point_lights.clear();
+
+    for (gpu_light, (entity, transform, light)) in gpu_lights.iter_mut().zip(lights) {
+        let shadow_index = budget.shadowed_point_lights.len();
+        let casts_shadows = lights_on && light.enabled && light.shadows.casts_shadows && shadow_index < max_shadows;
+        if !casts_shadows {
+            continue;
+        }
+
+        gpu_light.shadow_map_index = shadow_index as i32;
+        gpu_light.shadow_bias = light.shadows.bias;
+        gpu_light.shadow_far = POINT_SHADOW_FAR;
+
+        shadow_passes.0.push(ShadowPass {
+            target: ShadowTarget::Point(shadow_index),
+            view: ShadowView::Cube { position: transform.get_world_pos(), far: POINT_SHADOW_FAR },
+            resolution: light.shadows.resolution
+        });
+
+        budget.shadowed_point_lights.push(entity);
+    }
 }
 
-pub fn light_system(ecs: &mut Ecs, shader_loader: &mut ShaderLoader, r: &mut Renderer, camera: &Camera) {
-    let lit_shader = &shader_loader.get_shader_rc(DEFAULT_LIT_SHADER);
-    lit_shader.use_program();
-
-    light_subsystem::<SpotLight>(
-        lit_shader,
-        ecs,
-        "u_spot_lights",
-        &r.lights_on
-    );
-
-    light_subsystem::<PointLight>(
-        lit_shader,
-        ecs,
-        "u_point_lights",
-        &r.lights_on
-    );
-
-
-    ecs.do_one::<Transform, DirectionalLight, ()>(|transform, directional_light| {
-        directional_light.upload_data(
-            transform, 
-            "u_directional_light", 
-            lit_shader, 
-            &(r.lights_on && directional_light.is_enabled())
-        );
-
-        let rot = transform.get_rot().0;
-        let (theta, phi) = (rot.x.to_radians(), rot.y.to_radians());
-
-        // x = r * sin(theta) * cos(phi)
-        // y = r * cos(theta)
-        // z = r * sin(theta) * sin(phi)
-        
-        // TODO: fix this
-        let dx = theta.sin() * phi.cos();
-        let dy = theta.cos();
-        let dz = theta.sin() * phi.sin();
-
-        let bul = shader_loader.get_shader_rc(DEFAULT_UNLIT_SHADER);
-        bul.use_program();
-
-        bul.upload_uniforms(&map! {
-            "projection" => Uniform::Mat4(camera.get_proj_matrix()),
-            "view"       => Uniform::Mat4(camera.get_view_matrix()),
-            "model"      => Uniform::Mat4(transform.get_model_matrix())
-        }, "");
-
-        r.draw_line(*transform.get_pos(), transform.get_pos() + vec3(-dx, -dy, -dz) * 5.0);
-        None
-    });
+/// Gathers the lights into `LightsBlock` for the renderer to upload, and the shadow maps they need.
+#[allow(clippy::too_many_arguments)]
+pub fn light_system(
+    spot_lights: Query<(&Transform, &SpotLight)>,
+    point_lights: Query<(Entity, &Transform, &PointLight)>,
+    directional_lights: Query<(&Transform, &DirectionalLight)>,
+    settings: Res<LightSettings>,
+    mut lights: ResMut<LightsBlock>,
+    mut debug_lines: ResMut<DebugLines>,
+    mut shadow_passes: ResMut<ShadowPasses>,
+    mut shadow_budget: ResMut<ShadowBudget>,
+) {
+    let lights_on = settings.lights_on;
+
+    lights.spot_lights = spot_lights
+        .iter()
+        .map(|(transform, light)| light.gpu_light(transform, lights_on))
+        .collect();
+    spot_light_shadows(&mut lights.spot_lights, spot_lights.iter(), lights_on, &mut shadow_passes);
+
+    lights.point_lights = point_lights
+        .iter()
+        .map(|(_, transform, light)| light.gpu_light(transform, lights_on))
+        .collect();
+    point_light_shadows(&mut lights.point_lights, point_lights.iter(), lights_on, &mut shadow_budget, &mut shadow_passes);
+
+    let Some((transform, directional_light)) = directional_lights.iter().next() else {
+        lights.directional = GpuDirectionalLight::default();
+        return;
+    };
+
+    lights.directional = directional_light.gpu_light(transform, lights_on);
+
+    let shadows = &directional_light.shadows;
+    let casts_shadows = lights_on && directional_light.enabled && shadows.casts_shadows;
+
+    if casts_shadows {
+        let light_space = directional_light.light_space_matrix(transform, &settings.camera_frustum);
+
+        lights.directional.casts_shadows = true;
+        lights.directional.shadow_bias = shadows.bias;
+        lights.directional.light_space = light_space;
+
+        shadow_passes.0.push(ShadowPass {
+            target: ShadowTarget::Directional,
+            view: ShadowView::Single(light_space),
+            resolution: shadows.resolution
+        });
+    }
+
+    let rot = transform.get_rot().0;
+    let (theta, phi) = (rot.x.to_radians(), rot.y.to_radians());
+
+    // x = r * sin(theta) * cos(phi)
+    // y = r * cos(theta)
+    // z = r * sin(theta) * sin(phi)
+
+    // TODO: fix this
+    let dx = theta.sin() * phi.cos();
+    let dy = theta.cos();
+    let dz = theta.sin() * phi.sin();
+
+    debug_lines.0.push((transform.get_world_pos(), transform.get_world_pos() + vec3(-dx, -dy, -dz) * 5.0));
 }
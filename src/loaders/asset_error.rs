@@ -0,0 +1,74 @@
+use std::{fmt, io, path::{Path, PathBuf}};
+
+use obj::ObjError;
+
+use crate::loaders::GltfLoadError;
+
+pub type AssetResult<T> = Result<T, AssetError>;
+
+/// Why a texture, model or shader couldn't be loaded, along with the file it came from.
+#[derive(Debug)]
+pub enum AssetError {
+    Io { path: PathBuf, source: io::Error },
+    Image { path: PathBuf, source: image::ImageError },
+    Obj { path: PathBuf, source: ObjError },
+    Gltf { path: PathBuf, source: GltfLoadError },
+    ShaderCompile { path: PathBuf, log: String },
+
+    /// A bad `#include` at `line` of `path`, see `preprocess_shader`.
+    ShaderPreprocess { path: PathBuf, line: usize, message: String },
+    ShaderLink { vertex: PathBuf, fragment: PathBuf, log: String },
+
+    /// Loading succeeded but left nothing under the requested name.
+    MissingModel { path: PathBuf, name: String },
+}
+
+impl AssetError {
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        AssetError::Io { path: path.as_ref().to_path_buf(), source }
+    }
+
+    pub fn obj(path: impl AsRef<Path>, source: ObjError) -> Self {
+        AssetError::Obj { path: path.as_ref().to_path_buf(), source }
+    }
+
+    pub fn gltf(path: impl AsRef<Path>, source: GltfLoadError) -> Self {
+        AssetError::Gltf { path: path.as_ref().to_path_buf(), source }
+    }
+
+    /// The file that failed to load. Link errors point at the fragment shader.
+    pub fn path(&self) -> &Path {
+        match self {
+            AssetError::Io { path, .. }
+            | AssetError::Image { path, .. }
+            | AssetError::Obj { path, .. }
+            | AssetError::Gltf { path, .. }
+            | AssetError::ShaderCompile { path, .. }
+            | AssetError::ShaderPreprocess { path, .. }
+            | AssetError::MissingModel { path, .. } => path,
+            AssetError::ShaderLink { fragment, .. } => fragment,
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path().display();
+
+        match self {
+            AssetError::Io { source, .. } => write!(f, "Failed to read \"{path}\": {source}"),
+            AssetError::Image { source, .. } => write!(f, "Failed to decode image \"{path}\": {source}"),
+            AssetError::Obj { source, .. } => write!(f, "Failed to load OBJ \"{path}\": {source}"),
+            AssetError::Gltf { source, .. } => write!(f, "Failed to load glTF \"{path}\": {source}"),
+            AssetError::ShaderCompile { log, .. } => write!(f, "Failed to compile shader \"{path}\": {}", log.trim()),
+            AssetError::ShaderPreprocess { line, message, .. } => write!(f, "Failed to preprocess shader \"{path}\" at line {line}: {message}"),
+            AssetError::ShaderLink { vertex, log, .. } => write!(
+                f,
+                "Failed to link shaders \"{}\" and \"{path}\": {}",
+                vertex.display(),
+                log.trim()
+            ),
+            AssetError::MissingModel { name, .. } => write!(f, "Loading \"{path}\" didn't produce a model named \"{name}\""),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! One place to load, look up and free every kind of asset.
+//!
+//! Each loader keeps its assets behind `Handle`s keyed by `canonical_key`, so the same file
+//! reached through different relative paths is loaded once. The loader's own handle is the only
+//! one left once nothing else uses an asset, which is what `unused_assets` looks for.
+
+use std::{collections::HashSet, fmt, fs, path::{Component, Path, PathBuf}};
+
+use crate::{
+    loaders::{utils::Handle, AssetResult, BackgroundLoader, FileWatcher, LoadProgress, LoadedModel, ObjLoader, ShaderLoader, TextureLoader},
+    notifications::{self, Severity},
+    model::Model,
+    shader::{ShaderProgram, UniformMap},
+    texture::Texture,
+};
+
+/// Key assets are stored under: the canonical path for files that exist, otherwise the path
+/// without `.` components (names of generated assets, sub-assets like `model.gltf#0`).
+pub fn canonical_key(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| {
+        path.components().filter(|component| !matches!(component, Component::CurDir)).collect()
+    });
+
+    canonical.to_string_lossy().replace('\\', "/")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AssetKind {
+    Texture,
+    Model,
+    Shader,
+}
+
+impl fmt::Display for AssetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetKind::Texture => write!(f, "Texture"),
+            AssetKind::Model => write!(f, "Model"),
+            AssetKind::Shader => write!(f, "Shader"),
+        }
+    }
+}
+
+/// What the asset server knows about a loaded asset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetInfo {
+    pub kind: AssetKind,
+    pub key: String,
+
+    /// Handles to the asset outside of its loader, and for models, copies sharing its meshes.
+    pub references: usize,
+
+    /// Built in assets other things fall back to, these are never unloaded.
+    pub pinned: bool,
+
+    /// Files the asset is read from, empty for generated assets.
+    pub files: Vec<PathBuf>,
+}
+
+impl AssetInfo {
+    pub fn is_unused(&self) -> bool {
+        self.references == 0 && !self.pinned
+    }
+}
+
+pub struct AssetServer {
+    pub texture_loader: TextureLoader,
+    pub object_loader: ObjLoader,
+    pub shader_loader: ShaderLoader,
+    background_loader: BackgroundLoader,
+    file_watcher: FileWatcher,
+}
+
+impl AssetServer {
+    pub fn new(custom_shaders: &[(&str, &str, &str, UniformMap)]) -> Self {
+        let mut shader_loader = ShaderLoader::new(custom_shaders);
+        let mut texture_loader = TextureLoader::new();
+        let object_loader = ObjLoader::new(&mut shader_loader, &mut texture_loader);
+
+        AssetServer {
+            texture_loader,
+            object_loader,
+            shader_loader,
+            background_loader: BackgroundLoader::default(),
+            file_watcher: FileWatcher::default(),
+        }
+    }
+
+    pub fn loaders(&mut self) -> (&mut TextureLoader, &mut ObjLoader, &mut ShaderLoader) {
+        (&mut self.texture_loader, &mut self.object_loader, &mut self.shader_loader)
+    }
+
+    pub fn load_texture(&mut self, path: impl AsRef<Path>) -> AssetResult<Handle<Texture>> {
+        self.texture_loader.load_texture(path.as_ref())
+    }
+
+    pub fn load_model(&mut self, path: &str) -> AssetResult<Handle<Model>> {
+        self.object_loader.load_model(path, &mut self.texture_loader, &mut self.shader_loader)
+    }
+
+    /// Loads a model file on worker threads, see `BackgroundLoader`. Only takes file paths, not
+    /// glTF mesh keys.
+    pub fn load_model_async(&mut self, path: &str) -> Handle<Model> {
+        self.background_loader.load_model(path, &mut self.object_loader, &self.texture_loader)
+    }
+
+    /// Uploads what background loads decoded since the last frame, returns the models that
+    /// finished (or failed).
+    pub fn update(&mut self) -> Vec<LoadedModel> {
+        self.background_loader.update(&mut self.texture_loader, &mut self.object_loader, &mut self.shader_loader)
+    }
+
+    pub fn is_loading(&self, path: &str) -> bool {
+        self.background_loader.is_loading(path)
+    }
+
+    pub fn is_building(&self, path: &str) -> bool {
+        self.background_loader.is_building(path)
+    }
+
+    pub fn has_failed(&self, path: &str) -> bool {
+        self.background_loader.has_failed(path)
+    }
+
+    pub fn load_progress(&self) -> Vec<LoadProgress> {
+        self.background_loader.progress()
+    }
+
+    pub fn load_shader(&mut self, program_name: &str, vert_path: &str, frag_path: &str) -> AssetResult<Handle<ShaderProgram>> {
+        self.shader_loader.load_shader(program_name, vert_path, frag_path, UniformMap::new())
+    }
+
+    /// Every loaded asset, sorted by kind then key.
+    pub fn assets(&self) -> Vec<AssetInfo> {
+        let mut assets = self.texture_loader.asset_infos();
+        assets.extend(self.object_loader.asset_infos());
+        assets.extend(self.shader_loader.asset_infos());
+
+        assets.sort_by(|a, b| (a.kind, &a.key).cmp(&(b.kind, &b.key)));
+        assets
+    }
+
+    pub fn unused_assets(&self) -> Vec<AssetInfo> {
+        self.assets().into_iter().filter(AssetInfo::is_unused).collect()
+    }
+
+    /// Frees an unused asset and its GL objects. Returns false if it's still in use, pinned or unknown.
+    pub fn unload(&mut self, kind: AssetKind, key: &str) -> bool {
+        match kind {
+            AssetKind::Texture => self.texture_loader.unload(key),
+            AssetKind::Model => self.object_loader.unload(key),
+            AssetKind::Shader => self.shader_loader.unload(key),
+        }
+    }
+
+    /// Reads an asset's files again, see the loaders' `reload`. On failure the last good version
+    /// of the asset stays.
+    pub fn reload(&mut self, kind: AssetKind, key: &str) -> AssetResult<()> {
+        match kind {
+            AssetKind::Texture => self.texture_loader.reload(key),
+            AssetKind::Model => self.object_loader.reload(key, &mut self.texture_loader, &mut self.shader_loader),
+            AssetKind::Shader => self.shader_loader.reload(key),
+        }
+    }
+
+    /// Reloads the assets whose files changed on disk, reporting how that went.
+    /// Call once a frame, the disk is only checked every `POLL_INTERVAL`.
+    pub fn reload_changed(&mut self) {
+        if !self.file_watcher.is_due() {
+            return;
+        }
+
+        // Everything in the directories of loaded assets, so MTL libraries next to models are seen too
+        let directories: HashSet<PathBuf> = self.assets()
+            .iter()
+            .flat_map(|asset| &asset.files)
+            .filter_map(|file| file.parent())
+            .map(|directory| PathBuf::from(canonical_key(directory)))
+            .collect();
+
+        for path in self.file_watcher.poll(directories) {
+            self.reload_file(&path);
+        }
+    }
+
+    /// Reloads every asset read from the file at `path`.
+    pub fn reload_file(&mut self, path: &Path) {
+        let mut assets: Vec<(AssetKind, String)> = Vec::new();
+        assets.extend(self.texture_loader.textures_using(path).into_iter().map(|key| (AssetKind::Texture, key)));
+        assets.extend(self.object_loader.models_using(path).into_iter().map(|key| (AssetKind::Model, key)));
+        assets.extend(self.shader_loader.programs_using(path).into_iter().map(|key| (AssetKind::Shader, key)));
+
+        for (kind, key) in assets {
+            // Background loads pick up the new file anyway
+            if kind == AssetKind::Model && self.is_loading(&key) {
+                continue;
+            }
+
+            match self.reload(kind, &key) {
+                Ok(()) => notifications::notify(Severity::Info, format!("Reloaded {kind} \"{key}\"")),
+                Err(err) => notifications::error(format!("{err}, keeping the last good version")),
+            }
+        }
+    }
+
+    /// Unloads unused assets until there are none left, since unloading a model can leave its
+    /// textures unused. Returns how many assets were freed.
+    pub fn unload_unused(&mut self) -> usize {
+        let mut unloaded = 0;
+
+        loop {
+            let unused = self.unused_assets();
+            let freed = unused.iter().filter(|asset| self.unload(asset.kind, &asset.key)).count();
+
+            if freed == 0 {
+                return unloaded;
+            }
+
+            unloaded += freed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_keys_resolve_relative_paths() {
+        let direct = canonical_key("assets/textures/white.jpeg");
+        let roundabout = canonical_key("./assets/../assets/textures/white.jpeg");
+
+        assert_eq!(direct, roundabout);
+        assert!(Path::new(&direct).is_absolute());
+    }
+
+    #[test]
+    fn canonical_keys_keep_generated_names() {
+        assert_eq!(canonical_key("default_cube"), "default_cube");
+        assert_eq!(canonical_key("./assets/gltf/missing.gltf#2"), "assets/gltf/missing.gltf#2");
+    }
+}
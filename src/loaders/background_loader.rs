@@ -0,0 +1,450 @@
+//! Loads models without freezing the editor.
+//!
+//! A fixed pool of worker threads reads the files and decodes their images. Back on the main thread the
+//! model is built in one step, then its images are uploaded one per step, as many steps as fit in
+//! `UPLOAD_BUDGET` each frame. Until it's built the model shows as the default cube, and its
+//! textures show as white until they're uploaded.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicUsize, Ordering}, mpsc::{self, Receiver, Sender}, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    loaders::{utils::Handle, *},
+    model::Model,
+    texture::Texture,
+};
+
+/// Upload time per frame, one step is always done even if it takes longer.
+const UPLOAD_BUDGET: Duration = Duration::from_millis(8);
+
+/// Models read at once, the others wait their turn. Each one decodes its images on up to a
+/// thread per core on top of that.
+const MODEL_WORKERS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStage {
+    Reading,
+    Decoding,
+    Uploading,
+}
+
+impl fmt::Display for LoadStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadStage::Reading => write!(f, "Reading"),
+            LoadStage::Decoding => write!(f, "Decoding textures"),
+            LoadStage::Uploading => write!(f, "Uploading textures"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadProgress {
+    pub path: String,
+    pub stage: LoadStage,
+    pub done: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    /// How far along the current stage is, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        match self.total {
+            0 => 0.,
+            total => self.done as f32 / total as f32,
+        }
+    }
+}
+
+/// A model that finished building (its textures may still be uploading) or failed to load.
+pub struct LoadedModel {
+    pub path: String,
+    pub result: AssetResult<Handle<Model>>,
+}
+
+/// What worker threads hand back, nothing in here touches GL.
+enum Decoded {
+    Obj(DecodedObj, Vec<(PathBuf, DecodedImage)>),
+    Gltf(GltfAsset),
+}
+
+enum WorkerMessage {
+    Decoding { id: usize, done: usize, total: usize },
+    Decoded { id: usize, result: AssetResult<Decoded> },
+}
+
+enum JobState {
+    Reading,
+    Decoding { done: usize, total: usize },
+    Decoded(Box<Decoded>),
+    Uploading { images: Vec<(Handle<Texture>, DecodedImage)>, total: usize },
+}
+
+struct Job {
+    id: usize,
+    path: String,
+
+    /// The placeholder registered in the `ObjLoader`, filled in once the model is built.
+    model: Handle<Model>,
+    state: JobState,
+}
+
+impl Job {
+    fn progress(&self) -> LoadProgress {
+        let (stage, done, total) = match &self.state {
+            JobState::Reading => (LoadStage::Reading, 0, 1),
+            JobState::Decoding { done, total } => (LoadStage::Decoding, *done, *total),
+            JobState::Decoded(_) => (LoadStage::Uploading, 0, 1),
+            JobState::Uploading { images, total } => (LoadStage::Uploading, total - images.len(), *total),
+        };
+
+        LoadProgress { path: self.path.clone(), stage, done, total }
+    }
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads running tasks in the order they're sent.
+struct WorkerPool {
+    sender: Sender<Task>,
+}
+
+impl WorkerPool {
+    fn new(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..threads {
+            let receiver = Arc::clone(&receiver);
+
+            // Stops once the pool is dropped, the lock is only held while waiting for a task
+            thread::spawn(move || loop {
+                let task = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv();
+                let Ok(task) = task else { break };
+
+                // A panicking task only loses its own model, the thread keeps going
+                let _ = panic::catch_unwind(AssertUnwindSafe(task));
+            });
+        }
+
+        WorkerPool { sender }
+    }
+
+    fn run(&self, task: impl FnOnce() + Send + 'static) {
+        // The threads only stop once the sender is dropped, so this can't fail
+        let _ = self.sender.send(Box::new(task));
+    }
+}
+
+pub struct BackgroundLoader {
+    pool: WorkerPool,
+    sender: Sender<WorkerMessage>,
+    receiver: Receiver<WorkerMessage>,
+    jobs: Vec<Job>,
+    next_id: usize,
+
+    /// Keys of the files whose last load failed.
+    failed: HashSet<String>,
+}
+
+impl Default for BackgroundLoader {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        BackgroundLoader {
+            pool: WorkerPool::new(MODEL_WORKERS),
+            sender,
+            receiver,
+            jobs: Vec::new(),
+            next_id: 0,
+            failed: HashSet::new(),
+        }
+    }
+}
+
+impl BackgroundLoader {
+    /// Starts loading the model file at `path` and returns a placeholder for it right away, or
+    /// the model itself if it's already loaded (or loading).
+    pub fn load_model(&mut self, path: &str, object_loader: &mut ObjLoader, texture_loader: &TextureLoader) -> Handle<Model> {
+        if let Some(model) = object_loader.get(path) {
+            return model;
+        }
+
+        let mut placeholder: Model = object_loader.clone_handle(DEFAULT_CUBE_NAME).borrow().clone();
+        placeholder.name = Path::new(path).file_stem().map_or_else(|| path.to_string(), |stem| stem.to_string_lossy().into_owned());
+        placeholder.source = path.to_string();
+
+        let model = object_loader.add_model(canonical_key(path), placeholder);
+        self.failed.remove(&canonical_key(path));
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let worker_path = path.to_string();
+        let normals = object_loader.normal_generation;
+        let loaded_textures = texture_loader.loaded();
+        let sender = self.sender.clone();
+
+        self.pool.run(move || {
+            let result = decode(&worker_path, normals, &loaded_textures, |done, total| {
+                // The receiver is gone if the editor closed, nothing left to report to
+                let _ = sender.send(WorkerMessage::Decoding { id, done, total });
+            });
+            let _ = sender.send(WorkerMessage::Decoded { id, result });
+        });
+
+        self.jobs.push(Job { id, path: path.to_string(), model: Handle::clone(&model), state: JobState::Reading });
+        model
+    }
+
+    pub fn is_loading(&self, path: &str) -> bool {
+        let key = canonical_key(path);
+        self.jobs.iter().any(|job| canonical_key(&job.path) == key)
+    }
+
+    /// Whether the file is loading and its model isn't built yet, its textures may still be
+    /// uploading after that.
+    pub fn is_building(&self, path: &str) -> bool {
+        let key = canonical_key(path);
+        self.jobs.iter().any(|job| canonical_key(&job.path) == key && !matches!(job.state, JobState::Uploading { .. }))
+    }
+
+    /// Whether the last load of the file failed, until it's loaded again.
+    pub fn has_failed(&self, path: &str) -> bool {
+        self.failed.contains(&canonical_key(path))
+    }
+
+    pub fn progress(&self) -> Vec<LoadProgress> {
+        self.jobs.iter().map(Job::progress).collect()
+    }
+
+    /// Takes in what the workers decoded and uploads it for up to `UPLOAD_BUDGET`.
+    /// Call once a frame.
+    pub fn update(
+        &mut self,
+        texture_loader: &mut TextureLoader,
+        object_loader: &mut ObjLoader,
+        shader_loader: &mut ShaderLoader,
+    ) -> Vec<LoadedModel> {
+        let mut loaded = Vec::new();
+
+        while let Ok(message) = self.receiver.try_recv() {
+            match message {
+                WorkerMessage::Decoding { id, done, total } => {
+                    if let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) {
+                        job.state = JobState::Decoding { done, total };
+                    }
+                }
+                WorkerMessage::Decoded { id, result: Ok(decoded) } => {
+                    if let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) {
+                        job.state = JobState::Decoded(Box::new(decoded));
+                    }
+                }
+                WorkerMessage::Decoded { id, result: Err(err) } => {
+                    let Some(index) = self.jobs.iter().position(|job| job.id == id) else { continue };
+                    let job = self.jobs.remove(index);
+
+                    // Keeps showing the cube, but loading the file again starts over
+                    let key = canonical_key(&job.path);
+                    self.failed.insert(key.clone());
+                    if object_loader.models().get(&key) == Some(&job.model) {
+                        object_loader.forget_model(&key);
+                    }
+
+                    loaded.push(LoadedModel { path: job.path, result: Err(err) });
+                }
+            }
+        }
+
+        let start = Instant::now();
+        let has_work = |job: &&mut Job| matches!(job.state, JobState::Decoded(_) | JobState::Uploading { .. });
+
+        while let Some(job) = self.jobs.iter_mut().find(has_work) {
+            match std::mem::replace(&mut job.state, JobState::Reading) {
+                JobState::Decoded(decoded) => {
+                    let (model, images) = build(&job.path, *decoded, texture_loader, object_loader, shader_loader);
+                    object_loader.remember_materials(canonical_key(&job.path), &model);
+                    *job.model.borrow_mut() = model;
+
+                    loaded.push(LoadedModel { path: job.path.clone(), result: Ok(Handle::clone(&job.model)) });
+                    job.state = JobState::Uploading { total: images.len(), images };
+                }
+                JobState::Uploading { mut images, total } => {
+                    if let Some((texture, image)) = images.pop() {
+                        texture_loader.fill(&texture, &image);
+                    }
+                    job.state = JobState::Uploading { images, total };
+                }
+                state => job.state = state,
+            }
+
+            self.jobs.retain(|job| !matches!(&job.state, JobState::Uploading { images, .. } if images.is_empty()));
+
+            if start.elapsed() >= UPLOAD_BUDGET {
+                break;
+            }
+        }
+
+        loaded
+    }
+}
+
+/// Worker side: reads the file and decodes the images that aren't loaded yet.
+/// Images that fail to decode are left for `build` to load (and report) again.
+fn decode(path: &str, normals: NormalGeneration, loaded_textures: &HashSet<String>, progress: impl Fn(usize, usize) + Sync) -> AssetResult<Decoded> {
+    if is_gltf(path) {
+        let asset = GltfAsset::read(Path::new(path)).map_err(|err| AssetError::gltf(path, err))?;
+        return Ok(Decoded::Gltf(asset));
+    }
+
+    let obj = DecodedObj::read(path, normals)?;
+
+    let paths: Vec<PathBuf> = obj.texture_paths()
+        .into_iter()
+        .filter(|path| !loaded_textures.contains(&canonical_key(path)))
+        .collect();
+
+    let images = decode_images(&paths, progress);
+    Ok(Decoded::Obj(obj, images))
+}
+
+/// Decodes on a thread per core (at most one per image), each taking the next image left.
+fn decode_images(paths: &[PathBuf], progress: impl Fn(usize, usize) + Sync) -> Vec<(PathBuf, DecodedImage)> {
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get).min(paths.len());
+    let next = AtomicUsize::new(0);
+    let decoded = AtomicUsize::new(0);
+    progress(0, paths.len());
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| {
+                let mut images = Vec::new();
+
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Ok(image) = decode_image(path) {
+                        images.push((path.clone(), image));
+                    }
+                    progress(decoded.fetch_add(1, Ordering::Relaxed) + 1, paths.len());
+                }
+
+                images
+            }))
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+/// Main thread side: reserves the decoded images so the materials pick them up, then builds the
+/// model. Returns the images left to upload.
+fn build(
+    path: &str,
+    decoded: Decoded,
+    texture_loader: &mut TextureLoader,
+    object_loader: &mut ObjLoader,
+    shader_loader: &mut ShaderLoader,
+) -> (Model, Vec<(Handle<Texture>, DecodedImage)>) {
+    match decoded {
+        Decoded::Obj(obj, images) => {
+            let images = images
+                .into_iter()
+                .filter_map(|(path, image)| match texture_loader.reserve(&path.to_string_lossy()) {
+                    (texture, true) => Some((texture, image)),
+                    (_, false) => None,
+                })
+                .collect();
+
+            (ObjLoader::build_obj(obj, texture_loader, shader_loader), images)
+        }
+        Decoded::Gltf(asset) => {
+            let reserved: HashMap<String, Handle<Texture>> = asset.images
+                .iter()
+                .filter_map(|image| match texture_loader.reserve(&image.name) {
+                    (texture, true) => Some((image.name.clone(), texture)),
+                    (_, false) => None,
+                })
+                .collect();
+
+            let model = object_loader.build_gltf(path, &asset, texture_loader, shader_loader);
+
+            let images = asset.images
+                .into_iter()
+                .filter_map(|GltfImage { name, width, height, format, pixels }| {
+                    let texture = reserved.get(&name)?;
+                    Some((Handle::clone(texture), DecodedImage { width, height, format, pixels }))
+                })
+                .collect();
+
+            (model, images)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_obj_files_off_the_main_thread() {
+        let reports = std::sync::Mutex::new(Vec::new());
+        let decoded = decode("assets/obj/cube.obj", NormalGeneration::Flat, &HashSet::new(), |done, total| reports.lock().unwrap().push((done, total)));
+
+        let Ok(Decoded::Obj(obj, images)) = decoded else { panic!("The cube should decode as an OBJ file") };
+        assert_eq!(obj.meshes.len(), 1);
+        assert!(images.is_empty());
+        assert_eq!(reports.into_inner().unwrap(), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn pool_runs_more_tasks_than_threads() {
+        let pool = WorkerPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+
+        for task in 0..8 {
+            let sender = sender.clone();
+            pool.run(move || sender.send(task).unwrap());
+        }
+        pool.run(|| panic!("Only this task should be lost"));
+        pool.run(move || sender.send(8).unwrap());
+
+        let mut done: Vec<i32> = receiver.iter().collect();
+        done.sort();
+        assert_eq!(done, (0..=8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn decodes_every_image_once() {
+        let paths: Vec<PathBuf> = ["awesomeface.png", "container.jpg", "checker.jpg", "missing.png"]
+            .iter()
+            .map(|name| Path::new("assets/textures").join(name))
+            .collect();
+
+        let reports = std::sync::Mutex::new(Vec::new());
+        let mut images = decode_images(&paths, |done, total| reports.lock().unwrap().push((done, total)));
+        images.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let decoded: Vec<&Path> = images.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(decoded, [&paths[0], &paths[2], &paths[1]]);
+
+        let mut reports = reports.into_inner().unwrap();
+        reports.sort();
+        assert_eq!(reports, vec![(0, 4), (1, 4), (2, 4), (3, 4), (4, 4)]);
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let decoded = decode("assets/obj/does_not_exist.obj", NormalGeneration::Flat, &HashSet::new(), |_, _| {});
+        assert!(matches!(decoded, Err(AssetError::Obj { .. })));
+    }
+}
@@ -0,0 +1,105 @@
+//! Finds asset files that changed on disk, for hot reloading.
+//!
+//! Polls modification times instead of using OS notifications, the directories holding loaded
+//! assets are small enough that listing them a couple of times a second costs next to nothing.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Time between two looks at the disk.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+pub struct FileWatcher {
+    /// Last seen modification time of every file in the watched directories.
+    modified: HashMap<PathBuf, SystemTime>,
+    watched: HashSet<PathBuf>,
+    last_poll: Option<Instant>,
+}
+
+impl FileWatcher {
+    /// Files in `directories` modified since the last poll, at most once per `POLL_INTERVAL`.
+    /// Files in directories that weren't watched before aren't reported the first time.
+    pub fn poll(&mut self, directories: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+        if !self.is_due() {
+            return Vec::new();
+        }
+        self.last_poll = Some(Instant::now());
+
+        self.scan(directories)
+    }
+
+    /// Whether `poll` would look at the disk, to skip finding the directories when it won't.
+    pub fn is_due(&self) -> bool {
+        self.last_poll.is_none_or(|last_poll| last_poll.elapsed() >= POLL_INTERVAL)
+    }
+
+    /// `poll` without the rate limit.
+    pub fn scan(&mut self, directories: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+
+        for directory in directories {
+            let new_directory = self.watched.insert(directory.clone());
+            let Ok(entries) = fs::read_dir(&directory) else { continue };
+
+            for entry in entries.flatten() {
+                let Ok(modified) = entry.metadata().and_then(|metadata| metadata.modified()) else { continue };
+                let path = entry.path();
+
+                match self.modified.insert(path.clone(), modified) {
+                    Some(last_modified) if last_modified != modified => changed.push(path),
+                    None if !new_directory => changed.push(path),
+                    _ => {}
+                }
+            }
+        }
+
+        changed.sort();
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn reports_modified_and_new_files() {
+        let directory = std::env::temp_dir().join("ggl_file_watcher");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let texture = directory.join("texture.png");
+        fs::write(&texture, b"first").unwrap();
+
+        let mut watcher = FileWatcher::default();
+        assert!(watcher.scan([directory.clone()]).is_empty());
+        assert!(watcher.scan([directory.clone()]).is_empty());
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options().write(true).open(&texture).unwrap().set_modified(later).unwrap();
+
+        let shader = directory.join("shader.frag");
+        fs::write(&shader, b"void main() {}").unwrap();
+
+        assert_eq!(watcher.scan([directory.clone()]), vec![shader, texture]);
+        assert!(watcher.scan([directory]).is_empty());
+    }
+
+    #[test]
+    fn polls_once_per_interval() {
+        let mut watcher = FileWatcher::default();
+        assert!(watcher.is_due());
+
+        watcher.poll([]);
+        assert!(!watcher.is_due());
+
+        watcher.last_poll = Some(Instant::now() - POLL_INTERVAL);
+        assert!(watcher.is_due());
+    }
+}
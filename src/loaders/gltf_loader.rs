@@ -0,0 +1,794 @@
+//! glTF 2.0 models, both `.gltf` (with embedded or external buffers and images) and binary `.glb`.
+//!
+//! Files are read into a `GltfAsset` first, which doesn't touch GL, then uploaded by `ObjLoader::load_gltf`.
+
+use std::{collections::HashMap, fmt, path::Path, sync::Arc};
+
+use bevy_ecs::prelude::{Entity, World};
+use gltf::{image::Format, mesh::Mode};
+use nalgebra_glm::{self as glm, Mat3, Mat4, Quat, Vec3, vec3};
+
+use crate::{
+    hierarchy,
+    loaders::{canonical_key, utils::Handle, AssetError, AssetResult, ObjLoader, ShaderLoader, TextureLoader},
+    mesh::{Mesh, MeshRenderer},
+    model::Model,
+    notifications,
+    renderer::Material,
+    texture::{Texture, Texture2D, TextureType},
+    transform::Transform,
+};
+
+pub const GLTF_EXTENSIONS: [&str; 2] = ["gltf", "glb"];
+
+/// 3 floats for position, 3 for the normal, 2 for texture coordinates. Same layout as `Mesh`.
+const VERTEX_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub enum GltfLoadError {
+    Import(gltf::Error),
+    MissingPositions { mesh: String },
+    MissingMesh(String),
+}
+
+impl fmt::Display for GltfLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfLoadError::Import(err) => write!(f, "Failed to import glTF file: {err}"),
+            GltfLoadError::MissingPositions { mesh } => write!(f, "A primitive of mesh \"{mesh}\" has no positions"),
+            GltfLoadError::MissingMesh(key) => write!(f, "No glTF mesh named \"{key}\""),
+        }
+    }
+}
+
+impl From<gltf::Error> for GltfLoadError {
+    fn from(value: gltf::Error) -> Self {
+        GltfLoadError::Import(value)
+    }
+}
+
+/// True if the path (or the file of a mesh key) has a glTF extension.
+pub fn is_gltf(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| GLTF_EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+}
+
+/// Key of the model holding a single mesh of a glTF file, like `"assets/gltf/hierarchy.gltf#1"`.
+/// The entities spawned by `ObjLoader::spawn_gltf` use these.
+pub fn gltf_mesh_key(path: &str, mesh: usize) -> String {
+    format!("{path}#{mesh}")
+}
+
+/// Inverse of `gltf_mesh_key`, None for anything that isn't a glTF mesh key.
+pub fn split_gltf_mesh_key(key: &str) -> Option<(&str, usize)> {
+    let (path, mesh) = key.rsplit_once('#')?;
+    let mesh = mesh.parse().ok()?;
+
+    is_gltf(path).then_some((path, mesh))
+}
+
+/// Decoded image, named `<canonical path>#<image name or image index>` in the `TextureLoader`.
+pub struct GltfImage {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+
+    /// `glow::RED`, `glow::RGB` or `glow::RGBA`.
+    pub format: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// The parts of a metallic-roughness material the PBR and unlit shaders can show.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfMaterial {
+    pub name: String,
+    pub base_color: [f32; 4],
+
+    /// Index into `GltfAsset::images`.
+    pub base_color_texture: Option<usize>,
+
+    /// Already multiplied by `KHR_materials_emissive_strength`.
+    pub emissive: [f32; 3],
+    pub emissive_texture: Option<usize>,
+
+    pub metallic: f32,
+    pub roughness: f32,
+
+    /// Roughness in the green channel, metallic in the blue one.
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+
+    /// `KHR_materials_unlit`.
+    pub unlit: bool,
+
+    /// `alphaMode` is `BLEND`.
+    pub transparent: bool,
+}
+
+impl Default for GltfMaterial {
+    /// The material glTF uses for primitives without one.
+    fn default() -> Self {
+        GltfMaterial {
+            name: "Default".into(),
+            base_color: [1., 1., 1., 1.],
+            base_color_texture: None,
+            emissive: [0., 0., 0.],
+            emissive_texture: None,
+            metallic: 1.,
+            roughness: 1.,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            occlusion_strength: 1.,
+            unlit: false,
+            transparent: false,
+        }
+    }
+}
+
+pub struct GltfPrimitive {
+    /// Interleaved in the `Mesh` layout.
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+
+    /// Index into `GltfAsset::materials`, None for the default material.
+    pub material: Option<usize>,
+}
+
+pub struct GltfMesh {
+    pub name: String,
+    pub primitives: Vec<GltfPrimitive>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfNode {
+    pub name: String,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+
+    /// Index into `GltfAsset::meshes`.
+    pub mesh: Option<usize>,
+    pub children: Vec<GltfNode>,
+}
+
+impl GltfNode {
+    pub fn local_matrix(&self) -> Mat4 {
+        Transform::model_matrix(self.translation, &self.rotation, self.scale)
+    }
+}
+
+/// A glTF file read into memory, nothing is uploaded to GL yet.
+pub struct GltfAsset {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<GltfMaterial>,
+    pub images: Vec<GltfImage>,
+
+    /// Root nodes of the default scene (or the first one).
+    pub nodes: Vec<GltfNode>,
+}
+
+impl GltfAsset {
+    /// Reads `.gltf` and `.glb` files along with any external buffers and images.
+    pub fn read(path: &Path) -> Result<Self, GltfLoadError> {
+        let (document, buffers, images) = gltf::import(path)?;
+        let source = canonical_key(path);
+
+        let images = document.images()
+            .zip(images)
+            .map(|(image, data)| {
+                let name = match image.name() {
+                    Some(name) => format!("{source}#{name}"),
+                    None => format!("{source}#image{}", image.index()),
+                };
+
+                read_image(name, data)
+            })
+            .collect();
+
+        let materials = document.materials().map(|material| read_material(&material)).collect();
+
+        let meshes = document.meshes()
+            .map(|mesh| {
+                let name = mesh.name().map_or_else(|| format!("Mesh {}", mesh.index()), str::to_string);
+
+                let primitives = mesh.primitives()
+                    .filter_map(|primitive| read_primitive(&primitive, &buffers, &name).transpose())
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(GltfMesh { name, primitives })
+            })
+            .collect::<Result<Vec<_>, GltfLoadError>>()?;
+
+        let nodes = match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| read_node(&node)).collect(),
+
+            // Files without scenes are just a library of meshes
+            None => meshes
+                .iter()
+                .enumerate()
+                .map(|(index, mesh)| GltfNode {
+                    name: mesh.name.clone(),
+                    translation: Vec3::zeros(),
+                    rotation: Quat::identity(),
+                    scale: vec3(1., 1., 1.),
+                    mesh: Some(index),
+                    children: vec![],
+                })
+                .collect(),
+        };
+
+        Ok(GltfAsset { meshes, materials, images, nodes })
+    }
+
+    /// Every mesh in the node tree with the matrix from its node to the scene root.
+    pub fn mesh_instances(&self) -> Vec<(usize, Mat4)> {
+        fn walk(node: &GltfNode, parent: &Mat4, instances: &mut Vec<(usize, Mat4)>) {
+            let world = parent * node.local_matrix();
+
+            if let Some(mesh) = node.mesh {
+                instances.push((mesh, world));
+            }
+
+            for child in &node.children {
+                walk(child, &world, instances);
+            }
+        }
+
+        let mut instances = Vec::new();
+        for node in &self.nodes {
+            walk(node, &Mat4::identity(), &mut instances);
+        }
+
+        instances
+    }
+
+    fn upload_image(&self, index: usize, texture_loader: &mut TextureLoader) -> Option<Handle<Texture>> {
+        let image = self.images.get(index)?;
+        Some(texture_loader.load_pixels(&image.name, (image.width, image.height), image.format, &image.pixels))
+    }
+
+    fn upload_material(
+        &self,
+        material: &GltfMaterial,
+        texture_loader: &mut TextureLoader,
+        shader_loader: &mut ShaderLoader
+    ) -> Material {
+        let base_color = material.base_color_texture
+            .and_then(|image| self.upload_image(image, texture_loader))
+            .unwrap_or_else(|| texture_loader.white_texture());
+
+        let mut textures = vec![Texture2D::new(&base_color, TextureType::Diffuse, 1)];
+
+        let [r, g, b, a] = material.base_color;
+
+        let mut result = if material.unlit {
+            let mut unlit = Material::unlit(shader_loader, textures);
+            unlit.parameters.set("diffuse_color", [r, g, b]);
+            unlit
+        } else {
+            let maps = [
+                (material.metallic_roughness_texture, TextureType::MetallicRoughness),
+                (material.normal_texture, TextureType::Normal),
+                (material.occlusion_texture, TextureType::Occlusion),
+                (material.emissive_texture, TextureType::Emissive),
+            ];
+
+            for (image, tex_type) in maps {
+                if let Some(texture) = image.and_then(|image| self.upload_image(image, texture_loader)) {
+                    textures.push(Texture2D::new(&texture, tex_type, 1));
+                }
+            }
+
+            let mut pbr = Material::pbr(shader_loader, textures);
+            pbr.parameters
+                .set("base_color", [r, g, b])
+                .set("emissive_color", material.emissive)
+                .set("metallic", material.metallic)
+                .set("roughness", material.roughness)
+                .set("occlusion_strength", material.occlusion_strength);
+            pbr
+        };
+
+        result.name = material.name.clone();
+        result.transparent = material.transparent;
+        result.parameters.set("opacity", a);
+        result
+    }
+}
+
+fn read_image(name: String, data: gltf::image::Data) -> GltfImage {
+    let gltf::image::Data { pixels, format, width, height } = data;
+
+    // Luminance and alpha
+    let gray_alpha = |pixels: Vec<u8>| pixels.chunks(2).flat_map(|la| [la[0], la[0], la[0], la[1]]).collect();
+
+    // 16 bit and float images (like 16 bit normal maps) are uploaded with 8 bits per channel too
+    let (format, pixels) = match format {
+        Format::R8 => (glow::RED, pixels),
+        Format::R8G8B8 => (glow::RGB, pixels),
+        Format::R8G8B8A8 => (glow::RGBA, pixels),
+        Format::R8G8 => (glow::RGBA, gray_alpha(pixels)),
+        Format::R16 => (glow::RED, narrow_16_bit(&pixels)),
+        Format::R16G16 => (glow::RGBA, gray_alpha(narrow_16_bit(&pixels))),
+        Format::R16G16B16 => (glow::RGB, narrow_16_bit(&pixels)),
+        Format::R16G16B16A16 => (glow::RGBA, narrow_16_bit(&pixels)),
+        Format::R32G32B32FLOAT => (glow::RGB, narrow_float(&pixels)),
+        Format::R32G32B32A32FLOAT => (glow::RGBA, narrow_float(&pixels)),
+    };
+
+    GltfImage { name, width, height, format, pixels }
+}
+
+/// Channels of native endian `u16`s to bytes.
+fn narrow_16_bit(pixels: &[u8]) -> Vec<u8> {
+    pixels.chunks_exact(2).map(|channel| (u16::from_ne_bytes([channel[0], channel[1]]) >> 8) as u8).collect()
+}
+
+/// Channels of native endian `f32`s to bytes, clamped to 0..1.
+fn narrow_float(pixels: &[u8]) -> Vec<u8> {
+    pixels
+        .chunks_exact(4)
+        .map(|channel| (f32::from_ne_bytes([channel[0], channel[1], channel[2], channel[3]]).clamp(0., 1.) * 255.).round() as u8)
+        .collect()
+}
+
+fn read_material(material: &gltf::Material) -> GltfMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    let emissive_strength = material.emissive_strength().unwrap_or(1.);
+
+    GltfMaterial {
+        name: material.name().map_or_else(|| format!("Material {}", material.index().unwrap_or(0)), str::to_string),
+        base_color: pbr.base_color_factor(),
+        base_color_texture: pbr.base_color_texture().map(|info| info.texture().source().index()),
+        emissive: material.emissive_factor().map(|channel| channel * emissive_strength),
+        emissive_texture: material.emissive_texture().map(|info| info.texture().source().index()),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| info.texture().source().index()),
+        normal_texture: material.normal_texture().map(|info| info.texture().source().index()),
+        occlusion_texture: material.occlusion_texture().map(|info| info.texture().source().index()),
+        occlusion_strength: material.occlusion_texture().map_or(1., |info| info.strength()),
+        unlit: material.unlit(),
+        transparent: material.alpha_mode() == gltf::material::AlphaMode::Blend,
+    }
+}
+
+/// None for primitives that aren't triangle lists, those are skipped.
+fn read_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    mesh_name: &str
+) -> Result<Option<GltfPrimitive>, GltfLoadError> {
+    if primitive.mode() != Mode::Triangles {
+        notifications::warn(format!("Skipping {:?} primitive of mesh \"{mesh_name}\", only triangles are supported", primitive.mode()));
+        return Ok(None);
+    }
+
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+
+    let mut positions = reader
+        .read_positions()
+        .ok_or_else(|| GltfLoadError::MissingPositions { mesh: mesh_name.to_string() })?
+        .collect::<Vec<_>>();
+
+    let mut indices = reader
+        .read_indices()
+        .map(|indices| indices.into_u32().collect::<Vec<_>>())
+        .unwrap_or_else(|| (0..positions.len() as u32).collect());
+
+    let normals = reader.read_normals().map(|normals| normals.collect::<Vec<_>>());
+
+    // All textures are sampled with the base color's coordinates and transform
+    let base_color = primitive.material().pbr_metallic_roughness().base_color_texture();
+    let texture_transform = base_color.as_ref().and_then(|info| info.texture_transform());
+    let tex_coord_set = texture_transform
+        .as_ref()
+        .and_then(|transform| transform.tex_coord())
+        .or(base_color.as_ref().map(|info| info.tex_coord()))
+        .unwrap_or(0);
+
+    let mut tex_coords = reader
+        .read_tex_coords(tex_coord_set)
+        .map(|tex_coords| tex_coords.into_f32().collect::<Vec<_>>())
+        .unwrap_or_else(|| vec![[0., 0.]; positions.len()]);
+
+    let normals = normals.unwrap_or_else(|| flat_shade(&mut positions, &mut tex_coords, &mut indices));
+
+    let uv_matrix = texture_transform.map(|transform| {
+        uv_transform(transform.offset(), transform.rotation(), transform.scale())
+    });
+
+    let mut vertices = Vec::with_capacity(positions.len() * VERTEX_SIZE);
+    for (index, position) in positions.iter().enumerate() {
+        let normal = normals.get(index).copied().unwrap_or([0., 1., 0.]);
+        let [u, v] = tex_coords.get(index).copied().unwrap_or([0., 0.]);
+
+        let uv = match &uv_matrix {
+            Some(matrix) => {
+                let uv = matrix * glm::vec3(u, v, 1.);
+                [uv.x, uv.y]
+            }
+            None => [u, v],
+        };
+
+        vertices.extend(position);
+        vertices.extend(normal);
+        vertices.extend(uv);
+    }
+
+    Ok(Some(GltfPrimitive { vertices, indices, material: primitive.material().index() }))
+}
+
+/// `KHR_texture_transform`: translation * rotation * scale, as laid out in the extension's spec.
+fn uv_transform(offset: [f32; 2], rotation: f32, scale: [f32; 2]) -> Mat3 {
+    let (sin, cos) = rotation.sin_cos();
+
+    let translation = Mat3::new(
+        1., 0., offset[0],
+        0., 1., offset[1],
+        0., 0., 1.,
+    );
+    let rotation = Mat3::new(
+        cos, sin, 0.,
+        -sin, cos, 0.,
+        0., 0., 1.,
+    );
+    let scale = Mat3::new(
+        scale[0], 0., 0.,
+        0., scale[1], 0.,
+        0., 0., 1.,
+    );
+
+    translation * rotation * scale
+}
+
+/// Primitives without a `NORMAL` attribute are flat shaded (glTF 2.0 §3.7.2.1), so every
+/// triangle gets its own corners with the face normal. Unwelds the attributes and indices in
+/// place, and returns the normals of the new vertices.
+fn flat_shade(positions: &mut Vec<[f32; 3]>, tex_coords: &mut Vec<[f32; 2]>, indices: &mut Vec<u32>) -> Vec<[f32; 3]> {
+    let mut flat_positions = Vec::with_capacity(indices.len());
+    let mut flat_tex_coords = Vec::with_capacity(indices.len());
+    let mut normals = Vec::with_capacity(indices.len());
+
+    for triangle in indices.chunks_exact(3) {
+        let points = [0, 1, 2].map(|corner| positions.get(triangle[corner] as usize).copied().unwrap_or_default());
+
+        let [pa, pb, pc] = points.map(Vec3::from);
+        let face_normal = (pb - pa).cross(&(pc - pa));
+        let face_normal = if face_normal.norm() > f32::EPSILON { face_normal.normalize() } else { vec3(0., 1., 0.) };
+
+        for (corner, point) in triangle.iter().zip(points) {
+            flat_positions.push(point);
+            flat_tex_coords.push(tex_coords.get(*corner as usize).copied().unwrap_or_default());
+            normals.push([face_normal.x, face_normal.y, face_normal.z]);
+        }
+    }
+
+    *positions = flat_positions;
+    *tex_coords = flat_tex_coords;
+    *indices = (0..positions.len() as u32).collect();
+    normals
+}
+
+fn read_node(node: &gltf::Node) -> GltfNode {
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+
+    GltfNode {
+        name: node.name().map_or_else(|| format!("Node {}", node.index()), str::to_string),
+        translation: Vec3::from(translation),
+        rotation: glm::quat(x, y, z, w),
+        scale: Vec3::from(scale),
+        mesh: node.mesh().map(|mesh| mesh.index()),
+        children: node.children().map(|child| read_node(&child)).collect(),
+    }
+}
+
+/// Same triangles wound the other way, for meshes under a mirroring node matrix.
+fn flip_winding(indices: &[u32]) -> Vec<u32> {
+    indices
+        .chunks_exact(3)
+        .flat_map(|triangle| [triangle[0], triangle[2], triangle[1]])
+        .collect()
+}
+
+impl ObjLoader {
+    /// Loads a `.gltf` or `.glb` file as a single model under its `canonical_key`, with every
+    /// mesh of the node tree placed where its node puts it.
+    ///
+    /// Each glTF mesh is also added as its own model under `gltf_mesh_key` for `spawn_gltf`.
+    pub fn load_gltf(
+        &mut self,
+        path: impl Into<String>,
+        texture_loader: &mut TextureLoader,
+        shader_loader: &mut ShaderLoader,
+    ) -> AssetResult<Handle<Model>> {
+        let path: String = path.into();
+
+        let asset = GltfAsset::read(Path::new(&path)).map_err(|err| AssetError::gltf(&path, err))?;
+        let model = self.build_gltf(&path, &asset, texture_loader, shader_loader);
+
+        Ok(self.add_model(canonical_key(&path), model))
+    }
+
+    /// Uploads a read glTF file, adding its meshes and node tree but returning the model of the
+    /// whole file for the caller to register. Images already loaded (or reserved) under their
+    /// names aren't uploaded again.
+    pub fn build_gltf(
+        &mut self,
+        path: &str,
+        asset: &GltfAsset,
+        texture_loader: &mut TextureLoader,
+        shader_loader: &mut ShaderLoader,
+    ) -> Model {
+        let key = canonical_key(path);
+        let name = Path::new(path).file_stem().map_or_else(|| path.to_string(), |stem| stem.to_string_lossy().into_owned());
+        let directory = Path::new(path).parent().and_then(|dir| dir.to_str()).unwrap_or_default().to_string();
+
+        let materials = asset.materials
+            .iter()
+            .map(|material| asset.upload_material(material, texture_loader, shader_loader))
+            .collect::<Vec<_>>();
+        let default_material = asset.upload_material(&GltfMaterial::default(), texture_loader, shader_loader);
+        let material = |index: Option<usize>| index
+            .and_then(|index| materials.get(index))
+            .unwrap_or(&default_material)
+            .clone();
+
+        let upload = |mesh: &GltfMesh, flip: bool| -> Vec<MeshRenderer> {
+            mesh.primitives
+                .iter()
+                .map(|primitive| {
+                    let indices = if flip { flip_winding(&primitive.indices) } else { primitive.indices.clone() };
+                    let gl_mesh = Mesh::new(primitive.vertices.clone(), indices);
+                    MeshRenderer::new(Arc::new(gl_mesh), material(primitive.material))
+                })
+                .collect()
+        };
+
+        let meshes = asset.meshes.iter().map(|mesh| upload(mesh, false)).collect::<Vec<_>>();
+
+        for (mesh_index, (mesh, mesh_renderers)) in asset.meshes.iter().zip(&meshes).enumerate() {
+            let mut model = Model::new(&mesh.name, directory.clone(), mesh_renderers.clone());
+            model.source = gltf_mesh_key(path, mesh_index);
+            self.add_model(gltf_mesh_key(&key, mesh_index), model);
+        }
+
+        // The whole file draws the same meshes. Mirroring nodes turn the triangles inside out,
+        // so those get a copy wound the other way.
+        let mut mirrored = HashMap::new();
+
+        let mut model = Model::new(name, directory, Vec::new());
+        model.source = path.to_string();
+
+        for (mesh_index, matrix) in asset.mesh_instances() {
+            let mesh_renderers = match glm::determinant(&matrix) < 0. {
+                true => mirrored.entry(mesh_index).or_insert_with(|| upload(&asset.meshes[mesh_index], true)),
+                false => &meshes[mesh_index],
+            };
+
+            for MeshRenderer(mesh, material, _) in mesh_renderers {
+                model.add_mesh(MeshRenderer::with_matrix(Arc::clone(mesh), material.clone(), matrix));
+            }
+        }
+
+        self.gltf_scenes.insert(key, asset.nodes.clone());
+        model
+    }
+
+    /// Spawns the node tree of a glTF file as entities under a new root entity, loading the file
+    /// if needed. Nodes with a mesh get the model under `gltf_mesh_key`.
+    pub fn spawn_gltf(
+        &mut self,
+        world: &mut World,
+        path: &str,
+        texture_loader: &mut TextureLoader,
+        shader_loader: &mut ShaderLoader,
+    ) -> AssetResult<Entity> {
+        let key = canonical_key(path);
+        if !self.gltf_scenes.contains_key(&key) {
+            self.load_gltf(path, texture_loader, shader_loader)?;
+        }
+
+        let root_name = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or(path);
+
+        let root = world.spawn().insert(Transform::with_name(root_name)).id();
+        self.spawn_gltf_nodes(world, path, root)?;
+
+        Ok(root)
+    }
+
+    /// Spawns the node tree of an already loaded glTF file under `root`.
+    pub fn spawn_gltf_nodes(&mut self, world: &mut World, path: &str, root: Entity) -> AssetResult<()> {
+        let nodes = self.gltf_scenes.get(&canonical_key(path)).cloned().unwrap_or_default();
+
+        for node in &nodes {
+            self.spawn_gltf_node(world, path, node, root)?;
+        }
+
+        Ok(())
+    }
+
+    fn spawn_gltf_node(&mut self, world: &mut World, path: &str, node: &GltfNode, parent: Entity) -> AssetResult<()> {
+        let mut transform = Transform::with_name(&node.name);
+        transform
+            .set_pos(node.translation)
+            .set_rotation(node.rotation)
+            .set_scale(node.scale);
+
+        let entity = world.spawn().insert(transform).id();
+
+        if let Some(mesh) = node.mesh {
+            let key = gltf_mesh_key(path, mesh);
+            let model = self.get(&key).ok_or_else(|| AssetError::gltf(path, GltfLoadError::MissingMesh(key)))?;
+            world.entity_mut(entity).insert(model);
+        }
+
+        hierarchy::add_child(world, parent, entity);
+
+        for child in &node.children {
+            self.spawn_gltf_node(world, path, child, entity)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &str) -> GltfAsset {
+        GltfAsset::read(Path::new(path)).unwrap_or_else(|err| panic!("Failed to read \"{path}\": {err}"))
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "Expected {expected}, got {actual}");
+    }
+
+    fn check_hierarchy(asset: &GltfAsset) {
+        let names = |nodes: &[GltfNode]| nodes.iter().map(|node| node.name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(names(&asset.nodes), ["Base", "Empty"]);
+
+        let base = &asset.nodes[0];
+        assert_eq!(base.mesh, Some(0));
+        assert_eq!(names(&base.children), ["Arm"]);
+
+        let arm = &base.children[0];
+        assert_eq!(arm.mesh, Some(1));
+        assert_eq!(arm.translation, vec3(0., 1.5, 0.));
+        assert_close(arm.scale.x, 0.8);
+        assert_eq!(names(&arm.children), ["Panel"]);
+        assert_eq!(asset.nodes[1].mesh, None);
+
+        let primitives = asset.meshes.iter().map(|mesh| mesh.primitives.len()).collect::<Vec<_>>();
+        assert_eq!(primitives, [1, 2, 1]);
+
+        // Each primitive keeps its own material
+        let arm_materials = asset.meshes[1].primitives.iter().map(|p| p.material).collect::<Vec<_>>();
+        assert_eq!(arm_materials, [Some(1), Some(2)]);
+
+        let cube = &asset.meshes[0].primitives[0];
+        assert_eq!(cube.vertices.len(), 24 * VERTEX_SIZE);
+        assert_eq!(cube.indices.len(), 36);
+
+        let checker = &asset.images[0];
+        assert_eq!((checker.width, checker.height, checker.format), (2, 2, glow::RGB));
+        assert_eq!(checker.pixels.len(), 2 * 2 * 3);
+        assert!(checker.name.contains("assets/gltf/hierarchy.gl") && checker.name.ends_with("#checker"), "{}", checker.name);
+    }
+
+    #[test]
+    fn reads_gltf_with_embedded_buffers() {
+        check_hierarchy(&read("assets/gltf/hierarchy.gltf"));
+    }
+
+    #[test]
+    fn reads_glb() {
+        check_hierarchy(&read("assets/gltf/hierarchy.glb"));
+    }
+
+    #[test]
+    fn reads_materials_and_extensions() {
+        let asset = read("assets/gltf/hierarchy.gltf");
+        let [checker, red, unlit, glass] = &asset.materials[..] else { panic!("Expected 4 materials") };
+
+        assert_eq!(checker.base_color_texture, Some(0));
+        assert_close(checker.roughness, 0.5);
+        assert_close(checker.metallic, 1.);
+        assert_eq!(checker.metallic_roughness_texture, None);
+
+        assert_eq!(red.base_color, [1., 0., 0., 1.]);
+        assert_close(red.metallic, 0.);
+        assert_eq!(red.base_color_texture, None);
+        assert!(!red.unlit && !red.transparent);
+
+        assert!(unlit.unlit);
+
+        assert!(glass.transparent);
+        assert_eq!(glass.emissive, [1., 1., 2.], "Emissive factor should be scaled by KHR_materials_emissive_strength");
+    }
+
+    #[test]
+    fn mesh_instances_follow_the_node_tree() {
+        let asset = read("assets/gltf/hierarchy.gltf");
+        let instances = asset.mesh_instances();
+
+        assert_eq!(instances.iter().map(|(mesh, _)| *mesh).collect::<Vec<_>>(), [0, 1, 2]);
+
+        // Panel is 1 unit above Arm in Arm's (scaled by 0.8) space, Arm is 1.5 above Base
+        let panel_origin = instances[2].1 * glm::vec4(0., 0., 0., 1.);
+        assert_close(panel_origin.x, 0.);
+        assert_close(panel_origin.y, 1.5 + 0.8);
+        assert_close(panel_origin.z, 0.);
+    }
+
+    #[test]
+    fn reads_external_buffers_and_fills_missing_attributes() {
+        let asset = read("assets/gltf/external.gltf");
+        let primitive = &asset.meshes[0].primitives[0];
+
+        // Not indexed
+        assert_eq!(primitive.indices, (0..6).collect::<Vec<_>>());
+
+        for vertex in primitive.vertices.chunks_exact(VERTEX_SIZE) {
+            assert_eq!(&vertex[3..6], &[0., 1., 0.], "Generated normals should point up for a flat quad");
+        }
+
+        // KHR_texture_transform, offset (0.5, 0) and scale 4
+        let first_uv = &primitive.vertices[6..8];
+        assert_close(first_uv[0], 0.5);
+        assert_close(first_uv[1], 4.);
+
+        let image = &asset.images[0];
+        assert_eq!((image.width, image.height, image.format), (2, 2, glow::RGBA));
+        assert!(image.name.ends_with("assets/gltf/external.gltf#image0"), "Images are keyed by their file: {}", image.name);
+    }
+
+    #[test]
+    fn missing_normals_are_flat() {
+        // Two triangles folded along the shared x axis edge, one flat and one upright
+        let mut positions = vec![[0., 0., 0.], [1., 0., 0.], [0., 0., -1.], [0., 1., 0.]];
+        let mut tex_coords = vec![[0., 0.], [1., 0.], [0., 1.], [1., 1.]];
+        let mut indices = vec![0, 1, 2, 0, 3, 1];
+        let normals = flat_shade(&mut positions, &mut tex_coords, &mut indices);
+
+        assert_eq!(positions.len(), 6, "Shared corners are split");
+        assert_eq!(indices, (0..6).collect::<Vec<_>>());
+        assert_eq!(positions[3..], [[0., 0., 0.], [0., 1., 0.], [1., 0., 0.]]);
+        assert_eq!(tex_coords[4], [1., 1.]);
+        assert_eq!(normals[..3], [[0., 1., 0.]; 3]);
+        assert_eq!(normals[3..], [[0., 0., -1.]; 3]);
+    }
+
+    #[test]
+    fn narrows_16_bit_and_float_images() {
+        let pixels = [u16::MAX, 0, 0x80ff, 0x1234].iter().flat_map(|channel| channel.to_ne_bytes()).collect::<Vec<_>>();
+        let image = read_image("wide".into(), gltf::image::Data { pixels, format: Format::R16G16, width: 2, height: 1 });
+        assert_eq!((image.format, image.pixels), (glow::RGBA, vec![255, 255, 255, 0, 128, 128, 128, 18]));
+
+        let pixels = [2., 0.5, -1.].iter().flat_map(|channel: &f32| channel.to_ne_bytes()).collect::<Vec<_>>();
+        let image = read_image("hdr".into(), gltf::image::Data { pixels, format: Format::R32G32B32FLOAT, width: 1, height: 1 });
+        assert_eq!((image.format, image.pixels), (glow::RGB, vec![255, 128, 0]));
+    }
+
+    #[test]
+    fn flipping_winding_keeps_triangles() {
+        assert_eq!(flip_winding(&[0, 1, 2, 2, 1, 3]), [0, 2, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn mesh_keys_round_trip() {
+        let key = gltf_mesh_key("assets/gltf/hierarchy.glb", 2);
+
+        assert_eq!(split_gltf_mesh_key(&key), Some(("assets/gltf/hierarchy.glb", 2)));
+        assert_eq!(split_gltf_mesh_key("assets/obj/cube.obj"), None);
+        assert_eq!(split_gltf_mesh_key("model#1.obj"), None);
+        assert!(is_gltf("Model.GLB"));
+    }
+}
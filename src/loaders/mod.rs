@@ -1,9 +1,23 @@
+mod asset_error;
+mod asset_server;
+mod background_loader;
+mod file_watcher;
 mod texture_loader;
 mod obj_loader;
+mod gltf_loader;
+mod mtl_loader;
 mod shader_loader;
+mod shader_preprocessor;
 
 pub use crate::loaders::{
+    asset_error::*,
+    asset_server::*,
+    background_loader::*,
+    file_watcher::*,
     texture_loader::*,
     obj_loader::*,
-    shader_loader::*
+    gltf_loader::*,
+    mtl_loader::*,
+    shader_loader::*,
+    shader_preprocessor::*
 };
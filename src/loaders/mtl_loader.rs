@@ -0,0 +1,271 @@
+//! Wavefront MTL libraries.
+//!
+//! The obj crate's parser rejects statements it doesn't know (`map_Ke`, `norm`, PBR extensions)
+//! and keeps texture options like `-bm 1.0` as part of the file name, so libraries are read here instead.
+
+use std::{fs, io, path::Path};
+
+use crate::notifications;
+
+/// Everything the lit and PBR shaders can use from a `newmtl` block.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MtlMaterial {
+    pub name: String,
+
+    /// `Ka`, `Kd`, `Ks` and `Ke`.
+    pub ambient: Option<[f32; 3]>,
+    pub diffuse: Option<[f32; 3]>,
+    pub specular: Option<[f32; 3]>,
+    pub emissive: Option<[f32; 3]>,
+
+    /// `Ns`.
+    pub shininess: Option<f32>,
+
+    /// `d`, or `1 - Tr`.
+    pub opacity: Option<f32>,
+
+    /// `illum`, 0 and 1 turn specular highlights off.
+    pub illumination: Option<i32>,
+
+    /// `Pr` and `Pm` from the PBR extension.
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+
+    /// `map_Kd`, `map_Ks`, `map_Ke`, `map_Bump`/`bump`/`norm` and `map_d`, relative to the library.
+    pub diffuse_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub emissive_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub opacity_map: Option<String>,
+
+    /// `map_Pr` and `map_Pm`.
+    pub roughness_map: Option<String>,
+    pub metallic_map: Option<String>,
+}
+
+impl MtlMaterial {
+    pub fn has_specular(&self) -> bool {
+        !matches!(self.illumination, Some(0 | 1))
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.opacity.is_some_and(|opacity| opacity < 1.) || self.opacity_map.is_some()
+    }
+
+    /// Uses any of the PBR extension's statements, so it's better shown with the PBR shader.
+    pub fn is_pbr(&self) -> bool {
+        self.roughness.is_some() || self.metallic.is_some() || self.roughness_map.is_some() || self.metallic_map.is_some()
+    }
+
+    /// Every texture map the material has.
+    pub fn maps(&self) -> impl Iterator<Item = &String> {
+        [
+            &self.diffuse_map, &self.specular_map, &self.emissive_map, &self.normal_map, &self.opacity_map,
+            &self.roughness_map, &self.metallic_map,
+        ]
+            .into_iter()
+            .flatten()
+    }
+}
+
+/// Number of values each texture option takes, the ones taking up to 3 (`-o`, `-s`, `-t`) are `None`.
+fn texture_option_arguments(option: &str) -> Option<Option<usize>> {
+    match option {
+        "-o" | "-s" | "-t" => Some(None),
+        "-mm" => Some(Some(2)),
+        "-bm" | "-blendu" | "-blendv" | "-boost" | "-texres" | "-clamp" | "-imfchan" | "-type" | "-cc" => Some(Some(1)),
+        _ => None,
+    }
+}
+
+/// File name of a `map_*` statement, skipping any options before it.
+/// Names can contain spaces, and exporters on Windows write backslashes.
+fn texture_path(arguments: &[&str]) -> Option<String> {
+    let mut index = 0;
+
+    while let Some(option) = arguments.get(index) {
+        index += 1 + match texture_option_arguments(option) {
+            Some(Some(count)) => count,
+            Some(None) => arguments[index + 1..]
+                .iter()
+                .take(3)
+                .take_while(|value| value.parse::<f32>().is_ok())
+                .count(),
+            None => break,
+        };
+    }
+
+    let rest = arguments.get(index..)?;
+    (!rest.is_empty()).then(|| rest.join(" ").replace('\\', "/"))
+}
+
+fn floats<const N: usize>(arguments: &[&str]) -> Option<[f32; N]> {
+    // A single value is shorthand for a gray color
+    let values = arguments.iter().map(|value| value.parse::<f32>().ok()).collect::<Option<Vec<_>>>()?;
+
+    match values.as_slice() {
+        [value] => Some([*value; N]),
+        values if values.len() >= N => values[..N].try_into().ok(),
+        _ => None,
+    }
+}
+
+/// Reads every material in an MTL library. Unknown or malformed statements are skipped with a warning.
+pub fn parse_mtl(source: &str) -> Vec<MtlMaterial> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+
+        let Some(statement) = tokens.next() else { continue };
+
+        if statement == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            materials.push(MtlMaterial { name, ..MtlMaterial::default() });
+            continue;
+        }
+
+        let Some(material) = materials.last_mut() else {
+            notifications::warn(format!("MTL line {}: \"{statement}\" before any newmtl", line_index + 1));
+            continue;
+        };
+
+        let arguments = tokens.collect::<Vec<_>>();
+        let color = || floats::<3>(&arguments);
+        let float = || floats::<1>(&arguments).map(|[value]| value);
+        let path = || texture_path(&arguments);
+
+        let parsed = match statement {
+            "Ka" => color().map(|c| material.ambient = Some(c)),
+            "Kd" => color().map(|c| material.diffuse = Some(c)),
+            "Ks" => color().map(|c| material.specular = Some(c)),
+            "Ke" => color().map(|c| material.emissive = Some(c)),
+            "Ns" => float().map(|ns| material.shininess = Some(ns)),
+            "d" => float().map(|d| material.opacity = Some(d)),
+            "Tr" => float().map(|tr| material.opacity = Some(1. - tr)),
+            "illum" => arguments.first().and_then(|i| i.parse().ok()).map(|i| material.illumination = Some(i)),
+            "map_Kd" => path().map(|p| material.diffuse_map = Some(p)),
+            "map_Ks" => path().map(|p| material.specular_map = Some(p)),
+            "map_Ke" => path().map(|p| material.emissive_map = Some(p)),
+            "map_Bump" | "map_bump" | "bump" | "norm" => path().map(|p| material.normal_map = Some(p)),
+            "map_d" => path().map(|p| material.opacity_map = Some(p)),
+            "Pr" => float().map(|pr| material.roughness = Some(pr)),
+            "Pm" => float().map(|pm| material.metallic = Some(pm)),
+            "map_Pr" => path().map(|p| material.roughness_map = Some(p)),
+            "map_Pm" => path().map(|p| material.metallic_map = Some(p)),
+
+            // Nothing to use these for
+            "Ni" | "Tf" | "map_Ka" | "map_Ns" | "map_refl" | "refl" | "disp" | "decal"
+                | "Ps" | "Pc" | "Pcr" | "aniso" | "anisor" | "map_Ps" => Some(()),
+
+            _ => {
+                notifications::warn(format!("MTL line {}: skipping unknown statement \"{statement}\"", line_index + 1));
+                Some(())
+            }
+        };
+
+        if parsed.is_none() {
+            notifications::warn(format!("MTL line {}: couldn't parse \"{}\"", line_index + 1, line.trim()));
+        }
+    }
+
+    materials
+}
+
+pub fn load_mtl(path: &Path) -> io::Result<Vec<MtlMaterial>> {
+    Ok(parse_mtl(&fs::read_to_string(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_colors_and_scalars() {
+        let materials = parse_mtl("
+            # Blender MTL File
+            newmtl Body
+            Ns 250.0
+            Ka 1.0 1.0 1.0
+            Kd 0.8 0.1 0.1
+            Ks 0.5
+            Ke 0 0 0.2
+            Ni 1.45
+            Tr 0.25
+            illum 2
+        ");
+        let [body] = &materials[..] else { panic!("Expected one material") };
+
+        assert_eq!(body.name, "Body");
+        assert_eq!(body.shininess, Some(250.));
+        assert_eq!(body.diffuse, Some([0.8, 0.1, 0.1]));
+        assert_eq!(body.specular, Some([0.5, 0.5, 0.5]), "One value is a gray color");
+        assert_eq!(body.emissive, Some([0., 0., 0.2]));
+        assert_eq!(body.opacity, Some(0.75));
+        assert!(body.is_transparent() && body.has_specular());
+    }
+
+    #[test]
+    fn reads_maps_with_options() {
+        let materials = parse_mtl("
+            newmtl Wall
+            map_Kd -o 0.5 0.5 -s 2 2 1 textures\\wall diffuse.png
+            map_Bump -bm 1.5 wall_normal.png
+            norm wall_normal_2.png
+            map_d -imfchan m wall_alpha.png
+            map_Ke glow.jpg
+            Pr 0.5
+            illum 1
+        ");
+        let wall = &materials[0];
+
+        assert_eq!(wall.diffuse_map.as_deref(), Some("textures/wall diffuse.png"));
+        assert_eq!(wall.normal_map.as_deref(), Some("wall_normal_2.png"), "Later statements win");
+        assert_eq!(wall.opacity_map.as_deref(), Some("wall_alpha.png"));
+        assert_eq!(wall.emissive_map.as_deref(), Some("glow.jpg"));
+        assert!(wall.is_transparent());
+        assert!(!wall.has_specular());
+    }
+
+    #[test]
+    fn reads_pbr_extension() {
+        let materials = parse_mtl("
+            newmtl Phong
+            Kd 1 1 1
+            newmtl Metal
+            Kd 0.9 0.6 0.2
+            Pr 0.3
+            Pm 1
+            map_Pr -bm 1 metal_roughness.png
+            map_Pm metal_metallic.png
+            Ps 0.1
+        ");
+        let [phong, metal] = &materials[..] else { panic!("Expected two materials") };
+
+        assert!(!phong.is_pbr());
+        assert!(metal.is_pbr());
+        assert_eq!(metal.roughness, Some(0.3));
+        assert_eq!(metal.metallic, Some(1.));
+        assert_eq!(metal.roughness_map.as_deref(), Some("metal_roughness.png"));
+        assert_eq!(metal.metallic_map.as_deref(), Some("metal_metallic.png"));
+        assert_eq!(metal.maps().count(), 2);
+    }
+
+    #[test]
+    fn skips_unknown_and_malformed_statements() {
+        let materials = parse_mtl("
+            Kd 1 1 1
+            newmtl First
+            Kd one two three
+            frobnicate 1 2 3
+            d 0.5
+            newmtl Second Material
+        ");
+
+        let names = materials.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["First", "Second Material"]);
+        assert_eq!(materials[0].diffuse, None);
+        assert_eq!(materials[0].opacity, Some(0.5));
+    }
+}
@@ -4,75 +4,151 @@ extern crate itertools;
 extern crate obj;
 extern crate byteorder;
 
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
 
-use obj::Obj;
+use nalgebra_glm::{Vec2, Vec3, vec2, vec3};
+use obj::{Group, IndexTuple, Obj, ObjData, ObjMaterial, SimplePolygon};
 
 use crate::{
-    texture::{Texture2D, TextureType},
-    model::{Model, ObjLoadError}, 
+    texture::{Texture, Texture2D, TextureType},
+    model::Model, 
     mesh::{Mesh, MeshRenderer},
-    loaders::*, renderer::{Material, MaterialType}, map
+    loaders::*, renderer::Material, notifications::{self, Severity}, map
 };
 
 use self::utils::Handle;
 
 pub mod utils {
-    use std::{ops::{Deref, DerefMut}, rc::Rc, cell::RefCell};
+    use std::{fmt, hash::{Hash, Hasher}, ops::DerefMut, sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard}};
+
+    use bevy_ecs::component::{Component, TableStorage};
 
     use crate::egui_drawable::EguiDrawable;
 
-    pub struct Handle<T>(Rc<RefCell<T>>);
+    /// Shared, mutable reference to an asset.
+    /// Thread safe so it can be stored as a bevy_ecs component.
+    ///
+    /// Handles compare and hash by identity, two handles are equal if they point to the same asset.
+    pub struct Handle<T>(Arc<RwLock<T>>);
 
     impl<T> Handle<T> {
         pub fn new(t: T) -> Self {
-            Handle(Rc::new(RefCell::new(t)))
+            Handle(Arc::new(RwLock::new(t)))
+        }
+
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap()
         }
-    }
 
-    impl<T> Deref for Handle<T> {
-        type Target=RefCell<T>;
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap()
+        }
+
+        /// Returns None instead of blocking if the handle is already mutably borrowed.
+        pub fn try_borrow(&self) -> Option<RwLockReadGuard<'_, T>> {
+            self.0.try_read().ok()
+        }
 
-        fn deref(&self) -> &Self::Target {
-            self.0.deref()
+        /// Number of handles to this asset, including this one.
+        pub fn strong_count(&self) -> usize {
+            Arc::strong_count(&self.0)
         }
     }
 
-    impl<T: Clone> Clone for Handle<T> {
+    impl<T> Clone for Handle<T> {
         fn clone(&self) -> Self {
-            Handle(Rc::clone(&self.0))
+            Handle(Arc::clone(&self.0))
         }
     }
 
+    impl<T> PartialEq for Handle<T> {
+        fn eq(&self, other: &Self) -> bool {
+            Arc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    impl<T> Eq for Handle<T> {}
+
+    impl<T> Hash for Handle<T> {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            Arc::as_ptr(&self.0).hash(state);
+        }
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for Handle<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.0.try_read() {
+                Ok(asset) => f.debug_tuple("Handle").field(&*asset).finish(),
+                Err(_) => f.write_str("Handle(<locked>)"),
+            }
+        }
+    }
+
+    impl<T: Send + Sync + 'static> Component for Handle<T> {
+        type Storage = TableStorage;
+    }
+
     impl<T: EguiDrawable> EguiDrawable for Handle<T> {
         fn on_egui(&mut self, ui: &mut egui::Ui, index: usize) -> bool {
-            self.0.deref().borrow_mut().deref_mut().on_egui(ui, index)
+            self.borrow_mut().deref_mut().on_egui(ui, index)
         }
     }
 }
 
 
-pub type ModelLoadResult<T> = std::result::Result<T, ObjLoadError>;
-
 pub const DEFAULT_CUBE_NAME: &str = "default_cube";
 pub const DEFAULT_PLANE_NAME: &str = "default_plane";
 
+/// Extensions `load_model` accepts, for file dialogs.
+pub const MODEL_EXTENSIONS: [&str; 3] = ["obj", "gltf", "glb"];
+
 
 pub struct ObjLoader {
+    /// Loaded files by `canonical_key`, built in models by name, and single meshes of glTF files
+    /// by `gltf_mesh_key`.
     models: HashMap<String, Handle<Model>>,
-    model_meshes: HashMap<String, Vec<Rc<Mesh>>>
+
+    /// Root nodes of every loaded glTF file, keyed like `models`.
+    pub(super) gltf_scenes: HashMap<String, Vec<GltfNode>>,
+
+    /// Normals made up for OBJ faces without any, for the files loaded (or reloaded) from now on.
+    pub normal_generation: NormalGeneration,
+
+    /// Materials of each model as it was built, keyed like `models`. Editors can change the
+    /// loaded models, saving compares against these to find what was changed.
+    loaded_materials: HashMap<String, Vec<Material>>,
+}
+
+/// Key of a model path in `ObjLoader::models`.
+fn model_key(path: &str) -> String {
+    match split_gltf_mesh_key(path) {
+        Some((gltf_path, mesh_index)) => gltf_mesh_key(&canonical_key(gltf_path), mesh_index),
+        None => canonical_key(path),
+    }
 }
 
 impl ObjLoader {
     fn load_default_cube(&mut self, shader_loader: &mut ShaderLoader, texture_loader: &mut TextureLoader) {
         let cube_path = "assets/obj/cube.obj";
 
-        let cube_model = self.load_obj(DEFAULT_CUBE_NAME, cube_path, texture_loader, shader_loader).unwrap();
+        let cube_model = self.load_obj(cube_path, texture_loader, shader_loader)
+            .unwrap_or_else(|err| panic!("The default cube is needed as a fallback model: {err}"));
         let mat = Material::default_lit(shader_loader, texture_loader);
 
-        for mr in &mut cube_model.borrow_mut().mesh_renderers {
+        // Only available under its name, so loading the file itself still gives its own materials
+        self.models.remove(&canonical_key(cube_path));
+        self.loaded_materials.remove(&canonical_key(cube_path));
+        self.models.insert(DEFAULT_CUBE_NAME.into(), Handle::clone(&cube_model));
+
+        let mut cube_model = cube_model.borrow_mut();
+        cube_model.name = DEFAULT_CUBE_NAME.into();
+        cube_model.source = DEFAULT_CUBE_NAME.into();
+
+        for mr in &mut cube_model.mesh_renderers {
             mr.set_material(mat.clone());
         }
+
+        self.remember_materials(DEFAULT_CUBE_NAME, &cube_model);
     }
 
     fn load_default_plane(&mut self, shader_loader: &mut ShaderLoader, texture_loader: &mut TextureLoader) {
@@ -118,24 +194,30 @@ impl ObjLoader {
         
 
         let mat = Material::default_billboard(shader_loader, texture_loader);
-        let t = self.add_mesh(DEFAULT_PLANE_NAME.into(), Mesh::new(vertices, indices));
-        self.add_model(
+        let t = Arc::new(Mesh::new(vertices, indices));
+
+        let mut plane = Model::new(
             DEFAULT_PLANE_NAME, 
-            Model::new(
-                DEFAULT_PLANE_NAME, 
-                "",
-                vec![
-                    MeshRenderer::new(
-                        t, 
-                        mat
-                    )
-                ]
-            )
+            "",
+            vec![
+                MeshRenderer::new(
+                    t, 
+                    mat
+                )
+            ]
         );
+        plane.source = DEFAULT_PLANE_NAME.into();
+
+        self.add_model(DEFAULT_PLANE_NAME, plane);
     }
 
     pub fn new(shader_loader: &mut ShaderLoader, texture_loader: &mut TextureLoader) -> Self {
-        let mut loader = ObjLoader {models: map! {}, model_meshes: map! {}};
+        let mut loader = ObjLoader {
+            models: map! {},
+            gltf_scenes: map! {},
+            normal_generation: NormalGeneration::default(),
+            loaded_materials: map! {},
+        };
 
         loader.load_default_cube(shader_loader, texture_loader);
         loader.load_default_plane(shader_loader, texture_loader);
@@ -143,80 +225,238 @@ impl ObjLoader {
         loader
     }
 
-    pub fn add_mesh(&mut self, model_key: String, mesh: Mesh) -> Rc<Mesh> {
-        self
-            .model_meshes
-            .entry(model_key.clone())
-            .or_insert(vec![])
-            .push(Rc::new(mesh));
+    pub(super) fn add_model(&mut self, model_key: impl Into<String>, model: Model) -> Handle<Model> {
+        let model_key = model_key.into();
+        self.remember_materials(model_key.clone(), &model);
+
+        let model = Handle::new(model);
+        self.models.insert(model_key, Handle::clone(&model));
 
-        let r = self.model_meshes.get(&model_key).unwrap().last().unwrap();
-        Rc::clone(r)
+        model
     }
 
-    fn add_model(&mut self, model_key: impl Into<String>, model: Model) -> Handle<Model> {
-        let model_key = model_key.into();
-        self.models.insert(model_key.clone(), Handle::new(model));
-        Handle::clone(self.models.get(&model_key).unwrap())
+    /// Takes `model`'s materials as the ones the model under `model_key` was built with.
+    pub(super) fn remember_materials(&mut self, model_key: impl Into<String>, model: &Model) {
+        let materials = model.mesh_renderers.iter().map(|mr| mr.1.clone()).collect();
+        self.loaded_materials.insert(model_key.into(), materials);
     }
 
+    /// Removes a model from the loader without touching its meshes, for models that never loaded.
+    pub(super) fn forget_model(&mut self, model_key: &str) {
+        self.models.remove(model_key);
+        self.loaded_materials.remove(model_key);
+    }
+
+    /// Loads the model at `path`, or returns the loaded one if the same file was loaded before
+    /// (even through a different relative path).
+    ///
+    /// `path` can also be a `gltf_mesh_key` for a single mesh of a glTF file.
     pub fn load_model(
         &mut self,
-        name: impl Into<String>,
         path: impl Into<String>,
         texture_loader: &mut TextureLoader,
         shader_loader: &mut ShaderLoader
-    ) -> ModelLoadResult<Handle<Model>> {
-        let path_string: String = path.into();
-        let name: String = name.into();
+    ) -> AssetResult<Handle<Model>> {
+        let path: String = path.into();
+        let key = model_key(&path);
 
-        if !self.models.contains_key(&path_string) {
-            self.load_obj(name.clone(), &path_string, texture_loader, shader_loader)?;
+        if let Some(model) = self.models.get(&key) {
+            return Ok(Handle::clone(model));
         }
 
-        let t = self.models.get(&name).unwrap();
-        Ok(Handle::clone(t)) // Should always succeed
-    }
+        // A single mesh of a glTF file, added along with the rest of the file
+        if let Some((gltf_path, _)) = split_gltf_mesh_key(&path) {
+            if !self.gltf_scenes.contains_key(&canonical_key(gltf_path)) {
+                self.load_gltf(gltf_path, texture_loader, shader_loader)?;
+            }
 
-    pub fn clone_handle(&mut self, model_key: &str) -> Handle<Model> {
-        Handle::clone(
-            self
-            .models
-            .get(model_key)
-            .unwrap()
-        )
-    }
-    
-    pub fn clone(&mut self, old_name: &str, new_name: &str) -> Handle<Model> {
-        assert!(new_name != old_name, "New and old model names must be different!");
-
-        // Check if old model exists
-        //  if so: 
-        //      clone it
-        //  else:
-        //      clone the default cube
-        //
-        //   add the clone under the new name
-        //   return the clone's reference
-
-        let clone_name = if self.models.contains_key(old_name) {
-            old_name
+            let model = self.models.get(&key).cloned();
+            return model.ok_or_else(|| AssetError::gltf(gltf_path, GltfLoadError::MissingMesh(path.clone())));
+        }
+
+        let model = if is_gltf(&path) {
+            self.load_gltf(&path, texture_loader, shader_loader)?
         } else {
-            DEFAULT_CUBE_NAME
+            self.load_obj(&path, texture_loader, shader_loader)?
         };
 
-        let old_rc = self.models.get(clone_name).unwrap();
+        Ok(model)
+    }
+
+    /// Loaded model (or built in one like `DEFAULT_CUBE_NAME`) for a path, if any.
+    pub fn get(&self, path: &str) -> Option<Handle<Model>> {
+        self.models.get(&model_key(path)).cloned()
+    }
 
-        let mut model_clone: Model = (*old_rc).borrow().clone();
+    pub fn clone_handle(&mut self, path: &str) -> Handle<Model> {
+        self.get(path).unwrap_or_else(|| panic!("No model loaded from \"{path}\""))
+    }
+
+    /// Whether `model` is one of the loaded models themselves, rather than a copy of one.
+    pub fn is_loaded(&self, model: &Handle<Model>) -> bool {
+        self.models.values().any(|loaded| loaded == model)
+    }
+
+    /// Materials of the model for a path as it was loaded (or reloaded), whatever was edited since.
+    pub fn loaded_materials(&self, path: &str) -> Option<&[Material]> {
+        self.loaded_materials.get(&model_key(path)).map(Vec::as_slice)
+    }
+    
+    /// Copy of a loaded model (or of the default cube if there's none) with its own materials.
+    /// The meshes stay shared, and the copy isn't an asset itself, it's freed along with its
+    /// last handle.
+    pub fn clone(&mut self, path: &str, new_name: &str) -> Handle<Model> {
+        let original = self.get(path).unwrap_or_else(|| self.clone_handle(DEFAULT_CUBE_NAME));
+
+        let mut model_clone: Model = original.borrow().clone();
         model_clone.name = new_name.into();
         
-        self.add_model(new_name, model_clone)
+        Handle::new(model_clone)
     }
 
     pub fn models(&mut self) -> &mut HashMap<String, Handle<Model>> {
         &mut self.models
     }
 
+    /// Handles to the model outside of the loader, plus copies made with `clone`.
+    /// Meshes shared between loaded models, like the ones of a glTF file, aren't copies.
+    fn references(&self, model: &Handle<Model>) -> usize {
+        let Some(borrowed) = model.try_borrow() else { return usize::MAX };
+
+        let copies = borrowed.mesh_renderers
+            .iter()
+            .map(|mr| Arc::strong_count(&mr.0).saturating_sub(self.loaded_uses(&mr.0)))
+            .max()
+            .unwrap_or(0);
+
+        model.strong_count() - 1 + copies
+    }
+
+    /// How many mesh renderers of the loaded models draw `mesh`.
+    fn loaded_uses(&self, mesh: &Arc<Mesh>) -> usize {
+        self.models
+            .values()
+            .filter_map(|model| model.try_borrow())
+            .map(|model| model.mesh_renderers.iter().filter(|mr| Arc::ptr_eq(&mr.0, mesh)).count())
+            .sum()
+    }
+
+    /// Every model and how many entities and copies use it.
+    pub fn asset_infos(&self) -> Vec<AssetInfo> {
+        self.models
+            .iter()
+            .map(|(key, model)| AssetInfo {
+                kind: AssetKind::Model,
+                key: key.clone(),
+                references: self.references(model),
+                pinned: key == DEFAULT_CUBE_NAME || key == DEFAULT_PLANE_NAME,
+                files: Self::is_file_model(key, model).then(|| PathBuf::from(&model.borrow().source)).into_iter().collect(),
+            })
+            .collect()
+    }
+
+    /// Whether the model is a whole file, rather than a built in model or a single glTF mesh.
+    fn is_file_model(key: &str, model: &Handle<Model>) -> bool {
+        let pinned = key == DEFAULT_CUBE_NAME || key == DEFAULT_PLANE_NAME;
+        !pinned && model.try_borrow().is_some_and(|model| canonical_key(&model.source) == key)
+    }
+
+    /// Keys of the models read from the file at `path`. OBJ files are also read again when an
+    /// MTL library next to them changes.
+    pub fn models_using(&self, path: &Path) -> Vec<String> {
+        let key = canonical_key(path);
+        let directory = path.parent().map(canonical_key);
+        let is_mtl = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("mtl"));
+
+        self.models
+            .iter()
+            .filter(|(model_key, model)| Self::is_file_model(model_key, model))
+            .filter(|(model_key, model)| {
+                let model = model.borrow();
+                **model_key == key || (is_mtl && !is_gltf(&model.source) && Some(canonical_key(&model.directory)) == directory)
+            })
+            .map(|(model_key, _)| model_key.clone())
+            .collect()
+    }
+
+    /// Imports the model's file again and swaps the new meshes and materials in for everything
+    /// using it, including the single mesh models of glTF files. The old model stays if the file
+    /// doesn't load.
+    pub fn reload(&mut self, key: &str, texture_loader: &mut TextureLoader, shader_loader: &mut ShaderLoader) -> AssetResult<()> {
+        let Some(model) = self.models.get(key).cloned() else { return Ok(()) };
+        let path = model.borrow().source.clone();
+
+        let new_model = if is_gltf(&path) {
+            let asset = GltfAsset::read(Path::new(&path)).map_err(|err| AssetError::gltf(&path, err))?;
+
+            let old_meshes: Vec<(String, Handle<Model>)> = self.models
+                .iter()
+                .filter(|(mesh_key, _)| split_gltf_mesh_key(mesh_key).is_some_and(|(gltf_key, _)| gltf_key == key))
+                .map(|(mesh_key, mesh_model)| (mesh_key.clone(), Handle::clone(mesh_model)))
+                .collect();
+
+            let new_model = self.build_gltf(&path, &asset, texture_loader, shader_loader);
+
+            // Entities hold the old single mesh models, those get the new contents instead
+            for (mesh_key, old_mesh) in old_meshes {
+                let Some(new_mesh) = self.models.insert(mesh_key, Handle::clone(&old_mesh)) else { continue };
+                if new_mesh != old_mesh {
+                    Self::replace(&old_mesh, new_mesh.borrow().clone());
+                }
+            }
+
+            new_model
+        } else {
+            Self::build_obj(DecodedObj::read(&path, self.normal_generation)?, texture_loader, shader_loader)
+        };
+
+        self.remember_materials(key, &new_model);
+        Self::replace(&model, new_model);
+        Ok(())
+    }
+
+    /// Swaps a model's contents, deleting the old meshes if no copy of the model still uses them.
+    fn replace(model: &Handle<Model>, new_model: Model) {
+        let old_model = std::mem::replace(&mut *model.borrow_mut(), new_model);
+        Self::delete_meshes(&old_model);
+    }
+
+    /// Deletes the meshes only `model` draws, the ones other models share stay until the last goes.
+    fn delete_meshes(model: &Model) {
+        let mut deleted: Vec<&Arc<Mesh>> = Vec::new();
+
+        for MeshRenderer(mesh, ..) in &model.mesh_renderers {
+            if deleted.iter().any(|other| Arc::ptr_eq(other, mesh)) {
+                continue;
+            }
+
+            let uses = model.mesh_renderers.iter().filter(|mr| Arc::ptr_eq(&mr.0, mesh)).count();
+            if Arc::strong_count(mesh) == uses {
+                mesh.delete();
+                deleted.push(mesh);
+            }
+        }
+    }
+
+    /// Deletes an unused model's meshes from the GPU, see `AssetServer::unload`.
+    /// Its textures and shaders stay loaded, and show up as unused if nothing else holds them.
+    pub fn unload(&mut self, key: &str) -> bool {
+        let pinned = key == DEFAULT_CUBE_NAME || key == DEFAULT_PLANE_NAME;
+        let unused = self.models.get(key).is_some_and(|model| self.references(model) == 0);
+        if !unused || pinned {
+            return false;
+        }
+
+        let Some(model) = self.models.remove(key) else { return false };
+        self.loaded_materials.remove(key);
+        notifications::notify(Severity::Info, format!("Unloading model \"{key}\""));
+
+        Self::delete_meshes(&model.borrow());
+
+        self.gltf_scenes.remove(key);
+        true
+    }
+
 
     // default_model_getters![
     //     (DEFAULT_CUBE_NAME, default_cube_model),
@@ -224,114 +464,543 @@ impl ObjLoader {
     // ];
 }
 
+/// An OBJ file and its MTL libraries read into memory, nothing is uploaded to GL yet.
+pub struct DecodedObj {
+    pub path: String,
+    pub dir: PathBuf,
+    pub meshes: Vec<(ObjMeshData, MtlMaterial)>,
+}
+
+impl DecodedObj {
+    /// Parses the file and triangulates its groups, this doesn't need GL so it can run on any thread.
+    pub fn read(path: impl Into<String>, normals: NormalGeneration) -> AssetResult<Self> {
+        let path: String = path.into();
+        let objects = Obj::load(&path).map_err(|err| AssetError::obj(&path, err))?;
+
+        let dir = objects.path;
+        let materials = ObjLoader::load_material_libs(&objects.data, &dir);
+
+        let meshes = obj_mesh_data(&objects.data, normals)
+            .into_iter()
+            .map(|mesh_data| {
+                let material_name = mesh_data.material.as_ref().map(|material| match material {
+                    ObjMaterial::Ref(name) => name.as_str(),
+                    ObjMaterial::Mtl(material) => material.name.as_str(),
+                });
+
+                let mtl = match material_name {
+                    Some(material_name) => materials.get(material_name).cloned().unwrap_or_else(|| {
+                        notifications::warn(format!("Material \"{material_name}\" used by \"{path}\" isn't in its MTL libraries, using a plain one"));
+                        MtlMaterial::default()
+                    }),
+                    None => MtlMaterial::default(),
+                };
+
+                (mesh_data, mtl)
+            })
+            .collect();
+
+        Ok(DecodedObj { path, dir, meshes })
+    }
+
+    /// Every image the materials use, without duplicates.
+    pub fn texture_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.meshes
+            .iter()
+            .flat_map(|(_, mtl)| mtl.maps())
+            .map(|map| self.dir.join(map))
+            .collect();
+
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
 impl ObjLoader {
+    /// Loads an OBJ file with its MTL libraries, registered under the file's `canonical_key`.
     pub fn load_obj(
         &mut self,
-        name: impl Into<String>,
         path: impl Into<String>,
         texture_loader: &mut TextureLoader,
         shader_loader: &mut ShaderLoader,
-    ) -> Result<Handle<Model>, ObjLoadError> {
-        
-        let mut objects = Obj::load(path.into())?;
+    ) -> AssetResult<Handle<Model>> {
+        let obj = DecodedObj::read(path, self.normal_generation)?;
+        let key = canonical_key(&obj.path);
+        let model = Self::build_obj(obj, texture_loader, shader_loader);
 
-        // Must run this for materials to properly load
-        objects.load_mtls().unwrap();
+        Ok(self.add_model(key, model))
+    }
 
-        let dir = objects.path;
-        let name: String = name.into();
+    /// Uploads the meshes of a read OBJ file. Textures that aren't loaded (or reserved) yet are
+    /// loaded on the spot.
+    pub fn build_obj(obj: DecodedObj, texture_loader: &mut TextureLoader, shader_loader: &mut ShaderLoader) -> Model {
+        let DecodedObj { path, dir, meshes } = obj;
+        let mut model = { 
+            let name = Path::new(&path).file_stem().map_or_else(|| path.clone(), |stem| stem.to_string_lossy().into_owned());
+            Model::new(name, dir.to_string_lossy(), Vec::new())
+        };
+        model.source = path;
 
-        let all_pos = objects.data.position;
-        let all_norm = objects.data.normal;
-        let all_tex = objects.data.texture;
+        for (mesh_data, mtl) in meshes {
+            let mat = Self::mtl_material(&mtl, &dir, texture_loader, shader_loader);
 
-        let mut model = { 
-            let dir = String::from(dir.to_str().unwrap());
-            Model::new(dir.clone(), dir, Vec::new())
+            let mesh = Arc::new(Mesh::new(mesh_data.vertices, mesh_data.indices));
+            model.add_mesh(MeshRenderer::new(mesh, mat));
+        }
+
+        model
+    }
+
+    /// Materials of every `mtllib`, by name. Libraries that fail to load are skipped.
+    fn load_material_libs(data: &ObjData, dir: &Path) -> HashMap<String, MtlMaterial> {
+        data.material_libs
+            .iter()
+            .filter_map(|lib| {
+                let lib_path = dir.join(&lib.filename);
+                load_mtl(&lib_path)
+                    .map_err(|err| notifications::warn(AssetError::io(&lib_path, err)))
+                    .ok()
+            })
+            .flatten()
+            .map(|material| (material.name.clone(), material))
+            .collect()
+    }
+
+    fn mtl_material(mtl: &MtlMaterial, dir: &Path, texture_loader: &mut TextureLoader, shader_loader: &mut ShaderLoader) -> Material {
+        let white = texture_loader.white_texture();
+        let mut load = |map: &Option<String>| map.as_ref().map(|map| texture_loader.load_texture_or_default(&dir.join(map)));
+        let with_white = |texture: Option<Handle<Texture>>| texture.or_else(|| Some(Handle::clone(&white)));
+
+        // Colors and factors multiply the textures, white ones let them show as they are.
+        // PBR materials have no specular map, their highlights come from roughness and metallic.
+        let is_pbr = mtl.is_pbr();
+        let specular = match is_pbr {
+            true => None,
+            false => with_white(load(&mtl.specular_map)),
+        };
+
+        let textures = [
+            (TextureType::Diffuse, with_white(load(&mtl.diffuse_map))),
+            (TextureType::Specular, specular),
+            (TextureType::Emissive, load(&mtl.emissive_map)),
+            (TextureType::Normal, load(&mtl.normal_map)),
+            (TextureType::Opacity, load(&mtl.opacity_map)),
+            (TextureType::Roughness, load(&mtl.roughness_map)),
+            (TextureType::Metallic, load(&mtl.metallic_map)),
+        ]
+            .into_iter()
+            .filter_map(|(tex_type, texture)| Some(Texture2D::new(&texture?, tex_type, 1)))
+            .collect();
+
+        let mut material = match is_pbr {
+            true => Material::pbr(shader_loader, textures),
+            false => Material::lit(shader_loader, textures),
+        };
+        material.transparent = mtl.is_transparent();
+        if !mtl.name.is_empty() {
+            material.name = mtl.name.clone();
+        }
+
+        // Unset values keep the material type's defaults
+        let specular_color = match mtl.has_specular() {
+            true => mtl.specular,
+            false => Some([0., 0., 0.]),
+        };
+        let emissive_color = match (mtl.emissive, &mtl.emissive_map) {
+            (None, Some(_)) => Some([1., 1., 1.]),
+            (emissive, _) => emissive,
+        };
+        let colors = match is_pbr {
+            true => vec![("base_color", mtl.diffuse)],
+            false => vec![("ambient_color", mtl.ambient), ("diffuse_color", mtl.diffuse), ("specular_color", specular_color)],
+        };
+
+        let parameters = &mut material.parameters;
+        for (name, color) in colors.into_iter().chain([("emissive_color", emissive_color)]) {
+            if let Some(color) = color {
+                parameters.set(name, color);
+            }
+        }
+
+        // Maps are used as they are unless there's a factor for them
+        let factor = |value: Option<f32>, map: &Option<String>| value.or(map.as_ref().map(|_| 1.));
+        let scalars = match is_pbr {
+            true => vec![
+                ("metallic", factor(mtl.metallic, &mtl.metallic_map)),
+                ("roughness", factor(mtl.roughness, &mtl.roughness_map)),
+            ],
+            false => vec![("shininess", mtl.shininess)],
         };
-        let num_objects = objects.data.objects.len() as f32;
 
-        for (object_index, object) in objects.data.objects.iter().enumerate()  {
-            println!("Loading object {i}/{num_objects}", i = object_index);
+        for (name, value) in scalars.into_iter().chain([("opacity", mtl.opacity)]) {
+            if let Some(value) = value {
+                parameters.set(name, value);
+            }
+        }
 
-            let obj_group = &object.groups[0];
+        material
+    }
+}
 
-            let mut pnt: Vec<f32> = vec![];
-            let mut inds: Vec<u32> = vec![];
-            let mut index = 0u32;
+/// 3 floats for position, 3 for the normal, 2 for texture coordinates. Same layout as `Mesh`.
+const VERTEX_SIZE: usize = 8;
 
-            let mut textures: Vec<Texture2D> = vec![];
-            let mut num_diffuse = 1;
-            let mut num_specular = 1;
-            let _num_emissive = 1;
+/// How normals are made up for faces that don't reference any.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NormalGeneration {
+    /// Every face gets its own normal, so corners shared between faces are split.
+    #[default]
+    Flat,
 
-            for (_, poly) in obj_group.polys.iter().enumerate() {
-                for vertex in &poly.0 {
-                    let pos_index = vertex.0;
+    /// Faces sharing a position average their (area weighted) normals.
+    Smooth,
+}
 
-                    pnt.extend(all_pos[pos_index]);
+/// Triangulated and indexed geometry of one OBJ group, in the `Mesh` vertex layout.
+pub struct ObjMeshData {
+    pub name: String,
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub material: Option<ObjMaterial>,
+}
 
-                    if let Some(norm_index) = vertex.2 {
-                        pnt.extend(all_norm[norm_index]);
-                    }
+/// The normal a corner ends up with, as part of its deduplication key.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum NormalKey {
+    Index(usize),
+    Flat([u32; 3]),
+    Smooth,
+}
 
-                    if let Some(tex_index) = vertex.1 {
-                        pnt.extend(all_tex[tex_index]);
-                    }
-                }
+/// One `ObjMeshData` per group with faces. The obj crate starts a new group on every `usemtl`,
+/// so this is also one per material.
+pub fn obj_mesh_data(data: &ObjData, normals: NormalGeneration) -> Vec<ObjMeshData> {
+    data.objects
+        .iter()
+        .flat_map(|object| object.groups.iter().map(move |group| (object, group)))
+        .filter(|(_, group)| !group.polys.is_empty())
+        .map(|(object, group)| group_mesh_data(data, &object.name, group, normals))
+        .collect()
+}
 
-                inds.extend(vec![index, (index + 1), (index + 2)]);
-                index += 3;
-
-                if let Some(obj_mat) = &obj_group.material {
-                    match obj_mat {
-                        obj::ObjMaterial::Ref(_) => todo!(),
-                        obj::ObjMaterial::Mtl(material) => {
-                            if let Some(diffuse_map) = &material.map_kd {
-                                let tex_handle = texture_loader
-                                    .load_texture(&dir.join(diffuse_map));
-
-                                let texture =
-                                    Texture2D::from_native_handle(
-                                        tex_handle, 
-                                        TextureType::Diffuse,
-                                        num_diffuse
-                                    );
-
-                                if !textures.contains(&texture) {
-                                    textures.push(texture);
-                                    num_diffuse += 1;
-                                }
-                            }
-
-                            if let Some(spec_map) = &material.map_ks {
-                                let tex_handle = texture_loader
-                                    .load_texture(&dir.join(spec_map));
-
-                                let texture =
-                                    Texture2D::from_native_handle(
-                                        tex_handle, 
-                                        TextureType::Specular,
-                                        num_specular
-                                    );
-
-                                if !textures.contains(&texture) {
-                                    textures.push(texture);
-                                    num_specular += 1;
-                                }
-                            }
-                        }
-                    }
-                }
+fn group_mesh_data(data: &ObjData, object_name: &str, group: &Group, normal_generation: NormalGeneration) -> ObjMeshData {
+    let position = |corner: &IndexTuple| Vec3::from(data.position.get(corner.0).copied().unwrap_or_default());
+
+    let mut triangles: Vec<([IndexTuple; 3], Vec3)> = vec![];
+    let mut smooth_normals: HashMap<usize, Vec3> = HashMap::new();
+
+    for SimplePolygon(corners) in &group.polys {
+        let points = corners.iter().map(position).collect::<Vec<_>>();
+        let area_normal = polygon_normal(&points);
+        let face_normal = if area_normal.norm() > f32::EPSILON { area_normal.normalize() } else { vec3(0., 1., 0.) };
+
+        if normal_generation == NormalGeneration::Smooth {
+            for corner in corners.iter().filter(|corner| corner.2.is_none()) {
+                *smooth_normals.entry(corner.0).or_insert_with(Vec3::zeros) += area_normal;
             }
+        }
+
+        for [a, b, c] in triangulate(&points) {
+            triangles.push(([corners[a], corners[b], corners[c]], face_normal));
+        }
+    }
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut unique: HashMap<(usize, Option<usize>, NormalKey), u32> = HashMap::new();
+
+    for (triangle, face_normal) in triangles {
+        for corner in triangle {
+            let IndexTuple(position_index, tex_index, normal_index) = corner;
+
+            let (normal_key, normal) = match (normal_index, normal_generation) {
+                (Some(index), _) => (NormalKey::Index(index), Vec3::from(data.normal.get(index).copied().unwrap_or_default())),
+                (None, NormalGeneration::Flat) => (NormalKey::Flat(face_normal.map(f32::to_bits).into()), face_normal),
+                (None, NormalGeneration::Smooth) => {
+                    let normal = smooth_normals.get(&position_index).copied().unwrap_or(face_normal);
+                    let normal = if normal.norm() > f32::EPSILON { normal.normalize() } else { face_normal };
+                    (NormalKey::Smooth, normal)
+                }
+            };
 
-            let mat = Material::lit(shader_loader, textures);
+            let index = *unique.entry((position_index, tex_index, normal_key)).or_insert_with(|| {
+                let uv = tex_index.and_then(|index| data.texture.get(index)).copied().unwrap_or_default();
 
-            
-            let mesh = self.add_mesh(name.clone(), Mesh::new(pnt, inds));
-            model.add_mesh(MeshRenderer::new(mesh, mat));
+                vertices.extend(position(&corner).iter());
+                vertices.extend(normal.iter());
+                vertices.extend(uv);
+
+                (vertices.len() / VERTEX_SIZE - 1) as u32
+            });
+
+            indices.push(index);
+        }
+    }
+
+    ObjMeshData {
+        name: format!("{object_name}/{}", group.name),
+        vertices,
+        indices,
+        material: group.material.clone(),
+    }
+}
+
+/// Newell's method, works for concave and slightly non planar polygons.
+/// Its length is twice the polygon's area.
+fn polygon_normal(points: &[Vec3]) -> Vec3 {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .fold(Vec3::zeros(), |normal, (current, next)| normal + vec3(
+            (current.y - next.y) * (current.z + next.z),
+            (current.z - next.z) * (current.x + next.x),
+            (current.x - next.x) * (current.y + next.y),
+        ))
+}
+
+/// Ear clipping, keeping the polygon's winding. Returns indices into `points`.
+/// Falls back to a fan for degenerate polygons.
+fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    let fan = |remaining: &[usize]| (1..remaining.len().saturating_sub(1))
+        .map(|i| [remaining[0], remaining[i], remaining[i + 1]])
+        .collect::<Vec<_>>();
+
+    if points.len() <= 3 {
+        return fan(&(0..points.len()).collect::<Vec<_>>());
+    }
+
+    let normal = polygon_normal(points);
+    if normal.norm() <= f32::EPSILON {
+        return fan(&(0..points.len()).collect::<Vec<_>>());
+    }
+
+    // Flatten onto the polygon's plane, counter clockwise seen from the normal
+    let normal = normal.normalize();
+    let u = if normal.x.abs() > 0.9 { vec3(0., 1., 0.) } else { vec3(1., 0., 0.) };
+    let u = (u - normal * u.dot(&normal)).normalize();
+    let v = normal.cross(&u);
+    let flat = points.iter().map(|p| vec2(p.dot(&u), p.dot(&v))).collect::<Vec<_>>();
+
+    let cross = |a: Vec2, b: Vec2, c: Vec2| (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+    let inside = |p: Vec2, a: Vec2, b: Vec2, c: Vec2| cross(a, b, p) >= 0. && cross(b, c, p) >= 0. && cross(c, a, p) >= 0.;
+
+    let mut remaining = (0..points.len()).collect::<Vec<_>>();
+    let mut triangles = vec![];
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+
+        let ear = (0..count).find(|&i| {
+            let [prev, current, next] = [remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]];
+            let (a, b, c) = (flat[prev], flat[current], flat[next]);
+
+            // Reflex corners aren't ears, and neither are corners with another vertex in their triangle
+            cross(a, b, c) > f32::EPSILON && !remaining
+                .iter()
+                .filter(|&&other| other != prev && other != current && other != next)
+                .any(|&other| flat[other] != a && flat[other] != b && flat[other] != c && inside(flat[other], a, b, c))
+        });
+
+        let Some(ear) = ear else { break };
+
+        triangles.push([remaining[(ear + count - 1) % count], remaining[ear], remaining[(ear + 1) % count]]);
+        remaining.remove(ear);
+    }
+
+    triangles.extend(fan(&remaining));
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The obj crate takes names from fixed offsets, so the indentation has to go.
+    fn parse(source: &str) -> ObjData {
+        let source = source.lines().map(str::trim).collect::<Vec<_>>().join("\n");
+        ObjData::load_buf(source.as_bytes()).expect("Invalid OBJ")
+    }
+
+    fn vertex(mesh: &ObjMeshData, index: u32) -> &[f32] {
+        let start = index as usize * VERTEX_SIZE;
+        &mesh.vertices[start..start + VERTEX_SIZE]
+    }
+
+    fn triangle_area(mesh: &ObjMeshData, triangle: &[u32]) -> f32 {
+        let [a, b, c] = [0, 1, 2].map(|corner| Vec3::from_column_slice(&vertex(mesh, triangle[corner])[..3]));
+        (b - a).cross(&(c - a)).z / 2.
+    }
+
+    const QUAD: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+    ";
+
+    #[test]
+    fn triangulates_quads_with_shared_corners() {
+        let meshes = obj_mesh_data(&parse(QUAD), NormalGeneration::Flat);
+        let [quad] = &meshes[..] else { panic!("Expected a single mesh") };
+
+        assert_eq!(quad.indices.len(), 6);
+        assert_eq!(quad.vertices.len(), 4 * VERTEX_SIZE);
+
+        for triangle in quad.indices.chunks(3) {
+            assert!(triangle_area(quad, triangle) > 0., "Triangles should keep the counter clockwise winding");
+        }
+    }
+
+    #[test]
+    fn triangulates_concave_ngons() {
+        // An arrow pointing right, concave at (1, 1)
+        let arrow = "
+            v 0 0 0
+            v 3 1 0
+            v 0 2 0
+            v 1 1 0
+            v 0.5 1.5 0
+            f 1 2 3 5 4
+        ";
+        let meshes = obj_mesh_data(&parse(arrow), NormalGeneration::Flat);
+        let mesh = &meshes[0];
+
+        assert_eq!(mesh.indices.len(), 3 * 3);
+
+        // The triangles cover the polygon exactly once if their areas add up to its area
+        let area: f32 = mesh.indices.chunks(3).map(|triangle| triangle_area(mesh, triangle)).sum();
+        let expected = polygon_normal(&[
+            vec3(0., 0., 0.), vec3(3., 1., 0.), vec3(0., 2., 0.), vec3(0.5, 1.5, 0.), vec3(1., 1., 0.)
+        ]).norm() / 2.;
+
+        assert!((area - expected).abs() < 1e-4, "Triangles cover {area}, polygon is {expected}");
+        assert!(mesh.indices.chunks(3).all(|triangle| triangle_area(mesh, triangle) > 0.));
+    }
+
+    #[test]
+    fn one_mesh_per_group_and_material() {
+        let source = "
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            o first
+            g a
+            usemtl red
+            f 1 2 3
+            usemtl blue
+            f 1 3 4
+            o second
+            g b
+            f 1 2 3 4
+        ";
+        let meshes = obj_mesh_data(&parse(source), NormalGeneration::Flat);
+
+        let names = meshes.iter().map(|mesh| mesh.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["first/a", "first/a", "second/b"]);
+
+        let materials = meshes
+            .iter()
+            .map(|mesh| match &mesh.material {
+                Some(ObjMaterial::Ref(name)) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // `o` resets the material
+        assert_eq!(materials, [Some("red"), Some("blue"), None]);
+        assert_eq!(meshes[2].indices.len(), 6);
+    }
+
+    #[test]
+    fn deduplicates_identical_corners() {
+        let cube = std::fs::read_to_string("assets/obj/cube.obj").unwrap();
+        let meshes = obj_mesh_data(&parse(&cube), NormalGeneration::Flat);
+
+        let indices: usize = meshes.iter().map(|mesh| mesh.indices.len()).sum();
+        let vertices: usize = meshes.iter().map(|mesh| mesh.vertices.len() / VERTEX_SIZE).sum();
+
+        assert_eq!(indices, 36);
+        assert_eq!(vertices, 24, "Each face should share its 4 corners between its 2 triangles");
+    }
+
+    #[test]
+    fn generates_flat_normals_and_zero_uvs() {
+        // Two faces of a cube around the edge from (1, 0, 0) to (1, 1, 0), no normals or UVs
+        let source = "
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            v 1 0 -1
+            v 1 1 -1
+            f 1 2 3 4
+            f 2 5 6 3
+        ";
+        let meshes = obj_mesh_data(&parse(source), NormalGeneration::Flat);
+        let mesh = &meshes[0];
+
+        // Shared positions are split since their normals differ
+        assert_eq!(mesh.vertices.len(), 8 * VERTEX_SIZE);
+
+        for index in &mesh.indices[..6] {
+            assert_eq!(&vertex(mesh, *index)[3..], &[0., 0., 1., 0., 0.]);
+        }
+        for index in &mesh.indices[6..] {
+            assert_eq!(&vertex(mesh, *index)[3..], &[1., 0., 0., 0., 0.]);
         }
+    }
+
+    #[test]
+    fn generates_smooth_normals() {
+        let source = "
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            v 1 0 -1
+            v 1 1 -1
+            f 1 2 3 4
+            f 2 5 6 3
+        ";
+        let meshes = obj_mesh_data(&parse(source), NormalGeneration::Smooth);
+        let mesh = &meshes[0];
+
+        assert_eq!(mesh.vertices.len(), 6 * VERTEX_SIZE);
+
+        // The corner on the shared edge points between both faces
+        let shared = mesh.indices
+            .iter()
+            .map(|index| vertex(mesh, *index))
+            .find(|vertex| vertex[..3] == [1., 0., 0.])
+            .unwrap();
+        let normal = vec3(shared[3], shared[4], shared[5]);
+        let expected = vec3(1., 0., 1.).normalize();
+
+        assert!((normal - expected).norm() < 1e-4, "Expected {expected:?}, got {normal:?}");
+    }
 
-        Ok(self.add_model(name, model))
+    #[test]
+    fn missing_attributes_keep_vertices_aligned() {
+        // Only some corners have UVs
+        let source = "
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            vt 0.5 0.5
+            f 1/1 2 3
+        ";
+        let meshes = obj_mesh_data(&parse(source), NormalGeneration::Flat);
+        let mesh = &meshes[0];
+
+        assert_eq!(mesh.vertices.len(), 3 * VERTEX_SIZE);
+        assert_eq!(vertex(mesh, mesh.indices[0]), &[0., 0., 0., 0., 0., 1., 0.5, 0.5]);
+        assert_eq!(vertex(mesh, mesh.indices[1]), &[1., 0., 0., 0., 0., 1., 0., 0.]);
     }
 }
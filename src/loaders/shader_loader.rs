@@ -1,62 +1,288 @@
-use std::{collections::HashMap, rc::Rc};
-use crate::{shader::{ShaderProgram, UniformMap}, map};
+use std::{collections::{HashMap, HashSet}, path::Path};
+
+use glow::HasContext;
+
+use crate::{
+    gl::get_gl,
+    loaders::{canonical_key, utils::Handle, AssetInfo, AssetKind, AssetResult},
+    renderer::{CLUSTER_GRID, DATA_TEXTURE_WIDTH, MAX_POINT_SHADOWS, MAX_SPOT_SHADOWS},
+    shader::{ShaderDefines, ShaderProgram, UniformMap},
+    notifications::{self, Severity},
+    map
+};
 
 
 
 pub const DEFAULT_UNLIT_SHADER:     &str = "default_unlit";
 pub const DEFAULT_BILLBOARD_SHADER: &str = "default_billboard";
 pub const DEFAULT_LIT_SHADER:       &str = "default_lit";
+pub const DEFAULT_PBR_SHADER:       &str = "default_pbr";
+pub const DEFAULT_SHADOW_SHADER:    &str = "default_shadow";
+pub const DEFAULT_POINT_SHADOW_SHADER: &str = "default_point_shadow";
+pub const DEFAULT_UNLIT_TEXTURED_SHADER: &str = "default_unlit_textured";
 
-const DEFAULT_SHADERS: [(&str, &str, &str); 3] = [
+/// Shaders reading the lights and shadow maps.
+pub const LIT_SHADERS: [&str; 2] = [DEFAULT_LIT_SHADER, DEFAULT_PBR_SHADER];
+
+const DEFAULT_SHADERS: [(&str, &str, &str); 7] = [
     (DEFAULT_UNLIT_SHADER    , "assets/shaders/default_unlit.vert",  "assets/shaders/default_unlit.frag"),
     (DEFAULT_BILLBOARD_SHADER, "assets/shaders/billboard_textured.vert", "assets/shaders/simple.frag"),
     (DEFAULT_LIT_SHADER      , "assets/shaders/textured.vert", "assets/shaders/lit-textured.frag"),
+    (DEFAULT_PBR_SHADER      , "assets/shaders/textured.vert", "assets/shaders/pbr.frag"),
+    (DEFAULT_SHADOW_SHADER   , "assets/shaders/shadow_depth.vert", "assets/shaders/shadow_depth.frag"),
+    (DEFAULT_POINT_SHADOW_SHADER, "assets/shaders/point_shadow_depth.vert", "assets/shaders/point_shadow_depth.frag"),
+    (DEFAULT_UNLIT_TEXTURED_SHADER, "assets/shaders/textured.vert", "assets/shaders/simple.frag"),
 ];
 
 pub struct ShaderLoader {
-    shaders: HashMap<String, Rc<ShaderProgram>>,
+    /// Programs by the `canonical_key`s of their vertex and fragment shaders, see `shader_key`.
+    shaders: HashMap<String, Handle<ShaderProgram>>,
+
+    /// Program names (like `DEFAULT_LIT_SHADER`) to keys in `shaders`.
+    /// Loading the same pair of files under another name shares the program.
+    names: HashMap<String, String>,
+
+    /// Keys of the default shaders, which are never unloaded.
+    pinned: HashSet<String>,
+
+    /// Keys of the variants in `shaders` to the keys of the programs they're variants of,
+    /// see `get_variant`.
+    variants: HashMap<String, String>,
+}
+
+fn shader_key(vert_path: &str, frag_path: &str) -> String {
+    format!("{} + {}", canonical_key(vert_path), canonical_key(frag_path))
+}
+
+/// Like "<shader key> [HAS_NORMAL_MAP, SAMPLES=4]".
+fn variant_key(base_key: &str, defines: &ShaderDefines) -> String {
+    let defines: Vec<String> = defines
+        .iter()
+        .map(|(name, value)| match value.is_empty() {
+            true => name.clone(),
+            false => format!("{name}={value}"),
+        })
+        .collect();
+
+    format!("{base_key} [{}]", defines.join(", "))
+}
+
+/// Defines every program is compiled with, sizes that have to match between Rust and GLSL.
+fn global_defines() -> ShaderDefines {
+    [
+        ("MAX_SPOT_SHADOWS", MAX_SPOT_SHADOWS),
+        ("MAX_POINT_SHADOWS", MAX_POINT_SHADOWS),
+        ("CLUSTERS_X", CLUSTER_GRID[0]),
+        ("CLUSTERS_Y", CLUSTER_GRID[1]),
+        ("CLUSTERS_Z", CLUSTER_GRID[2]),
+        ("DATA_TEXTURE_WIDTH", DATA_TEXTURE_WIDTH),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect()
 }
 
 impl ShaderLoader {
     pub fn new(custom_shaders: &[(&str, &str, &str, UniformMap)]) -> Self {
         let mut shader_loader = ShaderLoader {
             shaders: HashMap::new(),
+            names: HashMap::new(),
+            pinned: HashSet::new(),
+            variants: HashMap::new(),
         };
 
-        for (program_name, vert_path, frag_path, uniforms) in custom_shaders {
-            shader_loader.load_shader(program_name, vert_path, frag_path, uniforms.clone());
-        }
+        let custom_shaders = custom_shaders
+            .iter()
+            .map(|(program_name, vert_path, frag_path, uniforms)| (*program_name, *vert_path, *frag_path, uniforms.clone()));
+        let default_shaders = DEFAULT_SHADERS
+            .into_iter()
+            .map(|(program_name, vert_path, frag_path)| (program_name, vert_path, frag_path, map!{}));
 
-        for (program_name, vert_path, frag_path) in DEFAULT_SHADERS {
-            shader_loader.load_shader(program_name, vert_path, frag_path, map!{});
+        for (program_name, vert_path, frag_path, uniforms) in custom_shaders.chain(default_shaders) {
+            if let Err(err) = shader_loader.load_shader(program_name, vert_path, frag_path, uniforms) {
+                notifications::error(err);
+            }
         }
 
+        shader_loader.pinned = DEFAULT_SHADERS
+            .iter()
+            .map(|(_, vert_path, frag_path)| shader_key(vert_path, frag_path))
+            .collect();
+
         shader_loader
     }
 
+    /// Compiles the program if these files weren't loaded yet and names it `program_name`.
+    /// Names that are already taken keep their program.
     pub fn load_shader(
         &mut self,
         program_name: &str,
         vert_path: &str,
         frag_path: &str,
         uniforms: UniformMap
-    ) {
-        if !self.shaders.contains_key(program_name) {
-            println!("Loading shader ({program_name})");
+    ) -> AssetResult<Handle<ShaderProgram>> {
+        if let Some(shader) = self.names.get(program_name).and_then(|key| self.shaders.get(key)) {
+            return Ok(Handle::clone(shader));
+        }
+
+        let key = shader_key(vert_path, frag_path);
+
+        let shader = match self.shaders.get(&key) {
+            Some(shader) => Handle::clone(shader),
+            None => {
+                let shader = ShaderProgram::with_defines(vert_path, frag_path, global_defines())?;
+                shader.report_uniform_errors(&uniforms, "");
+
+                let shader = Handle::new(shader);
+                self.shaders.insert(key.clone(), Handle::clone(&shader));
+                shader
+            }
+        };
+
+        self.names.insert(String::from(program_name), key);
+        Ok(shader)
+    }
+
+    /// Falls back to the default unlit shader for unknown names.
+    ///
+    /// Panics if the default unlit shader itself failed to load, there's nothing left to draw with.
+    pub fn get_shader_rc(&mut self, program_name: &str) -> Handle<ShaderProgram> {
+        let by_name = |name: &str| self.names.get(name).and_then(|key| self.shaders.get(key));
+
+        if let Some(shader) = by_name(program_name) {
+            return Handle::clone(shader);
+        }
+
+        if program_name != DEFAULT_UNLIT_SHADER {
+            notifications::warn(format!("No shader named \"{program_name}\", using \"{DEFAULT_UNLIT_SHADER}\""));
+        }
+
+        let fallback = by_name(DEFAULT_UNLIT_SHADER);
+        Handle::clone(fallback.unwrap_or_else(|| panic!("The \"{DEFAULT_UNLIT_SHADER}\" shader failed to load")))
+    }
 
-            let shader = ShaderProgram::new(vert_path, frag_path, uniforms);
-            match shader {
-                Ok(shader) => { self.shaders.insert(String::from(program_name), Rc::new(shader)); } ,
-                Err(err) => eprintln!("Failed to load shader: {}", err),
-            };
+    /// The program named `program_name` compiled with `defines` as well, compiled the first time
+    /// each set of defines is asked for. Falls back to the program without them if they don't compile.
+    pub fn get_variant(&mut self, program_name: &str, defines: &ShaderDefines) -> Handle<ShaderProgram> {
+        let shader = self.get_shader_rc(program_name);
+        let Some(base_key) = self.names.get(program_name).cloned() else { return shader };
+
+        if defines.is_empty() {
+            return shader;
+        }
+
+        let key = variant_key(&base_key, defines);
+        if let Some(variant) = self.shaders.get(&key) {
+            return Handle::clone(variant);
+        }
+
+        let (vert_path, frag_path, mut variant_defines) = {
+            let shader = shader.borrow();
+            (shader.vertex_path.clone(), shader.fragment_path.clone(), shader.defines.clone())
+        };
+        variant_defines.extend(defines.clone());
+
+        match ShaderProgram::with_defines(vert_path, frag_path, variant_defines) {
+            Ok(variant) => {
+                let variant = Handle::new(variant);
+                self.shaders.insert(key.clone(), Handle::clone(&variant));
+                self.variants.insert(key, base_key);
+                variant
+            }
+            Err(err) => {
+                notifications::error(err);
+                shader
+            }
         }
     }
 
-    pub fn get_shader_rc(&mut self, program_name: &str) -> Rc<ShaderProgram> {
-        if self.shaders.contains_key(program_name) {
-            Rc::clone(self.shaders.get_mut(program_name).unwrap())
-        } else {
-            Rc::clone(self.shaders.get_mut(DEFAULT_UNLIT_SHADER).unwrap())
+    /// The program named `program_name` and every variant of it compiled so far.
+    pub fn variants_of(&mut self, program_name: &str) -> Vec<Handle<ShaderProgram>> {
+        let shader = self.get_shader_rc(program_name);
+        let Some(base_key) = self.names.get(program_name) else { return vec![shader] };
+
+        let variants = self.variants
+            .iter()
+            .filter(|(_, variant_base)| *variant_base == base_key)
+            .filter_map(|(key, _)| self.shaders.get(key))
+            .map(Handle::clone);
+
+        std::iter::once(shader).chain(variants).collect()
+    }
+
+    /// Reverse of `get_shader_rc`, returns the first name (alphabetically) a shader program was
+    /// loaded under. Variants give the name of the program they're a variant of.
+    pub fn shader_name(&self, shader: &Handle<ShaderProgram>) -> Option<&str> {
+        let (key, _) = self.shaders.iter().find(|(_, loaded)| *loaded == shader)?;
+        let key = self.variants.get(key).unwrap_or(key);
+
+        self.names
+            .iter()
+            .filter(|(_, name_key)| *name_key == key)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+
+    /// Every program and how many materials and other users hold it.
+    pub fn asset_infos(&self) -> Vec<AssetInfo> {
+        self.shaders
+            .iter()
+            .map(|(key, shader)| {
+                let program = shader.borrow();
+
+                AssetInfo {
+                    kind: AssetKind::Shader,
+                    key: key.clone(),
+                    references: shader.strong_count() - 1,
+                    pinned: self.pinned.contains(key),
+                    files: program.sources.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Keys of the programs compiled from the file at `path`, includes count.
+    pub fn programs_using(&self, path: &Path) -> Vec<String> {
+        let key = canonical_key(path);
+
+        self.shaders
+            .iter()
+            .filter(|(_, shader)| shader.borrow().sources.iter().any(|source| canonical_key(source) == key))
+            .map(|(shader_key, _)| shader_key.clone())
+            .collect()
+    }
+
+    /// Compiles the program's files again and swaps the new program in for everything using it.
+    /// The old program stays if they don't compile.
+    pub fn reload(&mut self, key: &str) -> AssetResult<()> {
+        let Some(shader) = self.shaders.get(key) else { return Ok(()) };
+
+        let (vert_path, frag_path, defines) = {
+            let shader = shader.borrow();
+            (shader.vertex_path.clone(), shader.fragment_path.clone(), shader.defines.clone())
+        };
+        let program = ShaderProgram::with_defines(vert_path, frag_path, defines)?;
+
+        let old_program = std::mem::replace(&mut *shader.borrow_mut(), program);
+        unsafe { get_gl().delete_program(old_program.handle); }
+
+        Ok(())
+    }
+
+    /// Deletes an unused program from the GPU along with its names, see `AssetServer::unload`.
+    pub fn unload(&mut self, key: &str) -> bool {
+        let unused = self.shaders.get(key).is_some_and(|shader| shader.strong_count() == 1);
+        if !unused || self.pinned.contains(key) {
+            return false;
         }
+
+        if let Some(shader) = self.shaders.remove(key) {
+            notifications::notify(Severity::Info, format!("Unloading shader \"{key}\""));
+            unsafe { get_gl().delete_program(shader.borrow().handle); }
+        }
+
+        self.names.retain(|_, name_key| name_key != key);
+        self.variants.remove(key);
+        true
     }
 }
@@ -0,0 +1,231 @@
+//! Turns a shader file into the source handed to GL.
+//!
+//! - `#include "file"` pastes a file in, looked up next to the including file first and then
+//!   under `SHADER_INCLUDE_DIR`. Each file is pasted at most once, so includes don't need guards.
+//! - Defines are added right after `#version`, which has to stay the first line.
+//!
+//! Compile logs refer to lines of the combined source, `ShaderSource::map_log` points them back
+//! at the file and line they came from.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    loaders::{canonical_key, AssetError, AssetResult},
+    shader::ShaderDefines,
+};
+
+/// Where `#include`s that aren't next to the including file are looked up.
+pub const SHADER_INCLUDE_DIR: &str = "assets/shaders";
+
+/// Preprocessed source of one shader stage.
+#[derive(Debug, Clone)]
+pub struct ShaderSource {
+    pub code: String,
+
+    /// Every file read, the shader's own file first.
+    pub files: Vec<PathBuf>,
+
+    /// Index into `files` and line (from 1) of each line of `code`, None for injected defines.
+    lines: Vec<Option<(usize, usize)>>,
+}
+
+impl ShaderSource {
+    /// File and line (from 1) that line `line` (from 1) of `code` came from.
+    pub fn original_line(&self, line: usize) -> Option<(&Path, usize)> {
+        let (file, line) = (*self.lines.get(line.checked_sub(1)?)?)?;
+        Some((&self.files[file], line))
+    }
+
+    /// Prefixes every line of a compile log that mentions a line of `code` with the original
+    /// file and line. Understands the `0:12(5)` (Mesa), `0(12)` (Nvidia) and `0:12:` (AMD,
+    /// Intel on Windows) styles.
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|log_line| match log_line_number(log_line).and_then(|line| self.original_line(line)) {
+                Some((file, line)) => format!("{}:{line}: {log_line}", file.display()),
+                None => log_line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// The line number in the first `<source>:<line>` or `<source>(<line>)` of a log line.
+fn log_line_number(log_line: &str) -> Option<usize> {
+    let bytes = log_line.as_bytes();
+
+    let mut start = 0;
+    while start < bytes.len() {
+        let source_end = start + bytes[start..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+
+        let after_source = bytes.get(source_end).copied();
+        let starts_number = start == 0 || !bytes[start - 1].is_ascii_alphanumeric();
+
+        if source_end > start && starts_number && matches!(after_source, Some(b':' | b'(')) {
+            let line_start = source_end + 1;
+            let line_end = line_start + bytes[line_start..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+
+            if line_end > line_start {
+                return log_line[line_start..line_end].parse().ok();
+            }
+        }
+
+        start = source_end.max(start + 1);
+    }
+
+    None
+}
+
+/// Reads `path` and everything it includes, adding `defines` after its `#version` line.
+pub fn preprocess_shader(path: impl AsRef<Path>, defines: &ShaderDefines) -> AssetResult<ShaderSource> {
+    let path = path.as_ref();
+    let code = fs::read_to_string(path).map_err(|source| AssetError::io(path, source))?;
+
+    let mut source = ShaderSource { code: String::new(), files: vec![path.to_path_buf()], lines: Vec::new() };
+    let mut lines = code.lines().enumerate().peekable();
+
+    // `#version` has to come before anything else, defines included
+    if let Some((_, version)) = lines.next_if(|(_, line)| line.trim_start().starts_with("#version")) {
+        source.push_line(version, Some((0, 1)));
+    }
+
+    for (name, value) in defines {
+        source.push_line(&format!("#define {name} {value}"), None);
+    }
+
+    source.append(0, lines)?;
+    Ok(source)
+}
+
+impl ShaderSource {
+    fn push_line(&mut self, line: &str, origin: Option<(usize, usize)>) {
+        self.code.push_str(line);
+        self.code.push('\n');
+        self.lines.push(origin);
+    }
+
+    /// Appends lines of `files[file]`, pasting in includes that weren't pasted yet.
+    fn append<'a>(&mut self, file: usize, lines: impl Iterator<Item = (usize, &'a str)>) -> AssetResult<()> {
+        for (index, line) in lines {
+            let line_number = index + 1;
+
+            let Some(include) = line.trim_start().strip_prefix("#include") else {
+                self.push_line(line, Some((file, line_number)));
+                continue;
+            };
+
+            let error = |message: String| AssetError::ShaderPreprocess { path: self.files[file].clone(), line: line_number, message };
+
+            let include = include.trim();
+            let Some(include) = include.strip_prefix('"').and_then(|include| include.strip_suffix('"')) else {
+                return Err(error(format!("expected #include \"file\", found #include {include}")));
+            };
+
+            let next_to_file = self.files[file].parent().unwrap_or(Path::new("")).join(include);
+            let include_path = match next_to_file.is_file() {
+                true => next_to_file,
+                false => Path::new(SHADER_INCLUDE_DIR).join(include),
+            };
+
+            let code = fs::read_to_string(&include_path)
+                .map_err(|err| error(format!("can't read \"{}\": {err}", include_path.display())))?;
+
+            // Commented out rather than dropped, so the log for this line still points at it
+            self.push_line(&format!("// {}", line.trim()), Some((file, line_number)));
+
+            let key = canonical_key(&include_path);
+            if self.files.iter().any(|file| canonical_key(file) == key) {
+                continue;
+            }
+
+            self.files.push(include_path);
+            self.append(self.files.len() - 1, code.lines().enumerate())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shader_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ggl_preprocessor_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (file, code) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, code).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn pastes_includes_once() {
+        let dir = shader_dir("includes", &[
+            ("main.frag", "#version 330 core\n#include \"common/a.glsl\"\n#include \"common/b.glsl\"\nvoid main() {}\n"),
+            ("common/a.glsl", "float a() { return 1.0; }\n"),
+            ("common/b.glsl", "#include \"a.glsl\"\nfloat b() { return a(); }\n"),
+        ]);
+
+        let source = preprocess_shader(dir.join("main.frag"), &ShaderDefines::new()).unwrap();
+
+        assert_eq!(source.code.matches("float a()").count(), 1);
+        assert!(source.code.find("float a()").unwrap() < source.code.find("float b()").unwrap());
+        assert_eq!(source.files.len(), 3);
+    }
+
+    #[test]
+    fn defines_follow_the_version() {
+        let dir = shader_dir("defines", &[("main.frag", "#version 330 core\nvoid main() {}\n")]);
+        let defines = ShaderDefines::from([("HAS_NORMAL_MAP".to_string(), String::new()), ("MAX_LIGHTS".to_string(), "4".to_string())]);
+
+        let source = preprocess_shader(dir.join("main.frag"), &defines).unwrap();
+        let lines: Vec<&str> = source.code.lines().collect();
+
+        assert_eq!(lines[..3], ["#version 330 core", "#define HAS_NORMAL_MAP ", "#define MAX_LIGHTS 4"]);
+        assert_eq!(source.original_line(2), None);
+        assert_eq!(source.original_line(4), Some((dir.join("main.frag").as_path(), 2)));
+    }
+
+    #[test]
+    fn maps_log_lines_back_to_files() {
+        let dir = shader_dir("lines", &[
+            ("main.frag", "#version 330 core\n#include \"lib.glsl\"\nvoid main() { oops }\n"),
+            ("lib.glsl", "float a() {\n  return nope;\n}\n"),
+        ]);
+
+        let defines = ShaderDefines::from([("A".to_string(), "1".to_string())]);
+        let source = preprocess_shader(dir.join("main.frag"), &defines).unwrap();
+
+        // version, define, include comment, lib.glsl (3 lines), main
+        assert_eq!(source.original_line(5), Some((dir.join("lib.glsl").as_path(), 2)));
+        assert_eq!(source.original_line(7), Some((dir.join("main.frag").as_path(), 3)));
+
+        let mesa = source.map_log("0:5(10): error: `nope' undeclared");
+        assert!(mesa.starts_with(&format!("{}:2: ", dir.join("lib.glsl").display())), "{mesa}");
+
+        let nvidia = source.map_log("0(7) : error C1008: undefined variable \"oops\"");
+        assert!(nvidia.starts_with(&format!("{}:3: ", dir.join("main.frag").display())), "{nvidia}");
+
+        let amd = source.map_log("ERROR: 0:7: 'oops' : undeclared identifier");
+        assert!(amd.contains("main.frag:3: ERROR"), "{amd}");
+
+        assert_eq!(source.map_log("Linking failed"), "Linking failed");
+    }
+
+    #[test]
+    fn reports_missing_includes() {
+        let dir = shader_dir("missing", &[("main.frag", "#version 330 core\n\n#include \"nowhere.glsl\"\n")]);
+        let err = preprocess_shader(dir.join("main.frag"), &ShaderDefines::new()).unwrap_err();
+
+        assert!(matches!(err, AssetError::ShaderPreprocess { line: 3, .. }), "{err}");
+        assert!(err.to_string().contains("nowhere.glsl"));
+    }
+}
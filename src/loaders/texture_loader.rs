@@ -1,6 +1,11 @@
 use glow::HasContext;
-use std::{collections::HashMap, path::Path};
-use crate::get_gl;
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}};
+use crate::{
+    gl::get_gl,
+    loaders::{canonical_key, utils::Handle, AssetError, AssetInfo, AssetKind, AssetResult},
+    notifications::{self, Severity},
+    texture::Texture,
+};
 
 const DEFAULT_TEXTURE: &str = "default";
 const DEFAULT_TEXTURES: [&str; 6] = [
@@ -17,35 +22,51 @@ macro_rules! default_texture_getters {
     ($( ($name: expr, $fn_name: tt) ),*) => {
         $(
             #[allow(dead_code)]
-            pub fn $fn_name(&self) -> glow::Texture {
-                self.borrow($name)
+            pub fn $fn_name(&self) -> Handle<Texture> {
+                self.get($name)
             }
         )*
     };
 }
 
 pub struct TextureLoader {
-    /// Maps from the texture's name to its native handle
-    /// Note that the native handle does not specify the type of this texture (Diffuse, Specular,
-    /// Emissive, etc...)
-    textures: HashMap<String, glow::Texture>,
+    /// Maps from the texture's `canonical_key` to the texture.
+    /// Note that the texture does not specify its type (Diffuse, Specular, Emissive, etc...)
+    textures: HashMap<String, Handle<Texture>>,
+
+    /// Keys of the default textures, which are never unloaded.
+    pinned: HashSet<String>,
+
+    /// Bumped whenever a texture is added, removed or gets new pixels, see `changes`.
+    changes: usize,
 }
 
 impl TextureLoader {
     pub fn new() -> Self {
         let mut texture_loader = TextureLoader {
             textures: HashMap::new(),
+            pinned: HashSet::new(),
+            changes: 0,
         };
         
         texture_loader.setup_default_texture();
 
         for path in DEFAULT_TEXTURES {
-            texture_loader.load_texture(Path::new(path));
+            texture_loader.load_texture_or_default(Path::new(path));
         }
 
+        texture_loader.pinned = texture_loader.textures.keys().cloned().collect();
         texture_loader
     }
 
+    fn insert(&mut self, key: String, path: impl Into<String>, native_handle: glow::Texture) -> Handle<Texture> {
+        let texture = Handle::new(Texture { native_handle, path: path.into() });
+        self.textures.insert(key, Handle::clone(&texture));
+        self.changes += 1;
+
+        texture
+    }
+
     fn setup_default_texture(&mut self) {
         println!(
             "GL_RED = {:?}, GL_RGB = {:?}, GL_RGBA = {:?}",
@@ -64,15 +85,15 @@ impl TextureLoader {
 
         assert!( buffer.len() == w as usize * h as usize * 3 );
 
-        self.textures
-            .insert(
-                DEFAULT_TEXTURE.to_owned(), 
-                Self::from_data(
-                    (w, h), 
-                    glow::RGB, 
-                    &buffer
-                )
-            );
+        self.insert(
+            DEFAULT_TEXTURE.to_owned(), 
+            DEFAULT_TEXTURE,
+            Self::from_data(
+                (w, h), 
+                glow::RGB, 
+                &buffer
+            )
+        );
     }
 
     fn from_data(
@@ -85,6 +106,9 @@ impl TextureLoader {
             let texture_handle = gl.create_texture().unwrap();
 
             gl.bind_texture(glow::TEXTURE_2D, Some(texture_handle));
+
+            // Rows of RGB and single channel images aren't always 4 byte aligned
+            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
             gl.tex_image_2d(
                 glow::TEXTURE_2D,
                 0,
@@ -117,81 +141,267 @@ impl TextureLoader {
 
     /// Usually used when loading a texture for the first time.
     /// This mostly occurs when loading a mesh for example.
-    fn load_into_handle(&self, path: &Path) -> glow::Texture {
-        let path_string = path.to_str().unwrap();
-        let texture = image::io::Reader::open(path_string).unwrap().decode().unwrap();
-
-        let texture_w = texture.width() as i32;
-        let texture_h = texture.height() as i32;
-
-        let format = match texture.color() {
-            image::ColorType::L8 => glow::RED,
-            image::ColorType::Rgb8 => glow::RGB,
-            image::ColorType::Rgba8 => glow::RGBA,
-            _ => {
-                panic!("Unsupported color type {:?}", texture.color());
-            }
-        };
+    fn load_into_handle(&self, path: &Path) -> AssetResult<glow::Texture> {
+        let image = decode_image(path)?;
+        println!("Loaded texture [{}]", path.display());
 
-        // TODO: Find a way to make this const or static (no need to construct it every time we
-        // call this function)
-        let int_to_texture_format: HashMap<u32, &'static str> = HashMap::from([
-            (glow::RED, "GL_RED"),
-            (glow::RGB, "GL_RGB"),
-            (glow::RGBA, "GL_RGBA")
-        ]);
+        Ok(Self::from_data(
+            (image.width as i32, image.height as i32), 
+            image.format,
+            &image.pixels
+        ))
+    }
 
-        println!("Loaded texture [{}] of format {:#?}", path_string, int_to_texture_format.get(&format).unwrap());
+    /// Uploads already decoded pixels, for textures that don't come from an image file
+    /// (embedded in a model for example). Returns the existing texture if `name` is already loaded.
+    pub fn load_pixels(&mut self, name: &str, (width, height): (u32, u32), format: u32, pixels: &[u8]) -> Handle<Texture> {
+        let key = canonical_key(name);
+        if let Some(texture) = self.textures.get(&key) {
+            return Handle::clone(texture);
+        }
+
+        let texture_handle = Self::from_data((width as i32, height as i32), format, pixels);
+        self.insert(key, name, texture_handle)
+    }
 
+    /// 1x1 texture of a single color, shared between everything asking for the same color.
+    pub fn solid_color(&mut self, rgba: [f32; 4]) -> Handle<Texture> {
+        let pixel = rgba.map(|channel| (channel.clamp(0., 1.) * 255.).round() as u8);
+        let name = format!("#{:02x}{:02x}{:02x}{:02x}", pixel[0], pixel[1], pixel[2], pixel[3]);
 
-        Self::from_data(
-            (texture_w, texture_h), 
-            format,
-            texture.as_bytes()
-        )
+        self.load_pixels(&name, (1, 1), glow::RGBA, &pixel)
     }
 
     /// Used when we want to make sure a texture is loaded at most one time.
-    /// If the user attempt to load it again, it will return the already loaded instance.
-    /// Returns a fallback/default texture if the given texture path does not exist.
-    pub fn load_texture(&mut self, path: &Path) -> glow::Texture {
-        if path.exists() && path.is_file() {
-            let file_name = path.file_stem().unwrap().to_str().unwrap();
-
-            if self.textures.contains_key(file_name) {
-                return *self.textures.get(file_name).unwrap()
-            }
+    /// If the user attempt to load it again, even through a different relative path, it will
+    /// return the already loaded instance.
+    pub fn load_texture(&mut self, path: &Path) -> AssetResult<Handle<Texture>> {
+        let key = canonical_key(path);
 
-            let texture_handle = self.load_into_handle(path);
-            self.textures.insert(file_name.to_string(), texture_handle);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(Handle::clone(texture));
+        }
 
-            texture_handle
-        } else {
-            let texture_path = path.to_str().unwrap();
-            println!("Failed to load texture at {texture_path}, returning error texture");
+        let texture_handle = self.load_into_handle(path)?;
+        Ok(self.insert(key, path.to_string_lossy().replace('\\', "/"), texture_handle))
+    }
+
+    /// `load_texture`, reporting failures and returning the error texture instead.
+    pub fn load_texture_or_default(&mut self, path: &Path) -> Handle<Texture> {
+        self.load_texture(path).unwrap_or_else(|err| {
+            notifications::error(format!("{err}, using the error texture"));
             self.default_texture()
-        }
+        })
     }
 
-    /// Borrows a texture given its name.
-    /// The name is the file name WITHOUT its extension, for example: `path/to/img.png`.
-    /// Here `img` is the string that should be passed to the function.
-    pub fn borrow(&self, file_name: &str) -> glow::Texture {
-        match self.textures.get(file_name) {
-            Some(tex) => *tex,
-            None => { 
-                println!("Failed to load the texture named {file_name}, returning error texture");
-                self.default_texture()
+    /// Looks up a loaded texture by path, or by file name without its extension for scenes saved
+    /// before textures were keyed by path. Returns the error texture if there's none.
+    pub fn get(&self, path: &str) -> Handle<Texture> {
+        let by_path = self.textures.get(&canonical_key(path));
+        let by_stem = || self.textures.values().find(|texture| {
+            Path::new(&texture.borrow().path).file_stem().is_some_and(|stem| stem == path)
+        });
+
+        match by_path.or_else(by_stem) {
+            Some(texture) => Handle::clone(texture),
+            None => {
+                notifications::warn(format!("No texture named \"{path}\", using the error texture"));
+                self.textures.get(DEFAULT_TEXTURE).cloned().expect("The error texture is always loaded")
             }
         }
     }
 
+    /// Registers a white placeholder under `name` for `fill` to upload into later, so materials
+    /// can use the texture before it's decoded. Returns the loaded (or already reserved) texture
+    /// instead if there's one, along with whether a new placeholder was made.
+    pub fn reserve(&mut self, name: &str) -> (Handle<Texture>, bool) {
+        let key = canonical_key(name);
+        if let Some(texture) = self.textures.get(&key) {
+            return (Handle::clone(texture), false);
+        }
+
+        let placeholder = self.white_texture().borrow().native_handle;
+        (self.insert(key, name.replace('\\', "/"), placeholder), true)
+    }
+
+    /// Uploads a decoded image into a texture made by `reserve`.
+    pub fn fill(&mut self, texture: &Handle<Texture>, image: &DecodedImage) {
+        let native_handle = Self::from_data((image.width as i32, image.height as i32), image.format, &image.pixels);
+        texture.borrow_mut().native_handle = native_handle;
+        self.changes += 1;
+    }
+
+    /// Every loaded texture, sorted by path.
+    pub fn textures(&self) -> Vec<Handle<Texture>> {
+        let mut textures: Vec<_> = self.textures.values().cloned().collect();
+        textures.sort_by_cached_key(|texture| texture.borrow().path.clone());
+        textures
+    }
+
+    /// Changes whenever `textures` would, or one of them gets another GL texture, so what's built
+    /// from them only has to be updated then.
+    pub fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// Keys of every loaded (or reserved) texture.
+    pub fn loaded(&self) -> HashSet<String> {
+        self.textures.keys().cloned().collect()
+    }
+
+    /// Every texture and how many materials and other users hold it.
+    pub fn asset_infos(&self) -> Vec<AssetInfo> {
+        self.textures
+            .iter()
+            .map(|(key, texture)| {
+                // Generated textures and images inside models have names that aren't files
+                let path = PathBuf::from(&texture.borrow().path);
+
+                AssetInfo {
+                    kind: AssetKind::Texture,
+                    key: key.clone(),
+                    references: texture.strong_count() - 1,
+                    pinned: self.pinned.contains(key),
+                    files: path.is_file().then_some(path).into_iter().collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Keys of the textures read from the file at `path`.
+    pub fn textures_using(&self, path: &Path) -> Vec<String> {
+        let key = canonical_key(path);
+        self.textures.contains_key(&key).then_some(key).into_iter().collect()
+    }
+
+    /// Decodes the texture's file again and swaps the new pixels in for everything using it.
+    /// The old pixels stay if the file doesn't decode.
+    pub fn reload(&mut self, key: &str) -> AssetResult<()> {
+        let Some(texture) = self.textures.get(key) else { return Ok(()) };
+
+        let path = PathBuf::from(&texture.borrow().path);
+        let image = decode_image(&path)?;
+        let native_handle = Self::from_data((image.width as i32, image.height as i32), image.format, &image.pixels);
+
+        let old_handle = std::mem::replace(&mut texture.borrow_mut().native_handle, native_handle);
+        self.changes += 1;
+
+        // Placeholders share the white texture until they're filled
+        if !self.textures.values().any(|texture| texture.borrow().native_handle == old_handle) {
+            unsafe { get_gl().delete_texture(old_handle); }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes an unused texture from the GPU, see `AssetServer::unload`.
+    pub fn unload(&mut self, key: &str) -> bool {
+        let unused = self.textures.get(key).is_some_and(|texture| texture.strong_count() == 1);
+        if !unused || self.pinned.contains(key) {
+            return false;
+        }
+
+        if let Some(texture) = self.textures.remove(key) {
+            notifications::notify(Severity::Info, format!("Unloading texture \"{key}\""));
+            unsafe { get_gl().delete_texture(texture.borrow().native_handle); }
+            self.changes += 1;
+        }
+
+        true
+    }
+
     default_texture_getters![ 
-        (DEFAULT_TEXTURE          , default_texture),
-        ("checker_32_32"          , checker_texture),
-        ("white"                  , white_texture),
-        ("black"                  , black_texture),
-        ("point_light_white"      , point_light_texture),
-        ("directional_light_white", directional_light_texture)
+        (DEFAULT_TEXTURE   , default_texture),
+        (DEFAULT_TEXTURES[3], checker_texture),
+        (DEFAULT_TEXTURES[0], white_texture),
+        (DEFAULT_TEXTURES[1], black_texture),
+        (DEFAULT_TEXTURES[4], point_light_texture),
+        (DEFAULT_TEXTURES[5], directional_light_texture)
     ];
 }
+
+/// Pixels of an image file, ready to upload.
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+
+    /// `glow::RED`, `glow::RGB` or `glow::RGBA`.
+    pub format: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Reads and decodes an image file without touching GL, so it can run on any thread.
+pub fn decode_image(path: &Path) -> AssetResult<DecodedImage> {
+    let image = image::ImageReader::open(path)
+        .map_err(|source| AssetError::io(path, source))?
+        .decode()
+        .map_err(|source| AssetError::Image { path: path.to_path_buf(), source })?;
+
+    let (width, height) = (image.width(), image.height());
+
+    // 16 bit and float images (like HDR ones) are uploaded with 8 bits per channel too
+    let (format, pixels) = match image.color() {
+        image::ColorType::L8 => (glow::RED, image.into_bytes()),
+        image::ColorType::Rgb8 => (glow::RGB, image.into_bytes()),
+        image::ColorType::Rgba8 => (glow::RGBA, image.into_bytes()),
+        image::ColorType::L16 => (glow::RED, image.into_luma8().into_raw()),
+        color if color.has_alpha() => (glow::RGBA, image.into_rgba8().into_raw()),
+        _ => (glow::RGB, image.into_rgb8().into_raw()),
+    };
+
+    Ok(DecodedImage { width, height, format, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Failures happen before anything is uploaded, so no GL context is needed.
+    fn empty_loader() -> TextureLoader {
+        TextureLoader { textures: HashMap::new(), pinned: HashSet::new(), changes: 0 }
+    }
+
+    #[test]
+    fn missing_files_are_io_errors() {
+        let path = Path::new("assets/textures/does_not_exist.png");
+        let mut loader = empty_loader();
+        let err = loader.load_texture(path).unwrap_err();
+
+        assert!(matches!(err, AssetError::Io { .. }), "{err:?}");
+        assert_eq!(err.path(), path);
+        assert_eq!(loader.changes(), 0, "Nothing was loaded");
+    }
+
+    #[test]
+    fn undecodable_files_are_image_errors() {
+        let path = std::env::temp_dir().join("ggl_not_an_image.png");
+        std::fs::write(&path, b"definitely not a png").unwrap();
+
+        let err = empty_loader().load_texture(&path).unwrap_err();
+        assert!(matches!(err, AssetError::Image { .. }), "{err:?}");
+        assert!(err.to_string().contains("ggl_not_an_image.png"));
+    }
+
+    #[test]
+    fn converts_other_color_types_to_8_bits() {
+        let directory = std::env::temp_dir();
+
+        let gray_alpha = directory.join("ggl_gray_alpha.png");
+        image::GrayAlphaImage::from_pixel(2, 1, image::LumaA([200, 100])).save(&gray_alpha).unwrap();
+        let decoded = decode_image(&gray_alpha).unwrap();
+        assert_eq!((decoded.format, decoded.pixels), (glow::RGBA, vec![200, 200, 200, 100, 200, 200, 200, 100]));
+
+        let rgb16 = directory.join("ggl_rgb16.png");
+        image::ImageBuffer::<image::Rgb<u16>, _>::from_pixel(1, 1, image::Rgb([u16::MAX, 0, 32896])).save(&rgb16).unwrap();
+        let decoded = decode_image(&rgb16).unwrap();
+        assert_eq!((decoded.width, decoded.height), (1, 1));
+        assert_eq!((decoded.format, decoded.pixels), (glow::RGB, vec![255, 0, 128]));
+
+        let hdr = directory.join("ggl_float.hdr");
+        image::Rgb32FImage::from_pixel(1, 1, image::Rgb([1., 0.5, 0.])).save(&hdr).unwrap();
+        let decoded = decode_image(&hdr).unwrap();
+        assert_eq!(decoded.format, glow::RGB);
+        assert_eq!(decoded.pixels.len(), 3);
+        assert_eq!(decoded.pixels[0], 255);
+    }
+}
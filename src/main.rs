@@ -1,31 +1,16 @@
-mod loaders;
-mod ui;
-mod renderer;
-
-mod camera;
-mod ecs;
-mod egui_drawable;
-mod input;
-mod light;
-mod light_system;
-mod scene;
-mod shader;
-mod texture;
-mod transform;
-mod app;
-mod gl;
-
-mod model;
-mod mesh;
-
-use app::App;
-use scene::Scene;
-use crate::gl::get_gl;
-use crate::input::InputSystem;
-use crate::transform::Transform;
-use crate::light_system::light_system;
+use ggl::{app::App, headless::HeadlessArgs, scene::Scene};
 
 fn main() {
+    if let Some(args) = HeadlessArgs::from_env() {
+        match args.and_then(|args| args.run()) {
+            Ok(()) => return,
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        }
+    }
+
     let (window_width, window_height) = (1280, 720);
 
     let (mut app, event_loop) = App::init(window_width, window_height);
@@ -1,44 +1,42 @@
-use std::{mem::size_of, rc::Rc};
+use std::{mem::size_of, sync::Arc};
 
 use byteorder::{LittleEndian, WriteBytesExt};
 use glow::HasContext;
 use image::EncodableLayout;
+use nalgebra_glm::Mat4;
 
-use crate::{gl::get_gl, shader::{UniformMap}, renderer::{Material, MaterialType}, loaders::ShaderLoader};
+use crate::{gl::get_gl, renderer::Material};
 use std::hash::{Hasher, Hash};
 
-#[derive(Hash)]
+/// A mesh, its material and where the mesh sits in its model. The matrix is the identity except
+/// for models made of several placed meshes, like the whole of a glTF file.
 #[derive(Clone)]
-pub struct MeshRenderer(pub Rc<Mesh>, pub Material);
+pub struct MeshRenderer(pub Arc<Mesh>, pub Material, pub Mat4);
 
-impl MeshRenderer {
-    pub fn new(mesh: Rc<Mesh>, material: Material) -> Self {
-        Self ( mesh, material )
+impl Hash for MeshRenderer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+        self.1.hash(state);
     }
+}
 
-    pub fn draw(&self, uniforms: &UniformMap) {
-        let MeshRenderer(mesh, material) = self;
-
-        material.shader.use_program();
-        material.upload_uniforms(uniforms, "");
+impl MeshRenderer {
+    pub fn new(mesh: Arc<Mesh>, material: Material) -> Self {
+        Self ( mesh, material, Mat4::identity() )
+    }
 
-        let prefix = match material.material_type {
-            MaterialType::Lit          => "u_material.",
-            _                          => ""
-        };
+    pub fn with_matrix(mesh: Arc<Mesh>, material: Material, matrix: Mat4) -> Self {
+        Self ( mesh, material, matrix )
+    }
 
-        material.upload_textures(prefix);
+    /// The camera and lights come from the uniform blocks, only the model matrix is per mesh.
+    pub fn draw(&self, model: &Mat4) {
+        let MeshRenderer(mesh, material, matrix) = self;
 
-        unsafe {
-            let gl_rc = get_gl();
-            gl_rc.bind_vertex_array(Some(mesh.vao));
-            gl_rc.draw_elements(
-                glow::TRIANGLES,
-                mesh.ind_data.len() as i32,
-                glow::UNSIGNED_INT,
-                0,
-            );
-        }
+        material.shader.borrow().use_program().set_mat4("model", model * matrix);
+        material.upload_parameters();
+        material.upload_textures();
+        mesh.draw();
     }
 
     pub fn set_material(&mut self, mat: Material) {
@@ -96,6 +94,31 @@ impl Mesh {
         mesh
     }
 
+    /// Draws the geometry with whatever shader is currently in use.
+    pub fn draw(&self) {
+        unsafe {
+            let gl_rc = get_gl();
+            gl_rc.bind_vertex_array(Some(self.vao));
+            gl_rc.draw_elements(
+                glow::TRIANGLES,
+                self.ind_data.len() as i32,
+                glow::UNSIGNED_INT,
+                0,
+            );
+        }
+    }
+
+    /// Frees the GPU buffers, the mesh can't be drawn afterwards.
+    /// Meshes are shared between models, so only call this on the last user.
+    pub fn delete(&self) {
+        unsafe {
+            let gl_rc = get_gl();
+            gl_rc.delete_vertex_array(self.vao);
+            gl_rc.delete_buffer(self.vbo);
+            gl_rc.delete_buffer(self.ebo);
+        }
+    }
+
     fn setup_mesh(&mut self) {
         let gl_rc = get_gl();
         unsafe {
@@ -1,6 +1,4 @@
-use obj::{ObjError, MtlLibsLoadError};
 use crate::enabled_header;
-use crate::loaders::DEFAULT_CUBE_NAME;
 use crate::mesh::MeshRenderer;
 use crate::egui_drawable::EguiDrawable;
 
@@ -11,6 +9,10 @@ pub struct Model {
     pub enabled: bool,
     pub mesh_renderers: Vec<MeshRenderer>,
     pub directory: String,
+
+    /// Asset path, or built-in model name, this model was loaded from.
+    /// Clones keep the source of the original model.
+    pub source: String,
 }
 
 impl Model {
@@ -19,7 +21,8 @@ impl Model {
             name: name.into(),
             directory: directory.into(),
             enabled: true,
-            mesh_renderers: meshes
+            mesh_renderers: meshes,
+            source: String::new()
         }
     }
 
@@ -36,41 +39,13 @@ impl Model {
 impl EguiDrawable for Model {
     fn on_egui(&mut self, ui: &mut egui::Ui, index: usize) -> bool {
         enabled_header!(self, ui, "Model", index, {
-            ui.horizontal(|ui| {
-                ui.label(format!("Name: {}", self.name));
-
-                if ui.button("Load model").clicked() {
-                    let path = rfd::FileDialog::new().add_filter("Object model", &["obj"]).pick_file(); 
-                    if let Some(path) = path { 
-                        let t = path.to_str().unwrap_or(DEFAULT_CUBE_NAME).to_owned();
-                        self.name = t;
-                    }
-                }
-            });
-
-            ui.vertical(|_ui| {
-
-            });
+            ui.label(format!("Name: {}", self.name));
+            ui.label(format!("{} meshes", self.mesh_renderers.len()));
+            if !self.source.is_empty() {
+                ui.label(format!("Loaded from {}", self.source));
+            }
         });
         false
     }
 }
 
-
-#[derive(Debug)]
-pub enum ObjLoadError {
-    Obj(ObjError),
-    Mtl(MtlLibsLoadError),
-}
-
-impl From<ObjError> for ObjLoadError {
-    fn from(value: ObjError) -> Self {
-        ObjLoadError::Obj(value)
-    }
-}
-
-impl From<MtlLibsLoadError> for ObjLoadError {
-    fn from(value: MtlLibsLoadError) -> Self {
-        ObjLoadError::Mtl(value)
-    }
-}
@@ -0,0 +1,74 @@
+//! Messages for the user that shouldn't stop the editor, mostly assets failing to load.
+//!
+//! Anything can report from anywhere (loaders don't know about the UI), the log panel and toasts
+//! read them back every frame. Everything is also printed to stderr for headless runs.
+
+use std::{fmt::Display, sync::Mutex, time::Instant};
+
+/// Oldest notifications are dropped past this.
+const MAX_NOTIFICATIONS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub severity: Severity,
+    pub message: String,
+    pub time: Instant,
+}
+
+static NOTIFICATIONS: Mutex<Vec<Notification>> = Mutex::new(Vec::new());
+
+pub fn notify(severity: Severity, message: impl Display) {
+    let message = message.to_string();
+
+    match severity {
+        Severity::Info => println!("{message}"),
+        Severity::Warning | Severity::Error => eprintln!("{message}"),
+    }
+
+    let mut notifications = NOTIFICATIONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if notifications.len() >= MAX_NOTIFICATIONS {
+        notifications.remove(0);
+    }
+
+    notifications.push(Notification { severity, message, time: Instant::now() });
+}
+
+pub fn warn(message: impl Display) {
+    notify(Severity::Warning, message)
+}
+
+pub fn error(message: impl Display) {
+    notify(Severity::Error, message)
+}
+
+/// Copy of every notification still kept, oldest first.
+pub fn notifications() -> Vec<Notification> {
+    NOTIFICATIONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+pub fn clear() {
+    NOTIFICATIONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_reported_messages() {
+        // Other tests report too, so only look for ours
+        error("Texture \"tests/missing.png\" not found");
+
+        let found = notifications()
+            .into_iter()
+            .any(|n| n.severity == Severity::Error && n.message == "Texture \"tests/missing.png\" not found");
+        assert!(found);
+    }
+}
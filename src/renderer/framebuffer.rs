@@ -0,0 +1,92 @@
+use glow::HasContext;
+use image::RgbaImage;
+
+use crate::gl::get_gl;
+
+/// Offscreen render target with an RGBA8 color attachment and a depth attachment.
+pub struct Framebuffer {
+    fbo: glow::Framebuffer,
+    color: glow::Renderbuffer,
+    depth: glow::Renderbuffer,
+    width: u32,
+    height: u32,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        let gl = get_gl();
+
+        unsafe {
+            let fbo = gl.create_framebuffer().unwrap();
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(fbo));
+
+            let color = gl.create_renderbuffer().unwrap();
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(color));
+            gl.renderbuffer_storage(glow::RENDERBUFFER, glow::RGBA8, width as i32, height as i32);
+            gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::RENDERBUFFER, Some(color));
+
+            let depth = gl.create_renderbuffer().unwrap();
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(depth));
+            gl.renderbuffer_storage(glow::RENDERBUFFER, glow::DEPTH24_STENCIL8, width as i32, height as i32);
+            gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::DEPTH_STENCIL_ATTACHMENT, glow::RENDERBUFFER, Some(depth));
+
+            let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+            assert_eq!(status, glow::FRAMEBUFFER_COMPLETE, "Incomplete framebuffer ({width}x{height})");
+
+            gl.bind_renderbuffer(glow::RENDERBUFFER, None);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+
+            Framebuffer { fbo, color, depth, width, height }
+        }
+    }
+
+    /// Binds the framebuffer for drawing and sets the viewport to cover it.
+    pub fn bind(&self) {
+        let gl = get_gl();
+
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fbo));
+            gl.viewport(0, 0, self.width as i32, self.height as i32);
+        }
+    }
+
+    pub fn unbind(&self) {
+        unsafe { get_gl().bind_framebuffer(glow::FRAMEBUFFER, None); }
+    }
+
+    /// Reads the color attachment, flipped so the first row is the top of the image.
+    pub fn read_pixels(&self) -> RgbaImage {
+        let gl = get_gl();
+        let mut pixels = vec![0u8; (self.width * self.height * 4) as usize];
+
+        unsafe {
+            gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(self.fbo));
+            gl.pixel_store_i32(glow::PACK_ALIGNMENT, 1);
+            gl.read_pixels(
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(&mut pixels)
+            );
+            gl.bind_framebuffer(glow::READ_FRAMEBUFFER, None);
+        }
+
+        let image = RgbaImage::from_raw(self.width, self.height, pixels).unwrap();
+        image::imageops::flip_vertical(&image)
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        let gl = get_gl();
+
+        unsafe {
+            gl.delete_framebuffer(self.fbo);
+            gl.delete_renderbuffer(self.color);
+            gl.delete_renderbuffer(self.depth);
+        }
+    }
+}
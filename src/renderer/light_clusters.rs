@@ -0,0 +1,473 @@
+//! Clustered forward lighting.
+//!
+//! The view frustum is split into `CLUSTER_GRID` clusters, tiles across the screen times slices
+//! in depth that get exponentially deeper away from the camera. Every frame `LightClusters::assign`
+//! lists the point and spot lights whose range reaches each cluster, so the lit shader only loops
+//! over the lights of the cluster a fragment is in instead of every light in the scene.
+//!
+//! The lights and the lists reach the shaders through the two textures in `LightBuffers`,
+//! `common/lighting.glsl` reads them back.
+
+use glow::HasContext;
+use nalgebra_glm::{self as glm, vec4, Mat4, Vec2, Vec3, Vec4};
+
+use crate::{
+    gl::get_gl,
+    renderer::{MAX_POINT_SHADOWS, POINT_SHADOW_UNITS_START},
+};
+
+/// Tiles across and down the screen, and depth slices.
+/// Passed to the shaders as the `CLUSTERS_X`, `CLUSTERS_Y` and `CLUSTERS_Z` defines.
+pub const CLUSTER_GRID: [usize; 3] = [16, 9, 24];
+pub const CLUSTER_COUNT: usize = CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2];
+
+/// View space depths the slices are spread between. The first slice reaches up to the camera
+/// and the last one out to the far plane, so nothing closer or further is left out.
+pub const CLUSTER_DEPTH_RANGE: (f32, f32) = (0.5, 200.0);
+
+/// Texels of a row of the `LightBuffers` textures, the largest width GL 3.3 guarantees.
+/// Passed to the shaders as the `DATA_TEXTURE_WIDTH` define.
+pub const DATA_TEXTURE_WIDTH: usize = 1024;
+
+/// Texture units of the `LightBuffers` textures, after the shadow maps. The last of them has to
+/// stay below the 16 units GL 3.3 guarantees.
+pub const LIGHT_DATA_UNIT: u32 = POINT_SHADOW_UNITS_START + MAX_POINT_SHADOWS as u32;
+pub const LIGHT_CLUSTERS_UNIT: u32 = LIGHT_DATA_UNIT + 1;
+const _: () = assert!(LIGHT_CLUSTERS_UNIT < 16, "Texture units past the 16 GL 3.3 guarantees");
+
+/// `PointLight` in `common/lighting.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GpuPointLight {
+    pub position: Vec3,
+
+    /// Distance past which the light is left out, see `light_range`.
+    pub range: f32,
+
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+
+    /// Quadratic, linear and constant.
+    pub attenuation_constants: Vec3,
+    pub is_enabled: bool,
+
+    /// Into `u_point_shadow_maps`, -1 without a shadow map.
+    pub shadow_map_index: i32,
+    pub shadow_bias: f32,
+
+    /// Distance stored as 1.0 in the cube map.
+    pub shadow_far: f32,
+}
+
+impl GpuPointLight {
+    /// As read by `fetchPointLight`.
+    pub fn texels(&self) -> [Vec4; 5] {
+        [
+            self.position.push(self.range),
+            self.ambient.push(self.is_enabled as i32 as f32),
+            self.diffuse.push(self.shadow_map_index as f32),
+            self.specular.push(self.shadow_bias),
+            self.attenuation_constants.push(self.shadow_far),
+        ]
+    }
+}
+
+/// `SpotLight` in `common/lighting.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GpuSpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+
+    /// Distance past which the light is left out, see `light_range`.
+    pub range: f32,
+
+    /// Cosines of the inner and outer cutoff angles.
+    pub cutoff_cos: Vec2,
+
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+
+    /// Quadratic, linear and constant.
+    pub attenuation_constants: Vec3,
+    pub is_enabled: bool,
+
+    /// Into `u_spot_shadow_maps`, -1 without a shadow map.
+    pub shadow_map_index: i32,
+    pub shadow_bias: f32,
+    pub light_space: Mat4,
+}
+
+impl GpuSpotLight {
+    /// As read by `fetchSpotLight`.
+    pub fn texels(&self) -> [Vec4; 10] {
+        let column = |index: usize| self.light_space.column(index).into_owned();
+
+        [
+            self.position.push(self.range),
+            self.direction.push(self.cutoff_cos.x),
+            self.ambient.push(self.cutoff_cos.y),
+            self.diffuse.push(self.is_enabled as i32 as f32),
+            self.specular.push(self.shadow_map_index as f32),
+            self.attenuation_constants.push(self.shadow_bias),
+            column(0),
+            column(1),
+            column(2),
+            column(3),
+        ]
+    }
+}
+
+/// Slice of `CLUSTER_GRID` that a view space `depth` (positive in front of the camera) is in.
+pub fn depth_slice(depth: f32) -> usize {
+    let (near, far) = CLUSTER_DEPTH_RANGE;
+    let slices = CLUSTER_GRID[2];
+
+    // NaN and negative infinity for depths at or behind the camera, both end up in the first slice
+    let slice = (depth / near).ln() / (far / near).ln() * slices as f32;
+    (slice.floor().max(0.) as usize).min(slices - 1)
+}
+
+/// Near and far plane of a perspective projection.
+fn projection_depth_range(projection: &Mat4) -> (f32, f32) {
+    let (a, b) = (projection[(2, 2)], projection[(2, 3)]);
+    (b / (a - 1.), b / (a + 1.))
+}
+
+/// View space depths `slice` starts and ends at.
+fn slice_depths(slice: usize, camera_near: f32, camera_far: f32) -> (f32, f32) {
+    let (near, far) = CLUSTER_DEPTH_RANGE;
+    let slices = CLUSTER_GRID[2];
+    let depth = |slice: usize| near * (far / near).powf(slice as f32 / slices as f32);
+
+    let start = if slice == 0 { camera_near } else { depth(slice) };
+    let end = if slice == slices - 1 { camera_far } else { depth(slice + 1) };
+    (start, end)
+}
+
+/// View space bounding box (min, max) of every cluster, in the order of `LightClusters::clusters`.
+fn cluster_bounds(projection: &Mat4) -> Vec<(Vec3, Vec3)> {
+    let [tiles_x, tiles_y, slices] = CLUSTER_GRID;
+    let (camera_near, camera_far) = projection_depth_range(projection);
+    let inverse_projection = glm::inverse(projection);
+
+    // Through a corner of the tiles, reaching a depth of 1
+    let corner_ray = |x: usize, y: usize| {
+        let ndc = vec4(x as f32 / tiles_x as f32 * 2. - 1., y as f32 / tiles_y as f32 * 2. - 1., -1., 1.);
+        let near = inverse_projection * ndc;
+        let near = near.xyz() / near.w;
+        near / -near.z
+    };
+
+    let mut bounds = Vec::with_capacity(CLUSTER_COUNT);
+    for slice in 0..slices {
+        let (start, end) = slice_depths(slice, camera_near, camera_far);
+
+        for y in 0..tiles_y {
+            for x in 0..tiles_x {
+                let (mut min, mut max) = (Vec3::repeat(f32::MAX), Vec3::repeat(f32::MIN));
+                for ray in [corner_ray(x, y), corner_ray(x + 1, y), corner_ray(x, y + 1), corner_ray(x + 1, y + 1)] {
+                    for depth in [start, end] {
+                        min = min.inf(&(ray * depth));
+                        max = max.sup(&(ray * depth));
+                    }
+                }
+
+                bounds.push((min, max));
+            }
+        }
+    }
+
+    bounds
+}
+
+/// Clusters a sphere at view space `center` reaches.
+fn clusters_reached(bounds: &[(Vec3, Vec3)], center: Vec3, radius: f32) -> impl Iterator<Item = usize> + '_ {
+    let depth = -center.z;
+    let first_slice = depth_slice(depth - radius);
+    let last_slice = depth_slice(depth + radius);
+    let per_slice = CLUSTER_GRID[0] * CLUSTER_GRID[1];
+
+    (first_slice * per_slice..(last_slice + 1) * per_slice).filter(move |&cluster| {
+        let (min, max) = bounds[cluster];
+        let closest = center.sup(&min).inf(&max);
+        (closest - center).norm_squared() <= radius * radius
+    })
+}
+
+/// Lights reaching each cluster of the view frustum.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LightClusters {
+    /// Offset into `indices`, point light count and spot light count of every cluster.
+    /// x changes fastest, then y, then the depth slice.
+    pub clusters: Vec<[u32; 3]>,
+
+    /// The point lights of each cluster followed by its spot lights, as indices into the light lists.
+    pub indices: Vec<u32>,
+}
+
+impl LightClusters {
+    /// Assigns enabled lights to the clusters their range reaches.
+    /// Spot lights are treated as spheres, which is conservative but keeps the test cheap.
+    pub fn assign(point_lights: &[GpuPointLight], spot_lights: &[GpuSpotLight], view: &Mat4, projection: &Mat4) -> Self {
+        let bounds = cluster_bounds(projection);
+        let view_position = |position: Vec3| (view * position.push(1.)).xyz();
+
+        let mut point_lists = vec![Vec::new(); CLUSTER_COUNT];
+        for (index, light) in point_lights.iter().enumerate().filter(|(_, light)| light.is_enabled) {
+            for cluster in clusters_reached(&bounds, view_position(light.position), light.range) {
+                point_lists[cluster].push(index as u32);
+            }
+        }
+
+        let mut spot_lists = vec![Vec::new(); CLUSTER_COUNT];
+        for (index, light) in spot_lights.iter().enumerate().filter(|(_, light)| light.is_enabled) {
+            for cluster in clusters_reached(&bounds, view_position(light.position), light.range) {
+                spot_lists[cluster].push(index as u32);
+            }
+        }
+
+        let mut light_clusters = LightClusters { clusters: Vec::with_capacity(CLUSTER_COUNT), indices: Vec::new() };
+        for (points, spots) in point_lists.into_iter().zip(spot_lists) {
+            light_clusters.clusters.push([light_clusters.indices.len() as u32, points.len() as u32, spots.len() as u32]);
+            light_clusters.indices.extend(points);
+            light_clusters.indices.extend(spots);
+        }
+
+        light_clusters
+    }
+
+    /// Cluster at tile `x`, `y` of depth slice `z`.
+    pub fn cluster(&self, x: usize, y: usize, z: usize) -> [u32; 3] {
+        self.clusters[x + CLUSTER_GRID[0] * (y + CLUSTER_GRID[1] * z)]
+    }
+
+    /// Point and spot lights of a cluster.
+    pub fn lights(&self, [offset, points, spots]: [u32; 3]) -> (&[u32], &[u32]) {
+        let (offset, points, spots) = (offset as usize, points as usize, spots as usize);
+        (&self.indices[offset..offset + points], &self.indices[offset + points..offset + points + spots])
+    }
+
+    /// As read by `clusterData`: `clusters` with the offsets moved past them, then `indices`.
+    pub fn gpu_data(&self) -> Vec<u32> {
+        let header_len = (self.clusters.len() * 3) as u32;
+
+        self.clusters
+            .iter()
+            .flat_map(|[offset, points, spots]| [header_len + offset, *points, *spots])
+            .chain(self.indices.iter().copied())
+            .collect()
+    }
+}
+
+/// The point and spot lights and the `LightClusters`, as textures for the lit shaders.
+///
+/// glow doesn't expose `glTexBuffer`, so these are 2D textures `DATA_TEXTURE_WIDTH` texels wide
+/// standing in for texture buffers. Shaders read them with `texelFetch` a texel at a time.
+pub struct LightBuffers {
+    /// RGBA32F, `GpuPointLight::texels` of every point light followed by `GpuSpotLight::texels`.
+    light_data: glow::Texture,
+
+    /// R32UI, `LightClusters::gpu_data`.
+    clusters: glow::Texture,
+}
+
+impl LightBuffers {
+    pub fn new() -> Self {
+        LightBuffers { light_data: Self::create_texture(), clusters: Self::create_texture() }
+    }
+
+    fn create_texture() -> glow::Texture {
+        unsafe {
+            let gl = get_gl();
+            let texture = gl.create_texture().expect("Failed to create a light data texture");
+
+            // Only read with texelFetch, and integer textures can't be filtered anyway
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            texture
+        }
+    }
+
+    /// Fills `texture` with rows of `texels`, each `texel_size` bytes. The last row is padded with zeros.
+    fn upload_rows(texture: glow::Texture, internal_format: u32, format: u32, ty: u32, mut bytes: Vec<u8>, texel_size: usize) {
+        let row_size = DATA_TEXTURE_WIDTH * texel_size;
+        let rows = bytes.len().div_ceil(row_size).max(1);
+        bytes.resize(rows * row_size, 0);
+
+        unsafe {
+            let gl = get_gl();
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                internal_format as i32,
+                DATA_TEXTURE_WIDTH as i32,
+                rows as i32,
+                0,
+                format,
+                ty,
+                Some(&bytes)
+            );
+            gl.bind_texture(glow::TEXTURE_2D, None);
+        }
+    }
+
+    pub fn upload(&self, point_lights: &[GpuPointLight], spot_lights: &[GpuSpotLight], clusters: &LightClusters) {
+        let light_data: Vec<u8> = point_lights
+            .iter()
+            .flat_map(GpuPointLight::texels)
+            .chain(spot_lights.iter().flat_map(GpuSpotLight::texels))
+            .flat_map(<[f32; 4]>::from)
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        Self::upload_rows(self.light_data, glow::RGBA32F, glow::RGBA, glow::FLOAT, light_data, 16);
+
+        let cluster_data: Vec<u8> = clusters.gpu_data().into_iter().flat_map(u32::to_ne_bytes).collect();
+        Self::upload_rows(self.clusters, glow::R32UI, glow::RED_INTEGER, glow::UNSIGNED_INT, cluster_data, 4);
+    }
+
+    pub fn bind_textures(&self) {
+        unsafe {
+            let gl = get_gl();
+            gl.active_texture(glow::TEXTURE0 + LIGHT_DATA_UNIT);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.light_data));
+            gl.active_texture(glow::TEXTURE0 + LIGHT_CLUSTERS_UNIT);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.clusters));
+        }
+    }
+}
+
+impl Default for LightBuffers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::vec3;
+
+    fn camera() -> (Mat4, Mat4) {
+        let view = glm::look_at(&vec3(0., 0., 0.), &vec3(0., 0., -1.), &vec3(0., 1., 0.));
+        let projection = glm::perspective(16. / 9., 90f32.to_radians(), 0.01, 1000.);
+        (view, projection)
+    }
+
+    fn point_light(position: Vec3, range: f32) -> GpuPointLight {
+        GpuPointLight { position, range, is_enabled: true, shadow_map_index: -1, ..Default::default() }
+    }
+
+    /// Clusters a view space position is in, the same way `clusterIndex` picks one.
+    fn cluster_at(position: Vec3, projection: &Mat4) -> (usize, usize, usize) {
+        let clip = projection * position.push(1.);
+        let ndc = clip.xy() / clip.w;
+        let tile = |ndc: f32, tiles: usize| (((ndc * 0.5 + 0.5) * tiles as f32) as usize).min(tiles - 1);
+
+        (tile(ndc.x, CLUSTER_GRID[0]), tile(ndc.y, CLUSTER_GRID[1]), depth_slice(-position.z))
+    }
+
+    #[test]
+    fn slices_cover_the_depth_range() {
+        let (near, far) = CLUSTER_DEPTH_RANGE;
+
+        assert_eq!(depth_slice(0.), 0);
+        assert_eq!(depth_slice(-5.), 0);
+        assert_eq!(depth_slice(near), 0);
+        assert_eq!(depth_slice(far * 10.), CLUSTER_GRID[2] - 1);
+
+        let (_, projection) = camera();
+        assert!((projection_depth_range(&projection).0 - 0.01).abs() < 1e-5);
+
+        for slice in 0..CLUSTER_GRID[2] {
+            let (start, end) = slice_depths(slice, 0.01, 1000.);
+            assert_eq!(depth_slice((start + end) / 2.), slice);
+        }
+    }
+
+    #[test]
+    fn lights_reach_the_clusters_around_them() {
+        let (view, projection) = camera();
+        let position = vec3(1., 0.5, -10.);
+        let clusters = LightClusters::assign(&[point_light(position, 2.)], &[], &view, &projection);
+
+        let (x, y, z) = cluster_at(position, &projection);
+        assert_eq!(clusters.lights(clusters.cluster(x, y, z)), (&[0][..], &[][..]));
+
+        // Just within reach in front of and behind the light
+        assert_eq!(clusters.lights(clusters.cluster(x, y, depth_slice(8.1))).0, [0]);
+        assert_eq!(clusters.lights(clusters.cluster(x, y, depth_slice(11.9))).0, [0]);
+
+        // Out of reach
+        assert!(clusters.lights(clusters.cluster(x, y, depth_slice(5.))).0.is_empty());
+        assert!(clusters.lights(clusters.cluster(0, 0, z)).0.is_empty());
+
+        let reached = clusters.clusters.iter().filter(|[_, points, _]| *points > 0).count();
+        assert!(reached < CLUSTER_COUNT / 20, "{reached} clusters");
+    }
+
+    #[test]
+    fn more_lights_than_the_old_limit() {
+        let (view, projection) = camera();
+        let lights: Vec<_> = (0..300)
+            .map(|index| point_light(vec3((index % 20) as f32 - 10., (index / 20) as f32 - 7., -12.), 0.8))
+            .collect();
+
+        let clusters = LightClusters::assign(&lights, &[], &view, &projection);
+
+        for (index, light) in lights.iter().enumerate() {
+            let (x, y, z) = cluster_at(light.position, &projection);
+            assert!(clusters.lights(clusters.cluster(x, y, z)).0.contains(&(index as u32)), "light {index}");
+        }
+
+        // Each cluster only gets the handful of lights near it
+        let most = clusters.clusters.iter().map(|[_, points, _]| *points).max().unwrap();
+        assert!(most < 50, "{most} lights in a cluster");
+    }
+
+    #[test]
+    fn skips_lights_out_of_view() {
+        let (view, projection) = camera();
+        let behind = point_light(vec3(0., 0., 5.), 2.);
+        let disabled = GpuPointLight { is_enabled: false, ..point_light(vec3(0., 0., -5.), 2.) };
+        let spot = GpuSpotLight { position: vec3(0., 0., -5.), range: 2., is_enabled: true, ..Default::default() };
+
+        let clusters = LightClusters::assign(&[behind, disabled], &[spot], &view, &projection);
+
+        assert!(clusters.clusters.iter().all(|[_, points, _]| *points == 0));
+        assert!(clusters.clusters.iter().any(|[_, _, spots]| *spots > 0));
+        assert!(clusters.indices.iter().all(|&index| index == 0));
+    }
+
+    #[test]
+    fn gpu_data_offsets_skip_the_clusters() {
+        let clusters = LightClusters { clusters: vec![[0, 1, 0], [1, 1, 1]], indices: vec![4, 2, 3] };
+
+        assert_eq!(clusters.gpu_data(), [6, 1, 0, 7, 1, 1, 4, 2, 3]);
+        assert_eq!(clusters.lights(clusters.clusters[1]), (&[2][..], &[3][..]));
+    }
+
+    #[test]
+    fn light_texels() {
+        let light = GpuSpotLight {
+            range: 5.,
+            cutoff_cos: glm::vec2(0.9, 0.8),
+            is_enabled: true,
+            shadow_map_index: 2,
+            light_space: Mat4::identity(),
+            ..Default::default()
+        };
+        let texels = light.texels();
+
+        assert_eq!(texels[0].w, 5.);
+        assert_eq!((texels[1].w, texels[2].w), (0.9, 0.8));
+        assert_eq!((texels[3].w, texels[4].w), (1., 2.));
+        assert_eq!(texels[8], vec4(0., 0., 1., 0.));
+
+        assert_eq!(point_light(Vec3::zeros(), 3.).texels()[2].w, -1.);
+    }
+}
@@ -1,54 +1,373 @@
-use std::rc::Rc;
+use std::{collections::BTreeMap, hash::{Hash, Hasher}};
+
+use nalgebra_glm::{vec3, vec4};
+
+use serde::{Serialize, Deserialize};
 
 use crate::{
-    loaders::*,
+    loaders::{*, utils::Handle},
     texture::{Texture2D, TextureType}, 
-    shader::{UniformMap, ShaderProgram, self}, 
+    shader::{Uniform, UniformMap, UniformType, ShaderDefines, ShaderProgram},
 };
 
+/// A value a material sets on one of its shader's uniforms.
+/// Untagged so parameters read like the structs scenes were saved with before. Whole numbers read
+/// as ints, `MaterialParameters::coerce_to` turns them back into floats where the shader wants one.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MaterialValue {
+    Int(i32),
+    Float(f32),
+    Color([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl MaterialValue {
+    pub fn uniform(self) -> Uniform {
+        match self {
+            MaterialValue::Int(int) => Uniform::Int(int),
+            MaterialValue::Float(float) => Uniform::Float(float),
+            MaterialValue::Color([r, g, b]) => Uniform::Color(vec3(r, g, b)),
+            MaterialValue::Vec4([x, y, z, w]) => Uniform::Vec4(vec4(x, y, z, w)),
+        }
+    }
+
+    /// This value as the type a uniform is declared with, when it's a float saved as a whole number.
+    pub fn coerced(self, declared: UniformType) -> Self {
+        match (self, declared) {
+            (MaterialValue::Int(int), UniformType::Float) => MaterialValue::Float(int as f32),
+            (value, _) => value,
+        }
+    }
+
+    fn bits(&self) -> Vec<u32> {
+        match self {
+            MaterialValue::Int(int) => vec![*int as u32],
+            MaterialValue::Float(float) => vec![float.to_bits()],
+            MaterialValue::Color(color) => color.iter().map(|value| value.to_bits()).collect(),
+            MaterialValue::Vec4(vector) => vector.iter().map(|value| value.to_bits()).collect(),
+        }
+    }
+}
+
+impl From<i32> for MaterialValue {
+    fn from(value: i32) -> Self { MaterialValue::Int(value) }
+}
+
+impl From<f32> for MaterialValue {
+    fn from(value: f32) -> Self { MaterialValue::Float(value) }
+}
+
+impl From<[f32; 3]> for MaterialValue {
+    fn from(value: [f32; 3]) -> Self { MaterialValue::Color(value) }
+}
+
+impl From<[f32; 4]> for MaterialValue {
+    fn from(value: [f32; 4]) -> Self { MaterialValue::Vec4(value) }
+}
+
+impl Eq for MaterialValue {}
+
+impl Hash for MaterialValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        self.bits().hash(state);
+    }
+}
+
+/// Values the shaders combine with the material's textures, by uniform name inside `u_material`.
+/// Each material type starts with the ones its shader reads, see `MaterialParameters::defaults`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MaterialParameters(BTreeMap<String, MaterialValue>);
+
+impl MaterialParameters {
+    pub fn defaults(material_type: MaterialType) -> Self {
+        let mut parameters = MaterialParameters::default();
+
+        match material_type {
+            MaterialType::Lit => parameters
+                // Multiplied with the diffuse texture under ambient light
+                .set("ambient_color", [1., 1., 1.])
+                .set("diffuse_color", [1., 1., 1.])
+                .set("specular_color", [1., 1., 1.])
+                // Added on top of the lighting, multiplied with the emissive texture if there is one
+                .set("emissive_color", [0., 0., 0.])
+                .set("shininess", 32.),
+            MaterialType::Pbr => parameters
+                .set("base_color", [1., 1., 1.])
+                // Multiplied with the metallic and roughness maps
+                .set("metallic", 0.)
+                .set("roughness", 0.5)
+                // How much of the occlusion map is applied
+                .set("occlusion_strength", 1.)
+                .set("emissive_color", [0., 0., 0.]),
+            MaterialType::Unlit | MaterialType::Billboard => parameters
+                .set("diffuse_color", [1., 1., 1.]),
+        };
+
+        // Only has an effect on transparent materials
+        parameters.set("opacity", 1.);
+        parameters
+    }
+
+    pub fn get(&self, name: &str) -> Option<MaterialValue> {
+        self.0.get(name).copied()
+    }
+
+    pub fn float(&self, name: &str) -> Option<f32> {
+        match self.get(name)? {
+            MaterialValue::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self, name: &str) -> Option<[f32; 3]> {
+        match self.get(name)? {
+            MaterialValue::Color(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<MaterialValue>) -> &mut Self {
+        self.0.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<MaterialValue> {
+        self.0.remove(name)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, MaterialValue) -> bool) {
+        self.0.retain(|name, value| keep(name, *value));
+    }
+
+    /// Sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, MaterialValue)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), *value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Converts the values to the types `shader` declares them with in `u_material`, see `MaterialValue::coerced`.
+    pub fn coerce_to(&mut self, shader: &ShaderProgram) {
+        for (name, value) in self.0.iter_mut() {
+            if let Some(uniform) = shader.uniform(&format!("{MATERIAL_UNIFORM}{name}")) {
+                *value = value.coerced(uniform.uniform_type);
+            }
+        }
+    }
+
+    /// These with `overrides` replacing the values they have too.
+    pub fn with_overrides(&self, overrides: &MaterialParameters) -> MaterialParameters {
+        let mut parameters = self.clone();
+        parameters.0.extend(overrides.0.iter().map(|(name, value)| (name.clone(), *value)));
+        parameters
+    }
+
+    /// The defaults of `material_type` with the values it has too taken from these. The PBR base
+    /// color is the diffuse color of the others.
+    pub fn converted(&self, material_type: MaterialType) -> MaterialParameters {
+        let mut parameters = MaterialParameters::defaults(material_type);
+
+        for (name, value) in self.iter() {
+            let name = match name {
+                "diffuse_color" if material_type == MaterialType::Pbr => "base_color",
+                "base_color" => "diffuse_color",
+                name => name,
+            };
+
+            if parameters.get(name).is_some() {
+                parameters.set(name, value);
+            }
+        }
+
+        parameters
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Hash, Copy, Clone, PartialEq, Eq)]
+#[derive(Hash, Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum MaterialType {
     Unlit,
     Lit,
-    Billboard
+    Billboard,
+
+    /// Metallic-roughness materials lit with `DEFAULT_PBR_SHADER`.
+    Pbr,
 }
 
+impl MaterialType {
+    /// The types a material can be switched to in the editor, see `Material::with_type`.
+    /// Billboards only make sense on the planes made for them.
+    pub const EDITABLE: [MaterialType; 3] = [MaterialType::Lit, MaterialType::Pbr, MaterialType::Unlit];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MaterialType::Unlit => "Unlit",
+            MaterialType::Lit => "Lit (Phong)",
+            MaterialType::Billboard => "Billboard",
+            MaterialType::Pbr => "PBR",
+        }
+    }
+}
+
+/// Uniform struct the lit, PBR and unlit textured shaders read their textures and parameters from.
+pub const MATERIAL_UNIFORM: &str = "u_material.";
+
 #[derive(Hash, Clone, Eq)]
 pub struct Material {
-    pub shader: Rc<ShaderProgram>,
+    /// Shown in the editor, from the model file if it named the material.
+    pub name: String,
+    pub shader: Handle<ShaderProgram>,
     pub material_type: MaterialType,
     pub textures: Vec<Texture2D>,
-    pub transparent: bool
+    pub transparent: bool,
+    pub parameters: MaterialParameters,
+
+    /// Material this is an instance of, see `Material::instance`. Its textures and parameters
+    /// are used unless this one has its own for the same slot or name.
+    pub base: Option<Handle<Material>>,
 }
 
 impl Material {
-    pub fn billboard(shader_loader: &mut ShaderLoader, tex: Texture2D) -> Self {
+    fn new(shader: Handle<ShaderProgram>, material_type: MaterialType, textures: Vec<Texture2D>, transparent: bool) -> Self {
         Material {
-            shader : shader_loader.get_shader_rc(DEFAULT_BILLBOARD_SHADER),
-            material_type: MaterialType::Billboard,
-            textures     : vec![tex],
-            transparent  : true
+            name: material_type.label().to_string(),
+            shader,
+            material_type,
+            textures,
+            transparent,
+            parameters: MaterialParameters::defaults(material_type),
+            base: None,
         }
     }
 
+    pub fn billboard(shader_loader: &mut ShaderLoader, tex: Texture2D) -> Self {
+        Self::new(shader_loader.get_shader_rc(DEFAULT_BILLBOARD_SHADER), MaterialType::Billboard, vec![tex], true)
+    }
+
     pub fn lit(shader_loader: &mut ShaderLoader, textures: Vec<Texture2D>) -> Self {
+        let shader = shader_loader.get_variant(DEFAULT_LIT_SHADER, &Self::variant_defines(MaterialType::Lit, &textures));
+        Self::new(shader, MaterialType::Lit, textures, false)
+    }
+
+    /// Metallic-roughness material, see `assets/shaders/pbr.frag` for the maps it reads.
+    pub fn pbr(shader_loader: &mut ShaderLoader, textures: Vec<Texture2D>) -> Self {
+        let shader = shader_loader.get_variant(DEFAULT_PBR_SHADER, &Self::variant_defines(MaterialType::Pbr, &textures));
+        Self::new(shader, MaterialType::Pbr, textures, false)
+    }
+
+    /// Shows the first diffuse texture as is, ignoring lights.
+    pub fn unlit(shader_loader: &mut ShaderLoader, textures: Vec<Texture2D>) -> Self {
+        Self::new(shader_loader.get_shader_rc(DEFAULT_UNLIT_TEXTURED_SHADER), MaterialType::Unlit, textures, false)
+    }
+
+    /// Shares everything with `base` until it's given its own textures or parameters.
+    pub fn instance(base: &Handle<Material>) -> Self {
+        let material = base.borrow();
+
         Material {
-            shader   : shader_loader.get_shader_rc(DEFAULT_LIT_SHADER),
-            material_type: MaterialType::Lit,
-            transparent  : false,
-            textures
+            name: format!("{} instance", material.name),
+            shader: Handle::clone(&material.shader),
+            material_type: material.material_type,
+            textures: Vec::new(),
+            transparent: material.transparent,
+            parameters: MaterialParameters::default(),
+            base: Some(Handle::clone(base)),
         }
     }
 
+    /// Same as `material_type`'s constructor with this material's textures, keeping the parameters
+    /// the new shader has too, see `MaterialParameters::converted`.
+    pub fn with_type(&self, material_type: MaterialType, shader_loader: &mut ShaderLoader) -> Material {
+        let textures = self.resolved_textures();
+
+        let mut material = match material_type {
+            MaterialType::Lit => Material::lit(shader_loader, textures),
+            MaterialType::Pbr => Material::pbr(shader_loader, textures),
+            MaterialType::Unlit => Material::unlit(shader_loader, textures),
+            MaterialType::Billboard => {
+                let Some(texture) = textures.into_iter().next() else { return self.clone() };
+                Material::billboard(shader_loader, texture)
+            }
+        };
+
+        material.parameters = self.resolved_parameters().converted(material_type);
+        material.name = self.name.clone();
+        material.transparent = self.transparent;
+        material
+    }
+
+    /// Whether both look the same, no matter their names or whether they're instances.
+    pub fn same_as(&self, other: &Material) -> bool {
+        self.shader == other.shader
+            && self.material_type == other.material_type
+            && self.transparent == other.transparent
+            && self.resolved_textures() == other.resolved_textures()
+            && self.resolved_parameters() == other.resolved_parameters()
+    }
+
     pub fn set_textures(&mut self, new_tex: Vec<Texture2D>) {
         self.textures = new_tex;
     }
 
+    /// Puts `texture` in the slot of its type and index, replacing the one there (or the base's).
+    pub fn set_texture(&mut self, texture: Texture2D) {
+        let slot = self.textures
+            .iter_mut()
+            .find(|slot| slot.tex_type == texture.tex_type && slot.tex_index == texture.tex_index);
+
+        match slot {
+            Some(slot) => *slot = texture,
+            None => self.textures.push(texture),
+        }
+    }
+
+    /// Textures the shader gets, the base's with this material's in place of the ones in the same slots.
+    pub fn resolved_textures(&self) -> Vec<Texture2D> {
+        let Some(base) = &self.base else { return self.textures.clone() };
+
+        let mut textures = base.borrow().resolved_textures();
+        for texture in &self.textures {
+            let slot = textures
+                .iter_mut()
+                .find(|slot| slot.tex_type == texture.tex_type && slot.tex_index == texture.tex_index);
+
+            match slot {
+                Some(slot) => *slot = texture.clone(),
+                None => textures.push(texture.clone()),
+            }
+        }
+
+        textures
+    }
+
+    /// Parameters the shader gets, the base's with this material's overrides.
+    pub fn resolved_parameters(&self) -> MaterialParameters {
+        match &self.base {
+            Some(base) => base.borrow().resolved_parameters().with_overrides(&self.parameters),
+            None => self.parameters.clone(),
+        }
+    }
+
+    /// Picks the shader variant for the resolved textures, after the slots changed.
+    pub fn update_variant(&mut self, shader_loader: &mut ShaderLoader) {
+        let Some(name) = shader_loader.shader_name(&self.shader).map(str::to_string) else { return };
+        let defines = Self::variant_defines(self.material_type, &self.resolved_textures());
+        self.shader = shader_loader.get_variant(&name, &defines);
+    }
+
     pub fn default_billboard(shader_loader: &mut ShaderLoader, texture_loader: &mut TextureLoader) -> Self {
         let directional_light = texture_loader.directional_light_texture();
-        let diffuse_texture = Texture2D::from_native_handle(
-            directional_light,
+        let diffuse_texture = Texture2D::new(
+            &directional_light,
             crate::texture::TextureType::Diffuse,
             1
         );
@@ -56,24 +375,23 @@ impl Material {
         Self::billboard(shader_loader, diffuse_texture)
     }
 
+    /// A flat color with no textures or parameters.
     pub fn default_unlit(shader_loader: &mut ShaderLoader) -> Self {
         Material {
-            shader   : shader_loader.get_shader_rc(DEFAULT_UNLIT_SHADER),
-            material_type: MaterialType::Unlit,
-            textures     : vec![],
-            transparent  : false
+            parameters: MaterialParameters::default(),
+            ..Self::new(shader_loader.get_shader_rc(DEFAULT_UNLIT_SHADER), MaterialType::Unlit, vec![], false)
         }
     }
 
     pub fn default_lit(shader_loader: &mut ShaderLoader, texture_loader: &mut TextureLoader) -> Self {
-        let checker_diffuse = Texture2D::from_native_handle(
-            texture_loader.checker_texture(),
+        let checker_diffuse = Texture2D::new(
+            &texture_loader.checker_texture(),
             TextureType::Diffuse,
             1
         );
 
-        let white_specular = Texture2D::from_native_handle(
-            texture_loader.white_texture(),
+        let white_specular = Texture2D::new(
+            &texture_loader.white_texture(),
             TextureType::Specular,
             1
         );
@@ -82,11 +400,63 @@ impl Material {
     }
 
     pub fn upload_uniforms(&self, uniforms: &UniformMap, prefix: &str) {
-        self.shader.upload_uniforms(uniforms, prefix);
+        self.shader.borrow().upload_uniforms(uniforms, prefix);
     }
 
-    pub fn upload_textures(&self, prefix: &str) {
-        self.shader.upload_textures(&self.textures, prefix); 
+    pub fn upload_textures(&self) {
+        self.shader.borrow().upload_textures(&self.resolved_textures(), MATERIAL_UNIFORM);
+    }
+
+    pub fn has_texture(&self, tex_type: TextureType) -> bool {
+        self.resolved_textures().iter().any(|texture| texture.tex_type == tex_type)
+    }
+
+    /// Defines picking the shader variant for these textures, see `ShaderLoader::get_variant`.
+    /// Only the lit and PBR shaders have optional maps.
+    pub fn variant_defines(material_type: MaterialType, textures: &[Texture2D]) -> ShaderDefines {
+        let pbr_maps: &[_] = &[
+            (TextureType::MetallicRoughness, "HAS_METALLIC_ROUGHNESS_MAP"),
+            (TextureType::Roughness, "HAS_ROUGHNESS_MAP"),
+            (TextureType::Metallic, "HAS_METALLIC_MAP"),
+            (TextureType::Occlusion, "HAS_OCCLUSION_MAP"),
+        ];
+
+        let maps = match material_type {
+            MaterialType::Lit => &[][..],
+            MaterialType::Pbr => pbr_maps,
+            MaterialType::Unlit | MaterialType::Billboard => return ShaderDefines::new(),
+        };
+
+        [
+            (TextureType::Normal, "HAS_NORMAL_MAP"),
+            (TextureType::Emissive, "HAS_EMISSIVE_MAP"),
+            (TextureType::Opacity, "HAS_OPACITY_MAP"),
+        ]
+        .iter()
+        .chain(maps)
+        .filter(|(tex_type, _)| textures.iter().any(|texture| texture.tex_type == *tex_type))
+        .map(|(_, define)| (define.to_string(), String::new()))
+        .collect()
+    }
+
+    /// Sets the resolved parameters on `u_material`, base ones first so overrides win.
+    pub fn upload_parameters(&self) {
+        self.upload_parameters_to(&self.shader.borrow(), self.transparent);
+    }
+
+    fn upload_parameters_to(&self, shader: &ShaderProgram, transparent: bool) {
+        if let Some(base) = &self.base {
+            base.borrow().upload_parameters_to(shader, transparent);
+        }
+
+        for (name, value) in self.parameters.iter() {
+            let value = match name {
+                "opacity" if !transparent => MaterialValue::Float(1.),
+                _ => value,
+            };
+
+            shader.set_uniform(&format!("{MATERIAL_UNIFORM}{name}"), &value.uniform());
+        }
     }
 }
 
@@ -95,3 +465,66 @@ impl PartialEq for Material {
         self.shader == other.shader
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_parameters_saved_as_structs() {
+        let parameters: MaterialParameters = serde_json::from_str(r#"{
+            "diffuse_color": [0.5, 0.25, 1.0],
+            "shininess": 64.0,
+            "opacity": 1,
+            "tint": [1.0, 0.0, 0.0, 0.5]
+        }"#).unwrap();
+
+        assert_eq!(parameters.color("diffuse_color"), Some([0.5, 0.25, 1.]));
+        assert_eq!(parameters.float("shininess"), Some(64.));
+        assert_eq!(parameters.get("opacity"), Some(MaterialValue::Int(1)), "Whole numbers are ints");
+        assert_eq!(parameters.get("opacity").map(|opacity| opacity.coerced(UniformType::Float)), Some(MaterialValue::Float(1.)));
+        assert_eq!(MaterialValue::Int(1).coerced(UniformType::Int), MaterialValue::Int(1));
+        assert_eq!(parameters.get("tint"), Some(MaterialValue::Vec4([1., 0., 0., 0.5])));
+
+        let saved = serde_json::to_string(&parameters).unwrap();
+        assert_eq!(serde_json::from_str::<MaterialParameters>(&saved).unwrap(), parameters);
+    }
+
+    #[test]
+    fn overrides_replace_base_values() {
+        let base = MaterialParameters::defaults(MaterialType::Lit);
+        let mut overrides = MaterialParameters::default();
+        overrides.set("diffuse_color", [1., 0., 0.]).set("rim_strength", 2);
+
+        let resolved = base.with_overrides(&overrides);
+
+        assert_eq!(resolved.color("diffuse_color"), Some([1., 0., 0.]));
+        assert_eq!(resolved.float("shininess"), base.float("shininess"));
+        assert_eq!(resolved.get("rim_strength"), Some(MaterialValue::Int(2)));
+        assert_eq!(resolved.len(), base.len() + 1);
+    }
+
+    #[test]
+    fn defaults_match_the_shader_types() {
+        let pbr = MaterialParameters::defaults(MaterialType::Pbr);
+        assert_eq!(pbr.float("roughness"), Some(0.5));
+        assert_eq!(pbr.get("shininess"), None);
+
+        let unlit = MaterialParameters::defaults(MaterialType::Billboard);
+        assert_eq!(unlit.iter().map(|(name, _)| name).collect::<Vec<_>>(), ["diffuse_color", "opacity"]);
+    }
+
+    #[test]
+    fn converts_between_material_types() {
+        let mut lit = MaterialParameters::defaults(MaterialType::Lit);
+        lit.set("diffuse_color", [0.8, 0.2, 0.1]);
+
+        let pbr = lit.converted(MaterialType::Pbr);
+        assert_eq!(pbr.color("base_color"), Some([0.8, 0.2, 0.1]));
+        assert_eq!(pbr.get("shininess"), None);
+        assert_eq!(pbr.float("roughness"), Some(0.5));
+
+        assert_eq!(pbr.converted(MaterialType::Lit), lit, "Switching back only drops the PBR parameters");
+        assert_eq!(lit.converted(MaterialType::Billboard).color("diffuse_color"), Some([0.8, 0.2, 0.1]));
+    }
+}
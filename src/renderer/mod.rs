@@ -1,8 +1,16 @@
 #[allow(clippy::module_inception)]
 mod renderer;
 mod material;
+mod framebuffer;
+mod shadow_map;
+mod uniform_blocks;
+mod light_clusters;
 
 pub use crate::renderer::{
     renderer::*,
-    material::*
+    material::*,
+    framebuffer::*,
+    shadow_map::*,
+    uniform_blocks::*,
+    light_clusters::*
 };
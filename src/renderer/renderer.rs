@@ -1,28 +1,31 @@
 use std::{
     sync::Arc,
+    cmp::Ordering,
     collections::HashMap,
     mem::size_of
 };
 
+use bevy_ecs::{prelude::*, world::WorldId};
 use glow::HasContext;
 use glutin::dpi::PhysicalSize;
-use image::EncodableLayout;
-use nalgebra_glm::{Vec3, vec3};
+use image::{EncodableLayout, RgbaImage};
+use nalgebra_glm::{self as glm, Vec3};
 
 use crate::{
     gl::{set_gl, get_gl},
     app::EventLoop,
     camera::Camera,
+    light::DIRECTIONAL_SHADOW_DISTANCE,
     loaders::{*, utils::Handle},
-    ecs::Ecs,
-    light_system,
+    light_system::{light_system, LightSettings, DebugLines, ShadowBudget},
+    hierarchy::propagate_transforms,
     transform::Transform,
     model::Model, 
-    shader::Uniform, 
-    map, mesh::{MeshRenderer}
+    shader::ShaderProgram, 
+    mesh::{MeshRenderer}
 };
 
-use super::material::*;
+use super::{material::*, framebuffer::Framebuffer, shadow_map::*, uniform_blocks::*, light_clusters::*};
 
 pub type GlutinWindow = glutin::ContextWrapper<glutin::PossiblyCurrent, glutin::window::Window>;
 
@@ -73,19 +76,49 @@ impl Line {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub enum RenderStage {
+    /// Computes world matrices from the entity hierarchy.
+    PropagateTransforms,
+    /// Gathers the lights and the shadow maps they need.
+    Lights,
+    /// Gathers everything that needs to be drawn into `RenderCommands`.
+    CollectRenderCommands,
+}
+
+/// Filled by `collect_render_commands` every frame.
+#[derive(Default)]
+pub struct RenderCommands(pub Vec<RenderCommand>);
+
 pub struct Renderer {
     pub window_width: i32,
     pub window_height: i32,
-    pub window: GlutinWindow,
     pub lights_on: bool,
 
+    /// Systems run on the scene's world before drawing.
+    /// Single threaded since most of them talk to OpenGL.
+    schedule: Schedule,
+
+    /// Stages refuse to run on a different world than the first one, see `Renderer::render`.
+    schedule_world: Option<WorldId>,
+
+    /// Kept between frames, recreated when a light's shadow resolution changes.
+    shadow_maps: HashMap<ShadowTarget, ShadowMap>,
+
+    /// Backing the `Camera` and `Lights` blocks, see `UNIFORM_BLOCKS`.
+    camera_block: UniformBuffer,
+    lights_block: UniformBuffer,
+
+    /// Point and spot lights with the clusters they reach, see `LightClusters`.
+    light_buffers: LightBuffers,
+
     // debug_line: Line
     debug_line_vao: glow::NativeVertexArray,
     debug_line_vbo: glow::NativeBuffer,
 }
 
 impl Renderer {
-    pub fn new(window: GlutinWindow, window_width: usize, window_height: usize, shader_loader: &mut ShaderLoader) -> Self {
+    pub fn new(window_width: usize, window_height: usize) -> Self {
         let window_width = window_width as i32;
         let window_height = window_height as i32;
 
@@ -96,8 +129,13 @@ impl Renderer {
         Renderer {
             window_width,
             window_height,
-            window,
             lights_on: true,
+            schedule: Self::build_schedule(),
+            schedule_world: None,
+            shadow_maps: HashMap::new(),
+            camera_block: UniformBuffer::new(CAMERA_BLOCK_BINDING),
+            lights_block: UniformBuffer::new(LIGHTS_BLOCK_BINDING),
+            light_buffers: LightBuffers::new(),
 
             debug_line_vao: vao,
             debug_line_vbo: vbo
@@ -105,37 +143,134 @@ impl Renderer {
         }
     }
 
-    pub fn window_resized(&self, physical_size: &PhysicalSize<u32>) {
-        self.window.resize(*physical_size);
+    fn build_schedule() -> Schedule {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(
+                RenderStage::PropagateTransforms,
+                SystemStage::single_threaded().with_system(propagate_transforms)
+            )
+            .add_stage_after(
+                RenderStage::PropagateTransforms,
+                RenderStage::Lights,
+                SystemStage::single_threaded().with_system(light_system)
+            )
+            .add_stage_after(
+                RenderStage::Lights,
+                RenderStage::CollectRenderCommands,
+                SystemStage::single_threaded().with_system(Self::collect_render_commands)
+            );
+
+        schedule
+    }
+
+    pub fn window_resized(&mut self, physical_size: &PhysicalSize<u32>) {
+        self.window_width = physical_size.width as i32;
+        self.window_height = physical_size.height as i32;
+        unsafe { get_gl().viewport(0, 0, self.window_width, self.window_height); }
+    }
+
+    /// Renders into an offscreen framebuffer of the given size and reads the result back.
+    /// Doesn't touch the window's framebuffer, so it also works with a headless context.
+    pub fn render_to_image(
+        &mut self,
+        camera: &Camera,
+        world: &mut World,
+        shader_loader: &mut ShaderLoader,
+        width: u32,
+        height: u32
+    ) -> RgbaImage {
+        let framebuffer = Framebuffer::new(width, height);
+
+        // Fixed so the same scene always renders the same image
+        self.render_into(camera, world, shader_loader, Some(&framebuffer), 0.);
+        let image = framebuffer.read_pixels();
+
+        framebuffer.unbind();
         unsafe { get_gl().viewport(0, 0, self.window_width, self.window_height); }
+
+        image
     }
 
     pub fn render(
         &mut self,
         camera: &Camera,
-        ecs: &mut Ecs,
+        world: &mut World,
         shader_loader: &mut ShaderLoader,
         time: f32
     ) {
+        self.render_into(camera, world, shader_loader, None, time);
+    }
+
+    /// Renders into `target`, or the window if None.
+    /// The target is only bound after the shadow maps are drawn.
+    fn render_into(
+        &mut self,
+        camera: &Camera,
+        world: &mut World,
+        shader_loader: &mut ShaderLoader,
+        target: Option<&Framebuffer>,
+        time: f32
+    ) {
+        world.insert_resource(LightSettings {
+            lights_on: self.lights_on,
+            camera_frustum: camera.frustum_corners(DIRECTIONAL_SHADOW_DISTANCE)
+        });
+        world.insert_resource(LightsBlock::default());
+        world.insert_resource(DebugLines::default());
+        world.insert_resource(RenderCommands::default());
+        world.insert_resource(ShadowPasses::default());
+        world.init_resource::<ShadowBudget>();
+
+        // A new scene was opened
+        if self.schedule_world != Some(world.id()) {
+            self.schedule = Self::build_schedule();
+            self.schedule_world = Some(world.id());
+        }
+
+        self.schedule.run(world);
+
+        let camera_block = CameraBlock::new(camera, time);
+        self.camera_block.upload(&camera_block.std140());
+
+        let lights = world.remove_resource::<LightsBlock>().unwrap_or_default();
+        let clusters = LightClusters::assign(&lights.point_lights, &lights.spot_lights, &camera_block.view, &camera_block.projection);
+        self.lights_block.upload(&lights.std140());
+        self.light_buffers.upload(&lights.point_lights, &lights.spot_lights, &clusters);
+
+        let debug_lines = world.remove_resource::<DebugLines>().unwrap_or_default();
+        let RenderCommands(render_commands) = world.remove_resource::<RenderCommands>().unwrap_or_default();
+        let ShadowPasses(shadow_passes) = world.remove_resource::<ShadowPasses>().unwrap_or_default();
+
+        let (transparent, opaque): 
+            (Vec<_>, Vec<_>) = render_commands.into_iter().partition(|rc| rc.1.is_transparent());
+
+        unsafe { get_gl().enable(glow::DEPTH_TEST); }
+        self.render_shadow_maps(&shadow_passes, &opaque, shader_loader);
+        self.bind_light_textures(shader_loader);
+
+        match target {
+            Some(framebuffer) => framebuffer.bind(),
+            None => unsafe {
+                let gl = get_gl();
+                gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+                gl.viewport(0, 0, self.window_width, self.window_height);
+            }
+        }
+
         unsafe {
             let gl = get_gl();
-            gl.enable(glow::DEPTH_TEST);
             gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
             gl.enable(glow::BLEND);
             gl.clear_color(0.0, 0.0, 0.0, 1.0);
             gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
         }
 
-        light_system(ecs, shader_loader, self, camera);
-          
-        let render_commands = Self::collect_render_commands(ecs);
-
-        let (transparent, opaque): 
-            (Vec<_>, Vec<_>) = render_commands.into_iter().partition(|rc| rc.1.is_transparent());
+        self.draw_debug_lines(&debug_lines.0, shader_loader);
 
         opaque.iter().for_each(|opaque_rc| {
             let RenderCommand(t, mesh) = opaque_rc;
-            Self::draw_mesh(t, camera, mesh);
+            Self::draw_mesh(t, mesh);
         });
 
         Self::draw_transparent(transparent, &camera);
@@ -144,59 +279,133 @@ impl Renderer {
         // self.draw_line(vec3(0., 1., 0.), end);
     }
 
-    pub fn draw_mesh(transform: &Transform, camera: &Camera, mr: &MeshRenderer) {
-        let lit_uniforms = map! {
-            "projection" => Uniform::Mat4(camera.get_proj_matrix()),
-            "view"       => Uniform::Mat4(camera.get_view_matrix()),
-            "model"      => Uniform::Mat4(transform.get_model_matrix()),
-            "u_view_pos" => Uniform::Vec3(camera.get_pos()),
-            "u_material.shininess" => Uniform::Float(32.0)
-        };
+    /// Draws the depth maps requested by the light system from the opaque meshes.
+    fn render_shadow_maps(&mut self, passes: &[ShadowPass], casters: &[RenderCommand], shader_loader: &mut ShaderLoader) {
+        // Lights that stopped casting shadows
+        self.shadow_maps.retain(|target, _| passes.iter().any(|pass| pass.target == *target));
+
+        for pass in passes {
+            let cube = matches!(pass.view, ShadowView::Cube { .. });
+            let new_shadow_map = || if cube { ShadowMap::new_cube(pass.resolution) } else { ShadowMap::new(pass.resolution) };
+
+            let shadow_map = self.shadow_maps.entry(pass.target).or_insert_with(new_shadow_map);
+            if shadow_map.resolution() != pass.resolution || shadow_map.is_cube() != cube {
+                *shadow_map = new_shadow_map();
+            }
+
+            match pass.view {
+                ShadowView::Single(light_space) => {
+                    let depth_shader = shader_loader.get_shader_rc(DEFAULT_SHADOW_SHADER);
+                    let depth_shader = depth_shader.borrow();
+                    depth_shader.use_program();
+                    depth_shader.set_mat4("light_space", light_space);
+
+                    shadow_map.bind();
+                    Self::draw_shadow_casters(&depth_shader, casters);
+                }
+                ShadowView::Cube { position, far } => {
+                    let depth_shader = shader_loader.get_shader_rc(DEFAULT_POINT_SHADOW_SHADER);
+                    let depth_shader = depth_shader.borrow();
+                    depth_shader.use_program();
+                    depth_shader
+                        .set_vec3("light_position", position)
+                        .set_float("far", far);
+
+                    for (face, light_space) in cube_face_matrices(position, far).into_iter().enumerate() {
+                        depth_shader.set_mat4("light_space", light_space);
+
+                        shadow_map.bind_face(face as u32);
+                        Self::draw_shadow_casters(&depth_shader, casters);
+                    }
+                }
+            }
+        }
 
-        let other_uniforms = map! {
-            "projection" => Uniform::Mat4(camera.get_proj_matrix()),
-            "view"       => Uniform::Mat4(camera.get_view_matrix()),
-            "model"      => Uniform::Mat4(transform.get_model_matrix())
-        };
+    }
 
-        let uniforms = match &mr.1.material_type {
-            MaterialType::Lit => &lit_uniforms,
-            MaterialType::Billboard | MaterialType::Unlit => &other_uniforms,
-        };
+    /// Binds the shadow maps and the light buffers for the lit shader.
+    fn bind_light_textures(&self, shader_loader: &mut ShaderLoader) {
+        // Samplers of different types can't share a texture unit, so they're all assigned even when unused
+        let lit_shaders: Vec<_> = LIT_SHADERS.iter().flat_map(|name| shader_loader.variants_of(name)).collect();
+
+        for lit_shader in lit_shaders {
+            let lit_shader = lit_shader.borrow();
+            lit_shader.use_program();
+            for target in ShadowTarget::all() {
+                lit_shader.set_int(&target.sampler_name(), target.texture_unit() as i32);
+            }
+
+            lit_shader
+                .set_int("u_light_data", LIGHT_DATA_UNIT as i32)
+                .set_int("u_light_clusters", LIGHT_CLUSTERS_UNIT as i32);
+        }
 
-        mr.draw(uniforms);
+        for (target, shadow_map) in &self.shadow_maps {
+            shadow_map.bind_texture(target.texture_unit());
+        }
+        self.light_buffers.bind_textures();
+
+        unsafe { get_gl().active_texture(glow::TEXTURE0); }
     }
 
-    fn collect_render_commands(ecs: &mut Ecs) -> Vec<RenderCommand> {
-        let rcs: Vec<Option<Vec<_>>> = 
-            ecs.do_all(|model_handle: &Handle<Model>, transform: &Transform| {
-            let model_handle = model_handle.borrow(); if !model_handle.enabled { return None; }
-            Some(
-                model_handle.mesh_renderers.iter()
-                .map(|mr| { RenderCommand(transform.clone(), mr.clone()) })
-                .collect::<Vec<_>>()
-            )
-        });
+    fn draw_shadow_casters(depth_shader: &ShaderProgram, casters: &[RenderCommand]) {
+        for RenderCommand(transform, MeshRenderer(mesh, _, matrix)) in casters {
+            depth_shader.set_mat4("model", transform.get_world_matrix() * matrix);
+            mesh.draw();
+        }
+    }
+
+    pub fn draw_mesh(transform: &Transform, mr: &MeshRenderer) {
+        mr.draw(&transform.get_world_matrix());
+    }
 
-        rcs
-            .into_iter()
-            .filter(|v| v.is_some())
-            .map(|v| v.unwrap())
-            .flatten()
-            .collect::<Vec<RenderCommand>>()
+    fn collect_render_commands(
+        models: Query<(&Handle<Model>, &Transform)>, 
+        mut render_commands: ResMut<RenderCommands>
+    ) {
+        let rcs = models
+            .iter()
+            .filter_map(|(model_handle, transform)| {
+                let model_handle = model_handle.borrow(); if !model_handle.enabled { return None; }
+                Some(
+                    model_handle.mesh_renderers.iter()
+                    .map(|mr| { RenderCommand(transform.clone(), mr.clone()) })
+                    .collect::<Vec<_>>()
+                )
+            })
+            .flatten();
+
+        render_commands.0.extend(rcs);
+    }
+
+    fn draw_debug_lines(&mut self, lines: &[(Vec3, Vec3)], shader_loader: &mut ShaderLoader) {
+        if lines.is_empty() { return; }
+
+        let unlit = shader_loader.get_shader_rc(DEFAULT_UNLIT_SHADER);
+        let unlit = unlit.borrow();
+        unlit.use_program().set_mat4("model", glm::Mat4::identity());
+
+        for (start, end) in lines {
+            self.draw_line(*start, *end);
+        }
     }
 
     fn draw_transparent(mut transparent: Vec<RenderCommand>, camera: &Camera) {
+        let distance = |RenderCommand(transform, mr): &RenderCommand| {
+            let position = (transform.get_world_matrix() * mr.2).column(3).xyz();
+            (position - camera.get_pos()).norm()
+        };
+
         transparent.sort_by(|tr1, tr2| {
-            let dist1 = (tr1.0.get_pos() - camera.get_pos()).len();
-            let dist2 = (tr2.0.get_pos() - camera.get_pos()).len();
+            let dist1 = distance(tr1);
+            let dist2 = distance(tr2);
 
-            dist1.cmp(&dist2).reverse()
+            dist1.partial_cmp(&dist2).unwrap_or(Ordering::Equal).reverse()
         });
 
         transparent.iter().for_each(|tr_rc| {
             let RenderCommand(t, mesh) = tr_rc;
-            Self::draw_mesh(t, camera, mesh);
+            Self::draw_mesh(t, mesh);
         });
     }
 
@@ -0,0 +1,231 @@
+use glow::HasContext;
+use nalgebra_glm::{self as glm, Mat4, Vec3, vec3};
+
+use crate::gl::get_gl;
+
+/// Spot lights past this many (in query order) don't get a shadow map.
+/// Passed to the shaders as the `MAX_SPOT_SHADOWS` define.
+pub const MAX_SPOT_SHADOWS: usize = 2;
+
+/// Upper limit of `ShadowBudget::point_lights`.
+/// Passed to the shaders as the `MAX_POINT_SHADOWS` define.
+pub const MAX_POINT_SHADOWS: usize = 3;
+
+/// Texture units of material textures, `ShaderProgram::upload_textures` leaves out any past these.
+pub const MATERIAL_TEXTURE_UNITS: u32 = 8;
+
+/// Texture units of the shadow maps, above the ones used by material textures. Together with the
+/// two `LightBuffers` textures they fill the 16 units GL 3.3 guarantees, so keep
+/// `MAX_SPOT_SHADOWS` and `MAX_POINT_SHADOWS` from growing past them.
+pub const DIRECTIONAL_SHADOW_UNIT: u32 = MATERIAL_TEXTURE_UNITS;
+pub const SPOT_SHADOW_UNITS_START: u32 = DIRECTIONAL_SHADOW_UNIT + 1;
+pub const POINT_SHADOW_UNITS_START: u32 = SPOT_SHADOW_UNITS_START + MAX_SPOT_SHADOWS as u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShadowTarget {
+    Directional,
+
+    /// Index into `u_spot_shadow_maps`.
+    Spot(usize),
+
+    /// Index into `u_point_shadow_maps`.
+    Point(usize),
+}
+
+impl ShadowTarget {
+    pub fn texture_unit(&self) -> u32 {
+        match self {
+            ShadowTarget::Directional => DIRECTIONAL_SHADOW_UNIT,
+            ShadowTarget::Spot(index) => SPOT_SHADOW_UNITS_START + *index as u32,
+            ShadowTarget::Point(index) => POINT_SHADOW_UNITS_START + *index as u32,
+        }
+    }
+
+    pub fn sampler_name(&self) -> String {
+        match self {
+            ShadowTarget::Directional => "u_directional_shadow_map".into(),
+            ShadowTarget::Spot(index) => format!("u_spot_shadow_maps[{index}]"),
+            ShadowTarget::Point(index) => format!("u_point_shadow_maps[{index}]"),
+        }
+    }
+
+    /// Every shadow sampler in the lit shader.
+    pub fn all() -> impl Iterator<Item = ShadowTarget> {
+        std::iter::once(ShadowTarget::Directional)
+            .chain((0..MAX_SPOT_SHADOWS).map(ShadowTarget::Spot))
+            .chain((0..MAX_POINT_SHADOWS).map(ShadowTarget::Point))
+    }
+}
+
+pub enum ShadowView {
+    /// A single depth map seen through `projection * view` of the light.
+    Single(Mat4),
+
+    /// Six faces around a point light, storing the distance to it divided by `far`.
+    Cube { position: Vec3, far: f32 },
+}
+
+/// A depth map the renderer has to draw before the main pass.
+pub struct ShadowPass {
+    pub target: ShadowTarget,
+    pub view: ShadowView,
+    pub resolution: u32,
+}
+
+const POINT_SHADOW_NEAR: f32 = 0.05;
+
+/// `projection * view` of each cube map face, in the order of `TEXTURE_CUBE_MAP_POSITIVE_X + face`.
+pub fn cube_face_matrices(position: Vec3, far: f32) -> [Mat4; 6] {
+    // Cube map faces are looked up with y pointing down, except for the y faces
+    let faces = [
+        (vec3(1., 0., 0.), vec3(0., -1., 0.)),
+        (vec3(-1., 0., 0.), vec3(0., -1., 0.)),
+        (vec3(0., 1., 0.), vec3(0., 0., 1.)),
+        (vec3(0., -1., 0.), vec3(0., 0., -1.)),
+        (vec3(0., 0., 1.), vec3(0., -1., 0.)),
+        (vec3(0., 0., -1.), vec3(0., -1., 0.)),
+    ];
+
+    let projection = glm::perspective(1.0, 90f32.to_radians(), POINT_SHADOW_NEAR, far);
+    faces.map(|(direction, up)| projection * glm::look_at(&position, &(position + direction), &up))
+}
+
+/// Filled by the light system every frame.
+#[derive(Default)]
+pub struct ShadowPasses(pub Vec<ShadowPass>);
+
+/// Square depth texture, or cube of them, set up for hardware depth comparisons
+/// (`sampler2DShadow` and `samplerCubeShadow`).
+pub struct ShadowMap {
+    fbo: glow::Framebuffer,
+    depth: glow::Texture,
+    resolution: u32,
+
+    /// `TEXTURE_2D` or `TEXTURE_CUBE_MAP`.
+    texture_target: u32,
+}
+
+impl ShadowMap {
+    pub fn new(resolution: u32) -> Self {
+        Self::with_target(resolution, glow::TEXTURE_2D)
+    }
+
+    pub fn new_cube(resolution: u32) -> Self {
+        Self::with_target(resolution, glow::TEXTURE_CUBE_MAP)
+    }
+
+    fn with_target(resolution: u32, texture_target: u32) -> Self {
+        let gl = get_gl();
+
+        let (images, first_image) = match texture_target {
+            glow::TEXTURE_CUBE_MAP => (6, glow::TEXTURE_CUBE_MAP_POSITIVE_X),
+            _ => (1, glow::TEXTURE_2D),
+        };
+
+        unsafe {
+            let depth = gl.create_texture().unwrap();
+            gl.bind_texture(texture_target, Some(depth));
+
+            for image in 0..images {
+                gl.tex_image_2d(
+                    first_image + image,
+                    0,
+                    glow::DEPTH_COMPONENT24 as i32,
+                    resolution as i32,
+                    resolution as i32,
+                    0,
+                    glow::DEPTH_COMPONENT,
+                    glow::FLOAT,
+                    None
+                );
+            }
+
+            // Linear filtering with comparisons already averages 2x2 texels
+            gl.tex_parameter_i32(texture_target, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(texture_target, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(texture_target, glow::TEXTURE_COMPARE_MODE, glow::COMPARE_REF_TO_TEXTURE as i32);
+            gl.tex_parameter_i32(texture_target, glow::TEXTURE_COMPARE_FUNC, glow::LEQUAL as i32);
+
+            if texture_target == glow::TEXTURE_CUBE_MAP {
+                for wrap in [glow::TEXTURE_WRAP_S, glow::TEXTURE_WRAP_T, glow::TEXTURE_WRAP_R] {
+                    gl.tex_parameter_i32(texture_target, wrap, glow::CLAMP_TO_EDGE as i32);
+                }
+            } else {
+                // Everything outside of the map is lit
+                gl.tex_parameter_i32(texture_target, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_BORDER as i32);
+                gl.tex_parameter_i32(texture_target, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_BORDER as i32);
+                gl.tex_parameter_f32_slice(texture_target, glow::TEXTURE_BORDER_COLOR, &[1.0, 1.0, 1.0, 1.0]);
+            }
+
+            let fbo = gl.create_framebuffer().unwrap();
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(fbo));
+            gl.framebuffer_texture_2d(glow::FRAMEBUFFER, glow::DEPTH_ATTACHMENT, first_image, Some(depth), 0);
+            gl.draw_buffer(glow::NONE);
+            gl.read_buffer(glow::NONE);
+
+            let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+            assert_eq!(status, glow::FRAMEBUFFER_COMPLETE, "Incomplete shadow map framebuffer ({resolution}x{resolution})");
+
+            gl.bind_texture(texture_target, None);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+
+            ShadowMap { fbo, depth, resolution, texture_target }
+        }
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    pub fn is_cube(&self) -> bool {
+        self.texture_target == glow::TEXTURE_CUBE_MAP
+    }
+
+    /// Binds the framebuffer for drawing, sets the viewport to cover it and clears it.
+    pub fn bind(&self) {
+        let gl = get_gl();
+
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fbo));
+            gl.viewport(0, 0, self.resolution as i32, self.resolution as i32);
+            gl.clear(glow::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    /// Like `bind`, drawing into one face of a cube map.
+    pub fn bind_face(&self, face: u32) {
+        let gl = get_gl();
+
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fbo));
+            gl.framebuffer_texture_2d(
+                glow::FRAMEBUFFER,
+                glow::DEPTH_ATTACHMENT,
+                glow::TEXTURE_CUBE_MAP_POSITIVE_X + face,
+                Some(self.depth),
+                0
+            );
+            gl.viewport(0, 0, self.resolution as i32, self.resolution as i32);
+            gl.clear(glow::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    pub fn bind_texture(&self, unit: u32) {
+        unsafe {
+            let gl = get_gl();
+            gl.active_texture(glow::TEXTURE0 + unit);
+            gl.bind_texture(self.texture_target, Some(self.depth));
+        }
+    }
+}
+
+impl Drop for ShadowMap {
+    fn drop(&mut self) {
+        let gl = get_gl();
+
+        unsafe {
+            gl.delete_framebuffer(self.fbo);
+            gl.delete_texture(self.depth);
+        }
+    }
+}
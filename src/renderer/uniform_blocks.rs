@@ -0,0 +1,289 @@
+//! Per-frame data shared by every shader through std140 uniform blocks.
+//!
+//! The blocks are declared in `common/camera.glsl` and `common/lighting.glsl`. Programs get them
+//! attached to the binding points in `UNIFORM_BLOCKS` when they're linked, so the buffers only
+//! have to be uploaded once a frame no matter how many programs read them.
+
+use glow::HasContext;
+use nalgebra_glm::{vec2, Mat4, Vec2, Vec3};
+
+use crate::{
+    camera::Camera,
+    gl::get_gl,
+    renderer::{GpuPointLight, GpuSpotLight, CLUSTER_DEPTH_RANGE},
+};
+
+pub const CAMERA_BLOCK_BINDING: u32 = 0;
+pub const LIGHTS_BLOCK_BINDING: u32 = 1;
+
+/// Names of the uniform blocks in GLSL and their binding points.
+pub const UNIFORM_BLOCKS: [(&str, u32); 2] = [
+    ("Camera", CAMERA_BLOCK_BINDING),
+    ("Lights", LIGHTS_BLOCK_BINDING),
+];
+
+/// Writes values with the offsets std140 gives them: scalars align to 4 bytes, vec2 to 8,
+/// vec3, vec4 and matrix columns to 16. A float right after a vec3 fills its last 4 bytes.
+#[derive(Default)]
+pub struct Std140 {
+    bytes: Vec<u8>,
+}
+
+impl Std140 {
+    fn align(&mut self, alignment: usize) {
+        let aligned = self.bytes.len().next_multiple_of(alignment);
+        self.bytes.resize(aligned, 0);
+    }
+
+    fn floats(&mut self, alignment: usize, floats: &[f32]) -> &mut Self {
+        self.align(alignment);
+        self.bytes.extend(floats.iter().flat_map(|float| float.to_ne_bytes()));
+        self
+    }
+
+    pub fn float(&mut self, value: f32) -> &mut Self {
+        self.floats(4, &[value])
+    }
+
+    pub fn int(&mut self, value: i32) -> &mut Self {
+        self.align(4);
+        self.bytes.extend(value.to_ne_bytes());
+        self
+    }
+
+    /// GLSL bools take 4 bytes in a block.
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.int(value as i32)
+    }
+
+    pub fn vec2(&mut self, value: Vec2) -> &mut Self {
+        self.floats(8, value.as_slice())
+    }
+
+    pub fn vec3(&mut self, value: Vec3) -> &mut Self {
+        self.floats(16, value.as_slice())
+    }
+
+    /// Column major, same as nalgebra.
+    pub fn mat4(&mut self, value: &Mat4) -> &mut Self {
+        self.floats(16, value.as_slice())
+    }
+
+    /// Structs (array elements included) start and end on a 16 byte boundary.
+    pub fn structure(&mut self, write: impl FnOnce(&mut Self)) -> &mut Self {
+        self.align(16);
+        write(self);
+        self.align(16);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// The `Camera` block, written once a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CameraBlock {
+    pub view: Mat4,
+    pub projection: Mat4,
+    pub view_pos: Vec3,
+
+    /// Seconds since the editor started.
+    pub time: f32,
+}
+
+impl CameraBlock {
+    pub fn new(camera: &Camera, time: f32) -> Self {
+        CameraBlock {
+            view: camera.get_view_matrix(),
+            projection: camera.get_proj_matrix(),
+            view_pos: camera.get_pos(),
+            time,
+        }
+    }
+
+    pub fn std140(&self) -> Vec<u8> {
+        let mut std140 = Std140::default();
+        std140
+            .mat4(&self.view)
+            .mat4(&self.projection)
+            .vec3(self.view_pos)
+            .float(self.time);
+        std140.into_bytes()
+    }
+}
+
+/// `DirectionalLight` in `common/lighting.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GpuDirectionalLight {
+    pub direction: Vec3,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub is_enabled: bool,
+
+    pub casts_shadows: bool,
+    pub shadow_bias: f32,
+    pub light_space: Mat4,
+}
+
+impl GpuDirectionalLight {
+    fn write(&self, std140: &mut Std140) {
+        std140.structure(|std140| {
+            std140
+                .vec3(self.direction)
+                .vec3(self.ambient)
+                .vec3(self.diffuse)
+                .vec3(self.specular)
+                .bool(self.is_enabled)
+                .bool(self.casts_shadows)
+                .float(self.shadow_bias)
+                .mat4(&self.light_space);
+        });
+    }
+}
+
+/// The `Lights` block, filled by the light system every frame.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LightsBlock {
+    pub directional: GpuDirectionalLight,
+
+    /// Not part of the block, they're read per cluster from the `LightBuffers` textures.
+    /// Only their counts are written here.
+    pub point_lights: Vec<GpuPointLight>,
+    pub spot_lights: Vec<GpuSpotLight>,
+}
+
+impl LightsBlock {
+    pub fn std140(&self) -> Vec<u8> {
+        let (near, far) = CLUSTER_DEPTH_RANGE;
+
+        let mut std140 = Std140::default();
+        self.directional.write(&mut std140);
+        std140
+            .int(self.point_lights.len() as i32)
+            .int(self.spot_lights.len() as i32)
+            .vec2(vec2(near, far));
+
+        std140.into_bytes()
+    }
+}
+
+/// A GL buffer attached to one of the `UNIFORM_BLOCKS` binding points.
+pub struct UniformBuffer {
+    buffer: glow::Buffer,
+    binding: u32,
+}
+
+impl UniformBuffer {
+    pub fn new(binding: u32) -> Self {
+        let buffer = unsafe { get_gl().create_buffer().expect("Failed to create a uniform buffer") };
+        UniformBuffer { buffer, binding }
+    }
+
+    /// Replaces the buffer's contents and binds it for every program using the block.
+    pub fn upload(&self, bytes: &[u8]) {
+        unsafe {
+            let gl = get_gl();
+            gl.bind_buffer(glow::UNIFORM_BUFFER, Some(self.buffer));
+            gl.buffer_data_u8_slice(glow::UNIFORM_BUFFER, bytes, glow::DYNAMIC_DRAW);
+            gl.bind_buffer_base(glow::UNIFORM_BUFFER, self.binding, Some(self.buffer));
+            gl.bind_buffer(glow::UNIFORM_BUFFER, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::vec3;
+
+    fn float_at(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn int_at(bytes: &[u8], offset: usize) -> i32 {
+        i32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn aligns_like_std140() {
+        let mut std140 = Std140::default();
+        std140.float(1.).vec3(vec3(2., 3., 4.)).float(5.).vec2(vec2(6., 7.)).int(8).mat4(&Mat4::identity());
+        let bytes = std140.into_bytes();
+
+        assert_eq!(float_at(&bytes, 0), 1.);
+        // vec3 moves up to 16, the float after it fills its 4th slot
+        assert_eq!(float_at(&bytes, 16), 2.);
+        assert_eq!(float_at(&bytes, 28), 5.);
+        assert_eq!(float_at(&bytes, 32), 6.);
+        assert_eq!(int_at(&bytes, 40), 8);
+        // mat4 starts on the next 16 bytes, a column at a time
+        assert_eq!(float_at(&bytes, 48), 1.);
+        assert_eq!(float_at(&bytes, 48 + 20), 1.);
+        assert_eq!(bytes.len(), 48 + 64);
+    }
+
+    #[test]
+    fn structs_are_padded_to_16_bytes() {
+        let mut std140 = Std140::default();
+        std140.float(1.).structure(|std140| { std140.float(2.); }).float(3.);
+        let bytes = std140.into_bytes();
+
+        assert_eq!(float_at(&bytes, 16), 2.);
+        assert_eq!(float_at(&bytes, 32), 3.);
+    }
+
+    #[test]
+    fn camera_block_layout() {
+        let camera = CameraBlock {
+            view: Mat4::identity() * 2.,
+            projection: Mat4::identity() * 3.,
+            view_pos: vec3(4., 5., 6.),
+            time: 7.,
+        };
+        let bytes = camera.std140();
+
+        assert_eq!(bytes.len(), 144);
+        assert_eq!(float_at(&bytes, 0), 2.);
+        assert_eq!(float_at(&bytes, 64), 3.);
+        assert_eq!(float_at(&bytes, 128), 4.);
+        assert_eq!(float_at(&bytes, 136), 6.);
+        assert_eq!(float_at(&bytes, 140), 7.);
+    }
+
+    #[test]
+    fn directional_light_size() {
+        let mut std140 = Std140::default();
+        GpuDirectionalLight::default().write(&mut std140);
+
+        assert_eq!(std140.len(), 144);
+    }
+
+    #[test]
+    fn lights_block_layout() {
+        let lights = LightsBlock {
+            directional: GpuDirectionalLight { casts_shadows: true, shadow_bias: 0.5, ..Default::default() },
+            point_lights: vec![GpuPointLight::default(); 40],
+            spot_lights: vec![GpuSpotLight::default(); 3],
+        };
+        let bytes = lights.std140();
+
+        assert_eq!(bytes.len(), 160);
+        assert_eq!(int_at(&bytes, 64), 1);
+        assert_eq!(float_at(&bytes, 68), 0.5);
+        assert_eq!(int_at(&bytes, 144), 40);
+        assert_eq!(int_at(&bytes, 148), 3);
+        assert_eq!(float_at(&bytes, 152), CLUSTER_DEPTH_RANGE.0);
+        assert_eq!(float_at(&bytes, 156), CLUSTER_DEPTH_RANGE.1);
+    }
+}
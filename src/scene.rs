@@ -2,23 +2,24 @@ extern crate nalgebra_glm as glm;
 
 
 
+use bevy_ecs::prelude::*;
 use egui_gizmo::GizmoMode;
 use glm::{vec3, vec2, Vec3};
 use glutin::dpi::PhysicalSize;
 
 use crate::{
     camera::Camera,
-    ecs::Ecs,
+    ecs::ComponentRegistry,
     transform::{Transform, Degree3},
     light::*,
     loaders::{*, utils::Handle}, renderer::{Material}, texture::{Texture2D, TextureType}, model::Model,
 };
 
 pub struct Scene {
-    pub selected_entity: Option<usize>,
+    pub selected_entity: Option<Entity>,
     pub camera: Camera,
     pub gizmo_mode: GizmoMode,
-    pub ecs: Ecs
+    pub world: World
 }
 
 impl Scene {
@@ -28,10 +29,24 @@ impl Scene {
             selected_entity: None,
             camera: Camera::new(Vec3::zeros(), vec3(0., 1., 0.), vec2(0., 0.), aspect),
             gizmo_mode: GizmoMode::Translate,
-            ecs: Ecs::new()
+            world: Self::new_world()
         }
     }
 
+    fn new_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(ComponentRegistry::new());
+        world
+    }
+
+    pub fn add_empty_entity(&mut self) -> Entity {
+        let num_entities = self.world.entities().len();
+        self.world
+            .spawn()
+            .insert(Transform::with_name(format!("Entity {num_entities}")))
+            .id()
+    }
+
     pub fn light_test(
         window_width: i32, 
         window_height: i32,
@@ -40,31 +55,31 @@ impl Scene {
         object_loader: &mut ObjLoader
     ) -> Self {
 
-        let mut ecs = Ecs::new();
+        let mut world = Self::new_world();
         let _up_two = vec3(0., 2., 0.);
 
-        // let _spot0 = ecs
-        //     .add_entity()
-        //     .with(Transform::new(
+        // let _spot0 = world
+        //     .spawn()
+        //     .insert(Transform::new(
         //             positions[2] + up_two,
         //             Degree3::xyz(0.0, 0.0, 0.),
         //             "Spotlight 0",
         //     ))
-        //     .with(SpotLight {
+        //     .insert(SpotLight {
         //         enabled: true,
         //         colors: LightColors::no_ambient(vec3(0., 3., 3.), 0.1),
         //         attenuation_constants: vec3(0.1, 0.3, 0.),
         //         cutoff_angles: vec2(10f32, 15f32),
         //     });
         //
-        // let _spot1 = ecs
-        //     .add_entity()
-        //     .with(Transform::new(
+        // let _spot1 = world
+        //     .spawn()
+        //     .insert(Transform::new(
         //             vec3(-3.4, 1.7, 3.7),
         //             Degree3::xyz(-70., -25., -50.),
         //             "Spotlight 1",
         //     ))
-        //     .with(SpotLight {
+        //     .insert(SpotLight {
         //         enabled: true,
         //         colors: LightColors::no_ambient(vec3(1., 1., 1.), 0.7),
         //         attenuation_constants: vec3(0.1, 0.0, 1.0),
@@ -72,7 +87,7 @@ impl Scene {
         //     });
         //
         
-        let pl_tex = Texture2D::from_native_handle(texture_loader.point_light_texture(), TextureType::Diffuse, 1);
+        let pl_tex = Texture2D::new(&texture_loader.point_light_texture(), TextureType::Diffuse, 1);
         let pl_mat = Material::billboard(shader_loader, pl_tex);
         let pl_name = "Point light billboard";
         let pl_model = object_loader.clone(DEFAULT_PLANE_NAME, pl_name);
@@ -81,30 +96,31 @@ impl Scene {
            mr.set_material(pl_mat.clone());
         }
         
-        let _point0 = ecs
-            .add_entity()
-            .with(Transform::with_scale(
+        let _point0 = world
+            .spawn()
+            .insert(Transform::with_scale(
                     vec3(5.0, 5.0, 0.0),
                     Degree3::default(),
                     vec3(0.1, 0.1, 0.1),
                     "Point light 0",
             ))
-            .with(PointLight {
+            .insert(PointLight {
                 enabled: true,
                 colors:  LightColors::no_ambient(vec3(2., 0., 0.), 0.1),
                 attenuation_constants: vec3(0.2, 0.0, 0.5),
+                shadows: ShadowSettings::default(),
             })
-        .with(pl_model);
+        .insert(pl_model);
 
         //
-        // let _point1 = ecs
-        //     .add_entity()
-        //     .with(Transform::new(
+        // let _point1 = world
+        //     .spawn()
+        //     .insert(Transform::new(
         //             positions[1] + up_two,
         //             Degree3::default(),
         //             "Point light 1",
         //     ))
-        //     .with(PointLight {
+        //     .insert(PointLight {
         //         enabled: true,
         //         colors: LightColors::no_ambient(vec3(0., 1., 0.), 0.07),
         //         attenuation_constants: vec3(0.1, 0.0, 1.0),
@@ -112,16 +128,16 @@ impl Scene {
         
         {
             let default_cube = object_loader.clone_handle(DEFAULT_CUBE_NAME);
-            let _ground = ecs
-                .add_entity()
-                .with(
+            let _ground = world
+                .spawn()
+                .insert(
                     Transform::with_scale(
                         vec3(0., -2., 0.),
                         Degree3::xyz(0., 0., 0.), 
                         vec3(10., 1., 10.), 
                         "ground"
                     )
-                ).with(Handle::clone(&default_cube));
+                ).insert(Handle::clone(&default_cube));
 
 
             let positions = [
@@ -136,10 +152,10 @@ impl Scene {
                 .enumerate()
                 .for_each(|(index, pos)| {
                     let transform = Transform::new(*pos, Degree3::default(), &format!("cube {}", index));
-                    let _model = ecs
-                        .add_entity()
-                        .with(transform)
-                        .with(Handle::clone(&default_cube));
+                    let _model = world
+                        .spawn()
+                        .insert(transform)
+                        .insert(Handle::clone(&default_cube));
                     });
         }
 
@@ -152,34 +168,35 @@ impl Scene {
 
         {
             let default_plane = object_loader.clone_handle(DEFAULT_PLANE_NAME);
-            let _directional = ecs
-                .add_entity()
-                .with(Transform::with_scale(
+            let _directional = world
+                .spawn()
+                .insert(Transform::with_scale(
                         vec3(0.0, 0.0, 0.0),
                         Degree3(vec3(0., 0., 0.)),
                         vec3(0.1, 0.1, 0.1),
                         "Directional Light",
                 ))
-                .with(DirectionalLight {
+                .insert(DirectionalLight {
                     enabled: true,
                     colors: LightColors::default().ambient(vec3(0.1, 0.04, 0.1)).diffuse(vec3(0.5, 0.2, 0.5)),
+                    shadows: ShadowSettings::default(),
                 })
-            .with::<Handle<Model>>(Handle::clone(&default_plane));
+            .insert::<Handle<Model>>(Handle::clone(&default_plane));
         }
         
         // let bp_path = "assets/obj/backpack.obj";
         // let bp = object_loader.load_model("Backpack", bp_path, texture_loader, shader_loader).unwrap();
         //
-        // let _backpack = ecs
-        //     .add_entity()
-        //     .with(Transform::default().set_name("Backpack").clone())
-        //     .with::<Handle<Model>>(Handle::clone(&bp));
+        // let _backpack = world
+        //     .spawn()
+        //     .insert(Transform::default().set_name("Backpack").clone())
+        //     .insert::<Handle<Model>>(Handle::clone(&bp));
 
         Scene {
             selected_entity: None,
             camera: cam,
             gizmo_mode: GizmoMode::Translate,
-            ecs
+            world
         }
 
     }
@@ -0,0 +1,969 @@
+extern crate nalgebra_glm as glm;
+
+use std::{collections::{BTreeMap, HashMap}, fmt, fs, path::Path};
+
+use bevy_ecs::prelude::*;
+use glm::{vec2, vec3, Vec2, Vec3};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{
+    camera::Camera,
+    ecs::ComponentRegistry,
+    hierarchy::{self, Parent},
+    light::{DirectionalLight, LightColors, PointLight, ShadowSettings, SpotLight},
+    loaders::{split_gltf_mesh_key, utils::Handle, AssetServer},
+    notifications,
+    model::Model,
+    renderer::{Material, MaterialParameters, MaterialType, MATERIAL_UNIFORM},
+    scene::Scene,
+    shader::UniformError,
+    texture::{Texture2D, TextureType},
+    transform::{Degree3, Transform},
+};
+
+/// Bumped whenever the layout of `SceneFile` changes in a way older builds can't read.
+pub const SCENE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SceneFile {
+    pub version: u32,
+    pub camera: CameraData,
+    pub entities: Vec<EntityData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CameraData {
+    pub position: [f32; 3],
+    pub world_up: [f32; 3],
+
+    /// Rotation about the x and y axes in degrees.
+    pub orientation: [f32; 2],
+    pub fov: f32,
+}
+
+/// One entity and its components, keyed by `ComponentInfo::key`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct EntityData {
+    /// Index of the parent in `SceneFile::entities`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+
+    #[serde(flatten)]
+    pub components: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransformData {
+    pub name: String,
+    pub position: [f32; 3],
+
+    /// Euler angles in degrees.
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LightColorsData {
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+}
+
+/// Missing from scenes saved before shadows existed, so every field has a default.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ShadowSettingsData {
+    pub casts_shadows: bool,
+    pub bias: f32,
+    pub resolution: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PointLightData {
+    pub enabled: bool,
+    pub colors: LightColorsData,
+    pub attenuation_constants: [f32; 3],
+
+    #[serde(default)]
+    pub shadows: ShadowSettingsData,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpotLightData {
+    pub enabled: bool,
+    pub colors: LightColorsData,
+    pub attenuation_constants: [f32; 3],
+
+    /// Inner and outer angles in degrees.
+    pub cutoff_angles: [f32; 2],
+
+    #[serde(default)]
+    pub shadows: ShadowSettingsData,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DirectionalLightData {
+    pub enabled: bool,
+    pub colors: LightColorsData,
+
+    #[serde(default)]
+    pub shadows: ShadowSettingsData,
+}
+
+/// Reference to a model asset.
+/// Also used as a component on entities whose model hasn't been resolved yet, see `Scene::resolve_assets`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Component)]
+pub struct ModelRef {
+    pub name: String,
+
+    /// Asset path or built-in model name (e.g. `default_cube`).
+    pub asset: String,
+    pub enabled: bool,
+
+    /// Materials that differ from the ones the asset loads with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub material_overrides: Vec<MaterialOverride>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MaterialOverride {
+    /// Index into the model's mesh renderers.
+    pub mesh_index: usize,
+
+    #[serde(flatten)]
+    pub material: MaterialData,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MaterialData {
+    /// Missing in scenes saved before materials had names.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub shader: String,
+    pub material_type: MaterialType,
+    pub transparent: bool,
+
+    /// Only the instance's own for instances, see `Material::base`.
+    pub textures: Vec<TextureRef>,
+
+    /// Missing in scenes saved before materials had parameters. Only the instance's own for instances.
+    #[serde(default)]
+    pub parameters: MaterialParameters,
+
+    /// Material this is an instance of. Instances saved with the same base share one again when loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<Box<MaterialData>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextureRef {
+    /// Path the texture was loaded from, or the name of a generated texture.
+    /// Scenes from before textures were keyed by path have the file name without extension.
+    pub name: String,
+    pub tex_type: TextureType,
+    pub tex_index: u32,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    UnknownComponent(String),
+
+    /// Entity index with a parent index that's out of range or itself.
+    InvalidParent { entity: usize, parent: usize },
+
+    /// Entity index whose ancestors lead back to it.
+    ParentCycle(usize),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(err) => write!(f, "{err}"),
+            SceneFileError::Json(err) => write!(f, "Invalid scene file: {err}"),
+            SceneFileError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported scene format version {version} (expected {SCENE_FORMAT_VERSION})"
+            ),
+            SceneFileError::UnknownComponent(key) => write!(f, "Unknown component \"{key}\""),
+            SceneFileError::InvalidParent { entity, parent } => write!(f, "Entity {entity} has an invalid parent {parent}"),
+            SceneFileError::ParentCycle(entity) => write!(f, "Entity {entity} is its own ancestor"),
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneFileError {
+    fn from(value: std::io::Error) -> Self {
+        SceneFileError::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for SceneFileError {
+    fn from(value: serde_json::Error) -> Self {
+        SceneFileError::Json(value)
+    }
+}
+
+/// Translates between loaded models and `ModelRef`s.
+/// Implemented over the real loaders by `LoaderAssets`, tests use a fake that never touches OpenGL.
+pub trait SceneAssets {
+    fn load_model(&mut self, model_ref: &ModelRef) -> Option<Handle<Model>>;
+    fn model_ref(&mut self, model: &Model) -> ModelRef;
+
+    /// Models still loading in the background keep their `ModelRef` until a later `Scene::resolve_assets`.
+    fn is_pending(&mut self, _model_ref: &ModelRef) -> bool {
+        false
+    }
+}
+
+impl SceneFile {
+    pub fn from_json(json: &str) -> Result<Self, SceneFileError> {
+        let scene_file: SceneFile = serde_json::from_str(json)?;
+
+        if scene_file.version != SCENE_FORMAT_VERSION {
+            return Err(SceneFileError::UnsupportedVersion(scene_file.version));
+        }
+
+        Ok(scene_file)
+    }
+
+    pub fn to_json(&self) -> Result<String, SceneFileError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, SceneFileError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), SceneFileError> {
+        Ok(fs::write(path, self.to_json()?)?)
+    }
+
+    /// Captures the scene's entities and camera.
+    /// Only entities with at least one registered component are saved.
+    pub fn from_scene(scene: &mut Scene, assets: &mut impl SceneAssets) -> Self {
+        let world = &mut scene.world;
+
+        let mut entities = world
+            .query::<Entity>()
+            .iter(world)
+            .collect::<Vec<_>>();
+        entities.sort_by_key(|e| e.id());
+
+        let registry = world.resource::<ComponentRegistry>();
+        let mut entity_data = entities
+            .iter()
+            .map(|e| EntityData {
+                parent: None,
+                components: registry
+                    .components()
+                    .iter()
+                    .filter_map(|info| Some((info.key.to_string(), (info.save)(world, *e, assets)?)))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+
+        for (index, entity) in entities.iter().enumerate() {
+            let Some(Parent(parent)) = world.get::<Parent>(*entity) else { continue };
+            entity_data[index].parent = entities.iter().position(|e| e == parent);
+        }
+
+        // Drop entities with nothing to save, remapping the parent indices.
+        let kept = entity_data
+            .iter()
+            .map(|data| *data != EntityData::default())
+            .collect::<Vec<_>>();
+
+        let new_index = kept
+            .iter()
+            .scan(0, |next, keep| {
+                let index = *next;
+                *next += *keep as usize;
+                Some(index)
+            })
+            .collect::<Vec<_>>();
+
+        let entities = entity_data
+            .into_iter()
+            .zip(kept.iter())
+            .filter(|(_, keep)| **keep)
+            .map(|(mut data, _)| {
+                data.parent = data.parent.filter(|p| kept[*p]).map(|p| new_index[p]);
+                data
+            })
+            .collect();
+
+        SceneFile {
+            version: SCENE_FORMAT_VERSION,
+            camera: CameraData::from(&scene.camera),
+            entities,
+        }
+    }
+
+    /// Builds a scene out of the file.
+    /// Models aren't loaded here, entities get a `ModelRef` component until `Scene::resolve_assets` runs.
+    pub fn into_scene(self, aspect_ratio: f32) -> Result<Scene, SceneFileError> {
+        self.check_parents()?;
+
+        let mut scene = Scene::empty(1, 1);
+        scene.camera = self.camera.into_camera(aspect_ratio);
+
+        let world = &mut scene.world;
+        let registry = world.remove_resource::<ComponentRegistry>().unwrap_or_default();
+
+        let mut spawned = Vec::with_capacity(self.entities.len());
+        for data in &self.entities {
+            let mut entity = world.spawn();
+
+            for (key, value) in &data.components {
+                let info = registry.by_key(key).ok_or_else(|| SceneFileError::UnknownComponent(key.clone()))?;
+                (info.load)(&mut entity, value.clone())?;
+            }
+
+            spawned.push(entity.id());
+        }
+
+        world.insert_resource(registry);
+
+        for (data, child) in self.entities.iter().zip(spawned.iter()) {
+            let Some(parent) = data.parent.and_then(|p| spawned.get(p)) else { continue };
+            hierarchy::add_child(world, *parent, *child);
+        }
+
+        Ok(scene)
+    }
+
+    /// The hierarchy has to be a forest, the hierarchy systems never return from a cycle.
+    fn check_parents(&self) -> Result<(), SceneFileError> {
+        let count = self.entities.len();
+
+        for (entity, data) in self.entities.iter().enumerate() {
+            let Some(parent) = data.parent else { continue };
+            if parent >= count || parent == entity {
+                return Err(SceneFileError::InvalidParent { entity, parent });
+            }
+        }
+
+        for (entity, data) in self.entities.iter().enumerate() {
+            // Any chain longer than the entity count has to repeat itself
+            let mut ancestor = data.parent;
+            for _ in 0..count {
+                match ancestor {
+                    Some(index) if index == entity => return Err(SceneFileError::ParentCycle(entity)),
+                    Some(index) => ancestor = self.entities[index].parent,
+                    None => break,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn to_array3(v: &Vec3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+fn from_array3(a: &[f32; 3]) -> Vec3 {
+    vec3(a[0], a[1], a[2])
+}
+
+fn to_array2(v: &Vec2) -> [f32; 2] {
+    [v.x, v.y]
+}
+
+fn from_array2(a: &[f32; 2]) -> Vec2 {
+    vec2(a[0], a[1])
+}
+
+impl From<&Camera> for CameraData {
+    fn from(camera: &Camera) -> Self {
+        CameraData {
+            position: to_array3(&camera.get_pos()),
+            world_up: to_array3(&camera.get_world_up()),
+            orientation: to_array2(&camera.get_orientation()),
+            fov: camera.get_fov_euler(),
+        }
+    }
+}
+
+impl CameraData {
+    pub fn into_camera(self, aspect_ratio: f32) -> Camera {
+        let mut camera = Camera::new(
+            from_array3(&self.position),
+            from_array3(&self.world_up),
+            from_array2(&self.orientation),
+            aspect_ratio
+        );
+        camera.set_fov_euler(self.fov);
+
+        camera
+    }
+}
+
+impl From<&Transform> for TransformData {
+    fn from(transform: &Transform) -> Self {
+        TransformData {
+            name: transform.get_name().to_string(),
+            position: to_array3(transform.get_pos()),
+            rotation: to_array3(&transform.get_rot().0),
+            scale: to_array3(transform.get_scale()),
+        }
+    }
+}
+
+impl From<TransformData> for Transform {
+    fn from(data: TransformData) -> Self {
+        Transform::with_scale(
+            from_array3(&data.position),
+            Degree3(from_array3(&data.rotation)),
+            from_array3(&data.scale),
+            &data.name
+        )
+    }
+}
+
+impl From<&LightColors> for LightColorsData {
+    fn from(colors: &LightColors) -> Self {
+        LightColorsData {
+            ambient: to_array3(&colors.ambient),
+            diffuse: to_array3(&colors.diffuse),
+            specular: to_array3(&colors.specular),
+        }
+    }
+}
+
+impl From<LightColorsData> for LightColors {
+    fn from(data: LightColorsData) -> Self {
+        LightColors {
+            ambient: from_array3(&data.ambient),
+            diffuse: from_array3(&data.diffuse),
+            specular: from_array3(&data.specular),
+        }
+    }
+}
+
+impl Default for ShadowSettingsData {
+    fn default() -> Self {
+        ShadowSettingsData::from(&ShadowSettings::default())
+    }
+}
+
+impl From<&ShadowSettings> for ShadowSettingsData {
+    fn from(shadows: &ShadowSettings) -> Self {
+        ShadowSettingsData {
+            casts_shadows: shadows.casts_shadows,
+            bias: shadows.bias,
+            resolution: shadows.resolution,
+        }
+    }
+}
+
+impl From<ShadowSettingsData> for ShadowSettings {
+    fn from(data: ShadowSettingsData) -> Self {
+        ShadowSettings {
+            casts_shadows: data.casts_shadows,
+            bias: data.bias,
+            resolution: data.resolution,
+        }
+    }
+}
+
+impl From<&PointLight> for PointLightData {
+    fn from(light: &PointLight) -> Self {
+        PointLightData {
+            enabled: light.enabled,
+            colors: LightColorsData::from(&light.colors),
+            attenuation_constants: to_array3(&light.attenuation_constants),
+            shadows: ShadowSettingsData::from(&light.shadows),
+        }
+    }
+}
+
+impl From<PointLightData> for PointLight {
+    fn from(data: PointLightData) -> Self {
+        PointLight {
+            enabled: data.enabled,
+            colors: data.colors.into(),
+            attenuation_constants: from_array3(&data.attenuation_constants),
+            shadows: data.shadows.into(),
+        }
+    }
+}
+
+impl From<&SpotLight> for SpotLightData {
+    fn from(light: &SpotLight) -> Self {
+        SpotLightData {
+            enabled: light.enabled,
+            colors: LightColorsData::from(&light.colors),
+            attenuation_constants: to_array3(&light.attenuation_constants),
+            cutoff_angles: to_array2(&light.cutoff_angles),
+            shadows: ShadowSettingsData::from(&light.shadows),
+        }
+    }
+}
+
+impl From<SpotLightData> for SpotLight {
+    fn from(data: SpotLightData) -> Self {
+        SpotLight {
+            enabled: data.enabled,
+            colors: data.colors.into(),
+            attenuation_constants: from_array3(&data.attenuation_constants),
+            cutoff_angles: from_array2(&data.cutoff_angles),
+            shadows: data.shadows.into(),
+        }
+    }
+}
+
+impl From<&DirectionalLight> for DirectionalLightData {
+    fn from(light: &DirectionalLight) -> Self {
+        DirectionalLightData {
+            enabled: light.enabled,
+            colors: LightColorsData::from(&light.colors),
+            shadows: ShadowSettingsData::from(&light.shadows),
+        }
+    }
+}
+
+impl From<DirectionalLightData> for DirectionalLight {
+    fn from(data: DirectionalLightData) -> Self {
+        DirectionalLight {
+            enabled: data.enabled,
+            colors: data.colors.into(),
+            shadows: data.shadows.into(),
+        }
+    }
+}
+
+/// `SceneAssets` over the editor's `AssetServer`.
+pub struct LoaderAssets<'a> {
+    assets: &'a mut AssetServer,
+
+    /// Copies made for `ModelRef`s with overrides, entities with the same model name share one.
+    named_models: HashMap<String, Handle<Model>>,
+
+    /// Bases of the instances loaded so far, by what they were saved as.
+    bases: Vec<(MaterialData, Handle<Material>)>,
+}
+
+impl<'a> LoaderAssets<'a> {
+    pub fn new(assets: &'a mut AssetServer) -> Self {
+        LoaderAssets { assets, named_models: HashMap::new(), bases: Vec::new() }
+    }
+
+    fn material_data(&self, material: &Material) -> MaterialData {
+        MaterialData {
+            name: material.name.clone(),
+            shader: self.assets.shader_loader.shader_name(&material.shader).unwrap_or_default().to_string(),
+            material_type: material.material_type,
+            transparent: material.transparent,
+            textures: material.textures
+                .iter()
+                .map(|texture| TextureRef {
+                    name: texture.texture.borrow().path.clone(),
+                    tex_type: texture.tex_type,
+                    tex_index: texture.tex_index,
+                })
+                .collect(),
+            parameters: material.parameters.clone(),
+            base: material.base.as_ref().map(|base| Box::new(self.material_data(&base.borrow()))),
+        }
+    }
+
+    fn material(&mut self, data: &MaterialData) -> Material {
+        let textures: Vec<Texture2D> = data.textures
+            .iter()
+            .map(|texture| Texture2D::new(
+                &self.assets.texture_loader.get(&texture.name),
+                texture.tex_type,
+                texture.tex_index
+            ))
+            .collect();
+
+        let name = match data.name.is_empty() {
+            true => data.material_type.label().to_string(),
+            false => data.name.clone(),
+        };
+
+        let mut material = Material {
+            name,
+            shader: self.assets.shader_loader.get_shader_rc(&data.shader),
+            material_type: data.material_type,
+            textures,
+            transparent: data.transparent,
+            parameters: data.parameters.clone(),
+            base: data.base.as_deref().map(|base| self.base_material(base)),
+        };
+
+        // The variant depends on the base's textures too
+        material.update_variant(&mut self.assets.shader_loader);
+
+        // Scenes saved before parameters were by name have the ones of every material type
+        let shader = material.shader.borrow();
+        material.parameters.coerce_to(&shader);
+        material.parameters.retain(|name, value| !matches!(
+            shader.find_uniform(&format!("{MATERIAL_UNIFORM}{name}"), value.uniform().uniform_type()),
+            Err(UniformError::Unknown { .. })
+        ));
+        drop(shader);
+
+        material
+    }
+
+    fn base_material(&mut self, data: &MaterialData) -> Handle<Material> {
+        if let Some((_, base)) = self.bases.iter().find(|(saved, _)| saved == data) {
+            return Handle::clone(base);
+        }
+
+        let base = Handle::new(self.material(data));
+        self.bases.push((data.clone(), Handle::clone(&base)));
+        base
+    }
+
+    fn base_model(&mut self, asset: &str) -> Option<Handle<Model>> {
+        // Already reported when the background load failed, reading it again would block
+        if self.assets.object_loader.get(asset).is_none() && self.assets.has_failed(model_file(asset)) {
+            return None;
+        }
+
+        self.assets
+            .load_model(asset)
+            .map_err(notifications::error)
+            .ok()
+    }
+}
+
+/// The file a `ModelRef::asset` comes from, glTF mesh keys point into their file.
+fn model_file(asset: &str) -> &str {
+    split_gltf_mesh_key(asset).map_or(asset, |(path, _)| path)
+}
+
+impl<'a> SceneAssets for LoaderAssets<'a> {
+    fn is_pending(&mut self, model_ref: &ModelRef) -> bool {
+        self.assets.is_building(model_file(&model_ref.asset))
+    }
+
+    fn load_model(&mut self, model_ref: &ModelRef) -> Option<Handle<Model>> {
+        let base = self.base_model(&model_ref.asset)?;
+
+        if model_ref.material_overrides.is_empty() && model_ref.name == base.borrow().name {
+            base.borrow_mut().enabled = model_ref.enabled;
+            return Some(base);
+        }
+
+        // Models are shared by name, only clone the asset the first time we see this name.
+        let model = match self.named_models.get(&model_ref.name) {
+            Some(model) => Handle::clone(model),
+            None => {
+                let model = self.assets.object_loader.clone(&model_ref.asset, &model_ref.name);
+                self.named_models.insert(model_ref.name.clone(), Handle::clone(&model));
+                model
+            }
+        };
+
+        {
+            let mut model = model.borrow_mut();
+            model.enabled = model_ref.enabled;
+
+            for material_override in &model_ref.material_overrides {
+                let material = self.material(&material_override.material);
+                if let Some(mr) = model.mesh_renderers.get_mut(material_override.mesh_index) {
+                    mr.set_material(material);
+                }
+            }
+        }
+
+        Some(model)
+    }
+
+    fn model_ref(&mut self, model: &Model) -> ModelRef {
+        // `model` can be the loaded model itself and edited, so this is what the file gave
+        let loaded = self.assets.object_loader.loaded_materials(&model.source);
+
+        let material_overrides = model.mesh_renderers
+            .iter()
+            .enumerate()
+            .filter(|(index, mr)| {
+                loaded
+                    .and_then(|loaded| loaded.get(*index))
+                    .is_none_or(|loaded| !loaded.same_as(&mr.1))
+            })
+            .map(|(mesh_index, mr)| MaterialOverride { mesh_index, material: self.material_data(&mr.1) })
+            .collect();
+
+        ModelRef {
+            name: model.name.clone(),
+            asset: model.source.clone(),
+            enabled: model.enabled,
+            material_overrides,
+        }
+    }
+}
+
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> Result<Scene, SceneFileError> {
+        SceneFile::read(path)?.into_scene(16. / 9.)
+    }
+
+    pub fn save(&mut self, path: impl AsRef<Path>, assets: &mut impl SceneAssets) -> Result<(), SceneFileError> {
+        SceneFile::from_scene(self, assets).write(path)
+    }
+
+    /// Starts loading the model files the scene's `ModelRef`s need on worker threads, so
+    /// `resolve_assets` can skip them until they're done instead of loading them itself.
+    pub fn load_models_async(&mut self, assets: &mut AssetServer) {
+        let mut assets_to_load: Vec<String> = self.world
+            .query::<&ModelRef>()
+            .iter(&self.world)
+            .filter(|model_ref| assets.object_loader.get(&model_ref.asset).is_none())
+            .map(|model_ref| model_file(&model_ref.asset).to_string())
+            .collect();
+        assets_to_load.sort();
+        assets_to_load.dedup();
+
+        for path in assets_to_load {
+            assets.load_model_async(&path);
+        }
+    }
+
+    /// Replaces `ModelRef` components left by `Scene::load` with loaded models.
+    pub fn resolve_assets(&mut self, assets: &mut impl SceneAssets) {
+        let pending = self.world
+            .query::<(Entity, &ModelRef)>()
+            .iter(&self.world)
+            .map(|(e, model_ref)| (e, model_ref.clone()))
+            .collect::<Vec<_>>();
+
+        for (entity, model_ref) in pending {
+            if assets.is_pending(&model_ref) {
+                continue;
+            }
+
+            let mut entity = self.world.entity_mut(entity);
+            entity.remove::<ModelRef>();
+
+            match assets.load_model(&model_ref) {
+                Some(model) => { entity.insert(model); }
+                None => notifications::error(format!("Failed to resolve model \"{}\" ({})", model_ref.name, model_ref.asset)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out empty models and remembers what they were loaded as.
+    #[derive(Default)]
+    struct FakeAssets {
+        loaded: HashMap<String, ModelRef>,
+
+        /// Assets that are still loading.
+        pending: Vec<String>,
+    }
+
+    impl SceneAssets for FakeAssets {
+        fn load_model(&mut self, model_ref: &ModelRef) -> Option<Handle<Model>> {
+            self.loaded.insert(model_ref.name.clone(), model_ref.clone());
+
+            let mut model = Model::new(&model_ref.name, "", vec![]);
+            model.source = model_ref.asset.clone();
+            model.enabled = model_ref.enabled;
+
+            Some(Handle::new(model))
+        }
+
+        fn model_ref(&mut self, model: &Model) -> ModelRef {
+            self.loaded[&model.name].clone()
+        }
+
+        fn is_pending(&mut self, model_ref: &ModelRef) -> bool {
+            self.pending.contains(&model_ref.asset)
+        }
+    }
+
+    fn test_scene() -> Scene {
+        let mut scene = Scene::empty(16, 9);
+        let world = &mut scene.world;
+
+        let parent = world
+            .spawn()
+            .insert(Transform::with_scale(vec3(1., 2., 3.), Degree3::xyz(10., 20., 30.), vec3(2., 2., 2.), "parent"))
+            .insert(ModelRef {
+                name: "Point light billboard".into(),
+                asset: "default_plane".into(),
+                enabled: true,
+                material_overrides: vec![MaterialOverride {
+                    mesh_index: 0,
+                    material: MaterialData {
+                        name: "Faded billboard".into(),
+                        shader: "default_billboard".into(),
+                        material_type: MaterialType::Billboard,
+                        transparent: true,
+                        textures: vec![],
+                        parameters: {
+                            let mut parameters = MaterialParameters::default();
+                            parameters.set("opacity", 0.5);
+                            parameters
+                        },
+                        base: Some(Box::new(MaterialData {
+                            name: "Billboard".into(),
+                            shader: "default_billboard".into(),
+                            material_type: MaterialType::Billboard,
+                            transparent: true,
+                            textures: vec![TextureRef { name: "assets/textures/point_light_white.png".into(), tex_type: TextureType::Diffuse, tex_index: 1 }],
+                            parameters: MaterialParameters::defaults(MaterialType::Billboard),
+                            base: None,
+                        })),
+                    },
+                }],
+            })
+            .id();
+
+        let child = world
+            .spawn()
+            .insert(Transform::new(vec3(0., 1., 0.), Degree3::default(), "child"))
+            .insert(PointLight {
+                enabled: true,
+                colors: LightColors::no_ambient(vec3(2., 0., 0.), 0.1),
+                attenuation_constants: vec3(0.2, 0.0, 0.5),
+                shadows: ShadowSettings::default(),
+            })
+            .insert(SpotLight {
+                enabled: false,
+                cutoff_angles: vec2(10., 15.),
+                colors: LightColors::default(),
+                attenuation_constants: vec3(0.1, 0.3, 0.),
+                shadows: ShadowSettings { casts_shadows: true, bias: 0.01, resolution: 512 },
+            })
+            .id();
+
+        world
+            .spawn()
+            .insert(Transform::with_name("sun"))
+            .insert(DirectionalLight {
+                enabled: true,
+                colors: LightColors::default().diffuse(vec3(0.5, 0.2, 0.5)),
+                shadows: ShadowSettings::default(),
+            });
+
+        hierarchy::add_child(world, parent, child);
+
+        scene
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut assets = FakeAssets::default();
+        let mut scene = test_scene();
+        scene.resolve_assets(&mut assets);
+
+        let saved = SceneFile::from_scene(&mut scene, &mut assets);
+        assert_eq!(saved.entities.len(), 3);
+        assert_eq!(saved.entities[1].parent, Some(0));
+
+        let json = saved.to_json().unwrap();
+        let loaded = SceneFile::from_json(&json).unwrap();
+        assert_eq!(saved, loaded);
+
+        let mut reloaded_scene = loaded.into_scene(16. / 9.).unwrap();
+        reloaded_scene.resolve_assets(&mut assets);
+
+        assert_eq!(SceneFile::from_scene(&mut reloaded_scene, &mut assets), saved);
+    }
+
+    #[test]
+    fn pending_models_are_resolved_later() {
+        let mut assets = FakeAssets { pending: vec!["default_plane".into()], ..Default::default() };
+        let mut scene = test_scene();
+
+        scene.resolve_assets(&mut assets);
+        assert_eq!(scene.world.query::<&ModelRef>().iter(&scene.world).count(), 1);
+
+        assets.pending.clear();
+        scene.resolve_assets(&mut assets);
+        assert_eq!(scene.world.query::<&ModelRef>().iter(&scene.world).count(), 0);
+        assert_eq!(scene.world.query::<&Handle<Model>>().iter(&scene.world).count(), 1);
+    }
+
+    #[test]
+    fn unresolved_models_are_saved_as_is() {
+        let mut scene = test_scene();
+        let saved = SceneFile::from_scene(&mut scene, &mut FakeAssets::default());
+
+        assert_eq!(saved.entities[0].components["model"]["asset"], "default_plane");
+    }
+
+    #[test]
+    fn restores_hierarchy() {
+        let mut scene = SceneFile::from_scene(&mut test_scene(), &mut FakeAssets::default()).into_scene(1.).unwrap();
+
+        let (child, Parent(parent)) = scene.world
+            .query::<(Entity, &Parent)>()
+            .iter(&scene.world)
+            .map(|(e, p)| (e, *p))
+            .next()
+            .unwrap();
+
+        assert_eq!(scene.world.get::<Transform>(child).unwrap().get_name(), "child");
+        assert_eq!(scene.world.get::<Transform>(parent).unwrap().get_name(), "parent");
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut saved = SceneFile::from_scene(&mut test_scene(), &mut FakeAssets::default());
+        saved.version = SCENE_FORMAT_VERSION + 1;
+
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(matches!(SceneFile::from_json(&json), Err(SceneFileError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn rejects_unknown_components() {
+        let mut saved = SceneFile::from_scene(&mut test_scene(), &mut FakeAssets::default());
+        saved.entities[0].components.insert("rigid_body".into(), Value::Null);
+
+        assert!(matches!(saved.into_scene(1.), Err(SceneFileError::UnknownComponent(key)) if key == "rigid_body"));
+    }
+
+    #[test]
+    fn rejects_out_of_range_parents() {
+        let mut saved = SceneFile::from_scene(&mut test_scene(), &mut FakeAssets::default());
+        saved.entities[1].parent = Some(3);
+
+        assert!(matches!(saved.into_scene(1.), Err(SceneFileError::InvalidParent { entity: 1, parent: 3 })));
+    }
+
+    #[test]
+    fn rejects_self_parents() {
+        let mut saved = SceneFile::from_scene(&mut test_scene(), &mut FakeAssets::default());
+        saved.entities[2].parent = Some(2);
+
+        assert!(matches!(saved.into_scene(1.), Err(SceneFileError::InvalidParent { entity: 2, parent: 2 })));
+    }
+
+    #[test]
+    fn rejects_parent_cycles() {
+        let mut saved = SceneFile::from_scene(&mut test_scene(), &mut FakeAssets::default());
+        saved.entities[0].parent = Some(2);
+        saved.entities[2].parent = Some(1);
+
+        assert!(matches!(saved.into_scene(1.), Err(SceneFileError::ParentCycle(_))));
+    }
+
+    #[test]
+    fn lights_without_shadow_settings_get_defaults() {
+        let mut saved = SceneFile::from_scene(&mut test_scene(), &mut FakeAssets::default());
+        saved.entities[1].components.get_mut("spot_light").unwrap().as_object_mut().unwrap().remove("shadows");
+
+        let json = serde_json::to_string(&saved).unwrap();
+        let mut scene = SceneFile::from_json(&json).unwrap().into_scene(1.).unwrap();
+
+        let spot_light = scene.world.query::<&SpotLight>().single(&scene.world);
+        assert_eq!(ShadowSettingsData::from(&spot_light.shadows), ShadowSettingsData::default());
+    }
+}
@@ -1,13 +1,21 @@
-use glm::{Vec2, Vec3, Mat4};
+use glm::{Vec2, Vec3, Vec4, Mat4};
 use glow::*;
 use nalgebra_glm as glm;
 use std::{
-    fs,
-    error::Error, 
-    collections::HashMap
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Mutex
 };
 
-use crate::{get_gl, texture::{Texture2D, TextureType}};
+use crate::{
+    gl::get_gl,
+    loaders::{preprocess_shader, AssetError, AssetResult, ShaderSource},
+    notifications,
+    renderer::{MATERIAL_TEXTURE_UNITS, UNIFORM_BLOCKS},
+    texture::Texture2D
+};
 
 #[macro_export]
 macro_rules! map {
@@ -22,162 +30,477 @@ macro_rules! map {
 
 pub type UniformMap = HashMap<&'static str, Uniform>;
 
+/// `#define`s a shader is compiled with, by name. Sorted so the same set always gives the same
+/// variant, see `ShaderLoader::get_variant`.
+pub type ShaderDefines = BTreeMap<String, String>;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub enum Uniform {
     Float(f32),
+    Int(i32),
     Vec2(Vec2),
     Vec3(Vec3),
+    Vec4(Vec4),
     Color(Vec3),
     Mat4(Mat4),
 }
 
-#[derive(Hash, Debug, Eq)]
+impl Uniform {
+    pub fn uniform_type(&self) -> UniformType {
+        match self {
+            Uniform::Float(_) => UniformType::Float,
+            Uniform::Int(_) => UniformType::Int,
+            Uniform::Vec2(_) => UniformType::Vec2,
+            Uniform::Vec3(_) | Uniform::Color(_) => UniformType::Vec3,
+            Uniform::Vec4(_) => UniformType::Vec4,
+            Uniform::Mat4(_) => UniformType::Mat4,
+        }
+    }
+}
+
+/// GLSL type of an active uniform, see `ShaderProgram::uniforms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UniformType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Int,
+    Bool,
+    Mat3,
+    Mat4,
+    Sampler2D,
+    SamplerCube,
+    Sampler2DShadow,
+    SamplerCubeShadow,
+
+    /// Unsigned integer texture, like the light clusters.
+    USampler2D,
+
+    /// Any other GL type enum, nothing here sets those.
+    Other(u32),
+}
+
+impl UniformType {
+    pub fn from_gl(gl_type: u32) -> Self {
+        match gl_type {
+            glow::FLOAT => UniformType::Float,
+            glow::FLOAT_VEC2 => UniformType::Vec2,
+            glow::FLOAT_VEC3 => UniformType::Vec3,
+            glow::FLOAT_VEC4 => UniformType::Vec4,
+            glow::INT => UniformType::Int,
+            glow::BOOL => UniformType::Bool,
+            glow::FLOAT_MAT3 => UniformType::Mat3,
+            glow::FLOAT_MAT4 => UniformType::Mat4,
+            glow::SAMPLER_2D => UniformType::Sampler2D,
+            glow::SAMPLER_CUBE => UniformType::SamplerCube,
+            glow::SAMPLER_2D_SHADOW => UniformType::Sampler2DShadow,
+            glow::SAMPLER_CUBE_SHADOW => UniformType::SamplerCubeShadow,
+            glow::UNSIGNED_INT_SAMPLER_2D => UniformType::USampler2D,
+            other => UniformType::Other(other),
+        }
+    }
+
+    pub fn is_sampler(self) -> bool {
+        matches!(
+            self,
+            UniformType::Sampler2D | UniformType::SamplerCube | UniformType::Sampler2DShadow | UniformType::SamplerCubeShadow | UniformType::USampler2D
+        )
+    }
+
+    /// Whether a `value` can be set on a uniform of this type.
+    /// Ints and bools set each other, and samplers are set with the int of their texture unit.
+    pub fn accepts(self, value: UniformType) -> bool {
+        match value {
+            UniformType::Int | UniformType::Bool => matches!(self, UniformType::Int | UniformType::Bool) || self.is_sampler(),
+            value => self == value,
+        }
+    }
+}
+
+impl fmt::Display for UniformType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UniformType::Float => write!(f, "float"),
+            UniformType::Vec2 => write!(f, "vec2"),
+            UniformType::Vec3 => write!(f, "vec3"),
+            UniformType::Vec4 => write!(f, "vec4"),
+            UniformType::Int => write!(f, "int"),
+            UniformType::Bool => write!(f, "bool"),
+            UniformType::Mat3 => write!(f, "mat3"),
+            UniformType::Mat4 => write!(f, "mat4"),
+            UniformType::Sampler2D => write!(f, "sampler2D"),
+            UniformType::SamplerCube => write!(f, "samplerCube"),
+            UniformType::Sampler2DShadow => write!(f, "sampler2DShadow"),
+            UniformType::SamplerCubeShadow => write!(f, "samplerCubeShadow"),
+            UniformType::USampler2D => write!(f, "usampler2D"),
+            UniformType::Other(gl_type) => write!(f, "GL type {gl_type:#x}"),
+        }
+    }
+}
+
+/// An active uniform of a linked program. Arrays of plain types have one per element.
+#[derive(Debug, Clone)]
+pub struct ShaderUniform {
+    /// Like "u_view_pos", "u_point_lights[2].position" or "u_spot_shadow_maps[1]".
+    pub name: String,
+    pub uniform_type: UniformType,
+    pub location: glow::UniformLocation,
+}
+
+/// One of the `UNIFORM_BLOCKS` a linked program uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderUniformBlock {
+    pub name: String,
+    pub binding: u32,
+
+    /// In bytes, as the driver laid it out.
+    pub size: usize,
+}
+
+/// Why a uniform couldn't be set, see `ShaderProgram::find_uniform`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UniformError {
+    /// Nothing by that name is declared in the program's sources, likely a typo.
+    Unknown { shader: String, name: String },
+
+    /// Declared but unused, so the compiler optimized it out. Setting it wouldn't change anything.
+    Inactive { shader: String, name: String },
+
+    WrongType { shader: String, name: String, declared: UniformType, value: UniformType },
+}
+
+impl fmt::Display for UniformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UniformError::Unknown { shader, name } => write!(f, "Shader \"{shader}\" has no uniform named \"{name}\""),
+            UniformError::Inactive { shader, name } => write!(f, "Uniform \"{name}\" of shader \"{shader}\" is unused and was optimized out"),
+            UniformError::WrongType { shader, name, declared, value } => write!(
+                f,
+                "Uniform \"{name}\" of shader \"{shader}\" is a {declared}, can't set a {value}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct ShaderProgram {
     pub handle: glow::Program,
+    pub vertex_path: String,
+    pub fragment_path: String,
+
+    pub defines: ShaderDefines,
+
+    /// Every file the program was compiled from, includes too.
+    pub sources: Vec<PathBuf>,
+
+    /// Active uniforms sorted by name, read once the program is linked.
+    uniforms: Vec<ShaderUniform>,
+    uniform_indices: HashMap<String, usize>,
+    uniform_blocks: Vec<ShaderUniformBlock>,
+
+    /// Every word in the preprocessed sources, tells optimized out uniforms from typos.
+    identifiers: HashSet<String>,
+
+    /// Names of uniforms that failed to be set, each is only reported the first time.
+    reported: Mutex<HashSet<String>>,
 }
 
 impl ShaderProgram {
+    /// Reports the entries of `uniforms` that the program doesn't have, or has with another type.
     pub fn new(
         vert_shader_path: impl Into<String>,
         frag_shader_path: impl Into<String>,
-        _uniforms: UniformMap 
-    ) -> Result<ShaderProgram, Box<dyn Error>> {
+        uniforms: UniformMap 
+    ) -> AssetResult<ShaderProgram> {
+        let program = Self::with_defines(vert_shader_path, frag_shader_path, ShaderDefines::new())?;
+        program.report_uniform_errors(&uniforms, "");
+        Ok(program)
+    }
+
+    /// Compiles both shaders with `defines` added after their `#version`, see `preprocess_shader`.
+    pub fn with_defines(
+        vert_shader_path: impl Into<String>,
+        frag_shader_path: impl Into<String>,
+        defines: ShaderDefines,
+    ) -> AssetResult<ShaderProgram> {
 
         let vert_shader_path = vert_shader_path.into();
         let frag_shader_path = frag_shader_path.into();
 
-        let vert_shader_src = fs::read_to_string(&vert_shader_path)?;
-        let frag_shader_src = fs::read_to_string(&frag_shader_path)?;
+        let vert_shader_src = preprocess_shader(&vert_shader_path, &defines)?;
+        let frag_shader_src = preprocess_shader(&frag_shader_path, &defines)?;
+
+        let compile = |src: &ShaderSource, shader_type: u32, path: &str| create_shader(&src.code, shader_type)
+            .map_err(|log| AssetError::ShaderCompile { path: path.into(), log: src.map_log(&log) });
+        let vert_shader_handle = compile(&vert_shader_src, glow::VERTEX_SHADER, &vert_shader_path)?;
+        let frag_shader_handle = compile(&frag_shader_src, glow::FRAGMENT_SHADER, &frag_shader_path)?;
+
+        let shader_program_handle = create_program(vert_shader_handle, frag_shader_handle)
+            .map_err(|log| AssetError::ShaderLink {
+                vertex: Path::new(&vert_shader_path).into(),
+                fragment: Path::new(&frag_shader_path).into(),
+                log
+            })?;
+
+        let uniforms = active_uniforms(shader_program_handle);
+        let samplers = uniforms.iter().filter(|uniform| uniform.uniform_type.is_sampler()).count();
+        let texture_units = unsafe { get_gl().get_parameter_i32(glow::MAX_TEXTURE_IMAGE_UNITS) } as usize;
+        if samplers > texture_units {
+            unsafe { get_gl().delete_program(shader_program_handle); }
+            return Err(AssetError::ShaderLink {
+                vertex: Path::new(&vert_shader_path).into(),
+                fragment: Path::new(&frag_shader_path).into(),
+                log: format!("{samplers} samplers are used but the GPU only has {texture_units} texture units"),
+            });
+        }
+
+        let uniform_blocks = bind_uniform_blocks(shader_program_handle);
+        let uniform_indices = uniforms
+            .iter()
+            .enumerate()
+            .map(|(index, uniform)| (uniform.name.clone(), index))
+            .collect();
+
+        let identifiers = [&vert_shader_src.code, &frag_shader_src.code]
+            .into_iter()
+            .flat_map(|code| code.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_')))
+            .filter(|word| !word.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        println!("Loaded shader program ({shader_program_handle:?}) with {} uniforms, vertex shader: \"{vert_shader_path}\", fragment shader: \"{frag_shader_path}\"", uniforms.len());
+        Ok(ShaderProgram {
+            handle: shader_program_handle,
+            vertex_path: vert_shader_path,
+            fragment_path: frag_shader_path,
+            defines,
+            sources: vert_shader_src.files.into_iter().chain(frag_shader_src.files).collect(),
+            uniforms,
+            uniform_indices,
+            uniform_blocks,
+            identifiers,
+            reported: Mutex::new(HashSet::new()),
+        })
+    }
 
+    /// Every active uniform, samplers included, sorted by name.
+    pub fn uniforms(&self) -> &[ShaderUniform] {
+        &self.uniforms
+    }
 
-        let vert_shader_handle = create_shader(&vert_shader_src, glow::VERTEX_SHADER)?;
-        let frag_shader_handle = create_shader(&frag_shader_src, glow::FRAGMENT_SHADER)?;
+    /// The shared uniform blocks the program reads, already attached to their binding points.
+    pub fn uniform_blocks(&self) -> &[ShaderUniformBlock] {
+        &self.uniform_blocks
+    }
 
-        let shader_program_handle =
-            create_program(vert_shader_handle, frag_shader_handle)?;
+    pub fn uniform(&self, name: &str) -> Option<&ShaderUniform> {
+        self.uniform_indices.get(name).map(|index| &self.uniforms[*index])
+    }
 
-        unsafe {
-            let gl_rc = get_gl();
-            let num_uniforms = gl_rc.get_active_uniforms(shader_program_handle).min(8);
-            
-            if num_uniforms > 0 {
-                println!("Printing the first {num_uniforms} uniforms (max: 8): ");
-            }
-            for i in 0..num_uniforms {
-                let uni = gl_rc.get_active_uniform(shader_program_handle, i).unwrap();
-                println!("\tUniform name: {}", uni.name);
-            }
+    /// The uniform `name`, if a `value` can be set on it.
+    pub fn find_uniform(&self, name: &str, value: UniformType) -> Result<&ShaderUniform, UniformError> {
+        let shader = self.fragment_path.clone();
+
+        let Some(uniform) = self.uniform(name) else {
+            // Every identifier in "u_lights[2].position" has to be in the source for it to be declared
+            let declared = name
+                .split(['.', '[', ']'])
+                .filter(|part| !part.is_empty() && !part.bytes().all(|byte| byte.is_ascii_digit()))
+                .all(|part| self.identifiers.contains(part));
+
+            let name = name.to_string();
+            return Err(match declared {
+                true => UniformError::Inactive { shader, name },
+                false => UniformError::Unknown { shader, name },
+            });
+        };
+
+        match uniform.uniform_type.accepts(value) {
+            true => Ok(uniform),
+            false => Err(UniformError::WrongType { shader, name: name.to_string(), declared: uniform.uniform_type, value }),
+        }
+    }
+
+    /// Problems setting `uniforms` would run into, optimized out uniforms aside.
+    pub fn check_uniforms(&self, uniforms: &UniformMap, prefix: &str) -> Vec<UniformError> {
+        let mut errors: Vec<UniformError> = uniforms
+            .iter()
+            .filter_map(|(name, value)| self.find_uniform(&format!("{prefix}{name}"), value.uniform_type()).err())
+            .filter(|err| !matches!(err, UniformError::Inactive { .. }))
+            .collect();
+
+        errors.sort_by_key(|err| err.to_string());
+        errors
+    }
+
+    /// Warns about what `check_uniforms` finds.
+    pub fn report_uniform_errors(&self, uniforms: &UniformMap, prefix: &str) {
+        for err in self.check_uniforms(uniforms, prefix) {
+            self.report(err);
+        }
+    }
+
+    /// Warns about `err` unless a problem with the same uniform was reported before.
+    /// Optimized out uniforms are left alone, the shader just doesn't need them.
+    fn report(&self, err: UniformError) {
+        let name = match &err {
+            UniformError::Inactive { .. } => return,
+            UniformError::Unknown { name, .. } | UniformError::WrongType { name, .. } => name,
+        };
+
+        let mut reported = self.reported.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if reported.insert(name.clone()) {
+            notifications::warn(err);
         }
+    }
 
-        println!("Loaded shader program ({shader_program_handle:?}), vertex shader: \"{vert_shader_path}\", fragment shader: \"{frag_shader_path}\"");
-        Ok(ShaderProgram { handle: shader_program_handle, }) }
+    /// Location to set a `value` on, None after reporting why if there isn't one.
+    fn location(&self, name: &str, value: UniformType) -> Option<&glow::UniformLocation> {
+        self.find_uniform(name, value)
+            .map(|uniform| &uniform.location)
+            .map_err(|err| self.report(err))
+            .ok()
+    }
 
     pub fn use_program(&self) -> &Self {
         unsafe { get_gl().use_program(Some(self.handle)); }
         self
     }
 
-    fn get_uniform_location(&self, name: &str) -> Option<glow::UniformLocation > {
-        unsafe { get_gl().get_uniform_location(self.handle, name) }
-    }
-
     pub fn upload_uniforms(&self, uniforms: &UniformMap, prefix: &str) {
         uniforms
             .iter()
             .for_each(|(uniform_name, uniform_value)| {
-                let uniform_name = format!("{prefix}{uniform_name}");
-
-                match uniform_value {
-                    Uniform::Float(float)              => self.set_float(&uniform_name, *float),
-                    Uniform::Vec2(v2)                  => self.set_vec2(&uniform_name, *v2),
-                    Uniform::Vec3(v3) 
-                        | Uniform::Color(v3)           => self.set_vec3(&uniform_name, *v3),
-                    Uniform::Mat4(mat)                 => self.set_mat4(&uniform_name, *mat),
-                };    
+                self.set_uniform(&format!("{prefix}{uniform_name}"), uniform_value);
             });
     }
 
+    pub fn set_uniform(&self, name: &str, value: &Uniform) -> &Self {
+        match value {
+            Uniform::Float(float)              => self.set_float(name, *float),
+            Uniform::Int(int)                  => self.set_int(name, *int),
+            Uniform::Vec2(v2)                  => self.set_vec2(name, *v2),
+            Uniform::Vec3(v3)
+                | Uniform::Color(v3)           => self.set_vec3(name, *v3),
+            Uniform::Vec4(v4)                  => self.set_vec4(name, *v4),
+            Uniform::Mat4(mat)                 => self.set_mat4(name, *mat),
+        }
+    }
+
     // `prefix` is for when you have a texture inside a struct for example.
     // You'd have to set the uniform `struct_instance.texture_diffuse1` as an example.
+    // The units past `MATERIAL_TEXTURE_UNITS` belong to the shadow maps, textures that don't fit are left out.
     pub fn upload_textures(&self, textures: &[Texture2D], prefix: &str) {
         unsafe { get_gl().disable(glow::TEXTURE); }
-        for (i, texture) in textures.iter().enumerate() {
+        for (i, texture) in textures.iter().take(MATERIAL_TEXTURE_UNITS as usize).enumerate() {
             texture.activate_and_bind(i as u32);
 
-            let base_name = match texture.tex_type {
-                TextureType::Diffuse => "texture_diffuse",
-                TextureType::Specular => "texture_specular",
-                TextureType::Emissive => "texture_emissive",
-            };
-
-            let full_name = format!("{prefix}{base_name}{}", texture.tex_index);
-            self.set_int(&full_name, i as i32);
+            self.set_int(&format!("{prefix}{}", texture.uniform_name()), i as i32);
         }
 
     }
 
     pub fn set_int(&self, name: &str, value: i32) -> &Self {
-        unsafe {
-            match &self.get_uniform_location(name) {
-                Some(name) => get_gl().uniform_1_i32(Some(name), value),
-                None => eprintln!("Int uniform \"{name}\" not found")
-            }
-            
-            self
+        if let Some(location) = self.location(name, UniformType::Int) {
+            unsafe { get_gl().uniform_1_i32(Some(location), value); }
         }
+
+        self
     }
 
-    // GLSL doesn't have bools?
-    #[allow(dead_code)]
     pub fn set_bool(&self, name: &str, value: bool) -> &Self {
-        self.set_int(name, value as i32)
+        if let Some(location) = self.location(name, UniformType::Bool) {
+            unsafe { get_gl().uniform_1_i32(Some(location), value as i32); }
+        }
+
+        self
     }
 
     pub fn set_float(&self, name: &str, value: f32) -> &Self {
-        unsafe {
-            match &self.get_uniform_location(name) {
-                Some(name) => get_gl().uniform_1_f32(Some(name), value),
-                None => eprintln!("Float uniform \"{name}\" not found"),
-            }
-            self
+        if let Some(location) = self.location(name, UniformType::Float) {
+            unsafe { get_gl().uniform_1_f32(Some(location), value); }
         }
+
+        self
     }
 
     pub fn set_vec3(&self, name: &str, value: glm::Vec3) -> &Self {
-        unsafe {
-
+        if let Some(location) = self.location(name, UniformType::Vec3) {
+            unsafe { get_gl().uniform_3_f32(Some(location), value.x, value.y, value.z); }
+        }
 
-            match &self.get_uniform_location(name) {
-                Some(name) => get_gl().uniform_3_f32(Some(name), value.x, value.y, value.z),
-                None => eprintln!("Vec3 uniform \"{name}\" not found")
-            }
+        self
+    }
 
-            self
+    pub fn set_vec4(&self, name: &str, value: glm::Vec4) -> &Self {
+        if let Some(location) = self.location(name, UniformType::Vec4) {
+            unsafe { get_gl().uniform_4_f32(Some(location), value.x, value.y, value.z, value.w); }
         }
+
+        self
     }
 
     pub fn set_vec2(&self, name: &str, value: glm::Vec2) -> &Self {
-        unsafe {
-            match &self.get_uniform_location(name) {
-                Some(name) => get_gl().uniform_2_f32(Some(name), value.x, value.y),
-                None => eprintln!("Vec2 uniform \"{name}\" not found")
-            }
-            
-            self
+        if let Some(location) = self.location(name, UniformType::Vec2) {
+            unsafe { get_gl().uniform_2_f32(Some(location), value.x, value.y); }
         }
+
+        self
     }
 
     pub fn set_mat4(&self, name: &str, value: glm::Mat4) -> &Self {
-        unsafe {
-            match &self.get_uniform_location(name) {
-                Some(name) => get_gl().uniform_matrix_4_f32_slice(Some(name), false, glm::value_ptr(&value)),
-                None => eprintln!("Mat4 uniform \"{name}\" not found")
-            }
+        if let Some(location) = self.location(name, UniformType::Mat4) {
+            unsafe { get_gl().uniform_matrix_4_f32_slice(Some(location), false, glm::value_ptr(&value)); }
+        }
 
-            self
+        self
+    }
+}
+
+/// Reads the active uniforms of a linked program. Arrays of plain types come back as one
+/// uniform named "name[0]", they're split into one per element.
+fn active_uniforms(program: glow::Program) -> Vec<ShaderUniform> {
+    let gl = get_gl();
+    let mut uniforms = Vec::new();
+
+    unsafe {
+        for index in 0..gl.get_active_uniforms(program) {
+            let Some(active) = gl.get_active_uniform(program, index) else { continue };
+            let uniform_type = UniformType::from_gl(active.utype);
+
+            let names: Vec<String> = match active.name.strip_suffix("[0]") {
+                Some(array) => (0..active.size).map(|element| format!("{array}[{element}]")).collect(),
+                None => vec![active.name],
+            };
+
+            for name in names {
+                if let Some(location) = gl.get_uniform_location(program, &name) {
+                    uniforms.push(ShaderUniform { name, uniform_type, location });
+                }
+            }
         }
     }
+
+    uniforms.sort_by(|a, b| a.name.cmp(&b.name));
+    uniforms
+}
+
+/// GLSL 3.30 can't give blocks a binding in the source, so they're attached here by name.
+fn bind_uniform_blocks(program: glow::Program) -> Vec<ShaderUniformBlock> {
+    let gl = get_gl();
+
+    UNIFORM_BLOCKS
+        .into_iter()
+        .filter_map(|(name, binding)| unsafe {
+            let index = gl.get_uniform_block_index(program, name)?;
+            gl.uniform_block_binding(program, index, binding);
+
+            let size = gl.get_active_uniform_block_parameter_i32(program, index, glow::UNIFORM_BLOCK_DATA_SIZE);
+            Some(ShaderUniformBlock { name: name.to_string(), binding, size: size as usize })
+        })
+        .collect()
 }
 
 fn create_shader(shader_src: &str, shader_type: u32) -> Result<glow::Shader, String> {
@@ -228,3 +551,34 @@ impl PartialEq for ShaderProgram {
     }
 }
 
+impl Eq for ShaderProgram {}
+
+impl Hash for ShaderProgram {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.handle.hash(state);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samplers_and_bools_are_set_with_ints() {
+        assert!(UniformType::Sampler2DShadow.accepts(UniformType::Int));
+        assert!(UniformType::Bool.accepts(UniformType::Int));
+        assert!(UniformType::Int.accepts(UniformType::Bool));
+        assert!(!UniformType::Float.accepts(UniformType::Int));
+        assert!(!UniformType::Vec3.accepts(UniformType::Vec2));
+        assert!(UniformType::Mat4.accepts(UniformType::Mat4));
+    }
+
+    #[test]
+    fn reads_gl_type_enums() {
+        assert_eq!(UniformType::from_gl(glow::SAMPLER_CUBE_SHADOW), UniformType::SamplerCubeShadow);
+        assert_eq!(UniformType::from_gl(glow::FLOAT_VEC3), UniformType::Vec3);
+        assert_eq!(UniformType::from_gl(glow::DOUBLE), UniformType::Other(glow::DOUBLE));
+        assert_eq!(UniformType::Sampler2D.to_string(), "sampler2D");
+    }
+}
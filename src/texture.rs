@@ -1,13 +1,66 @@
 use glow::HasContext;
-use crate::gl::get_gl;
+use serde::{Serialize, Deserialize};
+use crate::{gl::get_gl, loaders::utils::Handle};
 
 
 #[allow(dead_code)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
 pub enum TextureType {
     Diffuse,
     Specular,
     Emissive,
+
+    /// Tangent space normals, the tangents come from screen space derivatives in the lit shader.
+    Normal,
+
+    /// Opacity in the red channel.
+    Opacity,
+
+    /// Roughness in the green channel and metallic in the blue one, as glTF packs them.
+    MetallicRoughness,
+
+    /// Roughness, metallic and ambient occlusion in the red channel.
+    Roughness,
+    Metallic,
+    Occlusion,
+}
+
+impl TextureType {
+    pub const ALL: [TextureType; 9] = [
+        TextureType::Diffuse,
+        TextureType::Specular,
+        TextureType::Emissive,
+        TextureType::Normal,
+        TextureType::Opacity,
+        TextureType::MetallicRoughness,
+        TextureType::Roughness,
+        TextureType::Metallic,
+        TextureType::Occlusion,
+    ];
+
+    /// Sampler name in the shaders without the index, see `Texture2D`.
+    pub fn uniform_name(self) -> &'static str {
+        match self {
+            TextureType::Diffuse => "texture_diffuse",
+            TextureType::Specular => "texture_specular",
+            TextureType::Emissive => "texture_emissive",
+            TextureType::Normal => "texture_normal",
+            TextureType::Opacity => "texture_opacity",
+            TextureType::MetallicRoughness => "texture_metallic_roughness",
+            TextureType::Roughness => "texture_roughness",
+            TextureType::Metallic => "texture_metallic",
+            TextureType::Occlusion => "texture_occlusion",
+        }
+    }
+}
+
+/// A texture on the GPU, owned by the `TextureLoader` and shared through `Handle<Texture>`.
+#[derive(Debug)]
+pub struct Texture {
+    pub native_handle: glow::Texture,
+
+    /// Path the texture was first loaded from, or the name it was generated under.
+    pub path: String,
 }
 
 /// I follow the following naming convention in shaders:
@@ -16,43 +69,52 @@ pub enum TextureType {
 /// So for a texture with a name `texture_diffuse` and and index of `1`:
 ///     uniform sampler2D texture_diffuse1;
 ///
-/// If you have an array of textures [ diffuse, diffuse, specular, emissive ], they'll be 
+/// If you have an array of textures [ diffuse, diffuse, specular, emissive ], they'll be
 /// uploaded as follows:
 /// diffuse  => texture_diffuse1 , unit 0
 /// diffuse  => texture_diffuse2 , unit 1
 /// specular => texture_sepcular1, unit 2
 /// emissive => texture_emissive1, unit 3
-#[derive(Hash, Copy, Clone, Debug, Eq)]
+#[derive(Hash, Clone, Debug, Eq)]
 pub struct Texture2D {
-    pub native_handle: glow::Texture,
+    pub texture: Handle<Texture>,
     pub tex_type: TextureType,
     pub tex_index: u32
 }
 
 impl Texture2D {
-    pub fn from_native_handle(
-        tex_handle: glow::Texture, 
-        tex_type: TextureType, 
+    pub fn new(
+        texture: &Handle<Texture>,
+        tex_type: TextureType,
         tex_index: u32,
     ) -> Texture2D {
         assert!( tex_index > 0, "Texture indices must be > 1 (Or change them in the shader to start from 0)" );
         Texture2D {
-            native_handle: tex_handle,
+            texture: Handle::clone(texture),
             tex_type,
             tex_index
         }
     }
 
+    /// Name of the sampler it's bound to, like `texture_diffuse1`.
+    pub fn uniform_name(&self) -> String {
+        format!("{}{}", self.tex_type.uniform_name(), self.tex_index)
+    }
+
+    pub fn native_handle(&self) -> glow::Texture {
+        self.texture.borrow().native_handle
+    }
+
     pub fn activate_and_bind(&self, unit: u32) {
-        unsafe { 
+        unsafe {
             get_gl().active_texture(glow::TEXTURE0 + unit);
-            get_gl().bind_texture(glow::TEXTURE_2D, Some(self.native_handle)); 
+            get_gl().bind_texture(glow::TEXTURE_2D, Some(self.native_handle()));
         }
     }
 }
 
 impl PartialEq for Texture2D {
     fn eq(&self, other: &Self) -> bool {
-        self.native_handle == other.native_handle && self.tex_type == other.tex_type
+        self.texture == other.texture && self.tex_type == other.tex_type
     }
 }
@@ -2,16 +2,25 @@
 
 use crate::{egui_drawable::EguiDrawable, light::float3_slider};
 
+use bevy_ecs::prelude::Component;
 use egui::Ui;
-use glm::{Vec3, Mat4, vec3};
+use glm::{Vec3, Mat3, Mat4, Quat, vec3};
 use nalgebra_glm as glm;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Component)]
 pub struct Transform {
     pos: Vec3,
-    rot: Degree3,
+    rot: Quat,
+
+    /// Editor view of `rot`, applied in X, Y, Z order.
+    /// Kept as entered so the UI doesn't jump between equivalent angles.
+    euler: Degree3,
     scale: Vec3,
     model: Mat4,
+
+    /// `model` combined with the world matrices of all ancestors.
+    /// Written by `propagate_transforms` every frame, equal to `model` for root entities.
+    world: Mat4,
     name: String,
 }
 
@@ -31,6 +40,35 @@ impl From<Vec3> for Degree3 {
     }
 }
 
+impl From<Degree3> for Quat {
+    fn from(val: Degree3) -> Self {
+        let Radian3(rot) = val.into();
+        let rot_x = glm::quat_angle_axis(rot.x, &Vec3::x());
+        let rot_y = glm::quat_angle_axis(rot.y, &Vec3::y());
+        let rot_z = glm::quat_angle_axis(rot.z, &Vec3::z());
+
+        rot_z * rot_y * rot_x
+    }
+}
+
+impl From<&Quat> for Degree3 {
+    // Inverse of `Rz * Ry * Rx`, see https://www.geometrictools.com/Documentation/EulerAngles.pdf
+    fn from(quat: &Quat) -> Self {
+        let m = glm::quat_to_mat3(quat);
+        let cos_y = m[(0, 0)].hypot(m[(1, 0)]);
+        let y = (-m[(2, 0)]).atan2(cos_y);
+
+        // Gimbal lock, only x - z (or x + z) is defined. Put it all in x.
+        let (x, z) = if cos_y < 1e-4 {
+            ((-m[(1, 2)]).atan2(m[(1, 1)]), 0.)
+        } else {
+            (m[(2, 1)].atan2(m[(2, 2)]), m[(1, 0)].atan2(m[(0, 0)]))
+        };
+
+        Degree3::xyz(x.to_degrees(), y.to_degrees(), z.to_degrees())
+    }
+}
+
 impl From<Degree3> for Radian3 {
     fn from(val: Degree3) -> Self {
         Radian3(vec3(
@@ -54,12 +92,16 @@ impl Transform {
         Self::with_scale(pos, rot, vec3(1., 1., 1.), name)
     }
 
-    pub fn with_scale(pos: Vec3, rot: Degree3, scale: Vec3, name: &str) -> Self {
+    pub fn with_scale(pos: Vec3, euler: Degree3, scale: Vec3, name: &str) -> Self {
+        let rot = euler.into();
+        let model = Transform::model_matrix(pos, &rot, scale);
         Transform {
             pos,
             rot,
+            euler,
             scale,
-            model: Transform::model_matrix(pos, rot, scale),
+            model,
+            world: model,
             name: String::from(name),
         }
     }
@@ -72,7 +114,7 @@ impl Transform {
     }
 
     pub fn update_model_matrix(&mut self) -> &mut Self {
-        self.model = Transform::model_matrix(self.pos, self.rot, self.scale);
+        self.model = Transform::model_matrix(self.pos, &self.rot, self.scale);
         self
     }
 
@@ -87,7 +129,19 @@ impl Transform {
     }
 
     pub fn set_rot(&mut self, euler: Degree3) -> &mut Self {
-        self.rot = euler;
+        self.rot = euler.into();
+        self.euler = euler;
+        self.update_model_matrix()
+    }
+
+    pub fn set_rotation(&mut self, rot: Quat) -> &mut Self {
+        self.rot = glm::quat_normalize(&rot);
+        self.euler = Degree3::from(&self.rot);
+        self.update_model_matrix()
+    }
+
+    pub fn set_scale(&mut self, scale: Vec3) -> &mut Self {
+        self.scale = scale;
         self.update_model_matrix()
     }
 
@@ -104,67 +158,93 @@ impl Transform {
         &self.pos
     }
 
+    /// Rotation as Euler angles in degrees, see `Transform::euler`.
     pub fn get_rot(&self) -> Degree3 {
-        self.rot
+        self.euler
     }
 
-    pub fn get_model_matrix(&self) -> glm::Mat4 {
-        self.model
+    pub fn get_rotation(&self) -> &Quat {
+        &self.rot
     }
 
-    pub fn model_matrix(pos: Vec3, rot: Degree3, scl: Vec3) -> glm::Mat4 {
-        let translation = glm::translate(&glm::Mat4::identity(), &pos);
+    pub fn get_scale(&self) -> &Vec3 {
+        &self.scale
+    }
 
-        let Radian3(rot) = rot.into();
-        let rot_x = glm::Mat4::from_euler_angles(rot.x, 0., 0.);
-        let rot_y = glm::Mat4::from_euler_angles(0., rot.y, 0.);
-        let rot_z = glm::Mat4::from_euler_angles(0., 0., rot.z);
-        let rotation = rot_z * rot_y * rot_x;
+    pub fn get_model_matrix(&self) -> glm::Mat4 {
+        self.model
+    }
 
-        translation * rotation * glm::scale(&rotation, &scl)
+    pub fn get_world_matrix(&self) -> glm::Mat4 {
+        self.world
     }
 
-    pub fn set_model(&mut self, mat: glm::Mat4) {
-        self.model = mat;
+    pub fn get_world_pos(&self) -> Vec3 {
+        self.world.column(3).xyz()
+    }
 
-        // https://math.stackexchange.com/questions/237369/given-this-transformation-matrix-how-do-i-decompose-it-into-translation-rotati
+    pub fn set_world_matrix(&mut self, world: glm::Mat4) {
+        self.world = world;
+    }
 
-        let translation = mat.column_part(3, 3);
-        self.pos = glm::vec3(translation[0], translation[1], translation[2]);
+    pub fn model_matrix(pos: Vec3, rot: &Quat, scl: Vec3) -> glm::Mat4 {
+        let translation = glm::translate(&glm::Mat4::identity(), &pos);
+        let rotation = glm::quat_to_mat4(rot);
 
-        self.rot = Transform::euler_from_model(&mat);
+        glm::scale(&(translation * rotation), &scl)
     }
 
-    // https://stackoverflow.com/questions/15022630/how-to-calculate-the-angle-from-rotation-matrix
-    fn euler_from_model(mat: &glm::Mat4) -> Degree3 {
-        // let scale_x = mat.column_part(0, 3).norm();
-        // let scale_y = mat.column_part(1, 3).norm();
-        // let scale_z = mat.column_part(2, 3).norm();
-
-        let rotation_submatrix = mat.fixed_slice::<3, 3>(0, 0);
-        // let rot_x_col = rotation_submatrix.column(0) / scale_x;
-        // let rot_y_col = rotation_submatrix.column(1) / scale_y;
-        // let rot_z_col = rotation_submatrix.column(2) / scale_z;
+    /// Sets the local matrix, decomposing it into translation, rotation and scale.
+    /// Shear can't be represented and is lost.
+    pub fn set_model(&mut self, mat: glm::Mat4) {
+        let (pos, rot, scale) = Transform::decompose(&mat);
 
-        let mut rot = glm::Vec3::zeros();
+        self.pos = pos;
+        self.scale = scale;
+        self.set_rotation(rot);
+    }
 
-        rot.x = rotation_submatrix[(2, 1)]
-            .atan2(rotation_submatrix[(2, 2)])
-            .to_degrees();
+    // https://math.stackexchange.com/questions/237369/given-this-transformation-matrix-how-do-i-decompose-it-into-translation-rotati
+    pub fn decompose(mat: &glm::Mat4) -> (Vec3, Quat, Vec3) {
+        let pos = mat.column(3).xyz();
 
-        let rot_y_atany =
-            (rotation_submatrix[(2, 1)].powi(2) 
-             + rotation_submatrix[(2, 2)].powi(2)).sqrt();
+        let linear = glm::mat4_to_mat3(mat);
+        let mut scale = vec3(
+            linear.column(0).norm(),
+            linear.column(1).norm(),
+            linear.column(2).norm(),
+        );
 
-        let rot_y_atanx = -rotation_submatrix[(2, 0)];
+        // A mirrored basis can't be a rotation, flip one axis to get it back
+        if linear.determinant() < 0. {
+            scale.x = -scale.x;
+        }
 
-        rot.y = rot_y_atanx.atan2(rot_y_atany).to_degrees();
+        let mut rotation = linear;
+        let mut flat_axes = Vec::new();
+        for (i, s) in scale.iter().enumerate() {
+            match s.abs() > f32::EPSILON {
+                true => rotation.set_column(i, &(linear.column(i) / *s)),
+                false => flat_axes.push(i),
+            }
+        }
 
-        rot.z = rotation_submatrix[(1, 0)]
-            .atan2(rotation_submatrix[(1, 1)])
-            .to_degrees();
+        // A zero scale flattens its axis, the rotation only has the other two to go by
+        match flat_axes[..] {
+            [] => {}
+            [i] => {
+                let axis = rotation.column((i + 1) % 3).cross(&rotation.column((i + 2) % 3));
+                rotation.set_column(i, &axis.normalize());
+            }
+            [i, j] => {
+                let k = 3 - i - j;
+                let unit = Mat3::identity().column(k).into_owned();
+                rotation = glm::quat_to_mat3(&glm::quat_rotation(&unit, &rotation.column(k).into_owned()));
+            }
+            _ => rotation = Mat3::identity(),
+        }
 
-        rot.into()
+        (pos, glm::quat_normalize(&glm::mat3_to_quat(&rotation)), scale)
     }
 
     pub fn face_camera(&mut self, camera: &crate::camera::Camera) {
@@ -173,9 +253,10 @@ impl Transform {
 
         let dist = vec3(pos_diff.x, pos_diff.z, 0.).norm();
         let sign = Vec3::dot(&pos_diff, &vec3(0., 0., 1.)).signum();
-        self.rot.0.x = (180. + f32::atan( sign * pos_diff.y / dist).to_degrees()) / 2.;
 
-        self.update_model_matrix(); 
+        let mut euler = self.euler;
+        euler.0.x = (180. + f32::atan( sign * pos_diff.y / dist).to_degrees()) / 2.;
+        self.set_rot(euler);
     }
 }
 
@@ -193,11 +274,15 @@ impl EguiDrawable for Transform {
                 let layout = egui::Layout::default();
                 columns[1].with_layout(layout, |ui| {
                     ui.vertical(|ui| {
-                        let any_changed = [ 
+                        let changed = [ 
                             self.pos.on_egui(ui, index),
-                            self.rot.on_egui(ui, index),
+                            self.euler.on_egui(ui, index),
                             self.scale.on_egui(ui, index)
-                        ].iter().any(|changed| *changed);
+                        ];
+
+                        if changed[1] { self.rot = self.euler.into(); }
+
+                        let any_changed = changed.iter().any(|changed| *changed);
 
                         any_changed
                     }).inner
@@ -215,14 +300,87 @@ impl EguiDrawable for Transform {
 impl Default for Transform {
     fn default() -> Self {
         let default_pos = glm::Vec3::zeros();
-        let default_rot = Degree3::default();
+        let default_rot = Quat::identity();
         let default_scale = vec3(1., 1., 1.);
+        let default_model = Transform::model_matrix(default_pos, &default_rot, default_scale);
         Transform {
             pos: default_pos,
             rot: default_rot,
+            euler: Degree3::default(),
             scale: default_scale,
-            model: Transform::model_matrix(default_pos, default_rot, default_scale),
+            model: default_model,
+            world: default_model,
             name: String::from(""),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_mat_eq(a: &Mat4, b: &Mat4) {
+        assert!((a - b).abs().max() < 1e-4, "{a} != {b}");
+    }
+
+    const CASES: [(Vec3, Vec3, Vec3); 5] = [
+        (Vec3::new(0., 0., 0.), Vec3::new(0., 0., 0.), Vec3::new(1., 1., 1.)),
+        (Vec3::new(1., -2., 3.), Vec3::new(10., 20., 30.), Vec3::new(2., 0.5, 3.)),
+        (Vec3::new(-4., 0., 2.), Vec3::new(-170., 45., 95.), Vec3::new(1., 1., 4.)),
+        // Gimbal lock
+        (Vec3::new(0., 5., 0.), Vec3::new(30., 90., -60.), Vec3::new(0.2, 0.2, 0.2)),
+        (Vec3::new(0., 0., 1.), Vec3::new(0., -90., 45.), Vec3::new(-1., 2., 1.)),
+    ];
+
+    #[test]
+    fn model_matrix_scales_then_rotates_then_translates() {
+        let transform = Transform::with_scale(vec3(1., 0., 0.), Degree3::xyz(0., 0., 90.), vec3(2., 1., 1.), "");
+        let point = transform.get_model_matrix() * glm::vec4(1., 0., 0., 1.);
+
+        assert!((point.xyz() - vec3(1., 2., 0.)).norm() < 1e-5, "{point}");
+    }
+
+    #[test]
+    fn decomposition_round_trips() {
+        for (pos, euler, scale) in CASES {
+            let mat = Transform::with_scale(pos, Degree3(euler), scale, "").get_model_matrix();
+            let (d_pos, d_rot, d_scale) = Transform::decompose(&mat);
+
+            assert_mat_eq(&Transform::model_matrix(d_pos, &d_rot, d_scale), &mat);
+        }
+    }
+
+    #[test]
+    fn decomposing_zero_scale_gives_a_rotation() {
+        for scale in [vec3(0., 1., 2.), vec3(3., 0., 0.), vec3(0., 0., 0.)] {
+            let mat = Transform::with_scale(vec3(1., 2., 3.), Degree3::xyz(10., 20., 30.), scale, "").get_model_matrix();
+            let (d_pos, d_rot, d_scale) = Transform::decompose(&mat);
+
+            assert!((glm::quat_length(&d_rot) - 1.).abs() < 1e-4, "{d_rot:?}");
+            assert_mat_eq(&Transform::model_matrix(d_pos, &d_rot, d_scale), &mat);
+        }
+    }
+
+    #[test]
+    fn set_model_keeps_scale() {
+        for (pos, euler, scale) in CASES {
+            let mat = Transform::with_scale(pos, Degree3(euler), scale, "").get_model_matrix();
+
+            let mut transform = Transform::default();
+            transform.set_model(mat);
+
+            assert_mat_eq(&transform.get_model_matrix(), &mat);
+            assert!((transform.get_scale().abs() - scale.abs()).norm() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn euler_view_round_trips() {
+        for (_, euler, _) in CASES {
+            let rot: Quat = Degree3(euler).into();
+            let back: Quat = Degree3::from(&rot).into();
+
+            assert_mat_eq(&glm::quat_to_mat4(&rot), &glm::quat_to_mat4(&back));
+        }
+    }
+}
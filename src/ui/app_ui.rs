@@ -1,31 +1,44 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use crate::{
     scene::Scene,
     input::InputSystem,
-    ecs::{Ecs, ADDABLE_COMPONENTS},
+    ecs::ComponentRegistry,
     transform::Transform,
-    loaders::ObjLoader,
+    hierarchy::{self, Parent, Children},
+    loaders::{AssetServer, NormalGeneration, MODEL_EXTENSIONS},
+    notifications::{self, Notification, Severity},
 };
+use bevy_ecs::prelude::*;
 use egui::{Ui, Context, LayerId};
+use nalgebra_glm as glm;
 use egui_gizmo::{GizmoMode, Gizmo, GizmoOrientation};
 
 
-pub fn draw_gizmo(ui: &mut Ui, scene: &Scene) {
+pub fn draw_gizmo(ui: &mut Ui, scene: &mut Scene) {
     let Some(eid) = scene.selected_entity else { return };
 
-    scene.ecs.do_entity(eid, |selected_entity_transform: &mut Transform| {
+    // The gizmo works in world space, its result is brought back into the parent's space
+    let parent_world = scene.world
+        .get::<Parent>(eid)
+        .map(|Parent(parent)| hierarchy::world_matrix(&scene.world, *parent))
+        .unwrap_or_else(glm::Mat4::identity);
+
+    if let Some(mut selected_entity_transform) = scene.world.get_mut::<Transform>(eid) {
+        let world_matrix = parent_world * selected_entity_transform.get_model_matrix();
+
         let gizmo = Gizmo::new("My gizmo")
             .view_matrix(scene.camera.get_view_matrix())
             .projection_matrix(scene.camera.get_proj_matrix())
-            .model_matrix(selected_entity_transform.get_model_matrix())
+            .model_matrix(world_matrix)
             .mode(scene.gizmo_mode)
             .orientation(GizmoOrientation::Local);
 
         if let Some(response) = gizmo.interact(ui) {
-            selected_entity_transform.set_model(response.transform.into());
+            let new_world: glm::Mat4 = response.transform.into();
+            selected_entity_transform.set_model(glm::inverse(&parent_world) * new_world);
         }
-    });
+    }
 }
 
 pub fn selected_entity_gizmo(ctx: &Context, current_scene: &mut Scene, input: &InputSystem) {
@@ -51,53 +64,172 @@ pub fn selected_entity_gizmo(ctx: &Context, current_scene: &mut Scene, input: &I
     }
 }
 
-fn add_component_ui(ui: &mut Ui, ecs: &mut Ecs) -> bool {
+fn add_component_ui(ui: &mut Ui, registry: &mut ComponentRegistry) -> Option<usize> {
 
     ui.horizontal(|ui| {
+        let names = registry.components().iter().map(|c| c.name).collect::<Vec<_>>();
 
-        let combobox = egui::ComboBox::from_label("")
-            .selected_text(ecs.component_to_add.to_string());
+        let combobox = egui::ComboBox::from_id_source("Component to add")
+            .selected_text(names.get(registry.component_to_add).copied().unwrap_or_default());
 
         // Changed component to be potentially added
         combobox.show_ui(ui, |ui| {
-            ADDABLE_COMPONENTS.map(|comp| {
-                ui.selectable_value(&mut ecs.component_to_add, comp, comp.to_string());
-            })
+            for (index, name) in names.iter().enumerate() {
+                ui.selectable_value(&mut registry.component_to_add, index, *name);
+            }
         });
 
         // Add the currently seleccted component type
-        ui.button("Add component").clicked()
+        ui.button("Add component").clicked().then_some(registry.component_to_add)
     }).inner
 }
 
+/// Same as `add_component_ui`, but only lists the components the entity has.
+fn remove_component_ui(ui: &mut Ui, registry: &mut ComponentRegistry, present: &[usize]) -> Option<usize> {
+
+    ui.horizontal(|ui| {
+        let name = |index: usize| registry.components()[index].name;
+        let selected_present = present.contains(&registry.component_to_remove);
+
+        let combobox = egui::ComboBox::from_id_source("Component to remove")
+            .selected_text(if selected_present { name(registry.component_to_remove) } else { "" });
+
+        let mut to_remove = registry.component_to_remove;
+        combobox.show_ui(ui, |ui| {
+            for index in present {
+                ui.selectable_value(&mut to_remove, *index, name(*index));
+            }
+        });
+        registry.component_to_remove = to_remove;
+
+        let clicked = ui
+            .add_enabled(present.contains(&to_remove), egui::Button::new("Remove component"))
+            .clicked();
+
+        clicked.then_some(to_remove)
+    }).inner
+}
+
+#[derive(Default)]
+struct SelectedEntityResponse {
+    /// Index of the component type in the `ComponentRegistry`.
+    add_component: Option<usize>,
+    remove_component: Option<usize>,
+    delete_entity: bool,
+}
 
 // Shows the entity's name and a list of its components.
-fn selected_entity_ui(ui: &mut Ui, ecs: &mut Ecs, selection: usize) -> bool {
+fn selected_entity_ui(ui: &mut Ui, world: &mut World, selection: Entity) -> SelectedEntityResponse {
     egui::CentralPanel::default()
         .frame(egui::Frame::default().outer_margin(10.0))
         .show_inside(ui, |ui| {
-            let name = ecs.do_entity(selection, |t: &mut Transform| { t.get_name().to_string() });
+            let name = world
+                .get::<Transform>(selection)
+                .map(|t| t.get_name().to_string())
+                .unwrap_or_else(|| format!("Entity {}", selection.id()));
+
             ui.vertical_centered(|ui| { ui.label(egui::RichText::new(name).heading().strong()); });
 
             ui.add_space(10.);
 
-            ecs.component_vecs
-                .iter_mut()
-                .for_each(|cv| cv.as_mut().draw_egui(ui, selection));
+            world.resource_scope(|world, mut registry: Mut<ComponentRegistry>| {
+                registry.draw_entity(world, selection, ui);
 
-            ui.add_space(10.);
-            ui.separator();
-            ui.add_space(10.);
+                ui.add_space(10.);
+                ui.separator();
+                ui.add_space(10.);
 
-            add_component_ui(ui, ecs)
+                let present = registry.components_of(world, selection);
+
+                SelectedEntityResponse {
+                    add_component: add_component_ui(ui, &mut registry),
+                    remove_component: remove_component_ui(ui, &mut registry, &present),
+                    delete_entity: ui.button("Delete entity").clicked(),
+                }
+            })
         }).inner
 }
 
-/// Shows a list of buttons where the label is the entity's name.
-/// Returns Some(id) if an entity was clicked. None otherwise.
-fn entity_selection(ui: &mut Ui, ecs: &mut Ecs) -> (Option<usize>, bool) {
-    let layout = egui::Layout::from_main_dir_and_cross_align(egui::Direction::TopDown, egui::Align::Center).with_cross_justify(true);
-   
+/// Id in egui's temporary memory of the entity being dragged in the entity tree.
+fn dragged_entity_id() -> egui::Id {
+    egui::Id::new("Dragged entity")
+}
+
+#[derive(Default)]
+struct EntityTreeResponse {
+    clicked: Option<Entity>,
+
+    /// Entity row under the pointer, used as the drop target while dragging.
+    hovered: Option<Entity>,
+}
+
+fn entity_tree_node(
+    ui: &mut Ui,
+    world: &World,
+    entity: Entity,
+    selected: Option<Entity>,
+    response: &mut EntityTreeResponse
+) {
+    let name = world
+        .get::<Transform>(entity)
+        .map(|t| t.get_name().to_string())
+        .unwrap_or_else(|| format!("Entity {}", entity.id()));
+
+    let children = world
+        .get::<Children>(entity)
+        .map(|c| c.0.clone())
+        .unwrap_or_default();
+
+    let row = |ui: &mut Ui| {
+        let row = ui
+            .selectable_label(selected == Some(entity), name)
+            .interact(egui::Sense::drag());
+
+        if row.clicked() {
+            response.clicked = Some(entity);
+        }
+
+        if row.drag_started() {
+            ui.memory().data.insert_temp(dragged_entity_id(), entity);
+        }
+
+        if ui.rect_contains_pointer(row.rect) {
+            response.hovered = Some(entity);
+        }
+    };
+
+    if children.is_empty() {
+        ui.horizontal(|ui| {
+            // Line up with the headers' labels
+            ui.add_space(ui.spacing().indent);
+            row(ui);
+        });
+    } else {
+        let id = ui.make_persistent_id(("Entity tree", entity));
+
+        egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, true)
+            .show_header(ui, row)
+            .body(|ui| {
+                for child in children {
+                    entity_tree_node(ui, world, child, selected, response);
+                }
+            });
+    }
+}
+
+struct EntitySelectionResponse {
+    clicked: Option<Entity>,
+    new_entity: bool,
+
+    /// (child, new parent) if an entity was dropped onto another one, or onto the root drop zone.
+    reparent: Option<(Entity, Option<Entity>)>,
+}
+
+/// Shows the entity hierarchy as a tree of labels with the entities' names.
+/// Entities can be dragged onto other entities to reparent them.
+fn entity_selection(ui: &mut Ui, world: &mut World, selected: Option<Entity>) -> EntitySelectionResponse {
+    let layout = egui::Layout::from_main_dir_and_cross_align(egui::Direction::TopDown, egui::Align::Min).with_cross_justify(true);
+
     egui::SidePanel::left("Entities")
         .resizable(true)
         .width_range(100.0..=200.0)
@@ -105,63 +237,246 @@ fn entity_selection(ui: &mut Ui, ecs: &mut Ecs) -> (Option<usize>, bool) {
         .show_inside(ui, |ui| {
             ui.heading("Entities");
             ui.with_layout(layout, |ui| {
-                let clicked = ecs.do_all_some(|(id, transform): (usize, &mut Transform)| {
-                    ui.button(transform.get_name())
-                        .clicked()
-                        .then_some(id) // Some(id) iff clicked            
-                });
+                let mut roots = world
+                    .query_filtered::<Entity, (With<Transform>, Without<Parent>)>()
+                    .iter(world)
+                    .collect::<Vec<_>>();
+
+                // Query order changes whenever an entity changes archetype
+                roots.sort_by_key(|e| e.id());
+
+                let mut tree = EntityTreeResponse::default();
+                for root in roots {
+                    entity_tree_node(ui, world, root, selected, &mut tree);
+                }
+
+                let mut reparent = None;
+                let dragged = ui.memory().data.get_temp::<Entity>(dragged_entity_id());
+
+                if let Some(dragged) = dragged {
+                    let root_zone = ui.add(egui::Label::new("Drop here to unparent").sense(egui::Sense::hover()));
+                    let over_root_zone = ui.rect_contains_pointer(root_zone.rect);
+
+                    if let Some(name) = world.get::<Transform>(dragged).map(|t| t.get_name().to_string()) {
+                        egui::show_tooltip_at_pointer(ui.ctx(), dragged_entity_id(), |ui| { ui.label(name); });
+                    }
+
+                    if ui.input().pointer.any_released() {
+                        ui.memory().data.remove::<Entity>(dragged_entity_id());
+
+                        reparent = match tree.hovered {
+                            Some(target) if target != dragged => Some((dragged, Some(target))),
+                            None if over_root_zone => Some((dragged, None)),
+                            _ => None
+                        };
+                    }
+                }
 
                 ui.separator();
 
                 let new_entity = ui.button("New Empty Entity").clicked();
 
-                // Shouldn't click more than one button
-                (clicked.first().copied(), new_entity)
+                EntitySelectionResponse { clicked: tree.clicked, new_entity, reparent }
             }).inner
         }).inner
 }
 
+pub struct EntitiesPanelResponse {
+    pub selected_entity: Option<Entity>,
+    pub add_entity: bool,
+    pub add_component: Option<usize>,
+    pub remove_component: Option<usize>,
+    pub delete_entity: bool,
+    pub reparent: Option<(Entity, Option<Entity>)>,
+}
+
 pub fn entities_panel(
     ui: &mut Ui,
     scene: &mut Scene,
     _lights_on: &mut bool
-) -> (Option<usize>, bool, bool) {
+) -> EntitiesPanelResponse {
     // ui.spacing();
 
-    let (selected_entity, add_entity, add_component) = {
-        let (selected_entity, add_entity) = entity_selection(ui, &mut scene.ecs);
-        let selected_entity = selected_entity.or(scene.selected_entity);
+    let selection = entity_selection(ui, &mut scene.world, scene.selected_entity);
 
-        let add_component = 
-            if let Some(selection) = selected_entity {
-                selected_entity_ui(ui, &mut scene.ecs, selection)
-            } else {
-                false
-            };
+    // Drop the selection if the selected entity was despawned
+    let selected_entity = selection.clicked
+        .or(scene.selected_entity)
+        .filter(|e| scene.world.get_entity(*e).is_some());
 
-        (selected_entity, add_entity, add_component)
-    };
+    let selected_entity_response = selected_entity
+        .map(|selection| selected_entity_ui(ui, &mut scene.world, selection))
+        .unwrap_or_default();
 
     // ui.checkbox(lights_on, "Global light toggle");
-    (selected_entity, add_entity, add_component)
+    EntitiesPanelResponse { 
+        selected_entity,
+        add_entity: selection.new_entity,
+        add_component: selected_entity_response.add_component,
+        remove_component: selected_entity_response.remove_component,
+        delete_entity: selected_entity_response.delete_entity,
+        reparent: selection.reparent
+    }
 }
 
-pub fn models_panel(ui: &mut Ui, object_loader: &mut ObjLoader) -> Option<PathBuf> {
-    ui.vertical_centered(|ui| {
+pub fn models_panel(ui: &mut Ui, assets: &mut AssetServer) -> Option<PathBuf> {
+    let picked = ui.vertical_centered(|ui| {
         ui.heading("Loaded Models");
         ui.separator();
 
-        object_loader.models().iter().for_each(|(name, _)| {
-            ui.label(name.to_string());
+        let mut model_names: Vec<String> = assets.object_loader.models().values().map(|model| model.borrow().name.clone()).collect();
+        model_names.sort();
+        model_names.iter().for_each(|name| {
+            ui.label(name);
         });
 
+        for progress in assets.load_progress() {
+            ui.label(&progress.path);
+            ui.add(
+                egui::ProgressBar::new(progress.fraction())
+                    .text(format!("{} {}/{}", progress.stage, progress.done, progress.total))
+            );
+        }
+
         ui.add_space(20.0);
 
+        ui.horizontal(|ui| {
+            let normals = &mut assets.object_loader.normal_generation;
+            ui.label("OBJ normals");
+            ui.selectable_value(normals, NormalGeneration::Flat, "Flat");
+            ui.selectable_value(normals, NormalGeneration::Smooth, "Smooth");
+        }).response.on_hover_text("For faces without normals in the file, used by the next loads");
+
         let load_a_model = egui::RichText::new("Load a model").size(20.0).strong();
         if ui.button(load_a_model).clicked() {
-            rfd::FileDialog::new().add_filter("Object model", &["obj"]).pick_file()
+            rfd::FileDialog::new().add_filter("Model", &MODEL_EXTENSIONS).pick_file()
         } else {
             None
         }
-    }).inner
+    }).inner;
+
+    ui.add_space(20.0);
+    unused_assets(ui, assets);
+
+    picked
+}
+
+/// Assets nothing references anymore, with buttons to free them.
+fn unused_assets(ui: &mut Ui, assets: &mut AssetServer) {
+    ui.collapsing("Unused assets", |ui| {
+        let unused = assets.unused_assets();
+
+        if unused.is_empty() {
+            ui.label("Nothing to unload");
+            return;
+        }
+
+        for asset in &unused {
+            ui.horizontal(|ui| {
+                if ui.small_button("Unload").clicked() {
+                    assets.unload(asset.kind, &asset.key);
+                }
+                ui.label(format!("{}: {}", asset.kind, asset.key));
+            });
+        }
+
+        if ui.button("Unload all unused").clicked() {
+            let unloaded = assets.unload_unused();
+            notifications::notify(Severity::Info, format!("Unloaded {unloaded} unused assets"));
+        }
+    });
+}
+
+fn severity_color(severity: Severity) -> egui::Color32 {
+    match severity {
+        Severity::Info => egui::Color32::LIGHT_GRAY,
+        Severity::Warning => egui::Color32::GOLD,
+        Severity::Error => egui::Color32::LIGHT_RED,
+    }
+}
+
+fn notification_label(ui: &mut Ui, notification: &Notification) {
+    let text = egui::RichText::new(&notification.message).color(severity_color(notification.severity));
+    ui.add(egui::Label::new(text).wrap(true));
+}
+
+/// Everything reported through `notifications`, newest first.
+pub fn log_panel(ui: &mut Ui) {
+    let log = notifications::notifications();
+
+    ui.horizontal(|ui| {
+        ui.heading("Log");
+        if ui.button("Clear").clicked() {
+            notifications::clear();
+        }
+    });
+    ui.separator();
+
+    egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+        if log.is_empty() {
+            ui.label("Nothing to report");
+        }
+
+        for notification in log.iter().rev() {
+            let age = notification.time.elapsed().as_secs();
+            ui.horizontal_top(|ui| {
+                ui.label(egui::RichText::new(format!("{age}s ago")).weak());
+                notification_label(ui, notification);
+            });
+        }
+    });
+}
+
+/// How long warnings and errors stay on screen outside of the log panel.
+const TOAST_DURATION: Duration = Duration::from_secs(6);
+
+/// Recent warnings and errors in the top right corner, so failures are noticed even with the log
+/// panel closed.
+pub fn notification_toasts(ctx: &Context) {
+    let recent = notifications::notifications()
+        .into_iter()
+        .filter(|n| n.severity >= Severity::Warning && n.time.elapsed() < TOAST_DURATION)
+        .collect::<Vec<_>>();
+
+    if recent.is_empty() {
+        return;
+    }
+
+    egui::Area::new("Notifications")
+        .anchor(egui::Align2::RIGHT_TOP, [-10., 10.])
+        .show(ctx, |ui| {
+            ui.set_max_width(350.);
+
+            for notification in recent.iter().rev().take(5) {
+                egui::Frame::popup(ui.style()).show(ui, |ui| notification_label(ui, notification));
+            }
+        });
+
+    // Keep repainting so toasts disappear on time
+    ctx.request_repaint();
+}
+
+pub enum FileAction {
+    Open(PathBuf),
+    Save(PathBuf),
+}
+
+/// File menu for opening and saving scenes.
+pub fn file_menu(ui: &mut Ui) -> Option<FileAction> {
+    let scene_dialog = || rfd::FileDialog::new().add_filter("Scene", &["json"]);
+    let mut action = None;
+
+    ui.menu_button("File", |ui| {
+        if ui.button("Open scene...").clicked() {
+            action = scene_dialog().pick_file().map(FileAction::Open);
+            ui.close_menu();
+        }
+
+        if ui.button("Save scene...").clicked() {
+            action = scene_dialog().set_file_name("scene.json").save_file().map(FileAction::Save);
+            ui.close_menu();
+        }
+    });
+
+    action
 }
@@ -0,0 +1,401 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy_ecs::prelude::*;
+use egui::Ui;
+use glow::HasContext;
+
+use crate::{
+    ecs::ComponentRegistry,
+    gl::get_gl,
+    loaders::{utils::Handle, AssetServer, ShaderLoader, TextureLoader},
+    model::Model,
+    renderer::{Material, MaterialType, MaterialValue, MATERIAL_TEXTURE_UNITS},
+    texture::{Texture, Texture2D, TextureType},
+};
+
+const THUMBNAIL_SIZE: f32 = 48.0;
+
+/// egui ids of the GL textures shown as thumbnails, registered with the painter before the UI runs.
+#[derive(Default)]
+pub struct TextureThumbnails {
+    ids: HashMap<glow::Texture, egui::TextureId>,
+
+    /// `TextureLoader::changes` when the ids were last updated.
+    changes: Option<usize>,
+}
+
+impl TextureThumbnails {
+    /// Registers the loaded textures and frees the ids of the ones that are gone, when they changed.
+    pub fn register(&mut self, painter: &mut egui_glow::Painter, texture_loader: &TextureLoader) {
+        if self.changes == Some(texture_loader.changes()) {
+            return;
+        }
+        self.changes = Some(texture_loader.changes());
+
+        let loaded: HashSet<glow::Texture> = texture_loader
+            .textures()
+            .iter()
+            .map(|texture| texture.borrow().native_handle)
+            .collect();
+
+        // Freeing an id deletes its texture too. The loader deleted those already, unless GL gave
+        // the name to some other texture since, that one has to stay
+        self.ids.retain(|native_handle, id| {
+            let in_use = loaded.contains(native_handle) || unsafe { get_gl().is_texture(*native_handle) };
+            if !in_use {
+                painter.free_texture(*id);
+            }
+            in_use
+        });
+
+        for native_handle in loaded {
+            self.ids.entry(native_handle).or_insert_with(|| painter.register_native_texture(native_handle));
+        }
+    }
+
+    fn show(&self, ui: &mut Ui, texture: &Handle<Texture>) {
+        let size = [THUMBNAIL_SIZE, THUMBNAIL_SIZE];
+
+        match self.ids.get(&texture.borrow().native_handle) {
+            Some(id) => ui.image(*id, size),
+            None => ui.add_sized(size, egui::Label::new("?")),
+        };
+    }
+}
+
+/// A material and the meshes using one that looks the same, see `Material::same_as`.
+pub struct MaterialUse {
+    pub material: Material,
+
+    /// Model and index of each mesh renderer.
+    pub meshes: Vec<(Handle<Model>, usize)>,
+}
+
+/// Materials of every model in the world. Models shared by several entities are only counted once.
+pub fn materials_in_use(world: &mut World) -> Vec<MaterialUse> {
+    let mut models: Vec<Handle<Model>> = Vec::new();
+    for model in world.query::<&Handle<Model>>().iter(world) {
+        if !models.contains(model) {
+            models.push(Handle::clone(model));
+        }
+    }
+
+    let mut uses: Vec<MaterialUse> = Vec::new();
+    for model in &models {
+        for (index, mr) in model.borrow().mesh_renderers.iter().enumerate() {
+            match uses.iter_mut().find(|used| used.material.same_as(&mr.1)) {
+                Some(used) => used.meshes.push((Handle::clone(model), index)),
+                None => uses.push(MaterialUse { material: mr.1.clone(), meshes: vec![(Handle::clone(model), index)] }),
+            }
+        }
+    }
+
+    uses.sort_by(|a, b| a.material.name.cmp(&b.material.name));
+    uses
+}
+
+/// Which material the panel edits. It's found again every frame by what it looks like,
+/// since materials are copied into each mesh renderer.
+#[derive(Default)]
+pub struct MaterialEditor {
+    selected: Option<Material>,
+}
+
+/// Lists the materials in use, and edits the picked one on every mesh using it.
+pub fn materials_panel(
+    ui: &mut Ui,
+    world: &mut World,
+    assets: &mut AssetServer,
+    editor: &mut MaterialEditor,
+    thumbnails: &TextureThumbnails
+) {
+    let uses = materials_in_use(world);
+    let mut selected = editor
+        .selected
+        .as_ref()
+        .and_then(|selected| uses.iter().position(|used| used.material.same_as(selected)));
+
+    egui::SidePanel::left("Materials")
+        .resizable(true)
+        .width_range(100.0..=200.0)
+        .default_width(140.0)
+        .show_inside(ui, |ui| {
+            ui.heading("Materials");
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (index, used) in uses.iter().enumerate() {
+                    let label = format!("{} ({})", used.material.name, used.meshes.len());
+                    if ui.selectable_label(selected == Some(index), label).clicked() {
+                        selected = Some(index);
+                    }
+                }
+
+                if uses.is_empty() {
+                    ui.label("No models in the scene");
+                }
+            });
+        });
+
+    let Some(used) = selected.and_then(|index| uses.get(index)) else {
+        editor.selected = None;
+        return;
+    };
+
+    let mut material = used.material.clone();
+
+    let changed = egui::CentralPanel::default()
+        .frame(egui::Frame::default().outer_margin(10.0))
+        .show_inside(ui, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| material_editor_ui(ui, &mut material, assets, thumbnails))
+                .inner
+        }).inner;
+
+    if changed {
+        apply_material(world, assets, &used.meshes, &material);
+    }
+
+    editor.selected = Some(material);
+}
+
+/// Gives `material` to the meshes, for every entity using their models. Models the loader holds
+/// are copied first (one copy for all their entities), so the loaded asset keeps its materials.
+pub fn apply_material(world: &mut World, assets: &AssetServer, meshes: &[(Handle<Model>, usize)], material: &Material) {
+    // Loaded models and their copies
+    let mut copies: Vec<(Handle<Model>, Handle<Model>)> = Vec::new();
+
+    for (model, index) in meshes {
+        let model = match copies.iter().find(|(loaded, _)| loaded == model) {
+            Some((_, copy)) => Handle::clone(copy),
+            None if assets.object_loader.is_loaded(model) => {
+                let copy = Handle::new(model.borrow().clone());
+                copies.push((Handle::clone(model), Handle::clone(&copy)));
+                copy
+            }
+            None => Handle::clone(model),
+        };
+
+        let mut model = model.borrow_mut();
+        if let Some(mr) = model.mesh_renderers.get_mut(*index) {
+            mr.set_material(material.clone());
+        }
+    }
+
+    if copies.is_empty() {
+        return;
+    }
+
+    for mut model in world.query::<&mut Handle<Model>>().iter_mut(world) {
+        if let Some((_, copy)) = copies.iter().find(|(loaded, _)| *loaded == *model) {
+            *model = Handle::clone(copy);
+        }
+    }
+}
+
+/// Shader, parameters and texture slots of `material`. Returns whether any changed.
+fn material_editor_ui(ui: &mut Ui, material: &mut Material, assets: &mut AssetServer, thumbnails: &TextureThumbnails) -> bool {
+    let (texture_loader, _, shader_loader) = assets.loaders();
+
+    ui.vertical_centered(|ui| { ui.label(egui::RichText::new(&material.name).heading().strong()); });
+    if let Some(base) = &material.base {
+        ui.label(format!("Instance of {}", base.borrow().name));
+    }
+    ui.add_space(10.);
+
+    let mut changed = shader_ui(ui, material, shader_loader);
+    changed |= ui.checkbox(&mut material.transparent, "Transparent").changed();
+
+    ui.add_space(10.);
+    ui.label(egui::RichText::new("Parameters").strong());
+    changed |= parameters_ui(ui, material);
+
+    ui.add_space(10.);
+    ui.label(egui::RichText::new("Textures").strong());
+    if texture_slots_ui(ui, material, texture_loader, thumbnails) {
+        material.update_variant(shader_loader);
+        changed = true;
+    }
+
+    changed
+}
+
+fn shader_ui(ui: &mut Ui, material: &mut Material, shader_loader: &mut ShaderLoader) -> bool {
+    let mut material_type = material.material_type;
+
+    ui.horizontal(|ui| {
+        ui.label("Shader");
+        egui::ComboBox::from_id_source("Material type")
+            .selected_text(material_type.label())
+            .show_ui(ui, |ui| {
+                for choice in MaterialType::EDITABLE {
+                    ui.selectable_value(&mut material_type, choice, choice.label());
+                }
+            });
+
+        if let Some(name) = shader_loader.shader_name(&material.shader) {
+            ui.label(egui::RichText::new(name).weak());
+        }
+    });
+
+    if material_type == material.material_type {
+        return false;
+    }
+
+    *material = material.with_type(material_type, shader_loader);
+    true
+}
+
+/// The resolved parameters, edited ones become overrides on instances.
+fn parameters_ui(ui: &mut Ui, material: &mut Material) -> bool {
+    let mut changed = false;
+
+    egui::Grid::new("Material parameters").num_columns(2).striped(true).show(ui, |ui| {
+        for (name, mut value) in material.resolved_parameters().iter() {
+            ui.label(name);
+
+            let edited = match &mut value {
+                MaterialValue::Int(int) => ui.add(egui::DragValue::new(int)).changed(),
+                MaterialValue::Float(float) => ui.add(egui::DragValue::new(float).speed(0.01)).changed(),
+                MaterialValue::Color(color) => ui.color_edit_button_rgb(color).changed(),
+                MaterialValue::Vec4(vector) => ui.horizontal(|ui| {
+                    let mut changed = false;
+                    for value in vector {
+                        changed |= ui.add(egui::DragValue::new(value).speed(0.01)).changed();
+                    }
+                    changed
+                }).inner,
+            };
+
+            if edited {
+                material.parameters.set(name, value);
+                changed = true;
+            }
+
+            ui.end_row();
+        }
+    });
+
+    changed
+}
+
+/// A row per texture slot with its thumbnail and a picker for the texture in it.
+/// Returns whether a slot changed, so the shader variant can follow.
+fn texture_slots_ui(ui: &mut Ui, material: &mut Material, texture_loader: &TextureLoader, thumbnails: &TextureThumbnails) -> bool {
+    let loaded = texture_loader.textures();
+    let mut picked = None;
+    let mut removed = None;
+
+    for slot in material.resolved_textures() {
+        ui.horizontal(|ui| {
+            thumbnails.show(ui, &slot.texture);
+
+            ui.vertical(|ui| {
+                ui.label(slot.uniform_name());
+
+                let path = slot.texture.borrow().path.clone();
+                egui::ComboBox::from_id_source(("Texture slot", slot.uniform_name()))
+                    .selected_text(path.rsplit('/').next().unwrap_or_default())
+                    .width(180.)
+                    .show_ui(ui, |ui| {
+                        for texture in &loaded {
+                            if ui.selectable_label(*texture == slot.texture, &texture.borrow().path).clicked() {
+                                picked = Some(Texture2D::new(texture, slot.tex_type, slot.tex_index));
+                            }
+                        }
+                    });
+
+                // Only the material's own slots, an instance's base keeps its textures
+                let own = material.textures.iter().any(|texture| texture.uniform_name() == slot.uniform_name());
+                if own && ui.small_button("Remove").clicked() {
+                    removed = Some(slot.uniform_name());
+                }
+            });
+        });
+    }
+
+    ui.horizontal(|ui| {
+        let mut new_slot = None;
+        let full = material.resolved_textures().len() >= MATERIAL_TEXTURE_UNITS as usize;
+
+        ui.add_enabled_ui(!full, |ui| {
+            ui.menu_button("Add texture", |ui| {
+                for tex_type in TextureType::ALL {
+                    if ui.button(tex_type.uniform_name()).clicked() {
+                        new_slot = Some(tex_type);
+                        ui.close_menu();
+                    }
+                }
+            });
+        }).response.on_disabled_hover_text(format!("Materials have up to {MATERIAL_TEXTURE_UNITS} textures"));
+
+        if let Some(tex_type) = new_slot {
+            let slots = material.resolved_textures();
+            let tex_index = slots.iter().filter(|slot| slot.tex_type == tex_type).count() as u32 + 1;
+            picked = Some(Texture2D::new(&texture_loader.white_texture(), tex_type, tex_index));
+        }
+    });
+
+    if let Some(uniform_name) = &removed {
+        material.textures.retain(|texture| texture.uniform_name() != *uniform_name);
+    }
+
+    if let Some(texture) = picked.clone() {
+        material.set_texture(texture);
+    }
+
+    removed.is_some() || picked.is_some()
+}
+
+/// Adds the editors of this module under the inspector's components.
+pub fn register_component_ui(world: &mut World) {
+    if let Some(mut registry) = world.get_resource_mut::<ComponentRegistry>() {
+        registry.set_extra_ui("model", |world, entity, ui| mesh_materials_ui(ui, world, entity));
+    }
+}
+
+/// The material of each of the entity's meshes, picked from the materials in use.
+/// Models shared with other entities or held by the loader are copied first, so only this entity changes.
+pub fn mesh_materials_ui(ui: &mut Ui, world: &mut World, entity: Entity) {
+    let Some(model) = world.get::<Handle<Model>>(entity).cloned() else { return };
+    let uses = materials_in_use(world);
+    let mut assigned = None;
+
+    ui.collapsing("Materials", |ui| {
+        for (mesh_index, mr) in model.borrow().mesh_renderers.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("Mesh {mesh_index}"));
+
+                egui::ComboBox::from_id_source(("Mesh material", entity, mesh_index))
+                    .selected_text(&mr.1.name)
+                    .show_ui(ui, |ui| {
+                        for (use_index, used) in uses.iter().enumerate() {
+                            if ui.selectable_label(used.material.same_as(&mr.1), &used.material.name).clicked() {
+                                assigned = Some((mesh_index, use_index));
+                            }
+                        }
+                    });
+            });
+        }
+    });
+
+    let Some((mesh_index, use_index)) = assigned else { return };
+
+    // `uses` holds handles to the model too
+    let material = uses[use_index].material.clone();
+    drop(uses);
+
+    // The entity's component and the clone above. Anything more is other entities or, for loaded
+    // models, the loader's own handle, and those keep theirs
+    let model = match model.strong_count() > 2 {
+        true => {
+            let copy = Handle::new(model.borrow().clone());
+            world.entity_mut(entity).insert(Handle::clone(&copy));
+            copy
+        }
+        false => model,
+    };
+
+    let mut model = model.borrow_mut();
+    if let Some(mr) = model.mesh_renderers.get_mut(mesh_index) {
+        mr.set_material(material);
+    }
+}
@@ -1,3 +1,5 @@
 mod app_ui;
+mod material_ui;
 
 pub use crate::ui::app_ui::*;
+pub use crate::ui::material_ui::*;
@@ -0,0 +1,823 @@
+//! Renders reference scenes headlessly and compares them against the PNGs in `tests/golden/`.
+//!
+//! Run with `GGL_BLESS=1` to (re)write the reference images after an intended rendering change.
+//! On failure the actual image and a diff are written to `target/golden/`.
+#![cfg(target_os = "linux")]
+
+extern crate nalgebra_glm as glm;
+
+use std::{
+    collections::HashMap,
+    fs,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use bevy_ecs::component::Component;
+use glm::{vec2, vec3};
+use image::{Rgba, RgbaImage};
+
+use ggl::{
+    camera::Camera,
+    headless::{HeadlessRenderer, LIGHT_TEST_SCENE},
+    light::{DirectionalLight, LightColors, PointLight, ShadowSettings, SpotLight},
+    model::Model,
+    loaders::{canonical_key, gltf_mesh_key, utils::Handle, AssetKind, ObjLoader, ShaderLoader, TextureLoader, DEFAULT_CUBE_NAME, DEFAULT_LIT_SHADER, DEFAULT_PBR_SHADER, DEFAULT_PLANE_NAME},
+    renderer::{CameraBlock, LightsBlock, Material, MaterialType},
+    notifications,
+    scene::Scene,
+    scene_file::{LoaderAssets, ModelRef, SceneFile},
+    shader::{ShaderDefines, Uniform, UniformError, UniformType},
+    texture::{Texture2D, TextureType},
+    transform::{Degree3, Transform},
+    ui::{apply_material, materials_in_use},
+};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 180;
+
+/// Per-pixel YIQ distance (0 to 1) above which two pixels count as different.
+/// Absorbs rounding differences between GL drivers.
+const PIXEL_THRESHOLD: f32 = 0.05;
+
+/// Fraction of pixels allowed to differ before an image fails.
+const MAX_DIFFERENT_PIXELS: f32 = 0.002;
+
+type Loaders<'a> = (&'a mut TextureLoader, &'a mut ObjLoader, &'a mut ShaderLoader);
+type SceneBuilder = fn(Loaders) -> Scene;
+
+/// The GL handle is global, so the tests take turns each with a context of their own.
+static GL_LOCK: Mutex<()> = Mutex::new(());
+
+/// A headless renderer that has GL to itself until it's dropped.
+struct Headless {
+    renderer: HeadlessRenderer,
+
+    // Released once the renderer and its context are gone
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Headless {
+    fn new() -> Self {
+        // Poisoned by a failed test, whose context is gone with it
+        let lock = GL_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let renderer = HeadlessRenderer::new(WIDTH, HEIGHT).expect("Golden image tests need EGL (e.g. Mesa)");
+
+        Headless { renderer, _lock: lock }
+    }
+}
+
+impl Deref for Headless {
+    type Target = HeadlessRenderer;
+
+    fn deref(&self) -> &HeadlessRenderer {
+        &self.renderer
+    }
+}
+
+impl DerefMut for Headless {
+    fn deref_mut(&mut self) -> &mut HeadlessRenderer {
+        &mut self.renderer
+    }
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn camera(pos: glm::Vec3, orientation: glm::Vec2) -> Camera {
+    Camera::new(pos, vec3(0., 1., 0.), orientation, WIDTH as f32 / HEIGHT as f32)
+}
+
+/// Ground and a few cubes for the lights to fall on, seen from above and in front.
+fn stage(loaders: Loaders) -> Scene {
+    let (_, object_loader, _) = loaders;
+    let cube = object_loader.clone_handle(DEFAULT_CUBE_NAME);
+
+    let mut scene = Scene::empty(WIDTH as i32, HEIGHT as i32);
+    scene.camera = camera(vec3(0., 4., 9.), vec2(-25., 0.));
+
+    scene.world
+        .spawn()
+        .insert(Transform::with_scale(vec3(0., -1., 0.), Degree3::default(), vec3(8., 0.5, 8.), "ground"))
+        .insert(Handle::clone(&cube));
+
+    for (index, x) in [-3., 0., 3.].into_iter().enumerate() {
+        scene.world
+            .spawn()
+            .insert(Transform::new(vec3(x, 0., 0.), Degree3::xyz(0., 30. * index as f32, 0.), &format!("cube {index}")))
+            .insert(Handle::clone(&cube));
+    }
+
+    scene
+}
+
+fn spawn_light(scene: &mut Scene, transform: Transform, light: impl Component) {
+    scene.world.spawn().insert(transform).insert(light);
+}
+
+/// `stage` under a single light.
+fn lit_stage(loaders: Loaders, transform: Transform, light: impl Component) -> Scene {
+    let mut scene = stage(loaders);
+    spawn_light(&mut scene, transform, light);
+    scene
+}
+
+/// Directional light from above and to the side, without shadows.
+fn spawn_sun(scene: &mut Scene, ambient: f32, diffuse: glm::Vec3) {
+    spawn_light(
+        scene,
+        Transform::new(vec3(0., 5., 0.), Degree3::xyz(30., 0., 40.), "directional light"),
+        DirectionalLight { enabled: true, colors: LightColors::default().ambient(vec3(ambient, ambient, ambient)).diffuse(diffuse), ..Default::default() },
+    );
+}
+
+fn point_light(loaders: Loaders) -> Scene {
+    lit_stage(
+        loaders,
+        Transform::new(vec3(-1.5, 1.5, 1.5), Degree3::default(), "point light"),
+        PointLight {
+            colors: LightColors::no_ambient(vec3(1., 0.6, 0.2), 0.9),
+            attenuation_constants: vec3(0.1, 0.1, 1.0),
+            ..Default::default()
+        },
+    )
+}
+
+/// Point light between the cubes, shadowing the ground around them.
+fn point_light_shadows(loaders: Loaders) -> Scene {
+    lit_stage(
+        loaders,
+        Transform::new(vec3(1.5, 1.5, 0.5), Degree3::default(), "point light"),
+        PointLight {
+            colors: LightColors::no_ambient(vec3(1., 0.8, 0.6), 0.9),
+            attenuation_constants: vec3(0.02, 0.05, 1.0),
+            shadows: ShadowSettings { casts_shadows: true, ..ShadowSettings::default() },
+            ..Default::default()
+        },
+    )
+}
+
+/// A grid of small lights, far more than fit in a uniform array, each lighting only its patch of ground.
+fn many_lights(loaders: Loaders) -> Scene {
+    let mut scene = stage(loaders);
+
+    for index in 0..200 {
+        let (column, row) = ((index % 20) as f32, (index / 20) as f32);
+        let hue = index as f32 / 200.;
+
+        spawn_light(
+            &mut scene,
+            Transform::new(vec3(column * 0.4 - 3.8, -0.4, row * 0.8 - 3.6), Degree3::default(), &format!("point light {index}")),
+            PointLight {
+                colors: LightColors::no_ambient(vec3(1. - hue, 0.3 + hue * 0.4, hue), 0.9),
+                attenuation_constants: vec3(8., 0.5, 1.),
+                ..Default::default()
+            },
+        );
+    }
+
+    scene
+}
+
+fn spot_light(loaders: Loaders) -> Scene {
+    lit_stage(
+        loaders,
+        Transform::new(vec3(0., 3., 1.), Degree3::xyz(20., 0., 0.), "spot light"),
+        SpotLight {
+            enabled: true,
+            colors: LightColors::no_ambient(vec3(0.2, 0.8, 1.), 0.9),
+            attenuation_constants: vec3(0.02, 0.05, 1.0),
+            cutoff_angles: vec2(20., 30.),
+            ..Default::default()
+        },
+    )
+}
+
+fn directional_light(loaders: Loaders) -> Scene {
+    let mut scene = stage(loaders);
+    spawn_sun(&mut scene, 0.1, vec3(0.8, 0.8, 0.7));
+    scene
+}
+
+/// Directional and spot lights from behind the cubes, casting their shadows towards the camera.
+fn shadows(loaders: Loaders) -> Scene {
+    let mut scene = stage(loaders);
+    let shadows = ShadowSettings { casts_shadows: true, ..ShadowSettings::default() };
+
+    spawn_light(
+        &mut scene,
+        Transform::new(vec3(0., 5., 0.), Degree3::xyz(-30., 0., -40.), "directional light"),
+        DirectionalLight {
+            enabled: true,
+            colors: LightColors::default().ambient(vec3(0.1, 0.1, 0.1)).diffuse(vec3(0.6, 0.6, 0.5)),
+            shadows: shadows.clone(),
+        },
+    );
+
+    spawn_light(
+        &mut scene,
+        Transform::new(vec3(1.5, 3., -2.), Degree3::xyz(-30., 0., 0.), "spot light"),
+        SpotLight {
+            enabled: true,
+            colors: LightColors::no_ambient(vec3(0.2, 0.8, 1.), 0.9),
+            attenuation_constants: vec3(0.02, 0.05, 1.0),
+            cutoff_angles: vec2(30., 40.),
+            shadows,
+        },
+    );
+
+    scene
+}
+
+/// glTF node tree spawned as entities: textured base, arm with a lit and an unlit primitive,
+/// and a transparent panel on top.
+fn gltf_hierarchy(loaders: Loaders) -> Scene {
+    let (texture_loader, object_loader, shader_loader) = loaders;
+
+    let mut scene = Scene::empty(WIDTH as i32, HEIGHT as i32);
+    scene.camera = camera(vec3(0., 1.8, 3.), vec2(-10., 0.));
+
+    let root = object_loader
+        .spawn_gltf(&mut scene.world, "assets/gltf/hierarchy.gltf", texture_loader, shader_loader)
+        .expect("Failed to load the glTF fixture");
+    scene.world.get_mut::<Transform>(root).unwrap().set_rot(Degree3::xyz(0., 20., 0.));
+
+    spawn_sun(&mut scene, 0.2, vec3(0.8, 0.8, 0.8));
+    scene
+}
+
+/// PBR cubes getting rougher from left to right, dielectric in the front row and metallic in the back,
+/// with a crate behind them whose metal frame comes from a metallic map.
+fn pbr_materials(loaders: Loaders) -> Scene {
+    let (texture_loader, object_loader, shader_loader) = loaders;
+
+    let mut scene = Scene::empty(WIDTH as i32, HEIGHT as i32);
+    scene.camera = camera(vec3(0., 4.5, 3.), vec2(-50., 0.));
+
+    let white = Texture2D::new(&texture_loader.white_texture(), TextureType::Diffuse, 1);
+    let mut spawn_cube = |scene: &mut Scene, name: &str, pos: glm::Vec3, material: Material| {
+        let model = object_loader.clone(DEFAULT_CUBE_NAME, &format!("golden {name}"));
+        for mr in &mut model.borrow_mut().mesh_renderers {
+            mr.set_material(material.clone());
+        }
+
+        scene.world
+            .spawn()
+            .insert(Transform::with_scale(pos, Degree3::xyz(0., 35., 0.), vec3(0.7, 0.7, 0.7), name))
+            .insert(model);
+    };
+
+    for (row, metallic) in [0., 1.].into_iter().enumerate() {
+        for column in 0..5 {
+            let mut material = Material::pbr(shader_loader, vec![white.clone()]);
+            material.parameters
+                .set("base_color", [1., 0.7, 0.3])
+                .set("metallic", metallic)
+                .set("roughness", 0.1 + column as f32 * 0.2);
+
+            let pos = vec3(column as f32 * 1.6 - 3.2, 0., 1. - row as f32 * 1.8);
+            spawn_cube(&mut scene, &format!("cube {row} {column}"), pos, material);
+        }
+    }
+
+    let crate_textures = vec![
+        Texture2D::new(&texture_loader.load_texture_or_default(Path::new("assets/textures/container2.png")), TextureType::Diffuse, 1),
+        Texture2D::new(&texture_loader.load_texture_or_default(Path::new("assets/textures/container2_specular.png")), TextureType::Metallic, 1),
+    ];
+    let mut crate_material = Material::pbr(shader_loader, crate_textures);
+    crate_material.parameters.set("metallic", 1.).set("roughness", 0.3);
+    spawn_cube(&mut scene, "crate", vec3(0., 0., -2.6), crate_material);
+
+    spawn_sun(&mut scene, 0.1, vec3(0.6, 0.6, 0.6));
+    spawn_light(
+        &mut scene,
+        Transform::new(vec3(0., 1.5, 2.), Degree3::default(), "point light"),
+        PointLight { colors: LightColors::no_ambient(vec3(1., 1., 1.), 0.9), attenuation_constants: vec3(0.02, 0.05, 1.), ..Default::default() },
+    );
+
+    scene
+}
+
+/// Crates sharing one base material: as is, tinted red and less shiny, and with another diffuse texture.
+fn material_instances(loaders: Loaders) -> Scene {
+    let (texture_loader, object_loader, shader_loader) = loaders;
+
+    let mut scene = Scene::empty(WIDTH as i32, HEIGHT as i32);
+    scene.camera = camera(vec3(0., 2., 6.), vec2(-15., 0.));
+
+    let mut load = |path: &str, tex_type| Texture2D::new(&texture_loader.load_texture_or_default(Path::new(path)), tex_type, 1);
+    let base = Handle::new(Material::lit(shader_loader, vec![
+        load("assets/textures/container2.png", TextureType::Diffuse),
+        load("assets/textures/container2_specular.png", TextureType::Specular),
+    ]));
+
+    let mut tinted = Material::instance(&base);
+    tinted.parameters.set("diffuse_color", [1., 0.3, 0.3]).set("shininess", 4.);
+
+    let mut retextured = Material::instance(&base);
+    retextured.set_texture(load("assets/textures/awesomeface.png", TextureType::Diffuse));
+
+    let materials = [Material::instance(&base), tinted, retextured];
+
+    for (index, material) in materials.into_iter().enumerate() {
+        let model = object_loader.clone(DEFAULT_CUBE_NAME, &format!("golden crate {index}"));
+        for mr in &mut model.borrow_mut().mesh_renderers {
+            mr.set_material(material.clone());
+        }
+
+        scene.world
+            .spawn()
+            .insert(Transform::new(vec3(index as f32 * 2.6 - 2.6, 0., 0.), Degree3::xyz(0., 30., 0.), &format!("crate {index}")))
+            .insert(model);
+    }
+
+    spawn_sun(&mut scene, 0.3, vec3(0.8, 0.8, 0.8));
+    spawn_light(
+        &mut scene,
+        Transform::new(vec3(0., 1., 3.), Degree3::default(), "point light"),
+        PointLight { colors: LightColors::no_ambient(vec3(1., 1., 1.), 0.9), attenuation_constants: vec3(0.02, 0.05, 1.), ..Default::default() },
+    );
+
+    scene
+}
+
+/// Billboards textured with the light icons in front of each other.
+fn billboard_scene(loaders: Loaders, positions: &[glm::Vec3]) -> Scene {
+    let (texture_loader, object_loader, shader_loader) = loaders;
+
+    let icons = [texture_loader.point_light_texture(), texture_loader.directional_light_texture()];
+    let mut scene = Scene::empty(WIDTH as i32, HEIGHT as i32);
+    scene.camera = camera(vec3(0., 0., 4.), vec2(0., 0.));
+
+    for (index, pos) in positions.iter().enumerate() {
+        let texture = Texture2D::new(&icons[index % icons.len()], TextureType::Diffuse, 1);
+        let material = Material::billboard(shader_loader, texture);
+        let model = object_loader.clone(DEFAULT_PLANE_NAME, &format!("golden billboard {index}"));
+
+        for mr in &mut model.borrow_mut().mesh_renderers {
+            mr.set_material(material.clone());
+        }
+
+        scene.world
+            .spawn()
+            .insert(Transform::with_scale(*pos, Degree3::default(), vec3(0.3, 0.3, 0.3), &format!("billboard {index}")))
+            .insert(model);
+    }
+
+    scene
+}
+
+fn billboards(loaders: Loaders) -> Scene {
+    billboard_scene(loaders, &[vec3(-1.5, 0., 0.), vec3(1.5, 0.5, -1.), vec3(0., -0.5, 1.)])
+}
+
+/// Half transparent quads partly in front of each other. Transparent meshes have to be drawn
+/// back to front for the farther ones to show through.
+fn transparency_ordering(loaders: Loaders) -> Scene {
+    let (texture_loader, object_loader, shader_loader) = loaders;
+
+    let white = Texture2D::new(&texture_loader.white_texture(), TextureType::Diffuse, 1);
+    let mut scene = Scene::empty(WIDTH as i32, HEIGHT as i32);
+    scene.camera = camera(vec3(0., 0., 2.5), vec2(0., 0.));
+
+    // Spawned front to back, so drawing in spawn order would hide the ones behind
+    let quads = [
+        (vec3(-0.4, -0.2, 1.), [1., 0.2, 0.2]),
+        (vec3(0., 0.2, 0.), [0.2, 1., 0.2]),
+        (vec3(0.6, -0.1, -1.), [0.2, 0.4, 1.]),
+    ];
+
+    for (index, (pos, color)) in quads.into_iter().enumerate() {
+        let mut material = Material::unlit(shader_loader, vec![white.clone()]);
+        material.transparent = true;
+        material.parameters.set("diffuse_color", color).set("opacity", 0.5);
+
+        let model = object_loader.clone(DEFAULT_PLANE_NAME, &format!("golden quad {index}"));
+        for mr in &mut model.borrow_mut().mesh_renderers {
+            mr.set_material(material.clone());
+        }
+
+        scene.world
+            .spawn()
+            .insert(Transform::with_scale(pos, Degree3::default(), vec3(1.5, 1.5, 1.5), &format!("quad {index}")))
+            .insert(model);
+    }
+
+    scene
+}
+
+/// YIQ color distance normalized to 0..1, from "Measuring perceived color difference using YIQ NTSC
+/// transmission color space in mobile applications" (Kotsarenko & Ramos), as used by pixelmatch.
+#[allow(clippy::excessive_precision)]
+fn color_distance(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    // Blend onto white so transparent pixels compare by what's visible
+    let blend = |p: &Rgba<u8>| {
+        let alpha = p[3] as f32 / 255.;
+        [0, 1, 2].map(|i| 255. + (p[i] as f32 - 255.) * alpha)
+    };
+
+    let ([r1, g1, b1], [r2, g2, b2]) = (blend(a), blend(b));
+
+    let y = |r: f32, g: f32, b: f32| r * 0.29889531 + g * 0.58662247 + b * 0.11448223;
+    let i = |r: f32, g: f32, b: f32| r * 0.59597799 - g * 0.27417610 - b * 0.32180189;
+    let q = |r: f32, g: f32, b: f32| r * 0.21147017 - g * 0.52261711 + b * 0.31114694;
+
+    let dy = y(r1, g1, b1) - y(r2, g2, b2);
+    let di = i(r1, g1, b1) - i(r2, g2, b2);
+    let dq = q(r1, g1, b1) - q(r2, g2, b2);
+
+    const MAX_DELTA: f32 = 35215.;
+    (0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq) / MAX_DELTA
+}
+
+/// Returns the fraction of differing pixels and an image highlighting them in red.
+fn compare(expected: &RgbaImage, actual: &RgbaImage) -> (f32, RgbaImage) {
+    let mut diff = RgbaImage::new(expected.width(), expected.height());
+    let mut different = 0;
+
+    for (x, y, expected_pixel) in expected.enumerate_pixels() {
+        let actual_pixel = actual.get_pixel(x, y);
+
+        let diff_pixel = if color_distance(expected_pixel, actual_pixel) > PIXEL_THRESHOLD {
+            different += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            // Faded grayscale of the expected image for context
+            let luma = (expected_pixel[0] as u32 + expected_pixel[1] as u32 + expected_pixel[2] as u32) / 3;
+            let faded = (255 - (255 - luma) / 4) as u8;
+            Rgba([faded, faded, faded, 255])
+        };
+
+        diff.put_pixel(x, y, diff_pixel);
+    }
+
+    (different as f32 / (expected.width() * expected.height()) as f32, diff)
+}
+
+/// Compares against the reference image, returning a description of the failure if any.
+fn check(name: &str, actual: &RgbaImage) -> Result<(), String> {
+    let golden_path = golden_dir().join(format!("{name}.png"));
+
+    if std::env::var_os("GGL_BLESS").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.save(&golden_path).unwrap();
+        println!("Blessed {}", golden_path.display());
+        return Ok(());
+    }
+
+    let save_actual = || {
+        std::fs::create_dir_all(output_dir()).unwrap();
+        let actual_path = output_dir().join(format!("{name}.actual.png"));
+        actual.save(&actual_path).unwrap();
+        actual_path
+    };
+
+    let expected = match image::open(&golden_path) {
+        Ok(expected) => expected.to_rgba8(),
+        Err(err) => {
+            let actual_path = save_actual();
+            return Err(format!("{name}: couldn't open {} ({err}), actual image at {}", golden_path.display(), actual_path.display()));
+        }
+    };
+
+    if expected.dimensions() != actual.dimensions() {
+        let actual_path = save_actual();
+        return Err(format!(
+            "{name}: size {:?} doesn't match the reference's {:?}, actual image at {}",
+            actual.dimensions(), expected.dimensions(), actual_path.display()
+        ));
+    }
+
+    let (different, diff) = compare(&expected, actual);
+    if different <= MAX_DIFFERENT_PIXELS {
+        return Ok(());
+    }
+
+    let actual_path = save_actual();
+    let diff_path = output_dir().join(format!("{name}.diff.png"));
+    diff.save(&diff_path).unwrap();
+
+    Err(format!(
+        "{name}: {:.2}% of pixels differ (max {:.2}%), see {} and {}",
+        different * 100., MAX_DIFFERENT_PIXELS * 100., actual_path.display(), diff_path.display()
+    ))
+}
+
+/// Reloads copies of a texture and a shader, broken versions have to leave the last good one.
+#[test]
+fn hot_reloading() {
+    let mut renderer = Headless::new();
+    let assets = renderer.assets();
+
+    let directory = std::env::temp_dir().join("ggl_hot_reload");
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    let texture_path = directory.join("texture.png");
+    fs::copy("assets/textures/point_light_white.png", &texture_path).unwrap();
+    let texture = assets.load_texture(&texture_path).unwrap();
+    let first_texture = texture.borrow().native_handle;
+
+    fs::write(&texture_path, b"not a png").unwrap();
+    assert!(assets.reload(AssetKind::Texture, &canonical_key(&texture_path)).is_err());
+    assert_eq!(texture.borrow().native_handle, first_texture);
+
+    fs::copy("assets/textures/directional_light_white.png", &texture_path).unwrap();
+    assets.reload(AssetKind::Texture, &canonical_key(&texture_path)).unwrap();
+    assert_ne!(texture.borrow().native_handle, first_texture);
+
+    let vert_path = directory.join("shader.vert");
+    let frag_path = directory.join("shader.frag");
+    fs::copy("assets/shaders/default_unlit.vert", &vert_path).unwrap();
+    fs::copy("assets/shaders/default_unlit.frag", &frag_path).unwrap();
+
+    let shader = assets.load_shader("hot_reload", vert_path.to_str().unwrap(), frag_path.to_str().unwrap()).unwrap();
+    let shader_key = assets.shader_loader.programs_using(&frag_path).pop().expect("The shader should be found by its files");
+    let first_program = shader.borrow().handle;
+
+    fs::write(&frag_path, "#version 330 core\nvoid main() { not glsl }").unwrap();
+    assert!(assets.reload(AssetKind::Shader, &shader_key).is_err());
+    assert_eq!(shader.borrow().handle, first_program);
+
+    fs::copy("assets/shaders/default_unlit.frag", &frag_path).unwrap();
+    assets.reload(AssetKind::Shader, &shader_key).unwrap();
+    assert_ne!(shader.borrow().handle, first_program);
+}
+
+/// Variants are compiled once per set of defines, and errors in includes point at the include.
+#[test]
+fn shader_variants() {
+    let mut renderer = Headless::new();
+    let assets = renderer.assets();
+
+    let defines = ShaderDefines::from([("HAS_NORMAL_MAP".to_string(), String::new())]);
+    let variant = assets.shader_loader.get_variant(DEFAULT_LIT_SHADER, &defines);
+
+    assert!(variant == assets.shader_loader.get_variant(DEFAULT_LIT_SHADER, &defines));
+    assert!(variant != assets.shader_loader.get_shader_rc(DEFAULT_LIT_SHADER));
+    assert!(assets.shader_loader.variants_of(DEFAULT_LIT_SHADER).contains(&variant));
+    assert_eq!(assets.shader_loader.shader_name(&variant), Some(DEFAULT_LIT_SHADER));
+
+    let lighting = Path::new("assets/shaders/common/lighting.glsl");
+    assert!(assets.shader_loader.programs_using(lighting).len() >= 2, "Every lit variant includes the lighting library");
+
+    let directory = std::env::temp_dir().join("ggl_shader_include");
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    let frag_path = directory.join("broken.frag");
+    fs::write(&frag_path, "#version 330 core\n#include \"broken.glsl\"\nout vec4 color;\nvoid main() { color = broken(); }\n").unwrap();
+    fs::write(directory.join("broken.glsl"), "vec4 broken() {\n    return not_declared;\n}\n").unwrap();
+
+    let err = assets.load_shader("broken", "assets/shaders/default_unlit.vert", frag_path.to_str().unwrap()).unwrap_err();
+    assert!(err.to_string().contains("broken.glsl:2:"), "{err}");
+}
+
+/// What the materials panel does to a material: switching shaders, and changing texture slots.
+#[test]
+fn material_editing() {
+    let mut renderer = Headless::new();
+    let (texture_loader, _, shader_loader) = renderer.assets().loaders();
+    let white = texture_loader.white_texture();
+
+    let mut lit = Material::lit(shader_loader, vec![Texture2D::new(&white, TextureType::Diffuse, 1)]);
+    lit.name = "Painted".into();
+    lit.parameters.set("diffuse_color", [0.8, 0.2, 0.1]);
+
+    let pbr = lit.with_type(MaterialType::Pbr, shader_loader);
+    assert_eq!(pbr.name, "Painted");
+    assert_eq!(pbr.parameters, lit.parameters.converted(MaterialType::Pbr));
+    assert_eq!(shader_loader.shader_name(&pbr.shader), Some(DEFAULT_PBR_SHADER));
+    assert!(pbr.textures == lit.textures);
+
+    assert!(!pbr.same_as(&lit));
+    assert!(pbr.with_type(MaterialType::Lit, shader_loader).same_as(&lit));
+
+    let mut normal_mapped = lit.clone();
+    normal_mapped.set_texture(Texture2D::new(&white, TextureType::Normal, 1));
+    normal_mapped.update_variant(shader_loader);
+    assert!(normal_mapped.shader != lit.shader);
+    assert!(!normal_mapped.same_as(&lit));
+}
+
+/// Instances are saved as their base and what they change, and share one base again once loaded.
+#[test]
+fn material_instances_are_saved() {
+    let mut renderer = Headless::new();
+    let mut scene = material_instances(renderer.get_resource_managers());
+
+    let json = SceneFile::from_scene(&mut scene, &mut LoaderAssets::new(renderer.assets())).to_json().unwrap();
+    let mut loaded = SceneFile::from_json(&json).unwrap().into_scene(WIDTH as f32 / HEIGHT as f32).unwrap();
+    loaded.resolve_assets(&mut LoaderAssets::new(renderer.assets()));
+
+    let materials: Vec<Material> = loaded.world
+        .query::<&Handle<Model>>()
+        .iter(&loaded.world)
+        .map(|model| model.borrow().mesh_renderers[0].1.clone())
+        .collect();
+    assert_eq!(materials.len(), 3);
+
+    let base = materials[0].base.clone().expect("Loaded as an instance");
+    assert!(materials.iter().all(|material| material.base.as_ref() == Some(&base)), "The crates share one base");
+    assert!(materials.iter().any(|material| material.parameters.len() == 2), "The tinted crate has only its own parameters");
+
+    let image = renderer.render(&mut loaded);
+    check("material_instances", &image).unwrap();
+}
+
+/// An edit in the materials panel goes to a copy of the loaded model, and is saved as an override.
+#[test]
+fn material_panel_edits_are_saved() {
+    let mut renderer = Headless::new();
+    let mut scene = stage(renderer.get_resource_managers());
+    let assets = renderer.assets();
+    let cube = assets.object_loader.clone_handle(DEFAULT_CUBE_NAME);
+    let loaded_color = cube.borrow().mesh_renderers[0].1.resolved_parameters().color("diffuse_color");
+
+    let uses = materials_in_use(&mut scene.world);
+    assert_eq!(uses.len(), 1, "Every cube uses the loaded cube");
+
+    let mut material = uses[0].material.clone();
+    material.parameters.set("diffuse_color", [1., 0., 0.]);
+    apply_material(&mut scene.world, assets, &uses[0].meshes, &material);
+    drop(uses);
+
+    assert_eq!(cube.borrow().mesh_renderers[0].1.resolved_parameters().color("diffuse_color"), loaded_color);
+
+    let models: Vec<_> = scene.world.query::<&Handle<Model>>().iter(&scene.world).cloned().collect();
+    assert!(models.iter().all(|model| *model != cube && *model == models[0]), "The cubes share one copy");
+
+    let saved = SceneFile::from_scene(&mut scene, &mut LoaderAssets::new(assets));
+    for entity in &saved.entities {
+        let model_ref: ModelRef = serde_json::from_value(entity.components["model"].clone()).unwrap();
+        assert_eq!(model_ref.asset, DEFAULT_CUBE_NAME);
+        assert_eq!(model_ref.material_overrides.len(), 1);
+        assert_eq!(model_ref.material_overrides[0].material.parameters.color("diffuse_color"), Some([1., 0., 0.]));
+    }
+}
+
+/// Programs know their uniforms, and problems setting them are found without a frame to render.
+#[test]
+fn uniform_reflection() {
+    let mut renderer = Headless::new();
+    let assets = renderer.assets();
+
+    let lit = assets.shader_loader.get_shader_rc(DEFAULT_LIT_SHADER);
+    let lit = lit.borrow();
+
+    assert_eq!(lit.uniform("u_material.shininess").map(|uniform| uniform.uniform_type), Some(UniformType::Float));
+    assert_eq!(lit.uniform("u_point_shadow_maps[2]").map(|uniform| uniform.uniform_type), Some(UniformType::SamplerCubeShadow));
+    assert!(lit.uniforms().windows(2).all(|pair| pair[0].name < pair[1].name));
+
+    let uniforms = HashMap::from([
+        ("u_material.diffuse_color", Uniform::Vec3(vec3(1., 1., 1.))),
+        ("u_material.difuse_color", Uniform::Vec3(vec3(1., 1., 1.))),
+        ("u_material.shininess", Uniform::Vec2(vec2(0., 0.))),
+    ]);
+
+    let errors = lit.check_uniforms(&uniforms, "");
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert!(errors.iter().any(|err| matches!(err, UniformError::Unknown { name, .. } if name == "u_material.difuse_color")));
+    assert!(errors.iter().any(|err| matches!(err, UniformError::WrongType { declared: UniformType::Float, .. })));
+
+    // Declared but not read, so the compiler drops it
+    let frag_path = std::env::temp_dir().join("ggl_unused_uniform.frag");
+    fs::write(&frag_path, "#version 330 core\nuniform float u_unused;\nout vec4 color;\nvoid main() { color = vec4(1.0); }\n").unwrap();
+
+    let unused = assets.load_shader("unused_uniform", "assets/shaders/default_unlit.vert", frag_path.to_str().unwrap()).unwrap();
+    let unused = unused.borrow();
+    assert!(matches!(unused.find_uniform("u_unused", UniformType::Float), Err(UniformError::Inactive { .. })));
+    assert!(matches!(unused.find_uniform("u_unsued", UniformType::Float), Err(UniformError::Unknown { .. })));
+    assert_eq!(unused.uniform_blocks().iter().map(|block| block.name.as_str()).collect::<Vec<_>>(), ["Camera"]);
+}
+
+/// The driver's std140 layout has to match what the renderer uploads.
+#[test]
+fn uniform_block_sizes() {
+    let mut renderer = Headless::new();
+    let lit = renderer.assets().shader_loader.get_shader_rc(DEFAULT_LIT_SHADER);
+    let lit = lit.borrow();
+
+    let block_sizes: Vec<_> = lit.uniform_blocks().iter().map(|block| (block.name.as_str(), block.size)).collect();
+    assert_eq!(block_sizes, [("Camera", CameraBlock::default().std140().len()), ("Lights", LightsBlock::default().std140().len())]);
+}
+
+/// Material textures, shadow maps and light data all have to fit in the 16 texture units GL 3.3 guarantees.
+#[test]
+fn samplers_fit_in_texture_units() {
+    let mut renderer = Headless::new();
+    let assets = renderer.assets();
+
+    let white = assets.texture_loader.white_texture();
+    let textures: Vec<_> = [
+        TextureType::Diffuse, TextureType::Emissive, TextureType::Normal, TextureType::Opacity,
+        TextureType::MetallicRoughness, TextureType::Roughness, TextureType::Metallic, TextureType::Occlusion,
+    ]
+    .into_iter()
+    .map(|tex_type| Texture2D::new(&white, tex_type, 1))
+    .collect();
+
+    let defines = Material::variant_defines(MaterialType::Pbr, &textures);
+    assert_eq!(defines.len(), 7);
+
+    let pbr = assets.shader_loader.get_variant(DEFAULT_PBR_SHADER, &defines);
+    assert!(pbr != assets.shader_loader.get_shader_rc(DEFAULT_PBR_SHADER), "Every map variant failed to link");
+
+    let samplers = pbr.borrow().uniforms().iter().filter(|uniform| uniform.uniform_type.is_sampler()).count();
+    assert!(samplers <= 16, "{samplers} samplers");
+}
+
+/// Every scene is checked before failing, so one run shows all the mismatches.
+#[test]
+fn golden_images() {
+    let mut renderer = Headless::new();
+
+    let scenes: [(&str, SceneBuilder); 11] = [
+        ("point_light", point_light),
+        ("point_light_shadows", point_light_shadows),
+        ("many_lights", many_lights),
+        ("spot_light", spot_light),
+        ("directional_light", directional_light),
+        ("shadows", shadows),
+        ("billboards", billboards),
+        ("transparency_ordering", transparency_ordering),
+        ("gltf_hierarchy", gltf_hierarchy),
+        ("pbr_materials", pbr_materials),
+        ("material_instances", material_instances),
+    ];
+
+    let mut failures = Vec::new();
+    notifications::clear();
+
+    let mut light_test = renderer.load_scene(LIGHT_TEST_SCENE).unwrap();
+    let image = renderer.render(&mut light_test);
+    failures.extend(check(LIGHT_TEST_SCENE, &image).err());
+
+    for (name, build) in scenes {
+        let mut scene = build(renderer.get_resource_managers());
+        let image = renderer.render(&mut scene);
+        failures.extend(check(name, &image).err());
+    }
+
+    // Setting a uniform the shader lacks is logged rather than failing the frame
+    failures.extend(
+        notifications::notifications()
+            .into_iter()
+            .filter(|notification| notification.message.contains("uniform"))
+            .map(|notification| format!("Rendering the scenes shouldn't set missing uniforms: {}", notification.message)),
+    );
+
+    assert!(failures.is_empty(), "Golden image failures:\n{}", failures.join("\n"));
+}
+
+/// Once the scenes are gone their assets can be freed, and loading them again still works.
+#[test]
+fn unused_assets_unload() {
+    let mut renderer = Headless::new();
+
+    let mut materials = pbr_materials(renderer.get_resource_managers());
+    renderer.render(&mut materials);
+    drop(materials);
+
+    let assets = renderer.assets();
+    assert!(assets.unload_unused() > 0);
+    assert!(assets.unused_assets().is_empty());
+
+    let mut light_test = renderer.load_scene(LIGHT_TEST_SCENE).unwrap();
+    let image = renderer.render(&mut light_test);
+    check(LIGHT_TEST_SCENE, &image).unwrap();
+}
+
+/// Background loads hand out a placeholder, then fill it in over a few updates.
+#[test]
+fn background_loads_fill_in_placeholders() {
+    let mut renderer = Headless::new();
+    let assets = renderer.assets();
+
+    let gltf_path = "assets/gltf/hierarchy.gltf";
+    let model = assets.load_model_async(gltf_path);
+    assert!(assets.is_loading(gltf_path));
+    assert_eq!(model.borrow().name, "hierarchy");
+
+    while assets.is_loading(gltf_path) {
+        for loaded in assets.update() {
+            assert!(loaded.result.is_ok(), "{:?}", loaded.result.err());
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(model.borrow().source, gltf_path);
+    assert!(model.borrow().mesh_renderers.len() > 1);
+
+    // The whole file draws the buffers of the mesh models rather than copies of them
+    let mesh_model = assets.object_loader.get(&gltf_mesh_key(gltf_path, 0)).expect("Meshes get their own models");
+    assert!(Arc::ptr_eq(&model.borrow().mesh_renderers[0].0, &mesh_model.borrow().mesh_renderers[0].0));
+}